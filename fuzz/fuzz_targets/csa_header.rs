@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = dicom_scanner::parse_csa_header(data);
+    let _ = dicom_scanner::csa_values(data, "MrPhoenixProtocol");
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use std::io::{Cursor, Write};
+
+use libfuzzer_sys::fuzz_target;
+use zip::ZipWriter;
+use zip::write::FileOptions;
+
+// The input is the single, stored entry of an archive, so mutations reach the DICOM reader
+// instead of the ZIP directory parser.
+fuzz_target!(|data: &[u8]| {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    if zip.start_file("entry.dcm", options).is_err() || zip.write_all(data).is_err() {
+        return;
+    }
+    let Ok(archive) = zip.finish() else {
+        return;
+    };
+    let _ = dicom_scanner::deep_scan_dicom_candidates_parallel(&archive.into_inner());
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use dicom_scanner::transcode::{Target, transcode};
+use libfuzzer_sys::fuzz_target;
+
+// The input is a whole DICOM file, re-encoded with both encoders written here
+fuzz_target!(|data: &[u8]| {
+    let _ = transcode(data, Target::Rle);
+    let _ = transcode(data, Target::JpegLs);
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use std::io::{Cursor, Write};
+
+use libfuzzer_sys::fuzz_target;
+use zip::ZipWriter;
+use zip::write::FileOptions;
+
+// As in deep_scan: the input is the single, stored entry of an archive
+fuzz_target!(|data: &[u8]| {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    if zip.start_file("entry.dcm", options).is_err() || zip.write_all(data).is_err() {
+        return;
+    }
+    let Ok(archive) = zip.finish() else {
+        return;
+    };
+    dicom_scanner::fuzzing::xprot(&archive.into_inner());
+});
@@ -0,0 +1,435 @@
+// --- Multi-archive batch mode ---
+//
+// Runs the scan / organize / export / xprot pipeline on many archives with a bounded number of
+// archives in flight. Per-archive output goes
+// to a sub-directory named after the archive, and the results are aggregated into
+// series_catalog.csv (one row per series, keyed by SourceArchive), failures.csv and
+// skipped_files.csv (one row per archive entry that was left out, with the reason).
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rayon::prelude::*;
+use tracing::{error, info, info_span, warn};
+
+use crate::bids::{self, Rule, Subjects};
+use crate::entry_error::{self, EntryError, SKIPPED_FILES_HEADER};
+use crate::filter::{self, Filter};
+use crate::nifti;
+use crate::parquet_export;
+use crate::preview;
+use crate::scan_index::{self, ScanIndex};
+use crate::sqlite_export::{self, HierarchyDb};
+use crate::template::PathTemplate;
+use crate::transcode;
+use crate::{
+    DeepDicomCandidate, DeepScan, SERIES_METADATA_HEADER, deep_scan_dicom_candidates_parallel,
+    dicomdir, escape_csv_field, export_series_metadata_csv, extract_and_organize_dicoms,
+    extract_xprotocol_from_zip, map_archive, sanitize_filename, series_metadata_rows,
+    unique_output_path,
+};
+
+#[derive(Default)]
+pub struct BatchOptions<'a> {
+    pub output: Option<&'a Path>,
+    pub xprot: Option<&'a Path>,
+    /// Where series_catalog.csv and failures.csv are written; no reports when None
+    pub report_dir: Option<&'a Path>,
+    pub template: Option<&'a PathTemplate>,
+    pub dicom_media: bool,
+    pub jobs: usize,
+    pub scan_index: Option<&'a Mutex<ScanIndex>>,
+    pub hierarchy_db: Option<&'a Mutex<HierarchyDb>>,
+    pub parquet: Option<&'a Path>,
+    pub transcode: Option<transcode::Target>,
+    /// Slices per series to preview, when previews are requested
+    pub preview: Option<usize>,
+    pub nifti: Option<&'a Path>,
+    pub bids: Option<&'a Path>,
+    pub bids_rules: &'a [Rule],
+    /// Subject labels for the BIDS dataset; hashed PatientIDs when None
+    pub bids_subjects: Option<&'a Subjects>,
+    /// Only the files matching this expression are extracted, exported and searched for XProtocol
+    pub filter: Option<&'a Filter>,
+}
+
+pub struct ArchiveResult {
+    pub file_count: usize,
+    pub series_rows: Vec<Vec<String>>,
+    /// Entries that were not scanned or extracted, with the reason
+    pub skipped: Vec<EntryError>,
+    /// Kept only when the batch also writes Parquet
+    candidates: Vec<DeepDicomCandidate>,
+}
+
+/// Expand `--file` values and `--file-list` entries into archive paths.
+/// Values containing glob characters are expanded; plain paths are kept as given.
+pub fn resolve_inputs(
+    files: &[PathBuf],
+    file_list: Option<&Path>,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut patterns: Vec<String> = files
+        .iter()
+        .map(|f| f.to_string_lossy().to_string())
+        .collect();
+
+    if let Some(list) = file_list {
+        let reader = BufReader::new(
+            File::open(list).map_err(|e| format!("Cannot open {}: {}", list.display(), e))?,
+        );
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                patterns.push(line.to_string());
+            }
+        }
+    }
+
+    let mut inputs = Vec::new();
+    let mut seen = HashSet::new();
+    for pattern in patterns {
+        let matches: Vec<PathBuf> = if pattern.contains(['*', '?', '[']) {
+            let mut matches: Vec<PathBuf> = glob::glob(&pattern)
+                .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?
+                .filter_map(|p| p.ok())
+                .filter(|p| p.is_file())
+                .collect();
+            if matches.is_empty() {
+                warn!("Pattern '{}' matched no files", pattern);
+            }
+            matches.sort();
+            matches
+        } else {
+            vec![PathBuf::from(pattern)]
+        };
+
+        for path in matches {
+            if seen.insert(path.clone()) {
+                inputs.push(path);
+            }
+        }
+    }
+
+    Ok(inputs)
+}
+
+/// Run the pipeline on a single archive, writing straight into the output folders, or on a batch.
+pub fn run(
+    inputs: &[PathBuf],
+    batch_mode: bool,
+    options: &BatchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if batch_mode {
+        return run_batch(inputs, options);
+    }
+    let result = process_archive(&inputs[0], Path::new(""), options)?;
+    finish(&[(inputs[0].clone(), Ok(result))], options)?;
+    Ok(())
+}
+
+/// Process every archive and write the aggregated reports.
+pub fn run_batch(
+    inputs: &[PathBuf],
+    options: &BatchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let start = Instant::now();
+
+    // Archives with the same file name in different folders still get separate output folders
+    let mut used = HashSet::new();
+    let work_dirs: Vec<(PathBuf, PathBuf)> = inputs
+        .iter()
+        .map(|input| {
+            let stem = input
+                .file_stem()
+                .map(|s| sanitize_filename(&s.to_string_lossy()))
+                .unwrap_or_else(|| "archive".to_string());
+            (
+                input.clone(),
+                unique_output_path(PathBuf::from(stem), &mut used),
+            )
+        })
+        .collect();
+
+    info!(
+        "Processing {} archive(s) with {} concurrent job(s)",
+        inputs.len(),
+        options.jobs
+    );
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.max(1))
+        .build()?;
+    let results: Vec<(PathBuf, Result<ArchiveResult, String>)> = pool.install(|| {
+        work_dirs
+            .par_iter()
+            .map(|(input, sub_dir)| {
+                let result = process_archive(input, sub_dir, options).map_err(|e| e.to_string());
+                match &result {
+                    Ok(r) => info!(
+                        archive = %input.display(),
+                        files = r.file_count,
+                        series = r.series_rows.len(),
+                        "Archive processed"
+                    ),
+                    Err(e) => error!(archive = %input.display(), "Archive failed: {}", e),
+                }
+                (input.clone(), result)
+            })
+            .collect()
+    });
+
+    if let Some(report_dir) = options.report_dir {
+        write_reports(&results, report_dir)?;
+    }
+    finish(&results, options)?;
+
+    let succeeded: Vec<&ArchiveResult> = results
+        .iter()
+        .filter_map(|(_, r)| r.as_ref().ok())
+        .collect();
+    let skipped: Vec<EntryError> = succeeded
+        .iter()
+        .flat_map(|r| r.skipped.iter().cloned())
+        .collect();
+    info!(
+        processed = succeeded.len(),
+        failed = results.len() - succeeded.len(),
+        files = succeeded.iter().map(|r| r.file_count).sum::<usize>(),
+        series = succeeded.iter().map(|r| r.series_rows.len()).sum::<usize>(),
+        skipped = skipped.len(),
+        elapsed = ?start.elapsed(),
+        "Batch finished"
+    );
+    if !skipped.is_empty() {
+        info!("Skipped entries: {}", entry_error::summarize(&skipped));
+    }
+    if let Some(report_dir) = options.report_dir {
+        info!(
+            "Series catalog: {}, failure report: {}, skipped entries: {}",
+            report_dir.join("series_catalog.csv").display(),
+            report_dir.join("failures.csv").display(),
+            report_dir.join("skipped_files.csv").display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Write the aggregated series catalog, the failure report and the skipped entries.
+fn write_reports(
+    results: &[(PathBuf, Result<ArchiveResult, String>)],
+    report_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(report_dir)?;
+
+    let mut catalog = File::create(report_dir.join("series_catalog.csv"))?;
+    writeln!(catalog, "SourceArchive,{}", SERIES_METADATA_HEADER)?;
+    for (input, result) in results {
+        if let Ok(result) = result {
+            for row in &result.series_rows {
+                writeln!(
+                    catalog,
+                    "{},{}",
+                    escape_csv_field(&input.display().to_string()),
+                    row.join(",")
+                )?;
+            }
+        }
+    }
+
+    let mut failures = File::create(report_dir.join("failures.csv"))?;
+    writeln!(failures, "SourceArchive,Error")?;
+    for (input, result) in results {
+        if let Err(e) = result {
+            writeln!(
+                failures,
+                "{},{}",
+                escape_csv_field(&input.display().to_string()),
+                escape_csv_field(e)
+            )?;
+        }
+    }
+
+    let mut skipped = File::create(report_dir.join("skipped_files.csv"))?;
+    writeln!(skipped, "SourceArchive,{}", SKIPPED_FILES_HEADER)?;
+    for (input, result) in results {
+        if let Ok(result) = result {
+            for e in &result.skipped {
+                writeln!(
+                    skipped,
+                    "{},{}",
+                    escape_csv_field(&input.display().to_string()),
+                    e.csv_row()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The steps that cover all archives of a run: the Parquet export and the BIDS dataset.
+fn finish(
+    results: &[(PathBuf, Result<ArchiveResult, String>)],
+    options: &BatchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parquet_dir) = options.parquet {
+        let rows: Vec<(String, &DeepDicomCandidate)> = results
+            .iter()
+            .filter_map(|(input, r)| r.as_ref().ok().map(|r| (input, r)))
+            .flat_map(|(input, r)| {
+                let source = input.display().to_string();
+                r.candidates.iter().map(move |c| (source.clone(), c))
+            })
+            .collect();
+        parquet_export::export_parquet(&rows, parquet_dir)?;
+    }
+
+    // One BIDS dataset for all archives, so that sessions and runs are numbered across them
+    if let (Some(output_root), Some(bids_dir)) = (options.output, options.bids) {
+        info!("Converting series to BIDS");
+        bids::convert_directory(
+            output_root,
+            bids_dir,
+            options.bids_rules,
+            options.bids_subjects.unwrap_or(&Subjects::default()),
+        )?;
+    }
+    Ok(())
+}
+
+fn process_archive(
+    input: &Path,
+    sub_dir: &Path,
+    options: &BatchOptions,
+) -> Result<ArchiveResult, Box<dyn std::error::Error>> {
+    let zip_data = map_archive(input)?;
+    process_zip_data(input, &zip_data, true, sub_dir, options)
+}
+
+/// The folder of one archive below an output root; a single archive writes into the root itself.
+fn archive_dir(root: &Path, sub_dir: &Path) -> PathBuf {
+    if sub_dir.as_os_str().is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(sub_dir)
+    }
+}
+
+/// Deep scan archive bytes, through the scan index when one is open and `indexed` is set.
+pub fn deep_scan(
+    input: &Path,
+    zip_data: &[u8],
+    indexed: bool,
+    options: &BatchOptions,
+) -> Result<DeepScan, Box<dyn std::error::Error>> {
+    match options.scan_index {
+        Some(index) if indexed => scan_index::deep_scan_with_index(index, input, zip_data),
+        _ => deep_scan_dicom_candidates_parallel(zip_data),
+    }
+}
+
+/// Run the pipeline on archive bytes. `input` names the source; it is only looked up in the scan
+/// index when `indexed` is set (i.e. it is the archive file the bytes came from).
+pub fn process_zip_data(
+    input: &Path,
+    zip_data: &[u8],
+    indexed: bool,
+    sub_dir: &Path,
+    options: &BatchOptions,
+) -> Result<ArchiveResult, Box<dyn std::error::Error>> {
+    let _archive = info_span!("archive", path = %input.display()).entered();
+    let DeepScan {
+        candidates: mut deep_candidates,
+        mut skipped,
+    } = deep_scan(input, zip_data, indexed, options)?;
+    if deep_candidates.is_empty() {
+        if skipped.is_empty() {
+            return Err("No DICOM files found".into());
+        }
+        return Err(format!(
+            "No DICOM files found ({} entries skipped: {})",
+            skipped.len(),
+            entry_error::summarize(&skipped)
+        )
+        .into());
+    }
+    let selected_series = options.filter.map(|filter| {
+        filter.apply(&mut deep_candidates);
+        filter::series_uids(&deep_candidates)
+    });
+    // Nothing in this archive is wanted; that is not a failure
+    if deep_candidates.is_empty() {
+        entry_error::report(&skipped);
+        return Ok(ArchiveResult {
+            file_count: 0,
+            series_rows: Vec::new(),
+            skipped,
+            candidates: Vec::new(),
+        });
+    }
+
+    if let Some(db) = options.hierarchy_db {
+        let counts =
+            sqlite_export::export_to_sqlite(db, input, &deep_candidates, selected_series.as_ref())?;
+        info!(
+            instances = counts.instances,
+            series = counts.series,
+            derivation_edges = counts.edges,
+            protocols = counts.protocols,
+            "SQLite export finished"
+        );
+    }
+
+    if let Some(xprot_root) = options.xprot {
+        let xprot_dir = archive_dir(xprot_root, sub_dir);
+        let (count, unparsed) =
+            extract_xprotocol_from_zip(zip_data, &xprot_dir, selected_series.as_ref())?;
+        entry_error::merge(&mut skipped, unparsed);
+        info!(
+            "Extracted {} XProtocol file(s) to {}",
+            count,
+            xprot_dir.display()
+        );
+    }
+
+    if let Some(output_root) = options.output {
+        let output_dir = archive_dir(output_root, sub_dir);
+        if options.dicom_media {
+            dicomdir::write_dicom_media_file_set(zip_data, &deep_candidates, &output_dir)?;
+        } else {
+            let unreadable = extract_and_organize_dicoms(
+                zip_data,
+                &deep_candidates,
+                &output_dir,
+                options.template,
+                options.transcode,
+            )?;
+            entry_error::merge(&mut skipped, unreadable);
+            export_series_metadata_csv(&deep_candidates, &output_dir)?;
+            entry_error::write_csv(&output_dir.join("skipped_files.csv"), &skipped)?;
+            if let Some(slices) = options.preview {
+                preview::write_previews(zip_data, &deep_candidates, &output_dir, slices)?;
+            }
+        }
+        if let Some(nifti_root) = options.nifti {
+            nifti::convert_directory(&output_dir, &archive_dir(nifti_root, sub_dir))?;
+        }
+    }
+
+    entry_error::report(&skipped);
+    Ok(ArchiveResult {
+        file_count: deep_candidates.len(),
+        series_rows: series_metadata_rows(&deep_candidates),
+        skipped,
+        candidates: if options.parquet.is_some() {
+            deep_candidates
+        } else {
+            Vec::new()
+        },
+    })
+}
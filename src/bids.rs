@@ -0,0 +1,904 @@
+// --- BIDS dataset output ---
+//
+// Converts extracted series into a BIDS raw dataset: sub-<label>/ses-<label>/<datatype>/ with
+// one NIfTI file and JSON sidecar per series, dataset_description.json and participants.tsv.
+// Series are assigned a datatype, suffix and entities by the first matching rule; rules match
+// glob patterns against SeriesDescription, ProtocolName, ImageType and sequence attributes and
+// can be replaced with a JSON file (--bids-rules). Subject labels come from a mapping file
+// (--bids-subjects) or are derived from a hash of the PatientID, so no identifier ends up in the
+// file names.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+use serde::Deserialize;
+use serde_json::{Map, json};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::DeepDicomCandidate;
+use crate::nifti::{self, ConversionJob, SeriesGroup, Slice};
+
+const BIDS_VERSION: &str = "1.9.0";
+
+/// Fields a rule can match on; `Description` matches either SeriesDescription or ProtocolName
+const RULE_FIELDS: &[&str] = &[
+    "Description",
+    "SeriesDescription",
+    "ProtocolName",
+    "ImageType",
+    "ScanningSequence",
+    "SequenceVariant",
+    "SequenceName",
+    "Modality",
+];
+
+const DATATYPES: &[&str] = &["anat", "func", "dwi", "fmap"];
+
+/// Entities a rule can set, in BIDS file name order (run, echo and part are added automatically)
+const RULE_ENTITIES: &[&str] = &["task", "acq", "ce", "rec", "dir"];
+
+/// Words of a protocol name that name the sequence rather than the task
+const SEQUENCE_WORDS: &[&str] = &[
+    "bold", "fmri", "func", "epi", "ep2d", "mbep2d", "cmrr", "sms", "moco", "task", "run", "ap",
+    "pa", "lr", "rl",
+];
+
+/// Built-in rules, in the same format as a --bids-rules file
+pub const DEFAULT_RULES: &str = r#"[
+  { "match": { "Description": ["*localizer*", "*scout*", "*survey*", "*aahead*"] }, "skip": true },
+  { "match": { "ImageType": "DERIVED" }, "skip": true },
+  { "match": { "Description": ["*field*map*", "*fmap*"], "ImageType": ["P", "PHASE"] },
+    "datatype": "fmap", "suffix": "phasediff" },
+  { "match": { "Description": ["*field*map*", "*fmap*"] }, "datatype": "fmap", "suffix": "magnitude" },
+  { "match": { "Description": ["*sbref*"] }, "skip": true },
+  { "match": { "Description": ["*rest*"], "ScanningSequence": "EP" },
+    "datatype": "func", "suffix": "bold", "entities": { "task": "rest" } },
+  { "match": { "Description": ["*bold*", "*fmri*"] }, "datatype": "func", "suffix": "bold" },
+  { "match": { "Description": ["*dwi*", "*dti*", "*diff*"] }, "datatype": "dwi", "suffix": "dwi" },
+  { "match": { "Description": ["*flair*"] }, "datatype": "anat", "suffix": "FLAIR" },
+  { "match": { "Description": ["*t1*", "*mprage*", "*mp2rage*", "*spgr*", "*bravo*"] },
+    "datatype": "anat", "suffix": "T1w" },
+  { "match": { "Description": ["*t2*"] }, "datatype": "anat", "suffix": "T2w" }
+]"#;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Patterns {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    #[serde(rename = "match", default)]
+    matches: BTreeMap<String, Patterns>,
+    #[serde(default)]
+    exclude: BTreeMap<String, Patterns>,
+    #[serde(default)]
+    datatype: String,
+    #[serde(default)]
+    suffix: String,
+    #[serde(default)]
+    entities: BTreeMap<String, String>,
+    #[serde(default)]
+    skip: bool,
+}
+
+/// A series classification rule
+pub struct Rule {
+    matches: Vec<(String, Vec<Pattern>)>,
+    exclude: Vec<(String, Vec<Pattern>)>,
+    datatype: String,
+    suffix: String,
+    entities: Vec<(String, String)>,
+    skip: bool,
+}
+
+/// Load the rules from a JSON file, or the built-in rules when no file is given.
+pub fn load_rules(path: Option<&Path>) -> Result<Vec<Rule>, Box<dyn std::error::Error>> {
+    let text = match path {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read BIDS rules {}: {}", path.display(), e))?,
+        None => DEFAULT_RULES.to_string(),
+    };
+    let specs: Vec<RuleSpec> =
+        serde_json::from_str(&text).map_err(|e| format!("Invalid BIDS rules: {}", e))?;
+
+    specs
+        .into_iter()
+        .enumerate()
+        .map(|(i, spec)| {
+            compile_rule(spec).map_err(|e| format!("BIDS rule {}: {}", i + 1, e).into())
+        })
+        .collect()
+}
+
+fn compile_rule(spec: RuleSpec) -> Result<Rule, String> {
+    let compile = |fields: BTreeMap<String, Patterns>| {
+        fields
+            .into_iter()
+            .map(|(field, patterns)| {
+                if !RULE_FIELDS.contains(&field.as_str()) {
+                    return Err(format!(
+                        "unknown field '{}' (expected one of {})",
+                        field,
+                        RULE_FIELDS.join(", ")
+                    ));
+                }
+                let patterns = match patterns {
+                    Patterns::One(p) => vec![p],
+                    Patterns::Many(p) => p,
+                };
+                let patterns = patterns
+                    .iter()
+                    .map(|p| Pattern::new(&p.to_lowercase()).map_err(|e| format!("'{}': {}", p, e)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((field, patterns))
+            })
+            .collect::<Result<Vec<_>, String>>()
+    };
+
+    let matches = compile(spec.matches)?;
+    let exclude = compile(spec.exclude)?;
+    if !spec.skip {
+        if !DATATYPES.contains(&spec.datatype.as_str()) {
+            return Err(format!(
+                "datatype must be one of {} (got '{}')",
+                DATATYPES.join(", "),
+                spec.datatype
+            ));
+        }
+        if spec.suffix.is_empty() || !spec.suffix.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("invalid suffix '{}'", spec.suffix));
+        }
+    }
+    let mut entities = Vec::new();
+    for entity in RULE_ENTITIES {
+        if let Some(value) = spec.entities.get(*entity) {
+            if value.is_empty() || !value.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(format!("invalid {} label '{}'", entity, value));
+            }
+            entities.push((entity.to_string(), value.clone()));
+        }
+    }
+    if let Some(entity) = spec
+        .entities
+        .keys()
+        .find(|e| !RULE_ENTITIES.contains(&e.as_str()))
+    {
+        return Err(format!(
+            "unknown entity '{}' (expected one of {})",
+            entity,
+            RULE_ENTITIES.join(", ")
+        ));
+    }
+
+    Ok(Rule {
+        matches,
+        exclude,
+        datatype: spec.datatype,
+        suffix: spec.suffix,
+        entities,
+        skip: spec.skip,
+    })
+}
+
+/// Lower-cased values of a rule field; multi-valued attributes are split into their components.
+fn field_values(slice: &Slice, field: &str) -> Vec<String> {
+    let c = &slice.candidate;
+    let values: Vec<&str> = match field {
+        "Description" => vec![&c.series_description, &c.protocol_name],
+        "SeriesDescription" => vec![&c.series_description],
+        "ProtocolName" => vec![&c.protocol_name],
+        "ImageType" => c.image_type.split('\\').collect(),
+        "ScanningSequence" => slice.scanning_sequence.split('\\').collect(),
+        "SequenceVariant" => slice.sequence_variant.split('\\').collect(),
+        "SequenceName" => vec![&slice.sequence_name],
+        "Modality" => vec![&c.modality],
+        _ => Vec::new(),
+    };
+    values
+        .into_iter()
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty() && v != "n/a")
+        .collect()
+}
+
+fn field_matches(slice: &Slice, field: &str, patterns: &[Pattern]) -> bool {
+    field_values(slice, field)
+        .iter()
+        .any(|value| patterns.iter().any(|p| p.matches(value)))
+}
+
+impl Rule {
+    fn matches(&self, slice: &Slice) -> bool {
+        self.matches
+            .iter()
+            .all(|(field, patterns)| field_matches(slice, field, patterns))
+            && !self
+                .exclude
+                .iter()
+                .any(|(field, patterns)| field_matches(slice, field, patterns))
+    }
+}
+
+/// Alphanumeric BIDS label, or `fallback` when nothing is left
+fn label(value: &str, fallback: &str) -> String {
+    let label: String = value
+        .trim()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    if label.is_empty() || value.trim() == "N/A" {
+        fallback.to_string()
+    } else {
+        label
+    }
+}
+
+/// Subject labels by PatientID, read from a --bids-subjects file
+#[derive(Default)]
+pub struct Subjects(HashMap<String, String>);
+
+impl Subjects {
+    /// The mapped label, or the first 12 hex digits of the SHA-256 of the PatientID
+    fn label(&self, patient_id: &str) -> String {
+        let patient_id = patient_id.trim();
+        if let Some(label) = self.0.get(patient_id) {
+            return label.clone();
+        }
+        if patient_id.is_empty() || patient_id == "N/A" {
+            return "unknown".to_string();
+        }
+        Sha256::digest(patient_id.as_bytes())[..6]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Load a subject mapping: one `PatientID<TAB>label` pair per line (a `sub-` prefix is optional).
+pub fn load_subjects(path: Option<&Path>) -> Result<Subjects, Box<dyn std::error::Error>> {
+    let Some(path) = path else {
+        return Ok(Subjects::default());
+    };
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read BIDS subjects {}: {}", path.display(), e))?;
+    let mut subjects = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("{} line {}: {}", path.display(), number + 1, message);
+        let (patient_id, label) = line
+            .split_once('\t')
+            .ok_or_else(|| error("expected PatientID<TAB>label"))?;
+        let label = label.trim();
+        let label = label.strip_prefix("sub-").unwrap_or(label);
+        if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(error(&format!("invalid subject label '{}'", label)).into());
+        }
+        subjects.insert(patient_id.trim().to_string(), label.to_string());
+    }
+    Ok(Subjects(subjects))
+}
+
+/// Task label of a functional series whose rule names none: the `task-<label>` part of the
+/// protocol name (ReproIn style), otherwise its words without sequence terms and numbers.
+fn task_label(c: &DeepDicomCandidate) -> Option<String> {
+    let protocol = [&c.protocol_name, &c.series_description]
+        .into_iter()
+        .map(|v| v.trim())
+        .find(|v| !v.is_empty() && *v != "N/A")?
+        .to_lowercase();
+    let words: Vec<&str> = protocol
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    if let Some(i) = words.iter().position(|w| *w == "task") {
+        return words.get(i + 1).map(|w| w.to_string());
+    }
+    let task: String = words
+        .into_iter()
+        .filter(|w| !SEQUENCE_WORDS.contains(w) && !is_numbered(w))
+        .collect();
+    (!task.is_empty()).then_some(task)
+}
+
+/// Run, multiband, resolution and coil numbers such as run1, mb4, 2mm or 64ch
+fn is_numbered(word: &str) -> bool {
+    let rest = word.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let prefix = &word[..word.len() - rest.len()];
+    let suffix = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+    if suffix.len() == rest.len() {
+        return false;
+    }
+    match (prefix, suffix) {
+        (prefix, "") => ["", "run", "mb", "sms", "p", "e", "te", "echo", "v"].contains(&prefix),
+        ("", suffix) => ["mm", "iso", "ch"].contains(&suffix),
+        _ => false,
+    }
+}
+
+/// A matched series group on its way to a BIDS file name
+struct Assignment<'a> {
+    group: &'a SeriesGroup<'a>,
+    rule: &'a Rule,
+    /// The rule's entities, with the task derived from the protocol when a func rule names none
+    entities: Vec<(String, String)>,
+    subject: String,
+    session: String,
+    /// Set when the series also has phase images (the magnitude file then gets part-mag)
+    has_phase: bool,
+}
+
+impl Assignment<'_> {
+    fn candidate(&self) -> &DeepDicomCandidate {
+        &self.group.slices[0].candidate
+    }
+
+    /// sub, ses and the rule entities: the part of the name that precedes run
+    fn prefix(&self) -> String {
+        let mut prefix = format!("sub-{}_ses-{}", self.subject, self.session);
+        for (entity, value) in &self.entities {
+            prefix.push_str(&format!("_{}-{}", entity, value));
+        }
+        prefix
+    }
+
+    /// Everything that identifies the file apart from run, echo and part
+    fn stem(&self) -> String {
+        format!(
+            "{}/{}_{}",
+            self.rule.datatype,
+            self.prefix(),
+            self.rule.suffix
+        )
+    }
+
+    fn file_name(&self, run: Option<usize>) -> PathBuf {
+        let mut name = self.prefix();
+        if let Some(run) = run {
+            name.push_str(&format!("_run-{}", run));
+        }
+
+        let mut suffix = self.rule.suffix.clone();
+        if self.rule.datatype == "fmap" {
+            // Field map echoes are magnitude1 / magnitude2 rather than echo entities
+            if suffix == "magnitude" {
+                suffix.push_str(&self.group.echo_number.unwrap_or(1).to_string());
+            }
+        } else {
+            if let Some(echo) = self.group.echo_number {
+                name.push_str(&format!("_echo-{}", echo));
+            }
+            if self.group.phase {
+                name.push_str("_part-phase");
+            } else if self.has_phase {
+                name.push_str("_part-mag");
+            }
+        }
+        name.push('_');
+        name.push_str(&suffix);
+
+        PathBuf::from(format!("sub-{}", self.subject))
+            .join(format!("ses-{}", self.session))
+            .join(&self.rule.datatype)
+            .join(name)
+    }
+}
+
+/// EchoTime1/EchoTime2 (s) of a phasediff series: the two echo times of its magnitude field map,
+/// preferably the one with the same ProtocolName, otherwise the nearest earlier series.
+fn phasediff_echo_times(phase: &Assignment, assignments: &[Assignment]) -> Option<(f64, f64)> {
+    let series_number = |a: &Assignment| {
+        a.candidate()
+            .series_number
+            .trim()
+            .parse::<i64>()
+            .unwrap_or(0)
+    };
+    let magnitudes: Vec<&Assignment> = assignments
+        .iter()
+        .filter(|a| {
+            a.rule.datatype == "fmap"
+                && a.rule.suffix == "magnitude"
+                && a.subject == phase.subject
+                && a.session == phase.session
+        })
+        .collect();
+    let partner = magnitudes.iter().max_by_key(|a| {
+        (
+            a.candidate().protocol_name == phase.candidate().protocol_name,
+            series_number(a) <= series_number(phase),
+            -(series_number(a) - series_number(phase)).abs(),
+        )
+    })?;
+
+    let mut echo_times: Vec<f64> = magnitudes
+        .iter()
+        .filter(|a| a.candidate().series_instance_uid == partner.candidate().series_instance_uid)
+        .filter_map(|a| a.candidate().echo_time.trim().parse::<f64>().ok())
+        .collect();
+    echo_times.sort_by(f64::total_cmp);
+    echo_times.dedup();
+    match echo_times[..] {
+        [first, second] => Some((first / 1000.0, second / 1000.0)),
+        _ => None,
+    }
+}
+
+/// Convert every series found below `input_dir` into a BIDS dataset in `output_dir`.
+pub fn convert_directory(
+    input_dir: &Path,
+    output_dir: &Path,
+    rules: &[Rule],
+    subjects: &Subjects,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let slices = nifti::read_slices(input_dir)?;
+    let groups = nifti::group_series(&slices);
+
+    // Sessions are labelled by StudyDate, with a letter suffix for further studies on the same day
+    let mut studies: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for group in &groups {
+        let c = &group.slices[0].candidate;
+        let subject = subjects.label(&c.patient_id);
+        let study = (
+            c.study_date.trim().to_string(),
+            c.study_instance_uid.clone(),
+        );
+        let studies = studies.entry(subject).or_default();
+        if !studies.contains(&study) {
+            studies.push(study);
+        }
+    }
+    let mut sessions: HashMap<(String, String), String> = HashMap::new();
+    for (subject, studies) in &mut studies {
+        studies.sort();
+        let mut used: HashMap<String, usize> = HashMap::new();
+        for (date, study_uid) in studies.iter() {
+            let base = label(date, "1");
+            let count = used.entry(base.clone()).or_insert(0);
+            let session = match *count {
+                0 => base.clone(),
+                n => format!("{}{}", base, (b'a' + (n as u8).min(25)) as char),
+            };
+            *count += 1;
+            sessions.insert((subject.clone(), study_uid.clone()), session);
+        }
+    }
+
+    let phase_series: HashSet<&str> = groups
+        .iter()
+        .filter(|g| g.phase)
+        .map(|g| g.slices[0].candidate.series_instance_uid.as_str())
+        .collect();
+
+    let mut assignments = Vec::new();
+    for group in &groups {
+        let first = group.slices[0];
+        let c = &first.candidate;
+        let description = format!(
+            "series {} {} ({})",
+            c.series_number.trim(),
+            c.series_description,
+            c.series_instance_uid
+        );
+        match rules.iter().find(|rule| rule.matches(first)) {
+            Some(rule) if rule.skip => info!("BIDS: skipped {} (rule)", description),
+            Some(rule) => {
+                let mut entities = rule.entities.clone();
+                if rule.datatype == "func" && !entities.iter().any(|(e, _)| e == "task") {
+                    let Some(task) = task_label(c) else {
+                        warn!(
+                            "BIDS: no task label in the protocol of {}; name the task in the rule",
+                            description
+                        );
+                        continue;
+                    };
+                    entities.insert(0, ("task".to_string(), task));
+                }
+                let subject = subjects.label(&c.patient_id);
+                let session = sessions[&(subject.clone(), c.study_instance_uid.clone())].clone();
+                assignments.push(Assignment {
+                    group,
+                    rule,
+                    entities,
+                    subject,
+                    session,
+                    has_phase: phase_series.contains(c.series_instance_uid.as_str()),
+                });
+            }
+            None => warn!("BIDS: no rule matched {}, not converted", description),
+        }
+    }
+
+    // Several series with the same name get run-1, run-2, ... in series number order
+    let mut series_per_stem: HashMap<String, Vec<&str>> = HashMap::new();
+    for assignment in &assignments {
+        let series = series_per_stem.entry(assignment.stem()).or_default();
+        let uid = assignment.group.slices[0]
+            .candidate
+            .series_instance_uid
+            .as_str();
+        if !series.contains(&uid) {
+            series.push(uid);
+        }
+    }
+
+    let jobs: Vec<ConversionJob> = assignments
+        .iter()
+        .map(|assignment| {
+            let series = &series_per_stem[&assignment.stem()];
+            let uid = &assignment.group.slices[0].candidate.series_instance_uid;
+            let run = (series.len() > 1)
+                .then(|| series.iter().position(|s| s == uid).map(|i| i + 1))
+                .flatten();
+
+            let mut sidecar = Map::new();
+            if assignment.rule.datatype == "func"
+                && let Some((_, task)) = assignment.entities.iter().find(|(e, _)| e == "task")
+            {
+                sidecar.insert("TaskName".to_string(), json!(task));
+            }
+            if assignment.rule.suffix == "phasediff" {
+                match phasediff_echo_times(assignment, &assignments) {
+                    Some((first, second)) => {
+                        sidecar.insert("EchoTime1".to_string(), json!(first));
+                        sidecar.insert("EchoTime2".to_string(), json!(second));
+                    }
+                    None => warn!(
+                        "BIDS: no magnitude echo times for {}, EchoTime1/EchoTime2 not written",
+                        assignment.file_name(run).display()
+                    ),
+                }
+            }
+            ConversionJob {
+                group: assignment.group,
+                base: assignment.file_name(run),
+                sidecar,
+            }
+        })
+        .collect();
+
+    let (written, skipped) = nifti::write_series(&jobs, output_dir);
+
+    write_dataset_description(output_dir)?;
+    let participants: Vec<&Slice> = assignments.iter().map(|a| a.group.slices[0]).collect();
+    write_participants(output_dir, &participants, subjects)?;
+
+    info!(
+        "BIDS conversion: {} file(s) written to {}, {} series skipped",
+        written,
+        output_dir.display(),
+        skipped
+    );
+    Ok(())
+}
+
+/// Write dataset_description.json unless the dataset already has one.
+fn write_dataset_description(output_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let path = output_dir.join("dataset_description.json");
+    if path.exists() {
+        return Ok(());
+    }
+    let name = output_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "dicom_scanner".to_string());
+    let description = json!({
+        "Name": name,
+        "BIDSVersion": BIDS_VERSION,
+        "DatasetType": "raw",
+        "GeneratedBy": [{
+            "Name": "dicom_scanner",
+            "Version": env!("CARGO_PKG_VERSION"),
+        }],
+    });
+    fs::create_dir_all(output_dir)?;
+    fs::write(path, serde_json::to_string_pretty(&description)? + "\n")?;
+    Ok(())
+}
+
+/// Add new subjects to participants.tsv, keeping existing rows and columns.
+fn write_participants(
+    output_dir: &Path,
+    slices: &[&Slice],
+    subjects: &Subjects,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = output_dir.join("participants.tsv");
+    let mut header = vec![
+        "participant_id".to_string(),
+        "sex".to_string(),
+        "age".to_string(),
+    ];
+    let mut rows: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let Ok(existing) = fs::read_to_string(&path) {
+        let mut lines = existing.lines().filter(|l| !l.trim().is_empty());
+        if let Some(first) = lines.next() {
+            header = first.split('\t').map(str::to_string).collect();
+        }
+        for line in lines {
+            let row: Vec<String> = line.split('\t').map(str::to_string).collect();
+            rows.insert(row[0].clone(), row);
+        }
+    }
+
+    for slice in slices {
+        let id = format!("sub-{}", subjects.label(&slice.candidate.patient_id));
+        if rows.contains_key(&id) {
+            continue;
+        }
+        let sex = match slice.patient_sex.trim() {
+            "M" => "M",
+            "F" => "F",
+            "O" => "O",
+            _ => "n/a",
+        };
+        let row = header
+            .iter()
+            .map(|column| match column.as_str() {
+                "participant_id" => id.clone(),
+                "sex" => sex.to_string(),
+                "age" => dicom_age(&slice.patient_age).unwrap_or_else(|| "n/a".to_string()),
+                _ => "n/a".to_string(),
+            })
+            .collect();
+        rows.insert(id, row);
+    }
+
+    let mut out = header.join("\t") + "\n";
+    for row in rows.values() {
+        out.push_str(&row.join("\t"));
+        out.push('\n');
+    }
+    fs::create_dir_all(output_dir)?;
+    fs::write(path, out)?;
+    Ok(())
+}
+
+/// DICOM AS (nnnD/W/M/Y) as age in years
+fn dicom_age(value: &str) -> Option<String> {
+    let value = value.trim();
+    let (number, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    let number = number.parse::<f64>().ok()?;
+    let years = match unit {
+        "Y" => number,
+        "M" => number / 12.0,
+        "W" => number * 7.0 / 365.25,
+        "D" => number / 365.25,
+        _ => return None,
+    };
+    Some(if years.fract() == 0.0 {
+        format!("{}", years)
+    } else {
+        format!("{:.2}", years)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use dicom::core::VR;
+    use dicom::dictionary_std::tags;
+    use dicom::object::InMemDicomObject;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::test_support::{
+        csa_header, file_bytes, instance, put_csa_image_header, put_pixels, scratch_dir,
+    };
+
+    /// A 2x2 axial MR image of patient P1 whose description is also its protocol name
+    fn image(series_number: u16, sop: &str, description: &str) -> InMemDicomObject {
+        let series = format!("1.2.3.{}", series_number);
+        let mut obj = instance("P1", "1.2.3", &series, sop);
+        obj.put_str(tags::MODALITY, VR::CS, "MR");
+        obj.put_str(tags::STUDY_DATE, VR::DA, "20240101");
+        obj.put_str(tags::SERIES_NUMBER, VR::IS, series_number.to_string());
+        obj.put_str(tags::SERIES_DESCRIPTION, VR::LO, description);
+        obj.put_str(tags::PROTOCOL_NAME, VR::LO, description);
+        obj.put_str(tags::IMAGE_ORIENTATION_PATIENT, VR::DS, "1\\0\\0\\0\\1\\0");
+        obj.put_str(tags::IMAGE_POSITION_PATIENT, VR::DS, "0\\0\\0");
+        obj.put_str(tags::PIXEL_SPACING, VR::DS, "1\\1");
+        put_pixels(&mut obj, 2, 2, 1, vec![0; 4]);
+        obj
+    }
+
+    fn field_map(
+        series_number: u16,
+        sop: &str,
+        image_type: &str,
+        echo: &str,
+        te: &str,
+    ) -> InMemDicomObject {
+        let mut obj = image(series_number, sop, "gre_field_mapping");
+        obj.put_str(tags::IMAGE_TYPE, VR::CS, image_type);
+        obj.put_str(tags::ECHO_NUMBERS, VR::IS, echo);
+        obj.put_str(tags::ECHO_TIME, VR::DS, te);
+        obj
+    }
+
+    fn session() -> Vec<InMemDicomObject> {
+        let mut files = Vec::new();
+
+        let mut bold = image(1, "1.2.3.1.1", "fMRI_nback_run1");
+        bold.put_str(tags::SCANNING_SEQUENCE, VR::CS, "EP");
+        files.push(bold);
+
+        // Siemens field map: a two-echo magnitude series and a phase difference series
+        files.push(field_map(
+            2,
+            "1.2.3.2.1",
+            "ORIGINAL\\PRIMARY\\M\\ND",
+            "1",
+            "4.92",
+        ));
+        files.push(field_map(
+            2,
+            "1.2.3.2.2",
+            "ORIGINAL\\PRIMARY\\M\\ND",
+            "2",
+            "7.38",
+        ));
+        files.push(field_map(
+            3,
+            "1.2.3.3.1",
+            "ORIGINAL\\PRIMARY\\P\\ND",
+            "2",
+            "7.38",
+        ));
+
+        // Three diffusion volumes of one slice: b=0, then gradients along x and in y-z
+        for (i, (b_value, direction)) in [
+            ("0", None),
+            ("1000", Some(["1", "0", "0"])),
+            ("1000", Some(["0", "0.6", "0.8"])),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut dwi = image(4, &format!("1.2.3.4.{}", i), "ep2d_diff_mddw");
+            dwi.put_str(tags::INSTANCE_NUMBER, VR::IS, (i + 1).to_string());
+            let csa = match &direction {
+                Some(direction) => csa_header(&[
+                    ("B_value", &[b_value]),
+                    ("DiffusionGradientDirection", direction),
+                ]),
+                None => csa_header(&[("B_value", &[b_value])]),
+            };
+            put_csa_image_header(&mut dwi, csa);
+            files.push(dwi);
+        }
+
+        // Only sequence words: no task label can be derived
+        files.push(image(5, "1.2.3.5.1", "ep2d_bold_moco"));
+        files
+    }
+
+    /// Convert the test session and return the dataset directory.
+    fn convert(name: &str, subjects: &Subjects) -> PathBuf {
+        let dir = scratch_dir(name);
+        let input = dir.join("in");
+        fs::create_dir_all(&input).unwrap();
+        for (i, obj) in session().into_iter().enumerate() {
+            fs::write(input.join(format!("{}.dcm", i)), file_bytes(obj)).unwrap();
+        }
+        let rules = load_rules(None).unwrap();
+        convert_directory(&input, &dir.join("bids"), &rules, subjects).unwrap();
+        dir.join("bids")
+    }
+
+    fn sidecar(path: &Path) -> Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn session_is_named_and_described() {
+        let bids = convert("bids_session", &Subjects::default());
+        // SHA-256 of "P1"
+        let subject = bids.join("sub-fbeae7c18667/ses-20240101");
+        let prefix = "sub-fbeae7c18667_ses-20240101";
+
+        assert_eq!(
+            files(&subject.join("func")),
+            [
+                format!("{}_task-nback_bold.json", prefix),
+                format!("{}_task-nback_bold.nii.gz", prefix)
+            ]
+        );
+        let bold = sidecar(&subject.join(format!("func/{}_task-nback_bold.json", prefix)));
+        assert_eq!(bold["TaskName"], "nback");
+
+        assert_eq!(
+            files(&subject.join("fmap")),
+            [
+                "magnitude1.json",
+                "magnitude1.nii.gz",
+                "magnitude2.json",
+                "magnitude2.nii.gz"
+            ]
+            .iter()
+            .chain(&["phasediff.json", "phasediff.nii.gz"])
+            .map(|s| format!("{}_{}", prefix, s))
+            .collect::<Vec<_>>()
+        );
+        let phasediff = sidecar(&subject.join(format!("fmap/{}_phasediff.json", prefix)));
+        assert_eq!(phasediff["EchoTime1"], 0.00492);
+        assert_eq!(phasediff["EchoTime2"], 0.00738);
+
+        // Gradients along the voxel axes; x is negated because the axial grid is right-handed
+        let dwi = subject.join(format!("dwi/{}_dwi", prefix));
+        assert_eq!(
+            fs::read_to_string(dwi.with_extension("bval")).unwrap(),
+            "0 1000 1000\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dwi.with_extension("bvec")).unwrap(),
+            "0 -1 0\n0 0 0.6\n0 0 0.8\n"
+        );
+
+        let participants = fs::read_to_string(bids.join("participants.tsv")).unwrap();
+        assert_eq!(
+            participants,
+            "participant_id\tsex\tage\nsub-fbeae7c18667\tn/a\tn/a\n"
+        );
+        fs::remove_dir_all(bids.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn subjects_are_mapped() {
+        let dir = scratch_dir("bids_subjects_file");
+        let path = dir.join("subjects.tsv");
+        fs::write(&path, "P1\tsub-01\n\nP2\t02\n").unwrap();
+        let subjects = load_subjects(Some(&path)).unwrap();
+        assert_eq!(subjects.label("P1"), "01");
+        assert_eq!(subjects.label(" P2 "), "02");
+        assert_eq!(subjects.label("P3").len(), 12);
+        assert_eq!(subjects.label("N/A"), "unknown");
+
+        fs::write(&path, "P1\tsub_01\n").unwrap();
+        assert!(load_subjects(Some(&path)).is_err());
+        fs::write(&path, "P1 01\n").unwrap();
+        assert!(load_subjects(Some(&path)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let bids = convert("bids_mapped", &subjects);
+        assert_eq!(
+            files(&bids),
+            ["dataset_description.json", "participants.tsv", "sub-01"]
+        );
+        fs::remove_dir_all(bids.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn tasks_come_from_the_protocol() {
+        let task = |protocol: &str| {
+            task_label(&DeepDicomCandidate {
+                protocol_name: protocol.to_string(),
+                series_description: "N/A".to_string(),
+                ..Default::default()
+            })
+        };
+        assert_eq!(task("func_task-nback_run-01").as_deref(), Some("nback"));
+        assert_eq!(task("fMRI_nback_run1").as_deref(), Some("nback"));
+        assert_eq!(
+            task("cmrr_mbep2d_bold_mb8_2mm_AP_faces").as_deref(),
+            Some("faces")
+        );
+        assert_eq!(task("BOLD 2back").as_deref(), Some("2back"));
+        assert_eq!(task("ep2d_bold_moco"), None);
+    }
+}
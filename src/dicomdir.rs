@@ -0,0 +1,1124 @@
+// --- DICOM media file-sets (PS3.10 / PS3.12) and DICOMDIR generation ---
+//
+// The media layout uses 8-character uppercase component names so that the output can be
+// burned to CD/DVD (ISO 9660 level 1) without any renaming:
+//
+//   DICOMDIR
+//   DICOM/PAT00001/STU00001/SER00001/IM000001
+//
+// The DICOMDIR is encoded by hand in Explicit VR Little Endian, because its directory
+// records reference each other by byte offset and those offsets have to be computed from
+// the exact encoded size of every record.
+
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+use dicom::core::Tag;
+use dicom::dictionary_std::tags;
+use dicom::object::mem::InMemDicomObject;
+use dicom::object::{
+    FileDicomObject, FileMetaTableBuilder, OpenFileOptions, StandardDataDictionary,
+};
+use tracing::info;
+use zip::ZipArchive;
+
+use crate::{DeepDicomCandidate, get_tag_string};
+
+/// Media Storage Directory Storage SOP Class
+const MEDIA_STORAGE_DIRECTORY_SOP_CLASS: &str = "1.2.840.10008.1.3.10";
+const EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1";
+
+/// Root folder of the file-set, next to the DICOMDIR.
+const MEDIA_ROOT: &str = "DICOM";
+
+const PREAMBLE_LEN: usize = 128;
+
+struct MediaInstance {
+    zip_index: usize,
+    patient_id: String,
+    patient_name: String,
+    study_instance_uid: String,
+    study_date: String,
+    study_time: String,
+    study_id: String,
+    study_description: String,
+    accession_number: String,
+    series_instance_uid: String,
+    series_number: String,
+    modality: String,
+    instance_number: String,
+    sop_class_uid: String,
+    sop_instance_uid: String,
+    transfer_syntax_uid: String,
+}
+
+struct DirectoryRecord {
+    record_type: &'static str,
+    elements: Vec<(Tag, &'static str, Vec<u8>)>,
+    children: Vec<usize>,
+}
+
+/// Write a PS3.10 compliant file-set with a DICOMDIR into `output_dir`.
+/// Returns the number of instances written.
+pub fn write_dicom_media_file_set(
+    zip_bytes: &[u8],
+    deep_candidates: &[DeepDicomCandidate],
+    output_dir: &Path,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    use std::fs;
+
+    fs::create_dir_all(output_dir)?;
+    let dicomdir_path = output_dir.join("DICOMDIR");
+    if dicomdir_path.exists() {
+        return Err(format!(
+            "{} already exists; media output must go into a fresh directory",
+            dicomdir_path.display()
+        )
+        .into());
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+
+    // Pass 1: read the header of every instance to fill in the directory record keys
+    let mut instances = Vec::new();
+    for candidate in deep_candidates {
+        let zip_file = archive.by_index(candidate.index)?;
+        let dcm_object = match OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .from_reader(zip_file)
+        {
+            Ok(obj) => obj,
+            Err(_) => continue,
+        };
+
+        let meta = dcm_object.meta();
+        let get = |tag| clean_value(&get_tag_string(&dcm_object, tag));
+        instances.push(MediaInstance {
+            zip_index: candidate.index,
+            patient_id: get(tags::PATIENT_ID),
+            patient_name: get(tags::PATIENT_NAME),
+            study_instance_uid: candidate.study_instance_uid.clone(),
+            study_date: get(tags::STUDY_DATE),
+            study_time: get(tags::STUDY_TIME),
+            study_id: get(tags::STUDY_ID),
+            study_description: get(tags::STUDY_DESCRIPTION),
+            accession_number: get(tags::ACCESSION_NUMBER),
+            series_instance_uid: candidate.series_instance_uid.clone(),
+            series_number: get(tags::SERIES_NUMBER),
+            modality: get(tags::MODALITY),
+            instance_number: get(tags::INSTANCE_NUMBER),
+            sop_class_uid: clean_value(meta.media_storage_sop_class_uid()),
+            sop_instance_uid: clean_value(meta.media_storage_sop_instance_uid()),
+            transfer_syntax_uid: clean_value(meta.transfer_syntax()),
+        });
+    }
+
+    // Group into patient -> study -> series -> instances, ordered the way viewers expect
+    type SeriesList = Vec<(String, Vec<usize>)>;
+    type StudyList = Vec<(String, SeriesList)>;
+    let mut patients: Vec<(String, StudyList)> = Vec::new();
+    let mut order: Vec<usize> = (0..instances.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&instances[a], &instances[b]);
+        (
+            &a.patient_id,
+            &a.study_date,
+            &a.study_instance_uid,
+            parse_number(&a.series_number),
+            &a.series_instance_uid,
+            parse_number(&a.instance_number),
+            &a.sop_instance_uid,
+        )
+            .cmp(&(
+                &b.patient_id,
+                &b.study_date,
+                &b.study_instance_uid,
+                parse_number(&b.series_number),
+                &b.series_instance_uid,
+                parse_number(&b.instance_number),
+                &b.sop_instance_uid,
+            ))
+    });
+    for idx in order {
+        let inst = &instances[idx];
+        if patients.last().map(|p| &p.0) != Some(&inst.patient_id) {
+            patients.push((inst.patient_id.clone(), Vec::new()));
+        }
+        let studies = &mut patients.last_mut().unwrap().1;
+        if studies.last().map(|s| &s.0) != Some(&inst.study_instance_uid) {
+            studies.push((inst.study_instance_uid.clone(), Vec::new()));
+        }
+        let series = &mut studies.last_mut().unwrap().1;
+        if series.last().map(|s| &s.0) != Some(&inst.series_instance_uid) {
+            series.push((inst.series_instance_uid.clone(), Vec::new()));
+        }
+        series.last_mut().unwrap().1.push(idx);
+    }
+
+    // Pass 2: copy the files to their 8-character locations and build the directory records
+    let mut records: Vec<DirectoryRecord> = Vec::new();
+    let mut root_records = Vec::new();
+    let mut written = 0;
+
+    for (p, (_, studies)) in patients.iter().enumerate() {
+        let first = &instances[studies[0].1[0].1[0]];
+        let patient_dir = format!("PAT{:05}", p + 1);
+        // Patient ID is Type 1 in the PATIENT record; patients without one are keyed by folder
+        let patient_id = match first.patient_id.as_str() {
+            "" => &patient_dir,
+            id => id,
+        };
+        let patient_record = push_record(
+            &mut records,
+            "PATIENT",
+            vec![
+                (tags::PATIENT_NAME, "PN", text_value(&first.patient_name)),
+                (tags::PATIENT_ID, "LO", text_value(patient_id)),
+            ],
+        );
+        root_records.push(patient_record);
+
+        for (s, (_, series_list)) in studies.iter().enumerate() {
+            let first = &instances[series_list[0].1[0]];
+            let study_record = push_record(
+                &mut records,
+                "STUDY",
+                vec![
+                    (tags::STUDY_DATE, "DA", text_value(&first.study_date)),
+                    (tags::STUDY_TIME, "TM", text_value(&first.study_time)),
+                    (
+                        tags::ACCESSION_NUMBER,
+                        "SH",
+                        text_value(&first.accession_number),
+                    ),
+                    (
+                        tags::STUDY_DESCRIPTION,
+                        "LO",
+                        text_value(&first.study_description),
+                    ),
+                    (
+                        tags::STUDY_INSTANCE_UID,
+                        "UI",
+                        uid_value(&first.study_instance_uid),
+                    ),
+                    (tags::STUDY_ID, "SH", text_value(&first.study_id)),
+                ],
+            );
+            records[patient_record].children.push(study_record);
+            let study_dir = format!("STU{:05}", s + 1);
+
+            for (r, (_, instance_ids)) in series_list.iter().enumerate() {
+                let first = &instances[instance_ids[0]];
+                let series_record = push_record(
+                    &mut records,
+                    "SERIES",
+                    vec![
+                        (tags::MODALITY, "CS", text_value(&first.modality)),
+                        (
+                            tags::SERIES_INSTANCE_UID,
+                            "UI",
+                            uid_value(&first.series_instance_uid),
+                        ),
+                        (tags::SERIES_NUMBER, "IS", text_value(&first.series_number)),
+                    ],
+                );
+                records[study_record].children.push(series_record);
+                let series_dir = format!("SER{:05}", r + 1);
+
+                let dir = output_dir
+                    .join(MEDIA_ROOT)
+                    .join(&patient_dir)
+                    .join(&study_dir)
+                    .join(&series_dir);
+                fs::create_dir_all(&dir)?;
+
+                for (i, &inst_idx) in instance_ids.iter().enumerate() {
+                    let inst = &instances[inst_idx];
+                    let file_name = format!("IM{:06}", i + 1);
+
+                    let mut zip_file = archive.by_index(inst.zip_index)?;
+                    let mut out_file = fs::File::create(dir.join(&file_name))?;
+                    std::io::copy(&mut zip_file, &mut out_file)?;
+                    written += 1;
+
+                    let file_id = [
+                        MEDIA_ROOT,
+                        &patient_dir,
+                        &study_dir,
+                        &series_dir,
+                        &file_name,
+                    ]
+                    .join("\\");
+                    let image_record = push_record(
+                        &mut records,
+                        "IMAGE",
+                        vec![
+                            (Tag(0x0004, 0x1500), "CS", text_value(&file_id)),
+                            (Tag(0x0004, 0x1510), "UI", uid_value(&inst.sop_class_uid)),
+                            (Tag(0x0004, 0x1511), "UI", uid_value(&inst.sop_instance_uid)),
+                            (
+                                Tag(0x0004, 0x1512),
+                                "UI",
+                                uid_value(&inst.transfer_syntax_uid),
+                            ),
+                            (
+                                tags::INSTANCE_NUMBER,
+                                "IS",
+                                text_value(&inst.instance_number),
+                            ),
+                        ],
+                    );
+                    records[series_record].children.push(image_record);
+                }
+            }
+        }
+    }
+
+    let dicomdir = encode_dicomdir(&records, &root_records)?;
+    let mut out = fs::File::create(&dicomdir_path)?;
+    out.write_all(&dicomdir)?;
+
+    info!(
+        "Wrote DICOMDIR with {} patient(s), {} record(s), {} instance(s)",
+        patients.len(),
+        records.len(),
+        written
+    );
+
+    Ok(written)
+}
+
+fn push_record(
+    records: &mut Vec<DirectoryRecord>,
+    record_type: &'static str,
+    elements: Vec<(Tag, &'static str, Vec<u8>)>,
+) -> usize {
+    records.push(DirectoryRecord {
+        record_type,
+        elements,
+        children: Vec::new(),
+    });
+    records.len() - 1
+}
+
+/// Serialize the complete DICOMDIR file, including preamble and file meta group.
+fn encode_dicomdir(
+    records: &[DirectoryRecord],
+    root_records: &[usize],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let meta = FileMetaTableBuilder::new()
+        .media_storage_sop_class_uid(MEDIA_STORAGE_DIRECTORY_SOP_CLASS)
+        .media_storage_sop_instance_uid(generate_uid())
+        .transfer_syntax(EXPLICIT_VR_LITTLE_ENDIAN)
+        .build()?;
+
+    let mut header = vec![0u8; PREAMBLE_LEN];
+    header.extend_from_slice(b"DICM");
+    meta.write(&mut header)?;
+
+    // Offsets of the next sibling / first child, in the order the records are written
+    let mut next_sibling = vec![None; records.len()];
+    let mut siblings_lists: Vec<&[usize]> = vec![root_records];
+    siblings_lists.extend(records.iter().map(|r| r.children.as_slice()));
+    for siblings in siblings_lists {
+        for pair in siblings.windows(2) {
+            next_sibling[pair[0]] = Some(pair[1]);
+        }
+    }
+
+    // Record sizes don't depend on the offset values, so a first pass with zeros gives the layout
+    let fixed_elements_len = encode_fixed_elements(0, 0).len();
+    let sequence_header_len = 12;
+    let first_item_offset = header.len() + fixed_elements_len + sequence_header_len;
+
+    let record_sizes: Vec<usize> = records
+        .iter()
+        .map(|r| encode_record(r, 0, 0).len())
+        .collect();
+    let mut record_offsets = Vec::with_capacity(records.len());
+    let mut offset = first_item_offset;
+    for size in &record_sizes {
+        record_offsets.push(offset as u32);
+        offset += size;
+    }
+
+    let mut items = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let next = next_sibling[i].map_or(0, |n| record_offsets[n]);
+        let lower = record.children.first().map_or(0, |&c| record_offsets[c]);
+        items.extend(encode_record(record, next, lower));
+    }
+
+    let first_root = root_records.first().map_or(0, |&r| record_offsets[r]);
+    let last_root = root_records.last().map_or(0, |&r| record_offsets[r]);
+
+    let mut file = header;
+    file.extend(encode_fixed_elements(first_root, last_root));
+    // Directory Record Sequence with explicit length
+    write_tag(&mut file, Tag(0x0004, 0x1220));
+    file.extend_from_slice(b"SQ");
+    file.extend_from_slice(&[0, 0]);
+    file.extend_from_slice(&(items.len() as u32).to_le_bytes());
+    file.extend(items);
+
+    Ok(file)
+}
+
+fn encode_fixed_elements(first_root: u32, last_root: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_element(
+        &mut out,
+        Tag(0x0004, 0x1130),
+        "CS",
+        &text_value("DICOM_SCANNER"),
+    );
+    encode_element(
+        &mut out,
+        Tag(0x0004, 0x1200),
+        "UL",
+        &first_root.to_le_bytes(),
+    );
+    encode_element(
+        &mut out,
+        Tag(0x0004, 0x1202),
+        "UL",
+        &last_root.to_le_bytes(),
+    );
+    encode_element(&mut out, Tag(0x0004, 0x1212), "US", &0u16.to_le_bytes());
+    out
+}
+
+fn encode_record(record: &DirectoryRecord, next: u32, lower: u32) -> Vec<u8> {
+    let mut elements: Vec<(Tag, &str, Vec<u8>)> = vec![
+        (Tag(0x0004, 0x1400), "UL", next.to_le_bytes().to_vec()),
+        (Tag(0x0004, 0x1410), "US", 0xFFFFu16.to_le_bytes().to_vec()),
+        (Tag(0x0004, 0x1420), "UL", lower.to_le_bytes().to_vec()),
+        (Tag(0x0004, 0x1430), "CS", text_value(record.record_type)),
+    ];
+    elements.extend(record.elements.iter().cloned());
+    elements.sort_by_key(|(tag, _, _)| *tag);
+
+    let mut body = Vec::new();
+    for (tag, vr, value) in &elements {
+        encode_element(&mut body, *tag, vr, value);
+    }
+
+    let mut item = Vec::with_capacity(body.len() + 8);
+    write_tag(&mut item, Tag(0xFFFE, 0xE000));
+    item.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    item.extend(body);
+    item
+}
+
+fn write_tag(out: &mut Vec<u8>, tag: Tag) {
+    out.extend_from_slice(&tag.group().to_le_bytes());
+    out.extend_from_slice(&tag.element().to_le_bytes());
+}
+
+fn encode_element(out: &mut Vec<u8>, tag: Tag, vr: &str, value: &[u8]) {
+    write_tag(out, tag);
+    out.extend_from_slice(vr.as_bytes());
+    match vr {
+        "OB" | "OW" | "SQ" | "UN" | "UT" | "UC" | "UR" => {
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        }
+        _ => out.extend_from_slice(&(value.len() as u16).to_le_bytes()),
+    }
+    out.extend_from_slice(value);
+}
+
+/// Text values are padded with a trailing space to an even length.
+fn text_value(s: &str) -> Vec<u8> {
+    let mut bytes = s.as_bytes().to_vec();
+    if bytes.len() % 2 == 1 {
+        bytes.push(b' ');
+    }
+    bytes
+}
+
+/// UIDs are padded with a trailing NUL to an even length.
+fn uid_value(s: &str) -> Vec<u8> {
+    let mut bytes = s.as_bytes().to_vec();
+    if bytes.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn clean_value(s: &str) -> String {
+    let s = s.trim().trim_end_matches('\0');
+    if s == "N/A" {
+        String::new()
+    } else {
+        s.to_string()
+    }
+}
+
+fn parse_number(s: &str) -> i64 {
+    s.trim()
+        .parse::<f64>()
+        .map(|n| n as i64)
+        .unwrap_or(i64::MAX)
+}
+
+/// Generate a UID under the 2.25 (UUID-derived) root from the clock and process id.
+pub fn generate_uid() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    hasher.write_u32(std::process::id());
+    let value = ((hasher.finish() as u128) << 64) | (nanos & u64::MAX as u128);
+    format!("2.25.{}", value)
+}
+
+// --- Reading existing DICOMDIR files ---
+//
+// Directory records point at their next sibling and first child by byte offset. dicom-rs
+// gives us the record contents but not where each item starts, so the item offsets are
+// recovered with a small raw walk over the Directory Record Sequence. If that walk fails
+// (non-conformant encoding), the hierarchy falls back to the record order, which is
+// depth-first for virtually all writers.
+
+const UNDEFINED_LENGTH: u32 = 0xFFFF_FFFF;
+
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    pub record_type: String,
+    /// Referenced file ID with '/' separators, relative to the DICOMDIR
+    pub file_id: Option<String>,
+    pub patient_id: String,
+    pub patient_name: String,
+    pub study_instance_uid: String,
+    pub study_date: String,
+    pub study_description: String,
+    pub series_instance_uid: String,
+    pub series_number: String,
+    pub modality: String,
+    pub children: Vec<DirectoryEntry>,
+}
+
+#[derive(Debug, Default)]
+pub struct DicomdirIndex {
+    pub roots: Vec<DirectoryEntry>,
+    /// Set when the record offsets could not be resolved and record order was used instead
+    pub hierarchy_from_order: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct DicomdirCrossCheck {
+    pub referenced: usize,
+    pub missing: Vec<String>,
+    pub unreferenced: Vec<String>,
+}
+
+impl DicomdirIndex {
+    /// All file IDs referenced by any record, in record order
+    pub fn referenced_files(&self) -> Vec<String> {
+        fn collect(entry: &DirectoryEntry, out: &mut Vec<String>) {
+            if let Some(id) = &entry.file_id {
+                out.push(id.clone());
+            }
+            for child in &entry.children {
+                collect(child, out);
+            }
+        }
+        let mut out = Vec::new();
+        for root in &self.roots {
+            collect(root, &mut out);
+        }
+        out
+    }
+
+    pub fn count(&self, record_type: &str) -> usize {
+        fn count(entry: &DirectoryEntry, record_type: &str) -> usize {
+            let own = usize::from(entry.record_type == record_type);
+            own + entry
+                .children
+                .iter()
+                .map(|c| count(c, record_type))
+                .sum::<usize>()
+        }
+        self.roots.iter().map(|r| count(r, record_type)).sum()
+    }
+
+    /// Compare referenced file IDs with the DICOM files actually present.
+    /// `present` holds paths relative to the DICOMDIR location with '/' separators.
+    pub fn cross_check(&self, present: &[String]) -> DicomdirCrossCheck {
+        use std::collections::{HashMap, HashSet};
+
+        let present_map: HashMap<String, &String> =
+            present.iter().map(|p| (normalize_file_id(p), p)).collect();
+        let referenced: Vec<String> = self.referenced_files();
+        let referenced_set: HashSet<String> =
+            referenced.iter().map(|r| normalize_file_id(r)).collect();
+
+        let mut missing: Vec<String> = referenced
+            .iter()
+            .filter(|r| !present_map.contains_key(&normalize_file_id(r)))
+            .cloned()
+            .collect();
+        missing.sort();
+
+        let mut unreferenced: Vec<String> = present_map
+            .iter()
+            .filter(|(key, _)| !referenced_set.contains(*key))
+            .map(|(_, p)| (*p).clone())
+            .collect();
+        unreferenced.sort();
+
+        DicomdirCrossCheck {
+            referenced: referenced.len(),
+            missing,
+            unreferenced,
+        }
+    }
+}
+
+/// File IDs on CD/DVD are often case-folded or carry an ISO 9660 ";1" version suffix
+fn normalize_file_id(id: &str) -> String {
+    id.split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(|c| {
+            c.trim_end_matches(";1")
+                .trim_end_matches('.')
+                .to_uppercase()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether a parsed file is a DICOMDIR (Media Storage Directory Storage)
+pub fn is_dicomdir(obj: &FileDicomObject<InMemDicomObject<StandardDataDictionary>>) -> bool {
+    obj.meta()
+        .media_storage_sop_class_uid()
+        .trim_end_matches('\0')
+        == MEDIA_STORAGE_DIRECTORY_SOP_CLASS
+}
+
+/// Parse a DICOMDIR file from its raw bytes.
+pub fn parse_dicomdir(bytes: &[u8]) -> Result<DicomdirIndex, Box<dyn std::error::Error>> {
+    use std::collections::HashMap;
+
+    let dcm_object = OpenFileOptions::new().from_reader(Cursor::new(bytes))?;
+    if dcm_object
+        .meta()
+        .media_storage_sop_class_uid()
+        .trim_end_matches('\0')
+        != MEDIA_STORAGE_DIRECTORY_SOP_CLASS
+    {
+        return Err("Not a DICOMDIR (unexpected Media Storage SOP Class)".into());
+    }
+
+    let items: Vec<_> = match dcm_object.element(tags::DIRECTORY_RECORD_SEQUENCE) {
+        Ok(element) => element.items().map(|i| i.to_vec()).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+
+    let mut entries: Vec<DirectoryEntry> = Vec::with_capacity(items.len());
+    let mut links: Vec<(u32, u32)> = Vec::with_capacity(items.len());
+    for item in &items {
+        let get = |tag| clean_value(&get_tag_string(item, tag));
+        let get_u32 = |tag| {
+            item.element(tag)
+                .ok()
+                .and_then(|e| e.to_int::<u32>().ok())
+                .unwrap_or(0)
+        };
+        let file_id = item
+            .element(tags::REFERENCED_FILE_ID)
+            .ok()
+            .and_then(|e| e.to_multi_str().ok().map(|parts| parts.join("/")))
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
+
+        links.push((
+            get_u32(tags::OFFSET_OF_THE_NEXT_DIRECTORY_RECORD),
+            get_u32(tags::OFFSET_OF_REFERENCED_LOWER_LEVEL_DIRECTORY_ENTITY),
+        ));
+        entries.push(DirectoryEntry {
+            record_type: get(tags::DIRECTORY_RECORD_TYPE),
+            file_id,
+            patient_id: get(tags::PATIENT_ID),
+            patient_name: get(tags::PATIENT_NAME),
+            study_instance_uid: get(tags::STUDY_INSTANCE_UID),
+            study_date: get(tags::STUDY_DATE),
+            study_description: get(tags::STUDY_DESCRIPTION),
+            series_instance_uid: get(tags::SERIES_INSTANCE_UID),
+            series_number: get(tags::SERIES_NUMBER),
+            modality: get(tags::MODALITY),
+            children: Vec::new(),
+        });
+    }
+
+    let first_root = dcm_object
+        .element(tags::OFFSET_OF_THE_FIRST_DIRECTORY_RECORD_OF_THE_ROOT_DIRECTORY_ENTITY)
+        .ok()
+        .and_then(|e| e.to_int::<u32>().ok())
+        .unwrap_or(0);
+
+    // Resolve offsets to record indices
+    let offsets = directory_record_offsets(bytes).filter(|o| o.len() == entries.len());
+    if let Some(offsets) = offsets {
+        let by_offset: HashMap<u32, usize> =
+            offsets.iter().enumerate().map(|(i, &o)| (o, i)).collect();
+        let mut visited = vec![false; entries.len()];
+
+        // Walk a sibling chain, guarding against cycles in corrupt files
+        fn chain(
+            start: u32,
+            by_offset: &HashMap<u32, usize>,
+            links: &[(u32, u32)],
+            visited: &mut [bool],
+        ) -> Vec<usize> {
+            let mut result = Vec::new();
+            let mut next = start;
+            while next != 0 {
+                let Some(&idx) = by_offset.get(&next) else {
+                    break;
+                };
+                if visited[idx] {
+                    break;
+                }
+                visited[idx] = true;
+                result.push(idx);
+                next = links[idx].0;
+            }
+            result
+        }
+
+        fn build(
+            idx: usize,
+            entries: &[DirectoryEntry],
+            by_offset: &HashMap<u32, usize>,
+            links: &[(u32, u32)],
+            visited: &mut [bool],
+        ) -> DirectoryEntry {
+            let mut entry = entries[idx].clone();
+            for child in chain(links[idx].1, by_offset, links, visited) {
+                entry
+                    .children
+                    .push(build(child, entries, by_offset, links, visited));
+            }
+            entry
+        }
+
+        let root_start = if first_root != 0 {
+            first_root
+        } else {
+            offsets.first().copied().unwrap_or(0)
+        };
+        let root_indices = chain(root_start, &by_offset, &links, &mut visited);
+        let roots = root_indices
+            .into_iter()
+            .map(|idx| build(idx, &entries, &by_offset, &links, &mut visited))
+            .collect();
+
+        return Ok(DicomdirIndex {
+            roots,
+            hierarchy_from_order: false,
+        });
+    }
+
+    Ok(DicomdirIndex {
+        roots: hierarchy_from_record_order(entries),
+        hierarchy_from_order: true,
+    })
+}
+
+/// Rebuild the tree assuming records are stored depth-first.
+fn hierarchy_from_record_order(entries: Vec<DirectoryEntry>) -> Vec<DirectoryEntry> {
+    fn level(record_type: &str) -> usize {
+        match record_type {
+            "PATIENT" => 0,
+            "STUDY" => 1,
+            "SERIES" => 2,
+            _ => 3,
+        }
+    }
+
+    // Descend along the last record of each level until the parent depth is reached
+    fn insert(siblings: &mut Vec<DirectoryEntry>, entry: DirectoryEntry, depth: usize) {
+        match siblings.last_mut() {
+            Some(last) if depth > 0 && level(&last.record_type) < level(&entry.record_type) => {
+                insert(&mut last.children, entry, depth - 1)
+            }
+            _ => siblings.push(entry),
+        }
+    }
+
+    let mut roots: Vec<DirectoryEntry> = Vec::new();
+    for entry in entries {
+        let depth = level(&entry.record_type);
+        insert(&mut roots, entry, depth);
+    }
+    roots
+}
+
+/// File offsets of every item in the Directory Record Sequence, in order.
+fn directory_record_offsets(bytes: &[u8]) -> Option<Vec<u32>> {
+    if bytes.len() < PREAMBLE_LEN + 4 || &bytes[PREAMBLE_LEN..PREAMBLE_LEN + 4] != b"DICM" {
+        return None;
+    }
+
+    // File meta group is always explicit VR little endian
+    let mut pos = PREAMBLE_LEN + 4;
+    let mut explicit_vr = true;
+    while let Some(header) = read_element_header(bytes, pos, true) {
+        if header.tag.group() != 0x0002 {
+            break;
+        }
+        let value_end = pos + header.header_len + header.length as usize;
+        if header.tag == Tag(0x0002, 0x0010) {
+            let ts = String::from_utf8_lossy(bytes.get(pos + header.header_len..value_end)?);
+            explicit_vr = ts.trim_end_matches(['\0', ' ']) != "1.2.840.10008.1.2";
+        }
+        pos = value_end;
+    }
+
+    // Walk the top-level data set until the Directory Record Sequence
+    while pos < bytes.len() {
+        let header = read_element_header(bytes, pos, explicit_vr)?;
+        let value_start = pos + header.header_len;
+        if header.tag == tags::DIRECTORY_RECORD_SEQUENCE {
+            let end = if header.length == UNDEFINED_LENGTH {
+                bytes.len()
+            } else {
+                value_start + header.length as usize
+            };
+            let mut offsets = Vec::new();
+            let mut item_pos = value_start;
+            while item_pos + 8 <= end.min(bytes.len()) {
+                let item = read_element_header(bytes, item_pos, explicit_vr)?;
+                if item.tag == Tag(0xFFFE, 0xE0DD) {
+                    break;
+                }
+                if item.tag != Tag(0xFFFE, 0xE000) {
+                    return None;
+                }
+                offsets.push(u32::try_from(item_pos).ok()?);
+                item_pos = skip_item(bytes, item_pos, explicit_vr)?;
+            }
+            return Some(offsets);
+        }
+        pos = skip_element(bytes, pos, explicit_vr)?;
+    }
+
+    None
+}
+
+struct ElementHeader {
+    tag: Tag,
+    is_sequence: bool,
+    length: u32,
+    header_len: usize,
+}
+
+fn read_element_header(bytes: &[u8], pos: usize, explicit_vr: bool) -> Option<ElementHeader> {
+    let u16_at = |p: usize| -> Option<u16> {
+        Some(u16::from_le_bytes(bytes.get(p..p + 2)?.try_into().ok()?))
+    };
+    let u32_at = |p: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(bytes.get(p..p + 4)?.try_into().ok()?))
+    };
+
+    let tag = Tag(u16_at(pos)?, u16_at(pos + 2)?);
+    if tag.group() == 0xFFFE || !explicit_vr {
+        let length = u32_at(pos + 4)?;
+        return Some(ElementHeader {
+            tag,
+            is_sequence: !explicit_vr && length == UNDEFINED_LENGTH,
+            length,
+            header_len: 8,
+        });
+    }
+
+    let vr = bytes.get(pos + 4..pos + 6)?;
+    match vr {
+        b"OB" | b"OD" | b"OF" | b"OL" | b"OV" | b"OW" | b"SQ" | b"SV" | b"UC" | b"UN" | b"UR"
+        | b"UT" | b"UV" => Some(ElementHeader {
+            tag,
+            is_sequence: vr == b"SQ",
+            length: u32_at(pos + 8)?,
+            header_len: 12,
+        }),
+        _ => Some(ElementHeader {
+            tag,
+            is_sequence: false,
+            length: u32::from(u16_at(pos + 6)?),
+            header_len: 8,
+        }),
+    }
+}
+
+/// Returns the position just past the element starting at `pos`.
+fn skip_element(bytes: &[u8], pos: usize, explicit_vr: bool) -> Option<usize> {
+    let header = read_element_header(bytes, pos, explicit_vr)?;
+    let mut next = pos + header.header_len;
+    if header.length != UNDEFINED_LENGTH {
+        next += header.length as usize;
+        return (next <= bytes.len()).then_some(next);
+    }
+    if !header.is_sequence && header.tag.group() != 0x7FE0 && !explicit_vr {
+        return None;
+    }
+    // Undefined length: a run of items closed by a sequence delimiter
+    loop {
+        let item = read_element_header(bytes, next, explicit_vr)?;
+        if item.tag == Tag(0xFFFE, 0xE0DD) {
+            return Some(next + 8);
+        }
+        next = skip_item(bytes, next, explicit_vr)?;
+    }
+}
+
+/// Returns the position just past the item starting at `pos`.
+fn skip_item(bytes: &[u8], pos: usize, explicit_vr: bool) -> Option<usize> {
+    let item = read_element_header(bytes, pos, explicit_vr)?;
+    let mut next = pos + 8;
+    if item.length != UNDEFINED_LENGTH {
+        next += item.length as usize;
+        return (next <= bytes.len()).then_some(next);
+    }
+    loop {
+        let header = read_element_header(bytes, next, explicit_vr)?;
+        if header.tag == Tag(0xFFFE, 0xE00D) {
+            return Some(next + 8);
+        }
+        next = skip_element(bytes, next, explicit_vr)?;
+    }
+}
+
+/// Print the DICOMDIR tree and the cross-check result.
+pub fn print_dicomdir_listing(index: &DicomdirIndex, check: &DicomdirCrossCheck) {
+    fn print_entry(entry: &DirectoryEntry, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        match entry.record_type.as_str() {
+            "PATIENT" => println!(
+                "{}Patient {} [{}]",
+                indent, entry.patient_id, entry.patient_name
+            ),
+            "STUDY" => println!(
+                "{}Study {} \"{}\" ({})",
+                indent, entry.study_date, entry.study_description, entry.study_instance_uid
+            ),
+            "SERIES" => println!(
+                "{}Series {} {} ({}) - {} record(s)",
+                indent,
+                entry.series_number,
+                entry.modality,
+                entry.series_instance_uid,
+                entry.children.len()
+            ),
+            _ => {}
+        }
+        // Image-level records are summarized in the series line to keep the listing short
+        if entry.record_type != "SERIES" {
+            for child in &entry.children {
+                print_entry(child, depth + 1);
+            }
+        }
+    }
+
+    println!(
+        "DICOMDIR: {} patient(s), {} study(ies), {} series, {} image record(s)",
+        index.count("PATIENT"),
+        index.count("STUDY"),
+        index.count("SERIES"),
+        index.referenced_files().len()
+    );
+    if index.hierarchy_from_order {
+        println!("  (record offsets could not be resolved; hierarchy inferred from record order)");
+    }
+    for root in &index.roots {
+        print_entry(root, 0);
+    }
+
+    println!("\nDICOMDIR cross-check:");
+    println!("  Referenced files:   {}", check.referenced);
+    println!("  Missing files:      {}", check.missing.len());
+    for missing in &check.missing {
+        println!("    missing: {}", missing);
+    }
+    println!("  Unreferenced DICOM: {}", check.unreferenced.len());
+    for unreferenced in &check.unreferenced {
+        println!("    unreferenced: {}", unreferenced);
+    }
+}
+
+/// A parsed DICOMDIR plus the DICOM files found next to it
+pub type LoadedDicomdir = (DicomdirIndex, Vec<String>);
+
+/// Load a DICOMDIR from a directory, a DICOMDIR file or a ZIP archive, together with the list
+/// of DICOM files present next to it (relative to the DICOMDIR location).
+pub fn load_dicomdir_index(
+    input_path: &Path,
+) -> Result<Option<LoadedDicomdir>, Box<dyn std::error::Error>> {
+    use std::fs;
+    use std::io::Read;
+
+    fn has_dicm_magic(header: &[u8]) -> bool {
+        header.len() >= 132 && &header[128..132] == b"DICM"
+    }
+
+    // A DICOMDIR given directly, or a directory that contains one
+    let dicomdir_file = if input_path.is_dir() {
+        fs::read_dir(input_path)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .find(|p| {
+                p.is_file()
+                    && p.file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|n| n.trim_end_matches(";1").eq_ignore_ascii_case("DICOMDIR"))
+                        .unwrap_or(false)
+            })
+    } else if input_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.eq_ignore_ascii_case("DICOMDIR"))
+        .unwrap_or(false)
+    {
+        Some(input_path.to_path_buf())
+    } else {
+        None
+    };
+
+    if let Some(dicomdir_file) = dicomdir_file {
+        let base = dicomdir_file
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let index = parse_dicomdir(&fs::read(&dicomdir_file)?)?;
+
+        let mut present = Vec::new();
+        let mut stack = vec![base.clone()];
+        while let Some(dir) = stack.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    stack.push(path);
+                } else if path != dicomdir_file {
+                    let mut header = [0u8; 132];
+                    let is_dicom = fs::File::open(&path)
+                        .and_then(|mut f| f.read_exact(&mut header))
+                        .map(|_| has_dicm_magic(&header))
+                        .unwrap_or(false);
+                    if is_dicom && let Ok(rel) = path.strip_prefix(&base) {
+                        present.push(rel.to_string_lossy().replace('\\', "/"));
+                    }
+                }
+            }
+        }
+        return Ok(Some((index, present)));
+    }
+
+    if input_path.is_dir() {
+        return Ok(None);
+    }
+
+    // Otherwise look for a DICOMDIR entry inside a ZIP archive
+    let mut archive = ZipArchive::new(fs::File::open(input_path)?)?;
+    let dicomdir_entry = archive.file_names().map(|n| n.to_string()).find(|n| {
+        n.rsplit('/')
+            .next()
+            .map(|b| b.trim_end_matches(";1").eq_ignore_ascii_case("DICOMDIR"))
+            .unwrap_or(false)
+    });
+    let Some(dicomdir_entry) = dicomdir_entry else {
+        return Ok(None);
+    };
+    let prefix = match dicomdir_entry.rfind('/') {
+        Some(pos) => dicomdir_entry[..=pos].to_string(),
+        None => String::new(),
+    };
+
+    let mut bytes = Vec::new();
+    archive.by_name(&dicomdir_entry)?.read_to_end(&mut bytes)?;
+    let index = parse_dicomdir(&bytes)?;
+
+    let mut present = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = match archive.by_index(i) {
+            Ok(f) => f,
+            Err(_) => continue,
+        };
+        let name = file.name().to_string();
+        if file.is_dir() || name == dicomdir_entry || !name.starts_with(&prefix) {
+            continue;
+        }
+        let mut header = [0u8; 132];
+        if file.read_exact(&mut header).is_ok() && has_dicm_magic(&header) {
+            present.push(name[prefix.len()..].to_string());
+        }
+    }
+
+    Ok(Some((index, present)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deep_scan_dicom_candidates_parallel;
+    use crate::test_support::{file_bytes, instance, scratch_dir, zip_of};
+    use dicom::core::VR;
+
+    #[test]
+    fn written_dicomdir_reads_back() {
+        let mut entries = Vec::new();
+        for (patient, series, count) in [("P1", "1.2.3.1", 3), ("", "1.2.4.1", 2)] {
+            let study = &series[..5];
+            for i in 1..=count {
+                let mut obj = instance(patient, study, series, &format!("{}.{}", series, i));
+                obj.put_str(tags::INSTANCE_NUMBER, VR::IS, i.to_string());
+                entries.push((format!("in/{}_{}", series, i), file_bytes(obj)));
+            }
+        }
+        let entries: Vec<(&str, Vec<u8>)> = entries
+            .iter()
+            .map(|(n, d)| (n.as_str(), d.clone()))
+            .collect();
+        let zip = zip_of(&entries);
+        let candidates = deep_scan_dicom_candidates_parallel(&zip)
+            .unwrap()
+            .candidates;
+
+        let dir = scratch_dir("dicomdir_round_trip");
+        assert_eq!(
+            write_dicom_media_file_set(&zip, &candidates, &dir).unwrap(),
+            5
+        );
+
+        let (index, present) = load_dicomdir_index(&dir).unwrap().unwrap();
+        assert!(
+            !index.hierarchy_from_order,
+            "record offsets did not resolve"
+        );
+        assert_eq!(index.count("PATIENT"), 2);
+        assert_eq!(index.count("STUDY"), 2);
+        assert_eq!(index.count("SERIES"), 2);
+        assert_eq!(index.count("IMAGE"), 5);
+        let check = index.cross_check(&present);
+        assert_eq!(check.referenced, 5);
+        assert!(check.missing.is_empty(), "{:?}", check.missing);
+        assert!(check.unreferenced.is_empty(), "{:?}", check.unreferenced);
+
+        // Each image hangs under the series it belongs to
+        for patient in &index.roots {
+            assert!(!patient.patient_id.is_empty());
+            let series = &patient.children[0].children[0];
+            let expected = if patient.patient_id == "P1" { 3 } else { 2 };
+            assert_eq!(series.children.len(), expected);
+            assert!(series.children.iter().all(|i| i.file_id.is_some()));
+        }
+        let ids: Vec<&str> = index.roots.iter().map(|p| p.patient_id.as_str()).collect();
+        assert!(ids.contains(&"PAT00001"), "{:?}", ids);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1079 @@
+// --- DICOMweb client and server ---
+//
+// `dicomweb-fetch` queries a DICOMweb service with QIDO-RS (same keys as `fetch`) and retrieves
+// every matching series with WADO-RS; the multipart parts are stored like `listen` stores
+// C-STORE instances. `dicomweb-serve` indexes a directory of DICOM files (e.g. an --output tree)
+// and answers QIDO-RS searches, WADO-RS instance, metadata and frame requests, enough for
+// viewers such as OHIF to browse it.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use base64::Engine;
+use dicom::core::header::Header;
+use dicom::core::value::{PrimitiveValue, Value as DicomValue};
+use dicom::core::{DataDictionary, Tag, VR};
+use dicom::dictionary_std::tags;
+use dicom::object::mem::{InMemDicomObject, InMemElement};
+use dicom::object::{FileDicomObject, OpenFileOptions, StandardDataDictionary};
+use serde_json::{Map, Value, json};
+use tracing::{debug, info, warn};
+
+use crate::fetch::{self, Level, Match};
+use crate::listen::Storage;
+use crate::template::PathTemplate;
+use crate::transcode;
+
+type DicomFile = FileDicomObject<InMemDicomObject<StandardDataDictionary>>;
+
+// --- Client ---
+
+pub struct WebFetchOptions<'a> {
+    pub output: &'a Path,
+    /// Service root, e.g. https://pacs.example.org/dicom-web
+    pub url: &'a str,
+    /// Sent as "Authorization: Bearer <token>"
+    pub token: Option<&'a str>,
+    pub patient_id: Option<&'a str>,
+    pub study_date: Option<&'a str>,
+    pub accession: Option<&'a str>,
+    pub level: Level,
+    pub template: Option<&'a PathTemplate>,
+    pub query_only: bool,
+}
+
+pub fn fetch(options: &WebFetchOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if options.patient_id.is_none() && options.study_date.is_none() && options.accession.is_none() {
+        return Err("dicomweb-fetch needs --patient-id, --study-date or --accession".into());
+    }
+    let base = options.url.trim_end_matches('/');
+    let agent = ureq::AgentBuilder::new().build();
+
+    let mut keys = vec![(
+        "includefield",
+        "StudyDescription,ModalitiesInStudy,NumberOfStudyRelatedInstances",
+    )];
+    for (key, value) in [
+        ("PatientID", options.patient_id),
+        ("StudyDate", options.study_date),
+        ("AccessionNumber", options.accession),
+    ] {
+        if let Some(value) = value {
+            keys.push((key, value));
+        }
+    }
+    let studies = qido(&agent, options, &format!("{}/studies", base), &keys)?;
+
+    let mut matches = Vec::new();
+    for study in &studies {
+        let study_uid = json_string(study, tags::STUDY_INSTANCE_UID);
+        if study_uid.is_empty() {
+            continue;
+        }
+        let description = format!(
+            "{} {} {}",
+            json_string(study, tags::PATIENT_ID),
+            json_string(study, tags::STUDY_DATE),
+            json_string(study, tags::STUDY_DESCRIPTION)
+        );
+        if options.level == Level::Study {
+            matches.push(Match {
+                study_uid,
+                series_uid: String::new(),
+                description,
+                summary: format!(
+                    "[{}] {} instance(s)",
+                    json_string(study, tags::MODALITIES_IN_STUDY),
+                    json_string(study, tags::NUMBER_OF_STUDY_RELATED_INSTANCES)
+                ),
+            });
+        } else {
+            matches.extend(series_of(&agent, options, base, &study_uid, &description)?);
+        }
+    }
+    if matches.is_empty() {
+        warn!("No matching studies found");
+        return Ok(());
+    }
+    fetch::list_matches(&matches);
+    if options.query_only {
+        return Ok(());
+    }
+
+    // Whole studies are retrieved series by series, so only one series is held in memory
+    let mut series = Vec::new();
+    for m in matches {
+        if m.series_uid.is_empty() {
+            series.extend(series_of(
+                &agent,
+                options,
+                base,
+                &m.study_uid,
+                &m.description,
+            )?);
+        } else {
+            series.push(m);
+        }
+    }
+
+    fs::create_dir_all(options.output)?;
+    let storage = Storage::new(options.output, options.template);
+    for m in &series {
+        let url = format!("{}/studies/{}/series/{}", base, m.study_uid, m.series_uid);
+        let response = request(&agent, options, &url)
+            .set(
+                "Accept",
+                "multipart/related; type=\"application/dicom\"; transfer-syntax=*",
+            )
+            .call()?;
+        let content_type = response.header("Content-Type").unwrap_or("").to_string();
+        let mut body = Vec::new();
+        response.into_reader().read_to_end(&mut body)?;
+
+        let mut stored = 0;
+        for part in multipart_parts(&content_type, &body)? {
+            match storage.store_file(part.to_vec()) {
+                Ok(path) => {
+                    stored += 1;
+                    debug!("Received: {}", path.display());
+                }
+                Err(e) => warn!("Skipping a part of series {}: {}", m.series_uid, e),
+            }
+        }
+        info!("Retrieved {}: {} instance(s)", m.series_uid, stored);
+    }
+
+    let count = storage.instance_count();
+    info!(
+        "Fetched {} instance(s) into {}",
+        count,
+        options.output.display()
+    );
+    if count > 0 {
+        storage.export_metadata()?;
+    }
+    Ok(())
+}
+
+fn request(agent: &ureq::Agent, options: &WebFetchOptions, url: &str) -> ureq::Request {
+    let request = agent.get(url);
+    match options.token {
+        Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+        None => request,
+    }
+}
+
+/// Run a QIDO-RS search and return the matching objects (DICOM JSON).
+fn qido(
+    agent: &ureq::Agent,
+    options: &WebFetchOptions,
+    url: &str,
+    keys: &[(&str, &str)],
+) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let mut request = request(agent, options, url).set("Accept", "application/dicom+json");
+    for (key, value) in keys {
+        request = request.query(key, value);
+    }
+    let body = request
+        .call()
+        .map_err(|e| format!("QIDO-RS {}: {}", url, e))?
+        .into_string()?;
+    // 204 No Content when nothing matched
+    if body.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    match serde_json::from_str(&body)? {
+        Value::Array(objects) => Ok(objects),
+        _ => Err(format!("QIDO-RS {}: expected a JSON array", url).into()),
+    }
+}
+
+/// Every series of a study as a series-level match.
+fn series_of(
+    agent: &ureq::Agent,
+    options: &WebFetchOptions,
+    base: &str,
+    study_uid: &str,
+    description: &str,
+) -> Result<Vec<Match>, Box<dyn std::error::Error>> {
+    let url = format!("{}/studies/{}/series", base, study_uid);
+    let series = qido(agent, options, &url, &[])?;
+    Ok(series
+        .iter()
+        .map(|s| Match {
+            study_uid: study_uid.to_string(),
+            series_uid: json_string(s, tags::SERIES_INSTANCE_UID),
+            description: description.to_string(),
+            summary: format!(
+                "#{} {} [{}] {} instance(s)",
+                json_string(s, tags::SERIES_NUMBER),
+                json_string(s, tags::SERIES_DESCRIPTION),
+                json_string(s, tags::MODALITY),
+                json_string(s, tags::NUMBER_OF_SERIES_RELATED_INSTANCES)
+            ),
+        })
+        .filter(|m| !m.series_uid.is_empty())
+        .collect())
+}
+
+/// Values of one attribute of a DICOM JSON object, backslash-joined ("" when missing)
+fn json_string(object: &Value, tag: Tag) -> String {
+    let key = format!("{:04X}{:04X}", tag.group(), tag.element());
+    let Some(values) = object[key.as_str()]["Value"].as_array() else {
+        return String::new();
+    };
+    values
+        .iter()
+        .map(|v| match v {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            Value::Object(pn) => pn
+                .get("Alphabetic")
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_string(),
+            _ => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\\")
+}
+
+/// Bodies of a multipart/related response; a single application/dicom body is one part.
+fn multipart_parts<'a>(
+    content_type: &str,
+    body: &'a [u8],
+) -> Result<Vec<&'a [u8]>, Box<dyn std::error::Error>> {
+    if !content_type.to_ascii_lowercase().starts_with("multipart/") {
+        return Ok(vec![body]);
+    }
+    let boundary = content_type
+        .split(';')
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(k, _)| k.eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v.trim_matches('"'))
+        .ok_or("multipart response without a boundary")?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let next_delimiter = [b"\r\n".as_slice(), &delimiter].concat();
+
+    let mut parts = Vec::new();
+    let mut position =
+        find(body, &delimiter).ok_or("multipart response without parts")? + delimiter.len();
+    // After each delimiter: "--" closes the body, otherwise headers, a blank line and content
+    while !body[position..].starts_with(b"--") {
+        let headers_end = find(&body[position..], b"\r\n\r\n").ok_or("truncated multipart part")?;
+        let start = position + headers_end + 4;
+        let length = find(&body[start..], &next_delimiter).ok_or("truncated multipart response")?;
+        parts.push(&body[start..start + length]);
+        position = start + length + next_delimiter.len();
+    }
+    Ok(parts)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// --- Server ---
+
+pub struct WebServeOptions<'a> {
+    pub input: &'a Path,
+    pub bind: &'a str,
+    pub port: u16,
+    /// Origin sent in Access-Control-Allow-Origin
+    pub cors_origin: Option<&'a str>,
+}
+
+struct Instance {
+    path: PathBuf,
+    header: DicomFile,
+}
+
+/// Attributes returned by QIDO-RS at each level (PS3.18 Table 10.6.3-3)
+const STUDY_ATTRIBUTES: &[Tag] = &[
+    tags::SPECIFIC_CHARACTER_SET,
+    tags::STUDY_DATE,
+    tags::STUDY_TIME,
+    tags::ACCESSION_NUMBER,
+    tags::REFERRING_PHYSICIAN_NAME,
+    tags::STUDY_DESCRIPTION,
+    tags::PATIENT_NAME,
+    tags::PATIENT_ID,
+    tags::PATIENT_BIRTH_DATE,
+    tags::PATIENT_SEX,
+    tags::STUDY_INSTANCE_UID,
+    tags::STUDY_ID,
+];
+const SERIES_ATTRIBUTES: &[Tag] = &[
+    tags::SPECIFIC_CHARACTER_SET,
+    tags::MODALITY,
+    tags::SERIES_DESCRIPTION,
+    tags::STUDY_INSTANCE_UID,
+    tags::SERIES_INSTANCE_UID,
+    tags::SERIES_NUMBER,
+    tags::PERFORMED_PROCEDURE_STEP_START_DATE,
+    tags::PERFORMED_PROCEDURE_STEP_START_TIME,
+];
+const INSTANCE_ATTRIBUTES: &[Tag] = &[
+    tags::SPECIFIC_CHARACTER_SET,
+    tags::SOP_CLASS_UID,
+    tags::SOP_INSTANCE_UID,
+    tags::STUDY_INSTANCE_UID,
+    tags::SERIES_INSTANCE_UID,
+    tags::INSTANCE_NUMBER,
+    tags::ROWS,
+    tags::COLUMNS,
+    tags::BITS_ALLOCATED,
+    tags::NUMBER_OF_FRAMES,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum QueryLevel {
+    Study,
+    Series,
+    Instance,
+}
+
+impl QueryLevel {
+    fn key(self) -> Tag {
+        match self {
+            QueryLevel::Study => tags::STUDY_INSTANCE_UID,
+            QueryLevel::Series => tags::SERIES_INSTANCE_UID,
+            QueryLevel::Instance => tags::SOP_INSTANCE_UID,
+        }
+    }
+}
+
+struct Reply {
+    status: u16,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json(value: &Value) -> Reply {
+        Reply {
+            status: 200,
+            content_type: "application/dicom+json".to_string(),
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Reply {
+        Reply {
+            status,
+            content_type: "text/plain".to_string(),
+            body: message.as_bytes().to_vec(),
+        }
+    }
+
+    fn multipart(part_type: &str, parts: &[Vec<u8>]) -> Reply {
+        let boundary = "dicom-scanner-boundary";
+        let mut body = Vec::new();
+        for part in parts {
+            body.extend_from_slice(
+                format!("--{}\r\nContent-Type: {}\r\n\r\n", boundary, part_type).as_bytes(),
+            );
+            body.extend_from_slice(part);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        Reply {
+            status: 200,
+            content_type: format!(
+                "multipart/related; type=\"{}\"; boundary={}",
+                part_type, boundary
+            ),
+            body,
+        }
+    }
+}
+
+/// Index the input directory and answer DICOMweb requests until the process is stopped.
+pub fn serve(options: &WebServeOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(origin) = options.cors_origin
+        && tiny_http::Header::from_bytes("Access-Control-Allow-Origin", origin).is_err()
+    {
+        return Err(format!("--cors-origin {} is not a valid header value", origin).into());
+    }
+    let instances = load_instances(options.input)?;
+    let studies = group(&instances.iter().collect::<Vec<_>>(), QueryLevel::Study).len();
+    let server = tiny_http::Server::http((options.bind, options.port))
+        .map_err(|e| format!("Cannot listen on {}:{}: {}", options.bind, options.port, e))?;
+    info!(
+        "Serving {} instance(s) in {} study(ies) from {} at http://{}:{}/",
+        instances.len(),
+        studies,
+        options.input.display(),
+        options.bind,
+        options.port
+    );
+
+    let cache = FrameCache::default();
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                while let Ok(request) = server.recv() {
+                    answer(&instances, &cache, options.cors_origin, request);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Read the header of every DICOM file below `input`, keeping the first copy of an instance.
+fn load_instances(input: &Path) -> Result<Vec<Instance>, Box<dyn std::error::Error>> {
+    if !input.is_dir() {
+        return Err(format!("Input path does not exist: {}", input.display()).into());
+    }
+    let mut paths = Vec::new();
+    let mut stack = vec![input.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+
+    let mut seen = std::collections::HashSet::new();
+    let mut instances = Vec::new();
+    for path in paths {
+        let Ok(header) = OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .open_file(&path)
+        else {
+            continue;
+        };
+        if seen.insert(attribute(&header, tags::SOP_INSTANCE_UID)) {
+            instances.push(Instance { path, header });
+        }
+    }
+    Ok(instances)
+}
+
+fn answer(
+    instances: &[Instance],
+    cache: &FrameCache,
+    cors_origin: Option<&str>,
+    request: tiny_http::Request,
+) {
+    let preflight = *request.method() == tiny_http::Method::Options;
+    let reply = if preflight && cors_origin.is_some() {
+        Reply {
+            status: 204,
+            content_type: "text/plain".to_string(),
+            body: Vec::new(),
+        }
+    } else if preflight {
+        Reply::error(405, "cross-origin requests are not enabled")
+    } else if *request.method() != tiny_http::Method::Get {
+        Reply::error(405, "only GET is supported")
+    } else {
+        route(instances, cache, request.url()).unwrap_or_else(|e| Reply::error(500, &e.to_string()))
+    };
+
+    let header = |name: &str, value: &str| {
+        tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+    };
+    let mut response = tiny_http::Response::from_data(reply.body)
+        .with_status_code(reply.status)
+        .with_header(header("Content-Type", &reply.content_type));
+    if let Some(origin) = cors_origin {
+        response = response
+            .with_header(header("Access-Control-Allow-Origin", origin))
+            .with_header(header("Access-Control-Allow-Headers", "Accept"))
+            .with_header(header("Access-Control-Allow-Methods", "GET"))
+            .with_header(header("Vary", "Origin"));
+    }
+    if let Err(e) = request.respond(response) {
+        warn!("Response failed: {}", e);
+    }
+}
+
+fn route(
+    instances: &[Instance],
+    cache: &FrameCache,
+    url: &str,
+) -> Result<Reply, Box<dyn std::error::Error>> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let select = |study: Option<&str>, series: Option<&str>, sop: Option<&str>| -> Vec<&Instance> {
+        instances
+            .iter()
+            .filter(|i| {
+                study.is_none_or(|uid| attribute(&i.header, tags::STUDY_INSTANCE_UID) == uid)
+                    && series
+                        .is_none_or(|uid| attribute(&i.header, tags::SERIES_INSTANCE_UID) == uid)
+                    && sop.is_none_or(|uid| attribute(&i.header, tags::SOP_INSTANCE_UID) == uid)
+            })
+            .collect()
+    };
+
+    let reply = match segments.as_slice() {
+        ["studies"] => search(&select(None, None, None), QueryLevel::Study, &params),
+        ["series"] => search(&select(None, None, None), QueryLevel::Series, &params),
+        ["instances"] => search(&select(None, None, None), QueryLevel::Instance, &params),
+        ["studies", study, "series"] => search(
+            &select(Some(study), None, None),
+            QueryLevel::Series,
+            &params,
+        ),
+        ["studies", study, "instances"] => search(
+            &select(Some(study), None, None),
+            QueryLevel::Instance,
+            &params,
+        ),
+        ["studies", study, "series", series, "instances"] => search(
+            &select(Some(study), Some(series), None),
+            QueryLevel::Instance,
+            &params,
+        ),
+        ["studies", study] => retrieve(&select(Some(study), None, None))?,
+        ["studies", study, "series", series] => retrieve(&select(Some(study), Some(series), None))?,
+        ["studies", study, "series", series, "instances", sop] => {
+            retrieve(&select(Some(study), Some(series), Some(sop)))?
+        }
+        ["studies", study, "metadata"] => metadata(&select(Some(study), None, None)),
+        ["studies", study, "series", series, "metadata"] => {
+            metadata(&select(Some(study), Some(series), None))
+        }
+        [
+            "studies",
+            study,
+            "series",
+            series,
+            "instances",
+            sop,
+            "metadata",
+        ] => metadata(&select(Some(study), Some(series), Some(sop))),
+        [
+            "studies",
+            study,
+            "series",
+            series,
+            "instances",
+            sop,
+            "frames",
+            list,
+        ] => match select(Some(study), Some(series), Some(sop)).first() {
+            Some(instance) => frames(instance, cache, list)?,
+            None => Reply::error(404, "no such instance"),
+        },
+        _ => Reply::error(404, "unknown resource"),
+    };
+    Ok(reply)
+}
+
+/// Text value of an attribute without padding ("" when missing)
+fn attribute(obj: &InMemDicomObject, tag: Tag) -> String {
+    obj.element(tag)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .unwrap_or_default()
+}
+
+/// Instances grouped by the key attribute of a level, in order of first appearance
+fn group<'a>(instances: &[&'a Instance], level: QueryLevel) -> Vec<Vec<&'a Instance>> {
+    let mut order = Vec::new();
+    let mut groups: BTreeMap<String, Vec<&Instance>> = BTreeMap::new();
+    for instance in instances {
+        let key = attribute(&instance.header, level.key());
+        if !groups.contains_key(&key) {
+            order.push(key.clone());
+        }
+        groups.entry(key).or_default().push(instance);
+    }
+    order
+        .into_iter()
+        .filter_map(|key| groups.remove(&key))
+        .collect()
+}
+
+/// QIDO-RS: one result per study, series or instance whose instances match every key.
+fn search(instances: &[&Instance], level: QueryLevel, params: &[(String, String)]) -> Reply {
+    let mut offset = 0;
+    let mut limit = usize::MAX;
+    let mut filters = Vec::new();
+    for (key, value) in params {
+        match key.as_str() {
+            "limit" => limit = value.parse().unwrap_or(usize::MAX),
+            "offset" => offset = value.parse().unwrap_or(0),
+            "includefield" | "fuzzymatching" => {}
+            _ => match resolve_tag(key) {
+                // ModalitiesInStudy matches the Modality of any instance
+                Some(tags::MODALITIES_IN_STUDY) => filters.push((tags::MODALITY, value.as_str())),
+                Some(tag) => filters.push((tag, value.as_str())),
+                None => {
+                    return Reply::error(400, &format!("unknown query key {}", key));
+                }
+            },
+        }
+    }
+
+    let results: Vec<Value> = group(instances, level)
+        .iter()
+        .filter(|members| {
+            filters.iter().all(|(tag, filter)| {
+                members
+                    .iter()
+                    .any(|i| matches_filter(*tag, filter, &attribute(&i.header, *tag)))
+            })
+        })
+        .skip(offset)
+        .take(limit)
+        .map(|members| summary(members, level))
+        .collect();
+    Reply::json(&Value::Array(results))
+}
+
+/// Keyword (PatientID) or tag (00100020) of a query key
+fn resolve_tag(key: &str) -> Option<Tag> {
+    if key.len() == 8
+        && key.is_ascii()
+        && let (Ok(group), Ok(element)) = (
+            u16::from_str_radix(&key[..4], 16),
+            u16::from_str_radix(&key[4..], 16),
+        )
+    {
+        return Some(Tag(group, element));
+    }
+    StandardDataDictionary
+        .by_name(key)
+        .map(|entry| entry.tag.inner())
+}
+
+/// QIDO-RS matching: date ranges, UID lists, * and ? wildcards, otherwise equality
+fn matches_filter(tag: Tag, filter: &str, value: &str) -> bool {
+    if tag == tags::STUDY_DATE
+        && let Some((from, to)) = filter.split_once('-')
+    {
+        return !value.is_empty()
+            && (from.is_empty() || value >= from)
+            && (to.is_empty() || value <= to);
+    }
+    if filter.contains(',') || filter.contains('\\') {
+        return filter.split([',', '\\']).any(|f| f.trim() == value);
+    }
+    if tag == tags::PATIENT_NAME {
+        return wildcard(&filter.to_uppercase(), &value.to_uppercase());
+    }
+    wildcard(filter, value)
+}
+
+/// `*` and `?` match in one pass: on a mismatch, the last `*` absorbs one more character and
+/// matching resumes after it, so no pattern takes more than pattern x value steps.
+fn wildcard(pattern: &str, value: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let v: Vec<char> = value.chars().collect();
+    let (mut pi, mut vi) = (0, 0);
+    // Pattern position after the last `*`, and the value position it resumes from
+    let mut star: Option<(usize, usize)> = None;
+    while vi < v.len() {
+        match p.get(pi) {
+            Some('*') => {
+                pi += 1;
+                star = Some((pi, vi));
+            }
+            Some(c) if *c == '?' || *c == v[vi] => {
+                pi += 1;
+                vi += 1;
+            }
+            _ => match star {
+                Some((after, from)) => {
+                    pi = after;
+                    vi = from + 1;
+                    star = Some((after, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// DICOM JSON result for one group, with the computed counts of its level
+fn summary(members: &[&Instance], level: QueryLevel) -> Value {
+    let first = &members[0].header;
+    let attributes = match level {
+        QueryLevel::Study => STUDY_ATTRIBUTES,
+        QueryLevel::Series => SERIES_ATTRIBUTES,
+        QueryLevel::Instance => INSTANCE_ATTRIBUTES,
+    };
+    let mut object = Map::new();
+    for tag in attributes {
+        if let Ok(element) = first.element(*tag)
+            && let Some(value) = element_json(element)
+        {
+            object.insert(tag_key(*tag), value);
+        }
+    }
+
+    let distinct = |tag: Tag| {
+        let mut values: Vec<String> = members.iter().map(|i| attribute(&i.header, tag)).collect();
+        values.sort();
+        values.dedup();
+        values
+    };
+    match level {
+        QueryLevel::Study => {
+            object.insert(
+                tag_key(tags::MODALITIES_IN_STUDY),
+                json!({"vr": "CS", "Value": distinct(tags::MODALITY)}),
+            );
+            object.insert(
+                tag_key(tags::NUMBER_OF_STUDY_RELATED_SERIES),
+                json!({"vr": "IS", "Value": [distinct(tags::SERIES_INSTANCE_UID).len()]}),
+            );
+            object.insert(
+                tag_key(tags::NUMBER_OF_STUDY_RELATED_INSTANCES),
+                json!({"vr": "IS", "Value": [members.len()]}),
+            );
+        }
+        QueryLevel::Series => {
+            object.insert(
+                tag_key(tags::NUMBER_OF_SERIES_RELATED_INSTANCES),
+                json!({"vr": "IS", "Value": [members.len()]}),
+            );
+        }
+        QueryLevel::Instance => {}
+    }
+    Value::Object(object)
+}
+
+/// WADO-RS: the instances as Part 10 files.
+fn retrieve(instances: &[&Instance]) -> Result<Reply, Box<dyn std::error::Error>> {
+    if instances.is_empty() {
+        return Ok(Reply::error(404, "no matching instances"));
+    }
+    let parts = instances
+        .iter()
+        .map(|i| fs::read(&i.path))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Reply::multipart("application/dicom", &parts))
+}
+
+/// WADO-RS metadata: every attribute except the pixel data.
+fn metadata(instances: &[&Instance]) -> Reply {
+    if instances.is_empty() {
+        return Reply::error(404, "no matching instances");
+    }
+    Reply::json(&Value::Array(
+        instances.iter().map(|i| object_json(&i.header)).collect(),
+    ))
+}
+
+/// Native pixel data of an instance and its number of frames
+struct Decoded {
+    pixel_data: Vec<u8>,
+    frames: usize,
+}
+
+/// Decoded pixel data of the most recently requested instances, so a viewer paging through the
+/// frames of a compressed multi-frame instance does not have it decoded again for every frame
+#[derive(Default)]
+struct FrameCache {
+    /// Least recently used first
+    entries: Mutex<VecDeque<(PathBuf, Arc<Decoded>)>>,
+}
+
+/// Bytes of decoded pixel data the frame cache keeps (the latest instance is always kept)
+const FRAME_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+impl FrameCache {
+    fn get(&self, path: &Path) -> Result<Arc<Decoded>, Box<dyn std::error::Error>> {
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(position) = entries.iter().position(|(p, _)| p == path)
+                && let Some(entry) = entries.remove(position)
+            {
+                let decoded = entry.1.clone();
+                entries.push_back(entry);
+                return Ok(decoded);
+            }
+        }
+
+        // Decoded without the lock, so other requests are not held up
+        let decoded = Arc::new(decode(path)?);
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.push_back((path.to_path_buf(), decoded.clone()));
+        let mut total: usize = entries.iter().map(|(_, d)| d.pixel_data.len()).sum();
+        while total > FRAME_CACHE_BYTES && entries.len() > 1 {
+            if let Some((_, evicted)) = entries.pop_front() {
+                total -= evicted.pixel_data.len();
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+/// Read an instance's pixel data as uncompressed little endian; compressed files are decoded.
+fn decode(path: &Path) -> Result<Decoded, Box<dyn std::error::Error>> {
+    let mut bytes = fs::read(path)?;
+    let mut obj = OpenFileOptions::new().from_reader(&bytes[..])?;
+    if let Ok(DicomValue::PixelSequence(_)) = obj.element(tags::PIXEL_DATA).map(|e| e.value()) {
+        bytes = transcode::transcode(&bytes, transcode::Target::Explicit)?;
+        obj = OpenFileOptions::new().from_reader(&bytes[..])?;
+    }
+    let pixel_data = obj.element(tags::PIXEL_DATA)?.to_bytes()?.into_owned();
+    let frames = attribute(&obj, tags::NUMBER_OF_FRAMES)
+        .parse::<usize>()
+        .unwrap_or(1)
+        .max(1);
+    Ok(Decoded { pixel_data, frames })
+}
+
+/// WADO-RS frames, uncompressed little endian.
+fn frames(
+    instance: &Instance,
+    cache: &FrameCache,
+    list: &str,
+) -> Result<Reply, Box<dyn std::error::Error>> {
+    let decoded = cache.get(&instance.path)?;
+    let frame_len = decoded.pixel_data.len() / decoded.frames;
+    let mut parts = Vec::new();
+    for number in list.split(',') {
+        match number.trim().parse::<usize>() {
+            Ok(n) if (1..=decoded.frames).contains(&n) => {
+                parts.push(decoded.pixel_data[(n - 1) * frame_len..n * frame_len].to_vec());
+            }
+            _ => return Ok(Reply::error(404, &format!("no frame {}", number))),
+        }
+    }
+    Ok(Reply::multipart(
+        "application/octet-stream; transfer-syntax=1.2.840.10008.1.2.1",
+        &parts,
+    ))
+}
+
+fn tag_key(tag: Tag) -> String {
+    format!("{:04X}{:04X}", tag.group(), tag.element())
+}
+
+/// DICOM JSON model (PS3.18 F.2) of a data set; pixel data is left out.
+fn object_json(obj: &InMemDicomObject) -> Value {
+    let mut object = Map::new();
+    for element in obj.iter() {
+        if let Some(value) = element_json(element) {
+            object.insert(tag_key(element.tag()), value);
+        }
+    }
+    Value::Object(object)
+}
+
+fn element_json(element: &InMemElement) -> Option<Value> {
+    let vr = element.vr();
+    let mut item = Map::new();
+    item.insert("vr".to_string(), json!(vr.to_string()));
+    match element.value() {
+        DicomValue::PixelSequence(_) => return None,
+        DicomValue::Sequence(sequence) => {
+            let items: Vec<Value> = sequence.items().iter().map(object_json).collect();
+            item.insert("Value".to_string(), Value::Array(items));
+        }
+        DicomValue::Primitive(PrimitiveValue::Empty) => {}
+        DicomValue::Primitive(value) => {
+            if element.tag() == tags::PIXEL_DATA {
+                return None;
+            }
+            let values: Vec<Value> = match vr {
+                VR::OB | VR::OD | VR::OF | VR::OL | VR::OV | VR::OW | VR::UN => {
+                    let bytes = value.to_bytes();
+                    item.insert(
+                        "InlineBinary".to_string(),
+                        json!(base64::engine::general_purpose::STANDARD.encode(bytes)),
+                    );
+                    return Some(Value::Object(item));
+                }
+                VR::PN => value
+                    .to_multi_str()
+                    .iter()
+                    .map(|s| json!({"Alphabetic": s.trim()}))
+                    .collect(),
+                VR::AT => match value {
+                    PrimitiveValue::Tags(tags) => tags.iter().map(|t| json!(tag_key(*t))).collect(),
+                    _ => Vec::new(),
+                },
+                VR::US | VR::SS | VR::UL | VR::SL | VR::UV | VR::SV | VR::IS => value
+                    .to_multi_int::<i64>()
+                    .map(|v| v.into_iter().map(|n| json!(n)).collect())
+                    .unwrap_or_default(),
+                VR::FL | VR::FD | VR::DS => value
+                    .to_multi_float64()
+                    .map(|v| v.into_iter().map(|n| json!(n)).collect())
+                    .unwrap_or_default(),
+                _ => value
+                    .to_multi_str()
+                    .iter()
+                    .map(|s| json!(s.trim_end_matches(['\0', ' '])))
+                    .collect(),
+            };
+            if !values.is_empty() {
+                item.insert("Value".to_string(), Value::Array(values));
+            }
+        }
+    }
+    Some(Value::Object(item))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::http_exchange;
+    use dicom::core::DataElement;
+    use dicom::object::FileMetaTableBuilder;
+    use dicom::transfer_syntax::entries;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard("DOE^*", "DOE^JOHN"));
+        assert!(wildcard("D?E*N", "DOE^JOHN"));
+        assert!(wildcard("*", ""));
+        assert!(wildcard("**A", "BA"));
+        assert!(!wildcard("DOE^?", "DOE^"));
+        assert!(!wildcard("*X*", "DOE^JOHN"));
+        // Exponential for a backtracking matcher
+        let value = "a".repeat(64);
+        assert!(!wildcard(&format!("{}b", "a*".repeat(20)), &value));
+        assert!(wildcard(&"a*".repeat(20), &value));
+    }
+
+    #[test]
+    fn filters() {
+        assert!(matches_filter(tags::STUDY_DATE, "20240101-", "20240315"));
+        assert!(!matches_filter(tags::STUDY_DATE, "-20231231", "20240315"));
+        assert!(!matches_filter(tags::STUDY_DATE, "20240101-", ""));
+        assert!(matches_filter(tags::SERIES_INSTANCE_UID, "1.2,1.3", "1.3"));
+        assert!(matches_filter(tags::PATIENT_NAME, "doe*", "DOE^JOHN"));
+        assert!(!matches_filter(tags::PATIENT_ID, "p1", "P1"));
+    }
+
+    #[test]
+    fn query_keys() {
+        assert_eq!(resolve_tag("PatientID"), Some(tags::PATIENT_ID));
+        assert_eq!(resolve_tag("00100020"), Some(tags::PATIENT_ID));
+        // Eight bytes, but not on a character boundary at four
+        assert_eq!(resolve_tag("a\u{e4}\u{e4}a"), None);
+        assert_eq!(resolve_tag("0010002G"), None);
+    }
+
+    #[test]
+    fn cors_is_opt_in() {
+        let cache = FrameCache::default();
+        let get = b"GET /studies HTTP/1.0\r\nConnection: close\r\n\r\n";
+        let preflight = b"OPTIONS /studies HTTP/1.0\r\nConnection: close\r\n\r\n";
+
+        let response = http_exchange(get, |r| answer(&[], &cache, None, r));
+        assert!(response.starts_with("HTTP/1.0 200"), "{}", response);
+        assert!(!response.contains("Access-Control"));
+        let response = http_exchange(preflight, |r| answer(&[], &cache, None, r));
+        assert!(response.starts_with("HTTP/1.0 405"), "{}", response);
+
+        let origin = Some("http://localhost:3000");
+        let response = http_exchange(get, |r| answer(&[], &cache, origin, r));
+        assert!(response.contains("Access-Control-Allow-Origin: http://localhost:3000"));
+        let response = http_exchange(preflight, |r| answer(&[], &cache, origin, r));
+        assert!(response.starts_with("HTTP/1.0 204"), "{}", response);
+    }
+
+    #[test]
+    fn multipart_round_trip() {
+        // Parts may contain CRLF and dashes; only CRLF followed by the delimiter ends a part
+        let parts = vec![
+            b"first\r\n--not-a-boundary".to_vec(),
+            Vec::new(),
+            vec![0u8; 300],
+        ];
+        let reply = Reply::multipart("application/dicom", &parts);
+        let parsed = multipart_parts(&reply.content_type, &reply.body).unwrap();
+        assert_eq!(parsed, parts.iter().map(Vec::as_slice).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn multipart_from_a_server() {
+        let body = b"preamble\r\n--b1\r\nContent-Type: application/dicom\r\nContent-Length: 3\r\n\r\nabc\r\n--b1\r\n\r\nde\r\n--b1--\r\n";
+        let parts = multipart_parts(
+            "Multipart/Related; type=\"application/dicom\"; boundary=\"b1\"",
+            body,
+        )
+        .unwrap();
+        assert_eq!(parts, [&b"abc"[..], &b"de"[..]]);
+
+        assert_eq!(
+            multipart_parts("application/dicom", b"DICM").unwrap(),
+            [&b"DICM"[..]]
+        );
+        assert!(multipart_parts("multipart/related", body).is_err());
+        assert!(multipart_parts("multipart/related; boundary=b1", b"--b1\r\n\r\nabc").is_err());
+        assert!(multipart_parts("multipart/related; boundary=b2", body).is_err());
+    }
+
+    #[test]
+    fn frames_are_decoded_once() {
+        let dir = std::env::temp_dir().join(format!("dicom_scanner_frames_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frames.dcm");
+        let pixels: Vec<u8> = (0..2 * 4 * 4).collect();
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put_str(tags::SOP_CLASS_UID, VR::UI, "1.2.840.10008.5.1.4.1.1.7");
+        obj.put_str(tags::SOP_INSTANCE_UID, VR::UI, "1.2.3.4");
+        obj.put_str(tags::NUMBER_OF_FRAMES, VR::IS, "2");
+        for (tag, value) in [
+            (tags::SAMPLES_PER_PIXEL, 1_u16),
+            (tags::ROWS, 4),
+            (tags::COLUMNS, 4),
+            (tags::BITS_ALLOCATED, 8),
+            (tags::BITS_STORED, 8),
+            (tags::HIGH_BIT, 7),
+            (tags::PIXEL_REPRESENTATION, 0),
+        ] {
+            obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+        }
+        obj.put(DataElement::new(
+            tags::PIXEL_DATA,
+            VR::OB,
+            PrimitiveValue::from(pixels.clone()),
+        ));
+        obj.with_meta(
+            FileMetaTableBuilder::new()
+                .transfer_syntax(entries::EXPLICIT_VR_LITTLE_ENDIAN.uid())
+                .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+                .media_storage_sop_instance_uid("1.2.3.4"),
+        )
+        .unwrap()
+        .write_to_file(&path)
+        .unwrap();
+        let instance = Instance {
+            header: OpenFileOptions::new()
+                .read_until(tags::PIXEL_DATA)
+                .open_file(&path)
+                .unwrap(),
+            path,
+        };
+
+        let cache = FrameCache::default();
+        let reply = frames(&instance, &cache, "2,1").unwrap();
+        let parts = multipart_parts(&reply.content_type, &reply.body).unwrap();
+        assert_eq!(parts, [&pixels[16..], &pixels[..16]]);
+        assert_eq!(frames(&instance, &cache, "3").unwrap().status, 404);
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+
+        // Served from the cache once the file is gone
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(frames(&instance, &cache, "1").unwrap().status, 200);
+    }
+}
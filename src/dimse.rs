@@ -0,0 +1,752 @@
+// --- DIMSE messaging ---
+//
+// Command sets and message exchange over an established association (PS3.7). Commands are
+// always Implicit VR Little Endian; data sets travel as raw bytes in the transfer syntax of
+// their presentation context and are only parsed by the caller when needed.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::tags;
+use dicom::encoding::TransferSyntaxIndex;
+use dicom::object::mem::InMemDicomObject;
+use dicom::transfer_syntax::TransferSyntaxRegistry;
+use dicom::transfer_syntax::entries::IMPLICIT_VR_LITTLE_ENDIAN;
+use dicom::ul::pdu::{
+    AssociationRQ, MAXIMUM_PDU_SIZE, PDU_HEADER_SIZE, PDataValue, PDataValueType,
+    PresentationContextProposed, PresentationContextResultReason, UserVariableItem, read_pdu,
+    write_pdu,
+};
+use dicom::ul::{IMPLEMENTATION_CLASS_UID, IMPLEMENTATION_VERSION_NAME, Pdu, ServerAssociation};
+
+/// Command Field values (PS3.7 E.1)
+pub const C_STORE_RQ: u16 = 0x0001;
+pub const C_STORE_RSP: u16 = 0x8001;
+pub const C_GET_RQ: u16 = 0x0010;
+pub const C_GET_RSP: u16 = 0x8010;
+pub const C_FIND_RQ: u16 = 0x0020;
+pub const C_FIND_RSP: u16 = 0x8020;
+pub const C_MOVE_RQ: u16 = 0x0021;
+pub const C_MOVE_RSP: u16 = 0x8021;
+pub const C_ECHO_RQ: u16 = 0x0030;
+pub const C_ECHO_RSP: u16 = 0x8030;
+
+/// Status values (PS3.7 C)
+pub const STATUS_SUCCESS: u16 = 0x0000;
+pub const STATUS_OUT_OF_RESOURCES: u16 = 0xA700;
+pub const STATUS_UNRECOGNIZED_OPERATION: u16 = 0x0211;
+
+/// Pending (0xFF00) and pending with unsupported optional keys (0xFF01)
+pub fn is_pending(status: u16) -> bool {
+    status == 0xFF00 || status == 0xFF01
+}
+
+/// Command Data Set Type value meaning "no data set follows"
+const NO_DATA_SET: u16 = 0x0101;
+
+pub const VERIFICATION_SOP_CLASS: &str = "1.2.840.10008.1.1";
+
+/// DICOM Application Context Name (PS3.7 A.2.1)
+const APPLICATION_CONTEXT_NAME: &str = "1.2.840.10008.3.1.1.1";
+
+/// How long a requestor waits to connect, or for the peer to send or accept a PDU, before
+/// giving up on an association. Generous, as a C-MOVE may stay silent while the SCP retrieves.
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The two ends of an association, as far as message exchange is concerned
+pub trait Link {
+    fn send_pdu(&mut self, pdu: &Pdu) -> Result<(), Box<dyn std::error::Error>>;
+    fn receive_pdu(&mut self) -> Result<Pdu, Box<dyn std::error::Error>>;
+    /// Write a data set, split into as many P-DATA PDUs as the peer's maximum length requires
+    fn send_data(&mut self, context_id: u8, data: &[u8]) -> Result<(), Box<dyn std::error::Error>>;
+    /// Transfer syntax accepted for a presentation context
+    fn transfer_syntax(&self, context_id: u8) -> Option<String>;
+}
+
+impl Link for ServerAssociation<TcpStream> {
+    fn send_pdu(&mut self, pdu: &Pdu) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.send(pdu)?)
+    }
+
+    fn receive_pdu(&mut self) -> Result<Pdu, Box<dyn std::error::Error>> {
+        Ok(self.receive()?)
+    }
+
+    fn send_data(&mut self, context_id: u8, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = self.send_pdata(context_id);
+        writer.write_all(data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn transfer_syntax(&self, context_id: u8) -> Option<String> {
+        accepted_transfer_syntax(self.presentation_contexts(), context_id)
+    }
+}
+
+/// What the requesting end proposes when it opens an association
+pub struct Proposal<'a> {
+    pub calling_ae_title: &'a str,
+    pub called_ae_title: &'a str,
+    /// One presentation context per abstract syntax, with ids 1, 3, 5, ... in this order
+    pub abstract_syntaxes: Vec<&'a str>,
+    pub transfer_syntaxes: &'a [&'a str],
+    /// Abstract syntaxes for which we offer the SCP role (C-STORE sub-operations of C-GET)
+    pub scp_roles: &'a [&'a str],
+}
+
+/// Requesting end of an association. The dicom-ul client cannot send SCP/SCU Role Selection
+/// items, so the association request is written here and PDUs are framed directly on the stream.
+pub struct Requestor {
+    stream: TcpStream,
+    /// Largest PDU the acceptor takes (0: no limit)
+    peer_max_pdu: u32,
+    /// Accepted presentation contexts: id, abstract syntax, transfer syntax
+    contexts: Vec<(u8, String, String)>,
+}
+
+/// SCP/SCU Role Selection item type (PS3.7 D.3.3.4)
+const ROLE_SELECTION_ITEM: u8 = 0x54;
+
+/// Role selection for one SOP class: SCU role not offered, SCP role offered
+fn role_selection(sop_class: &str) -> UserVariableItem {
+    let mut data = Vec::with_capacity(sop_class.len() + 4);
+    data.extend_from_slice(&(sop_class.len() as u16).to_be_bytes());
+    data.extend_from_slice(sop_class.as_bytes());
+    data.extend_from_slice(&[0, 1]);
+    UserVariableItem::Unknown(ROLE_SELECTION_ITEM, data)
+}
+
+/// A-ASSOCIATE-RQ for a proposal
+fn association_rq(proposal: &Proposal) -> Result<Pdu, Box<dyn std::error::Error>> {
+    let presentation_contexts = proposal
+        .abstract_syntaxes
+        .iter()
+        .enumerate()
+        .map(|(i, abstract_syntax)| {
+            Ok(PresentationContextProposed {
+                id: u8::try_from(2 * i + 1).map_err(|_| "too many presentation contexts")?,
+                abstract_syntax: abstract_syntax.to_string(),
+                transfer_syntaxes: proposal
+                    .transfer_syntaxes
+                    .iter()
+                    .map(|ts| ts.to_string())
+                    .collect(),
+            })
+        })
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+    let mut user_variables = vec![
+        UserVariableItem::MaxLength(MAXIMUM_PDU_SIZE),
+        UserVariableItem::ImplementationClassUID(IMPLEMENTATION_CLASS_UID.to_string()),
+        UserVariableItem::ImplementationVersionName(IMPLEMENTATION_VERSION_NAME.to_string()),
+    ];
+    user_variables.extend(proposal.scp_roles.iter().map(|uid| role_selection(uid)));
+
+    Ok(Pdu::AssociationRQ(AssociationRQ {
+        protocol_version: 1,
+        calling_ae_title: proposal.calling_ae_title.to_string(),
+        called_ae_title: proposal.called_ae_title.to_string(),
+        application_context_name: APPLICATION_CONTEXT_NAME.to_string(),
+        presentation_contexts,
+        user_variables,
+    }))
+}
+
+/// Read one whole PDU: the 6-byte header gives the length of the rest.
+fn read_pdu_from(stream: &mut TcpStream) -> Result<Pdu, Box<dyn std::error::Error>> {
+    let mut header = [0u8; PDU_HEADER_SIZE as usize];
+    stream.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+    if length > MAXIMUM_PDU_SIZE {
+        return Err(format!("PDU of {} bytes exceeds the maximum length", length).into());
+    }
+    let mut pdu = header.to_vec();
+    pdu.resize(header.len() + length as usize, 0);
+    stream.read_exact(&mut pdu[header.len()..])?;
+    read_pdu(&pdu[..], MAXIMUM_PDU_SIZE, false)?.ok_or_else(|| "truncated PDU".into())
+}
+
+fn write_pdu_to(stream: &mut TcpStream, pdu: &Pdu) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    write_pdu(&mut bytes, pdu)?;
+    stream.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Connect to the first address of `peer` that answers, with read and write timeouts set.
+fn connect(peer: &str) -> Result<TcpStream, Box<dyn std::error::Error>> {
+    let mut last_error = None;
+    for addr in peer.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, NETWORK_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+                stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => format!("{}: {}", peer, e).into(),
+        None => format!("{}: no address", peer).into(),
+    })
+}
+
+impl Requestor {
+    /// Connect to `peer` (host:port) and negotiate the proposed presentation contexts.
+    pub fn establish(peer: &str, proposal: &Proposal) -> Result<Self, Box<dyn std::error::Error>> {
+        let request = association_rq(proposal)?;
+        let mut stream = connect(peer)?;
+        write_pdu_to(&mut stream, &request)?;
+
+        match read_pdu_from(&mut stream)? {
+            Pdu::AssociationAC(ac) => {
+                let contexts = ac
+                    .presentation_contexts
+                    .iter()
+                    .filter(|pc| pc.reason == PresentationContextResultReason::Acceptance)
+                    .filter_map(|pc| {
+                        let abstract_syntax = proposal
+                            .abstract_syntaxes
+                            .get(usize::from(pc.id) / 2)
+                            .filter(|_| pc.id % 2 == 1)?;
+                        Some((
+                            pc.id,
+                            abstract_syntax.to_string(),
+                            pc.transfer_syntax.trim_end_matches('\0').to_string(),
+                        ))
+                    })
+                    .collect();
+                let peer_max_pdu = ac
+                    .user_variables
+                    .iter()
+                    .find_map(|item| match item {
+                        UserVariableItem::MaxLength(len) => Some(*len),
+                        _ => None,
+                    })
+                    .unwrap_or(0);
+                Ok(Requestor {
+                    stream,
+                    peer_max_pdu,
+                    contexts,
+                })
+            }
+            Pdu::AssociationRJ(rj) => Err(format!("association rejected: {:?}", rj.source).into()),
+            other => Err(format!("unexpected {}", other.short_description()).into()),
+        }
+    }
+
+    /// Presentation context accepted for an abstract syntax: id and transfer syntax
+    pub fn context(
+        &self,
+        abstract_syntax: &str,
+    ) -> Result<(u8, String), Box<dyn std::error::Error>> {
+        self.contexts
+            .iter()
+            .find(|(_, a, _)| a == abstract_syntax)
+            .map(|(id, _, ts)| (*id, ts.clone()))
+            .ok_or_else(|| format!("the peer did not accept {}", abstract_syntax).into())
+    }
+
+    /// Release the association and close the connection.
+    pub fn release(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.send_pdu(&Pdu::ReleaseRQ)?;
+        loop {
+            match self.receive_pdu()? {
+                Pdu::ReleaseRP => break,
+                // Late data on the association is dropped once we asked for release
+                Pdu::PData { .. } => continue,
+                other => {
+                    return Err(format!("unexpected {}", other.short_description()).into());
+                }
+            }
+        }
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+        Ok(())
+    }
+}
+
+impl Link for Requestor {
+    fn send_pdu(&mut self, pdu: &Pdu) -> Result<(), Box<dyn std::error::Error>> {
+        write_pdu_to(&mut self.stream, pdu)
+    }
+
+    fn receive_pdu(&mut self) -> Result<Pdu, Box<dyn std::error::Error>> {
+        read_pdu_from(&mut self.stream)
+    }
+
+    fn send_data(&mut self, context_id: u8, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        // PDV item header: 4-byte length, context id and message control header
+        let max_pdu = match self.peer_max_pdu {
+            0 => MAXIMUM_PDU_SIZE,
+            len => len,
+        };
+        let chunk = (max_pdu as usize).saturating_sub(6).max(1);
+        let mut fragments = data.chunks(chunk).peekable();
+        if fragments.peek().is_none() {
+            return self.send_pdu(&data_pdu(context_id, Vec::new(), true));
+        }
+        while let Some(fragment) = fragments.next() {
+            let is_last = fragments.peek().is_none();
+            self.send_pdu(&data_pdu(context_id, fragment.to_vec(), is_last))?;
+        }
+        Ok(())
+    }
+
+    fn transfer_syntax(&self, context_id: u8) -> Option<String> {
+        self.contexts
+            .iter()
+            .find(|(id, _, _)| *id == context_id)
+            .map(|(_, _, ts)| ts.clone())
+    }
+}
+
+fn data_pdu(context_id: u8, data: Vec<u8>, is_last: bool) -> Pdu {
+    Pdu::PData {
+        data: vec![PDataValue {
+            presentation_context_id: context_id,
+            value_type: PDataValueType::Data,
+            is_last,
+            data,
+        }],
+    }
+}
+
+fn accepted_transfer_syntax(
+    contexts: &[dicom::ul::pdu::PresentationContextResult],
+    context_id: u8,
+) -> Option<String> {
+    contexts
+        .iter()
+        .find(|pc| pc.id == context_id)
+        .map(|pc| pc.transfer_syntax.trim_end_matches('\0').to_string())
+}
+
+/// A DIMSE message: the command set and, when present, the raw data set
+pub struct Message {
+    pub context_id: u8,
+    pub command: InMemDicomObject,
+    pub data: Option<Vec<u8>>,
+}
+
+impl Message {
+    pub fn field(&self) -> u16 {
+        self.uint(tags::COMMAND_FIELD).unwrap_or(0)
+    }
+
+    pub fn uint(&self, tag: Tag) -> Option<u16> {
+        self.command.element(tag).ok()?.to_int::<u16>().ok()
+    }
+
+    /// Text value of a command element, without padding ("" when missing)
+    pub fn string(&self, tag: Tag) -> String {
+        self.command
+            .element(tag)
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// What arrived on the association
+pub enum Incoming {
+    Message(Message),
+    /// The peer requested release; it has been answered
+    Released,
+    Aborted,
+}
+
+/// Receive the next complete message, answering a release request.
+pub fn receive(link: &mut impl Link) -> Result<Incoming, Box<dyn std::error::Error>> {
+    let mut command = Vec::new();
+    let mut data = Vec::new();
+    let mut context_id;
+    let mut command_done = false;
+    let mut expects_data = false;
+
+    loop {
+        match link.receive_pdu()? {
+            Pdu::PData { data: values } => {
+                for value in values {
+                    context_id = value.presentation_context_id;
+                    match value.value_type {
+                        PDataValueType::Command => {
+                            command.extend(value.data);
+                            if value.is_last {
+                                command_done = true;
+                                let parsed = parse_command(&command)?;
+                                expects_data = parsed
+                                    .element(tags::COMMAND_DATA_SET_TYPE)
+                                    .ok()
+                                    .and_then(|e| e.to_int::<u16>().ok())
+                                    != Some(NO_DATA_SET);
+                                if !expects_data {
+                                    return Ok(Incoming::Message(Message {
+                                        context_id,
+                                        command: parsed,
+                                        data: None,
+                                    }));
+                                }
+                            }
+                        }
+                        PDataValueType::Data => {
+                            data.extend(value.data);
+                            if value.is_last && command_done && expects_data {
+                                return Ok(Incoming::Message(Message {
+                                    context_id,
+                                    command: parse_command(&command)?,
+                                    data: Some(data),
+                                }));
+                            }
+                        }
+                    }
+                }
+            }
+            Pdu::ReleaseRQ => {
+                link.send_pdu(&Pdu::ReleaseRP)?;
+                return Ok(Incoming::Released);
+            }
+            Pdu::AbortRQ { .. } => return Ok(Incoming::Aborted),
+            other => {
+                return Err(format!("unexpected {}", other.short_description()).into());
+            }
+        }
+    }
+}
+
+fn parse_command(bytes: &[u8]) -> Result<InMemDicomObject, Box<dyn std::error::Error>> {
+    Ok(InMemDicomObject::read_dataset_with_ts(
+        bytes,
+        &IMPLICIT_VR_LITTLE_ENDIAN.erased(),
+    )?)
+}
+
+/// Encode a data set (query identifier) in the transfer syntax of its presentation context.
+pub fn encode_data(
+    obj: &InMemDicomObject,
+    transfer_syntax: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let ts = TransferSyntaxRegistry
+        .get(transfer_syntax)
+        .ok_or_else(|| format!("unknown transfer syntax {}", transfer_syntax))?;
+    let mut out = Vec::new();
+    obj.write_dataset_with_ts(&mut out, ts)?;
+    Ok(out)
+}
+
+/// Parse a received data set, e.g. a C-FIND match.
+pub fn decode_data(
+    bytes: &[u8],
+    transfer_syntax: &str,
+) -> Result<InMemDicomObject, Box<dyn std::error::Error>> {
+    let ts = TransferSyntaxRegistry
+        .get(transfer_syntax)
+        .ok_or_else(|| format!("unknown transfer syntax {}", transfer_syntax))?;
+    Ok(InMemDicomObject::read_dataset_with_ts(bytes, ts)?)
+}
+
+/// Send a command (built by [`command`]) and its optional data set.
+pub fn send(
+    link: &mut impl Link,
+    context_id: u8,
+    command: &[u8],
+    data: Option<&[u8]>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    link.send_pdu(&Pdu::PData {
+        data: vec![PDataValue {
+            presentation_context_id: context_id,
+            value_type: PDataValueType::Command,
+            is_last: true,
+            data: command.to_vec(),
+        }],
+    })?;
+    if let Some(data) = data {
+        link.send_data(context_id, data)?;
+    }
+    Ok(())
+}
+
+/// Encode a command set: the given elements plus Command Field, Command Data Set Type and the
+/// group length.
+pub fn command(
+    field: u16,
+    elements: Vec<DataElement<InMemDicomObject>>,
+    has_data: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut obj = InMemDicomObject::from_element_iter(elements);
+    obj.put(DataElement::new(
+        tags::COMMAND_FIELD,
+        VR::US,
+        PrimitiveValue::from(field),
+    ));
+    obj.put(DataElement::new(
+        tags::COMMAND_DATA_SET_TYPE,
+        VR::US,
+        PrimitiveValue::from(if has_data { 0x0000 } else { NO_DATA_SET }),
+    ));
+
+    let mut body = Vec::new();
+    obj.write_dataset_with_ts(&mut body, &IMPLICIT_VR_LITTLE_ENDIAN.erased())?;
+
+    // (0000,0000) UL in Implicit VR: tag, 4-byte length, value
+    let mut out = Vec::with_capacity(body.len() + 12);
+    out.extend_from_slice(&[0, 0, 0, 0, 4, 0, 0, 0]);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend(body);
+    Ok(out)
+}
+
+/// Shorthand for command elements
+pub fn uid(tag: Tag, value: &str) -> DataElement<InMemDicomObject> {
+    DataElement::new(tag, VR::UI, value)
+}
+
+pub fn ushort(tag: Tag, value: u16) -> DataElement<InMemDicomObject> {
+    DataElement::new(tag, VR::US, PrimitiveValue::from(value))
+}
+
+/// Response to a request: same SOP class (and instance, when given), with a status.
+pub fn response(
+    request: &Message,
+    field: u16,
+    status: u16,
+    has_data: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut elements = vec![
+        uid(
+            tags::AFFECTED_SOP_CLASS_UID,
+            &request.string(tags::AFFECTED_SOP_CLASS_UID),
+        ),
+        ushort(
+            tags::MESSAGE_ID_BEING_RESPONDED_TO,
+            request.uint(tags::MESSAGE_ID).unwrap_or(0),
+        ),
+        ushort(tags::STATUS, status),
+    ];
+    let instance = request.string(tags::AFFECTED_SOP_INSTANCE_UID);
+    if !instance.is_empty() {
+        elements.push(uid(tags::AFFECTED_SOP_INSTANCE_UID, &instance));
+    }
+    command(field, elements, has_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::net::TcpListener;
+
+    /// A peer that replays scripted PDUs and records what was sent to it
+    #[derive(Default)]
+    struct Script {
+        incoming: VecDeque<Pdu>,
+        sent: Vec<Pdu>,
+    }
+
+    impl Link for Script {
+        fn send_pdu(&mut self, pdu: &Pdu) -> Result<(), Box<dyn std::error::Error>> {
+            self.sent.push(pdu.clone());
+            Ok(())
+        }
+
+        fn receive_pdu(&mut self) -> Result<Pdu, Box<dyn std::error::Error>> {
+            self.incoming
+                .pop_front()
+                .ok_or_else(|| "end of script".into())
+        }
+
+        fn send_data(
+            &mut self,
+            context_id: u8,
+            data: &[u8],
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            self.send_pdu(&data_pdu(context_id, data.to_vec(), true))
+        }
+
+        fn transfer_syntax(&self, _context_id: u8) -> Option<String> {
+            Some(IMPLICIT_VR_LITTLE_ENDIAN.uid().to_string())
+        }
+    }
+
+    fn pdv(value_type: PDataValueType, data: &[u8], is_last: bool) -> PDataValue {
+        PDataValue {
+            presentation_context_id: 3,
+            value_type,
+            is_last,
+            data: data.to_vec(),
+        }
+    }
+
+    fn store_rq() -> Vec<u8> {
+        command(
+            C_STORE_RQ,
+            vec![
+                uid(tags::AFFECTED_SOP_CLASS_UID, "1.2.840.10008.5.1.4.1.1.2"),
+                ushort(tags::MESSAGE_ID, 42),
+                uid(tags::AFFECTED_SOP_INSTANCE_UID, "1.2.3.4"),
+            ],
+            true,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn command_set_has_group_length_and_fields() {
+        let bytes = command(C_ECHO_RQ, vec![ushort(tags::MESSAGE_ID, 7)], false).unwrap();
+        let obj = parse_command(&bytes).unwrap();
+        let group_length = obj
+            .element(tags::COMMAND_GROUP_LENGTH)
+            .unwrap()
+            .to_int::<u32>()
+            .unwrap();
+        assert_eq!(group_length as usize, bytes.len() - 12);
+
+        let message = Message {
+            context_id: 1,
+            command: obj,
+            data: None,
+        };
+        assert_eq!(message.field(), C_ECHO_RQ);
+        assert_eq!(message.uint(tags::MESSAGE_ID), Some(7));
+        assert_eq!(message.uint(tags::COMMAND_DATA_SET_TYPE), Some(NO_DATA_SET));
+    }
+
+    #[test]
+    fn response_answers_the_request() {
+        let request = Message {
+            context_id: 3,
+            command: parse_command(&store_rq()).unwrap(),
+            data: None,
+        };
+        let response = Message {
+            context_id: 3,
+            command: parse_command(&response(&request, C_STORE_RSP, 0xA700, false).unwrap())
+                .unwrap(),
+            data: None,
+        };
+        assert_eq!(response.field(), C_STORE_RSP);
+        assert_eq!(response.uint(tags::MESSAGE_ID_BEING_RESPONDED_TO), Some(42));
+        assert_eq!(response.uint(tags::STATUS), Some(0xA700));
+        assert_eq!(response.string(tags::AFFECTED_SOP_INSTANCE_UID), "1.2.3.4");
+        assert_eq!(
+            response.string(tags::AFFECTED_SOP_CLASS_UID),
+            "1.2.840.10008.5.1.4.1.1.2"
+        );
+    }
+
+    #[test]
+    fn receive_joins_fragments() {
+        let command = store_rq();
+        let (head, tail) = command.split_at(20);
+        let mut link = Script::default();
+        link.incoming.extend([
+            Pdu::PData {
+                data: vec![
+                    pdv(PDataValueType::Command, head, false),
+                    pdv(PDataValueType::Command, tail, true),
+                ],
+            },
+            Pdu::PData {
+                data: vec![pdv(PDataValueType::Data, b"abcd", false)],
+            },
+            Pdu::PData {
+                data: vec![pdv(PDataValueType::Data, b"ef", true)],
+            },
+        ]);
+
+        let Incoming::Message(message) = receive(&mut link).unwrap() else {
+            panic!("no message");
+        };
+        assert_eq!(message.context_id, 3);
+        assert_eq!(message.field(), C_STORE_RQ);
+        assert_eq!(message.data.as_deref(), Some(&b"abcdef"[..]));
+        assert!(link.incoming.is_empty());
+    }
+
+    #[test]
+    fn receive_answers_release() {
+        let mut link = Script::default();
+        link.incoming.push_back(Pdu::ReleaseRQ);
+        assert!(matches!(receive(&mut link).unwrap(), Incoming::Released));
+        assert_eq!(link.sent, [Pdu::ReleaseRP]);
+    }
+
+    #[test]
+    fn send_data_splits_by_peer_maximum() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        let mut requestor = Requestor {
+            stream,
+            peer_max_pdu: 16,
+            contexts: Vec::new(),
+        };
+
+        let data: Vec<u8> = (0..25).collect();
+        requestor.send_data(5, &data).unwrap();
+
+        let mut received = Vec::new();
+        loop {
+            let Pdu::PData { data: values } = read_pdu_from(&mut peer).unwrap() else {
+                panic!("not P-DATA");
+            };
+            let [value] = &values[..] else {
+                panic!("one PDV per PDU expected");
+            };
+            assert_eq!(value.presentation_context_id, 5);
+            assert!(value.data.len() <= 10);
+            received.extend_from_slice(&value.data);
+            if value.is_last {
+                break;
+            }
+        }
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn connections_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = connect(&listener.local_addr().unwrap().to_string()).unwrap();
+        assert_eq!(stream.read_timeout().unwrap(), Some(NETWORK_TIMEOUT));
+        assert_eq!(stream.write_timeout().unwrap(), Some(NETWORK_TIMEOUT));
+        assert!(connect("127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn association_request_carries_role_selection() {
+        let storage = ["1.2.840.10008.5.1.4.1.1.2", "1.2.840.10008.5.1.4.1.1.4"];
+        let proposal = Proposal {
+            calling_ae_title: "SCANNER",
+            called_ae_title: "PACS",
+            abstract_syntaxes: vec!["1.2.840.10008.5.1.4.1.2.2.3", storage[0], storage[1]],
+            transfer_syntaxes: &[IMPLICIT_VR_LITTLE_ENDIAN.uid()],
+            scp_roles: &storage,
+        };
+        let mut bytes = Vec::new();
+        write_pdu(&mut bytes, &association_rq(&proposal).unwrap()).unwrap();
+        let Some(Pdu::AssociationRQ(rq)) = read_pdu(&bytes[..], MAXIMUM_PDU_SIZE, true).unwrap()
+        else {
+            panic!("not an association request");
+        };
+
+        let ids: Vec<u8> = rq.presentation_contexts.iter().map(|pc| pc.id).collect();
+        assert_eq!(ids, [1, 3, 5]);
+        let roles: Vec<&Vec<u8>> = rq
+            .user_variables
+            .iter()
+            .filter_map(|item| match item {
+                UserVariableItem::Unknown(ROLE_SELECTION_ITEM, data) => Some(data),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(roles.len(), 2);
+        let uid = storage[0].as_bytes();
+        assert_eq!(roles[0][..2], (uid.len() as u16).to_be_bytes());
+        assert_eq!(&roles[0][2..2 + uid.len()], uid);
+        assert_eq!(roles[0][2 + uid.len()..], [0, 1]);
+    }
+}
@@ -0,0 +1,373 @@
+// --- Per-entry errors ---
+//
+// Why an archive entry (or a file of a folder) was left out of a scan. The readers collect one
+// EntryError per skipped entry instead of silently moving on; the errors are counted in the
+// summary output and listed in skipped_files.csv.
+
+use std::fmt;
+use std::fs::File;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use dicom::object::ReadError;
+use dicom::object::meta::Error as MetaError;
+use dicom::parser::stateful::decode::Error as DecodeError;
+use tracing::{debug, info, warn};
+use zip::result::ZipError;
+
+use crate::escape_csv_field;
+
+/// Header of skipped_files.csv
+pub const SKIPPED_FILES_HEADER: &str = "Entry,Kind,Detail";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    /// No DICM magic code, or too short to hold one
+    NotDicom,
+    /// The data ends before the header is complete
+    Truncated,
+    /// Transfer syntax that cannot be decoded
+    TransferSyntax,
+    /// Decompressed data does not match the CRC-32 of the ZIP entry
+    CrcMismatch,
+    /// Password-protected ZIP entry
+    Encrypted,
+    /// DICOM file whose data set cannot be parsed
+    Malformed,
+    /// Entry or file that cannot be read at all (I/O error, unsupported compression)
+    Unreadable,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::NotDicom => "not_dicom",
+            Kind::Truncated => "truncated",
+            Kind::TransferSyntax => "transfer_syntax",
+            Kind::CrcMismatch => "crc_mismatch",
+            Kind::Encrypted => "encrypted",
+            Kind::Malformed => "malformed",
+            Kind::Unreadable => "unreadable",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Kind::NotDicom => "not DICOM",
+            Kind::Truncated => "truncated",
+            Kind::TransferSyntax => "unsupported transfer syntax",
+            Kind::CrcMismatch => "CRC mismatch",
+            Kind::Encrypted => "encrypted",
+            Kind::Malformed => "malformed",
+            Kind::Unreadable => "unreadable",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "not_dicom" => Ok(Kind::NotDicom),
+            "truncated" => Ok(Kind::Truncated),
+            "transfer_syntax" => Ok(Kind::TransferSyntax),
+            "crc_mismatch" => Ok(Kind::CrcMismatch),
+            "encrypted" => Ok(Kind::Encrypted),
+            "malformed" => Ok(Kind::Malformed),
+            "unreadable" => Ok(Kind::Unreadable),
+            other => Err(format!("Unknown entry error kind '{}'", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EntryError {
+    /// Entry name in the archive, or path relative to the scanned folder
+    pub name: String,
+    pub kind: Kind,
+    pub detail: String,
+}
+
+impl EntryError {
+    pub fn new(name: impl Into<String>, kind: Kind, detail: impl Into<String>) -> Self {
+        EntryError {
+            name: name.into(),
+            kind,
+            detail: detail.into(),
+        }
+    }
+
+    /// Entry too small to hold the 128-byte preamble and the DICM code
+    pub fn too_short(name: impl Into<String>, size: u64) -> Self {
+        EntryError::new(
+            name,
+            Kind::NotDicom,
+            format!("{} bytes, shorter than a DICOM preamble", size),
+        )
+    }
+
+    /// Entry that the ZIP reader cannot open
+    pub fn from_zip(name: impl Into<String>, error: &ZipError) -> Self {
+        let kind = match error {
+            ZipError::UnsupportedArchive(message) if *message == ZipError::PASSWORD_REQUIRED => {
+                Kind::Encrypted
+            }
+            ZipError::Io(e) => io_kind(e).unwrap_or(Kind::Unreadable),
+            _ => Kind::Unreadable,
+        };
+        EntryError::new(name, kind, error.to_string())
+    }
+
+    /// Entry or file whose DICOM header cannot be parsed
+    pub fn from_dicom(name: impl Into<String>, error: &ReadError) -> Self {
+        EntryError::new(name, classify(error), describe(error))
+    }
+
+    /// Entry that fails while it is decompressed
+    pub fn from_io(name: impl Into<String>, error: &std::io::Error) -> Self {
+        EntryError::new(
+            name,
+            io_kind(error).unwrap_or(Kind::Unreadable),
+            error.to_string(),
+        )
+    }
+
+    /// One skipped_files.csv row, without the trailing newline
+    pub fn csv_row(&self) -> String {
+        format!(
+            "{},{},{}",
+            escape_csv_field(&self.name),
+            self.kind,
+            escape_csv_field(&self.detail)
+        )
+    }
+}
+
+/// The decompressed entry does not match the CRC-32 recorded in the archive
+#[derive(Debug)]
+pub struct CrcMismatch {
+    expected: u32,
+    actual: u32,
+}
+
+impl fmt::Display for CrcMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CRC-32 {:08x} does not match {:08x} recorded in the archive",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for CrcMismatch {}
+
+/// Reads an archive entry while computing its CRC-32. The ZIP reader only reports a mismatch as a
+/// bare message, so once the declared size has been read a mismatch is reported as `CrcMismatch`.
+pub struct CrcReader<R> {
+    inner: R,
+    expected: u32,
+    size: u64,
+    read: u64,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> CrcReader<R> {
+    pub fn new(inner: R, expected: u32, size: u64) -> Self {
+        CrcReader {
+            inner,
+            expected,
+            size,
+            read: 0,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    fn mismatch(&self) -> Option<CrcMismatch> {
+        let actual = self.hasher.clone().finalize();
+        (self.read == self.size && actual != self.expected).then_some(CrcMismatch {
+            expected: self.expected,
+            actual,
+        })
+    }
+}
+
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(n) => {
+                self.hasher.update(&buf[..n]);
+                self.read += n as u64;
+                if n == 0
+                    && !buf.is_empty()
+                    && let Some(mismatch) = self.mismatch()
+                {
+                    return Err(std::io::Error::new(IoErrorKind::InvalidData, mismatch));
+                }
+                Ok(n)
+            }
+            Err(e) => match self.mismatch() {
+                Some(mismatch) => Err(std::io::Error::new(IoErrorKind::InvalidData, mismatch)),
+                None => Err(e),
+            },
+        }
+    }
+}
+
+fn io_kind(error: &std::io::Error) -> Option<Kind> {
+    if error.kind() == IoErrorKind::UnexpectedEof {
+        Some(Kind::Truncated)
+    } else if error.get_ref().is_some_and(|e| e.is::<CrcMismatch>()) {
+        Some(Kind::CrcMismatch)
+    } else {
+        None
+    }
+}
+
+/// Walk the error chain for the first cause that tells what went wrong.
+fn classify(error: &ReadError) -> Kind {
+    match error {
+        ReadError::ReadUnsupportedTransferSyntax { .. } => return Kind::TransferSyntax,
+        ReadError::PrematureEnd { .. } => return Kind::Truncated,
+        _ => {}
+    }
+    let mut cause: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(e) = cause {
+        if let Some(MetaError::NotDicom { .. }) = e.downcast_ref::<MetaError>() {
+            return Kind::NotDicom;
+        }
+        if let Some(DecodeError::UnsupportedTransferSyntax { .. }) = e.downcast_ref::<DecodeError>()
+        {
+            return Kind::TransferSyntax;
+        }
+        if let Some(kind) = e.downcast_ref::<std::io::Error>().and_then(io_kind) {
+            return kind;
+        }
+        cause = e.source();
+    }
+    Kind::Malformed
+}
+
+/// The error with its causes, e.g. "Could not read data set token: ...: failed to fill whole buffer"
+fn describe(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut text = error.to_string();
+    let mut cause = error.source();
+    while let Some(e) = cause {
+        text.push_str(": ");
+        text.push_str(&e.to_string());
+        cause = e.source();
+    }
+    text
+}
+
+/// Counts per kind, e.g. "3 not DICOM, 1 truncated"
+pub fn summarize(errors: &[EntryError]) -> String {
+    let mut counts = std::collections::BTreeMap::new();
+    for e in errors {
+        *counts.entry(e.kind).or_insert(0) += 1;
+    }
+    counts
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind.label()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Log the counts, and every entry at debug level. Files that are not DICOM at all are expected
+/// in most archives; anything else is a warning.
+pub fn report(errors: &[EntryError]) {
+    if errors.is_empty() {
+        return;
+    }
+    for e in errors {
+        debug!(entry = %e.name, kind = %e.kind, "Skipped: {}", e.detail);
+    }
+    let summary = summarize(errors);
+    if errors.iter().all(|e| e.kind == Kind::NotDicom) {
+        info!(skipped = errors.len(), "Skipped {}", summary);
+    } else {
+        warn!(skipped = errors.len(), "Skipped {}", summary);
+    }
+}
+
+/// Write a per-archive skipped_files.csv; it only has the header when nothing was skipped.
+pub fn write_csv(path: &Path, errors: &[EntryError]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", SKIPPED_FILES_HEADER)?;
+    for e in errors {
+        writeln!(file, "{}", e.csv_row())?;
+    }
+    Ok(())
+}
+
+/// Add the errors for entries not listed yet, keeping the first reason seen for each entry.
+pub fn merge(errors: &mut Vec<EntryError>, more: Vec<EntryError>) {
+    let known: std::collections::HashSet<String> = errors.iter().map(|e| e.name.clone()).collect();
+    errors.extend(more.into_iter().filter(|e| !known.contains(&e.name)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn stored_zip(data: &[u8]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("IM0001", options).unwrap();
+        zip.write_all(data).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn read_entry(bytes: Vec<u8>) -> Result<Vec<u8>, EntryError> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let entry = archive.by_index(0).unwrap();
+        let (crc, size) = (entry.crc32(), entry.size());
+        let mut data = Vec::new();
+        CrcReader::new(entry, crc, size)
+            .read_to_end(&mut data)
+            .map_err(|e| EntryError::from_io("IM0001", &e))?;
+        Ok(data)
+    }
+
+    #[test]
+    fn intact_entries_read_through() {
+        let data = b"DICM and some pixels".repeat(100);
+        assert_eq!(read_entry(stored_zip(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn corrupted_entries_are_crc_mismatches() {
+        let data = b"DICM and some pixels".repeat(100);
+        let mut bytes = stored_zip(&data);
+        let at = bytes
+            .windows(data.len())
+            .position(|w| w == &data[..])
+            .unwrap();
+        bytes[at + 500] ^= 0xff;
+
+        let error = read_entry(bytes).unwrap_err();
+        assert_eq!(error.kind, Kind::CrcMismatch);
+        assert!(
+            error.detail.contains("recorded in the archive"),
+            "{}",
+            error.detail
+        );
+    }
+
+    #[test]
+    fn other_read_errors_are_not_crc_mismatches() {
+        let error = std::io::Error::other("Invalid checksum");
+        assert_eq!(EntryError::from_io("IM0001", &error).kind, Kind::Unreadable);
+    }
+}
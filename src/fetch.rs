@@ -0,0 +1,734 @@
+// --- Query/retrieve client ---
+//
+// `fetch` finds studies on a PACS with C-FIND (Study Root) by PatientID, study date range or
+// accession number, optionally narrows them down to series, and retrieves the matches with C-GET
+// or C-MOVE. Retrieved instances go through the same storage as `listen`: organized layout, deep
+// scan, and series_metadata.csv once everything has arrived.
+
+use std::net::{IpAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::{tags, uids};
+use dicom::object::mem::InMemDicomObject;
+use dicom::transfer_syntax::entries;
+use tracing::{info, warn};
+
+use crate::dimse::{self, Incoming, Link, Proposal, Requestor};
+use crate::listen::{self, Storage};
+use crate::template::PathTemplate;
+
+/// Query/retrieve level for `--level`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Level {
+    Study,
+    Series,
+}
+
+/// Retrieve service for `--method`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Method {
+    /// C-GET: instances come back on the query association
+    Get,
+    /// C-MOVE: the PACS sends instances to this AE title on --port
+    Move,
+}
+
+pub struct FetchOptions<'a> {
+    pub output: &'a Path,
+    /// PACS address, host:port
+    pub peer: &'a str,
+    pub called_ae_title: &'a str,
+    pub ae_title: &'a str,
+    pub patient_id: Option<&'a str>,
+    /// StudyDate match: a date or a DICOM range (20240101-20240131)
+    pub study_date: Option<&'a str>,
+    pub accession: Option<&'a str>,
+    pub level: Level,
+    pub method: Method,
+    /// Port for incoming C-MOVE sub-operations
+    pub port: u16,
+    pub template: Option<&'a PathTemplate>,
+    pub query_only: bool,
+}
+
+/// Storage SOP classes offered for C-GET sub-operations
+const STORAGE_SOP_CLASSES: &[&str] = &[
+    uids::COMPUTED_RADIOGRAPHY_IMAGE_STORAGE,
+    uids::DIGITAL_X_RAY_IMAGE_STORAGE_FOR_PRESENTATION,
+    uids::DIGITAL_X_RAY_IMAGE_STORAGE_FOR_PROCESSING,
+    uids::DIGITAL_MAMMOGRAPHY_X_RAY_IMAGE_STORAGE_FOR_PRESENTATION,
+    uids::CT_IMAGE_STORAGE,
+    uids::ENHANCED_CT_IMAGE_STORAGE,
+    uids::MR_IMAGE_STORAGE,
+    uids::ENHANCED_MR_IMAGE_STORAGE,
+    uids::MR_SPECTROSCOPY_STORAGE,
+    uids::ULTRASOUND_IMAGE_STORAGE,
+    uids::ULTRASOUND_MULTI_FRAME_IMAGE_STORAGE,
+    uids::SECONDARY_CAPTURE_IMAGE_STORAGE,
+    uids::MULTI_FRAME_GRAYSCALE_WORD_SECONDARY_CAPTURE_IMAGE_STORAGE,
+    uids::MULTI_FRAME_TRUE_COLOR_SECONDARY_CAPTURE_IMAGE_STORAGE,
+    uids::X_RAY_ANGIOGRAPHIC_IMAGE_STORAGE,
+    uids::X_RAY_RADIOFLUOROSCOPIC_IMAGE_STORAGE,
+    uids::NUCLEAR_MEDICINE_IMAGE_STORAGE,
+    uids::POSITRON_EMISSION_TOMOGRAPHY_IMAGE_STORAGE,
+    uids::ENHANCED_PET_IMAGE_STORAGE,
+    uids::RT_IMAGE_STORAGE,
+    uids::RT_DOSE_STORAGE,
+    uids::RT_STRUCTURE_SET_STORAGE,
+    uids::RT_PLAN_STORAGE,
+    uids::SEGMENTATION_STORAGE,
+    uids::GRAYSCALE_SOFTCOPY_PRESENTATION_STATE_STORAGE,
+    uids::BASIC_TEXT_SR_STORAGE,
+    uids::ENHANCED_SR_STORAGE,
+    uids::COMPREHENSIVE_SR_STORAGE,
+    uids::ENCAPSULATED_PDF_STORAGE,
+    uids::RAW_DATA_STORAGE,
+];
+
+/// Uncompressed syntaxes proposed for every presentation context, so retrieved files stay
+/// readable by the scanner
+const TRANSFER_SYNTAXES: [&str; 2] = [
+    entries::EXPLICIT_VR_LITTLE_ENDIAN.uid(),
+    entries::IMPLICIT_VR_LITTLE_ENDIAN.uid(),
+];
+
+/// One query match (C-FIND or QIDO-RS), reduced to what is listed and retrieved
+pub struct Match {
+    pub study_uid: String,
+    /// Empty for study-level matches
+    pub series_uid: String,
+    pub description: String,
+    pub summary: String,
+}
+
+/// Print the matches of a query.
+pub fn list_matches(matches: &[Match]) {
+    info!("{} match(es)", matches.len());
+    for m in matches {
+        println!("  {}  {}  {}", m.study_uid, m.description, m.summary);
+        if !m.series_uid.is_empty() {
+            println!("      series {}", m.series_uid);
+        }
+    }
+}
+
+pub fn fetch(options: &FetchOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if options.patient_id.is_none() && options.study_date.is_none() && options.accession.is_none() {
+        return Err("fetch needs --patient-id, --study-date or --accession".into());
+    }
+
+    let matches = find(options)?;
+    if matches.is_empty() {
+        warn!("No matching studies found");
+        return Ok(());
+    }
+    list_matches(&matches);
+    if options.query_only {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(options.output)?;
+    let storage = Storage::new(options.output, options.template);
+    match options.method {
+        Method::Get => retrieve_get(options, &matches, &storage)?,
+        Method::Move => retrieve_move(options, &matches, &storage)?,
+    }
+
+    let count = storage.instance_count();
+    info!(
+        "Fetched {} instance(s) into {}",
+        count,
+        options.output.display()
+    );
+    if count > 0 {
+        storage.export_metadata()?;
+    }
+    Ok(())
+}
+
+/// Open an association proposing the query/retrieve SOP class and, for C-GET, the storage SOP
+/// classes with the SCP role for the C-STORE sub-operations.
+fn associate(
+    options: &FetchOptions,
+    abstract_syntax: &str,
+    storage: bool,
+) -> Result<Requestor, Box<dyn std::error::Error>> {
+    let scp_roles: &[&str] = if storage { STORAGE_SOP_CLASSES } else { &[] };
+    let mut abstract_syntaxes = vec![abstract_syntax];
+    abstract_syntaxes.extend_from_slice(scp_roles);
+    let proposal = Proposal {
+        calling_ae_title: options.ae_title,
+        called_ae_title: options.called_ae_title,
+        abstract_syntaxes,
+        transfer_syntaxes: &TRANSFER_SYNTAXES,
+        scp_roles,
+    };
+    Requestor::establish(options.peer, &proposal)
+        .map_err(|e| format!("association with {} failed: {}", options.peer, e).into())
+}
+
+fn text(tag: Tag, vr: VR, value: &str) -> DataElement<InMemDicomObject> {
+    DataElement::new(tag, vr, PrimitiveValue::from(value))
+}
+
+fn value(obj: &InMemDicomObject, tag: Tag) -> String {
+    obj.element(tag)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim_end_matches(['\0', ' ']).to_string())
+        .unwrap_or_default()
+}
+
+/// C-FIND at study level, then at series level for every study when requested.
+fn find(options: &FetchOptions) -> Result<Vec<Match>, Box<dyn std::error::Error>> {
+    let mut association = associate(
+        options,
+        uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND,
+        false,
+    )?;
+    let (context_id, transfer_syntax) =
+        association.context(uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND)?;
+
+    let study_keys = InMemDicomObject::from_element_iter([
+        text(tags::QUERY_RETRIEVE_LEVEL, VR::CS, "STUDY"),
+        text(tags::PATIENT_ID, VR::LO, options.patient_id.unwrap_or("")),
+        text(tags::STUDY_DATE, VR::DA, options.study_date.unwrap_or("")),
+        text(
+            tags::ACCESSION_NUMBER,
+            VR::SH,
+            options.accession.unwrap_or(""),
+        ),
+        text(tags::STUDY_INSTANCE_UID, VR::UI, ""),
+        text(tags::PATIENT_NAME, VR::PN, ""),
+        text(tags::STUDY_DESCRIPTION, VR::LO, ""),
+        text(tags::MODALITIES_IN_STUDY, VR::CS, ""),
+        text(tags::NUMBER_OF_STUDY_RELATED_INSTANCES, VR::IS, ""),
+    ]);
+    let studies = query(
+        &mut association,
+        context_id,
+        &transfer_syntax,
+        &study_keys,
+        1,
+    )?;
+
+    let mut matches = Vec::new();
+    let mut message_id: u16 = 1;
+    for study in &studies {
+        let study_uid = value(study, tags::STUDY_INSTANCE_UID);
+        if study_uid.is_empty() {
+            continue;
+        }
+        let description = format!(
+            "{} {} {}",
+            value(study, tags::PATIENT_ID),
+            value(study, tags::STUDY_DATE),
+            value(study, tags::STUDY_DESCRIPTION)
+        );
+        if options.level == Level::Study {
+            matches.push(Match {
+                study_uid,
+                series_uid: String::new(),
+                description,
+                summary: format!(
+                    "[{}] {} instance(s)",
+                    value(study, tags::MODALITIES_IN_STUDY),
+                    value(study, tags::NUMBER_OF_STUDY_RELATED_INSTANCES)
+                ),
+            });
+            continue;
+        }
+
+        let series_keys = InMemDicomObject::from_element_iter([
+            text(tags::QUERY_RETRIEVE_LEVEL, VR::CS, "SERIES"),
+            text(tags::STUDY_INSTANCE_UID, VR::UI, &study_uid),
+            text(tags::SERIES_INSTANCE_UID, VR::UI, ""),
+            text(tags::SERIES_NUMBER, VR::IS, ""),
+            text(tags::SERIES_DESCRIPTION, VR::LO, ""),
+            text(tags::MODALITY, VR::CS, ""),
+            text(tags::NUMBER_OF_SERIES_RELATED_INSTANCES, VR::IS, ""),
+        ]);
+        message_id = message_id.wrapping_add(1);
+        for series in query(
+            &mut association,
+            context_id,
+            &transfer_syntax,
+            &series_keys,
+            message_id,
+        )? {
+            let series_uid = value(&series, tags::SERIES_INSTANCE_UID);
+            if series_uid.is_empty() {
+                continue;
+            }
+            matches.push(Match {
+                study_uid: study_uid.clone(),
+                series_uid,
+                description: description.clone(),
+                summary: format!(
+                    "#{} {} [{}] {} instance(s)",
+                    value(&series, tags::SERIES_NUMBER),
+                    value(&series, tags::SERIES_DESCRIPTION),
+                    value(&series, tags::MODALITY),
+                    value(&series, tags::NUMBER_OF_SERIES_RELATED_INSTANCES)
+                ),
+            });
+        }
+    }
+    association.release()?;
+    Ok(matches)
+}
+
+/// Send one C-FIND request and collect the pending responses' identifiers.
+fn query(
+    association: &mut Requestor,
+    context_id: u8,
+    transfer_syntax: &str,
+    keys: &InMemDicomObject,
+    message_id: u16,
+) -> Result<Vec<InMemDicomObject>, Box<dyn std::error::Error>> {
+    let command = dimse::command(
+        dimse::C_FIND_RQ,
+        vec![
+            dimse::uid(
+                tags::AFFECTED_SOP_CLASS_UID,
+                uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND,
+            ),
+            dimse::ushort(tags::MESSAGE_ID, message_id),
+            dimse::ushort(tags::PRIORITY, 0),
+        ],
+        true,
+    )?;
+    let identifier = dimse::encode_data(keys, transfer_syntax)?;
+    dimse::send(association, context_id, &command, Some(&identifier))?;
+
+    let mut results = Vec::new();
+    loop {
+        let Incoming::Message(response) = dimse::receive(association)? else {
+            return Err("association closed during C-FIND".into());
+        };
+        if response.field() != dimse::C_FIND_RSP {
+            return Err(format!("unexpected command 0x{:04X}", response.field()).into());
+        }
+        let status = response.uint(tags::STATUS).unwrap_or(0xFFFF);
+        if !dimse::is_pending(status) {
+            if status != dimse::STATUS_SUCCESS {
+                return Err(format!("C-FIND failed with status 0x{:04X}", status).into());
+            }
+            return Ok(results);
+        }
+        if let Some(data) = &response.data {
+            results.push(dimse::decode_data(data, transfer_syntax)?);
+        }
+    }
+}
+
+/// Identifier selecting one match for C-GET/C-MOVE
+fn retrieve_keys(m: &Match) -> InMemDicomObject {
+    let mut keys =
+        InMemDicomObject::from_element_iter([text(tags::STUDY_INSTANCE_UID, VR::UI, &m.study_uid)]);
+    if m.series_uid.is_empty() {
+        keys.put(text(tags::QUERY_RETRIEVE_LEVEL, VR::CS, "STUDY"));
+    } else {
+        keys.put(text(tags::QUERY_RETRIEVE_LEVEL, VR::CS, "SERIES"));
+        keys.put(text(tags::SERIES_INSTANCE_UID, VR::UI, &m.series_uid));
+    }
+    keys
+}
+
+fn retrieve_command(
+    field: u16,
+    sop_class: &str,
+    message_id: u16,
+    move_destination: Option<&str>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut elements = vec![
+        dimse::uid(tags::AFFECTED_SOP_CLASS_UID, sop_class),
+        dimse::ushort(tags::MESSAGE_ID, message_id),
+        dimse::ushort(tags::PRIORITY, 0),
+    ];
+    if let Some(destination) = move_destination {
+        elements.push(text(tags::MOVE_DESTINATION, VR::AE, destination));
+    }
+    dimse::command(field, elements, true)
+}
+
+/// Log the sub-operation counts of a final C-GET/C-MOVE response.
+fn report(m: &Match, response: &dimse::Message) {
+    let count = |tag| response.uint(tag).unwrap_or(0);
+    let status = response.uint(tags::STATUS).unwrap_or(0xFFFF);
+    let target = if m.series_uid.is_empty() {
+        &m.study_uid
+    } else {
+        &m.series_uid
+    };
+    info!(
+        "Retrieved {}: {} completed, {} failed, {} warning (status 0x{:04X})",
+        target,
+        count(tags::NUMBER_OF_COMPLETED_SUBOPERATIONS),
+        count(tags::NUMBER_OF_FAILED_SUBOPERATIONS),
+        count(tags::NUMBER_OF_WARNING_SUBOPERATIONS),
+        status
+    );
+}
+
+/// C-GET every match; C-STORE sub-operations arrive on the same association.
+fn retrieve_get(
+    options: &FetchOptions,
+    matches: &[Match],
+    storage: &Storage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sop_class = uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_GET;
+    let mut association = associate(options, sop_class, true)?;
+    let (context_id, transfer_syntax) = association.context(sop_class)?;
+
+    let mut message_id: u16 = 0;
+    for m in matches {
+        message_id = message_id.wrapping_add(1);
+        let command = retrieve_command(dimse::C_GET_RQ, sop_class, message_id, None)?;
+        let identifier = dimse::encode_data(&retrieve_keys(m), &transfer_syntax)?;
+        dimse::send(&mut association, context_id, &command, Some(&identifier))?;
+
+        loop {
+            let Incoming::Message(message) = dimse::receive(&mut association)? else {
+                return Err("association closed during C-GET".into());
+            };
+            match message.field() {
+                dimse::C_STORE_RQ => {
+                    let store_syntax = association
+                        .transfer_syntax(message.context_id)
+                        .unwrap_or_default();
+                    let status = storage.answer(&message, &store_syntax);
+                    let rsp = dimse::response(&message, dimse::C_STORE_RSP, status, false)?;
+                    dimse::send(&mut association, message.context_id, &rsp, None)?;
+                }
+                dimse::C_GET_RSP => {
+                    if !dimse::is_pending(message.uint(tags::STATUS).unwrap_or(0xFFFF)) {
+                        report(m, &message);
+                        break;
+                    }
+                }
+                other => return Err(format!("unexpected command 0x{:04X}", other).into()),
+            }
+        }
+    }
+    association.release()?;
+    Ok(())
+}
+
+/// C-MOVE every match to our own AE title, receiving on `--port` meanwhile. Only the queried PACS
+/// (its AE title, from one of its addresses) may deliver the sub-operations.
+fn retrieve_move(
+    options: &FetchOptions,
+    matches: &[Match],
+    storage: &Storage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let peer_addresses: Vec<IpAddr> = options
+        .peer
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", options.peer, e))?
+        .map(|address| address.ip())
+        .collect();
+    let allowed = [options.called_ae_title.to_string()];
+    let listener = TcpListener::bind(("0.0.0.0", options.port))?;
+    let scp = listen::storage_scp(options.ae_title, Some(&allowed));
+    let stop = AtomicBool::new(false);
+    info!(
+        "Receiving C-MOVE sub-operations on port {} as {} from {}",
+        options.port, options.ae_title, options.called_ae_title
+    );
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| listen::accept(&listener, &scp, &peer_addresses, storage, &stop));
+        let moved = move_matches(options, matches);
+        // Sub-operations are complete once the final response arrived; wait for the PACS to
+        // release its storage association
+        stop.store(true, Ordering::Relaxed);
+        moved
+    })
+}
+
+fn move_matches(
+    options: &FetchOptions,
+    matches: &[Match],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sop_class = uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_MOVE;
+    let mut association = associate(options, sop_class, false)?;
+    let (context_id, transfer_syntax) = association.context(sop_class)?;
+
+    let mut message_id: u16 = 0;
+    for m in matches {
+        message_id = message_id.wrapping_add(1);
+        let command = retrieve_command(
+            dimse::C_MOVE_RQ,
+            sop_class,
+            message_id,
+            Some(options.ae_title),
+        )?;
+        let identifier = dimse::encode_data(&retrieve_keys(m), &transfer_syntax)?;
+        dimse::send(&mut association, context_id, &command, Some(&identifier))?;
+
+        loop {
+            let Incoming::Message(message) = dimse::receive(&mut association)? else {
+                return Err("association closed during C-MOVE".into());
+            };
+            if message.field() != dimse::C_MOVE_RSP {
+                return Err(format!("unexpected command 0x{:04X}", message.field()).into());
+            }
+            if !dimse::is_pending(message.uint(tags::STATUS).unwrap_or(0xFFFF)) {
+                report(m, &message);
+                break;
+            }
+        }
+    }
+    association.release()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::ul::{ServerAssociation, ServerAssociationOptions};
+    use std::net::TcpStream;
+    use std::path::PathBuf;
+    use std::thread::JoinHandle;
+
+    const STUDY: &str = "1.2.826.0.1.3680043.2.1125.1";
+    const SERIES: &str = "1.2.826.0.1.3680043.2.1125.1.2";
+    const INSTANCE: &str = "1.2.826.0.1.3680043.2.1125.1.2.3";
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dicom_scanner_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn options<'a>(output: &'a Path, peer: &'a str, method: Method, port: u16) -> FetchOptions<'a> {
+        FetchOptions {
+            output,
+            peer,
+            called_ae_title: "PACS",
+            ae_title: "SCANNER",
+            patient_id: Some("P1"),
+            study_date: None,
+            accession: None,
+            level: Level::Series,
+            method,
+            port,
+            template: None,
+            query_only: false,
+        }
+    }
+
+    /// PACS stand-in: accepts `count` associations in turn on a local port and hands each to
+    /// `serve`
+    fn pacs(
+        scp: ServerAssociationOptions<'static, dicom::ul::association::server::AcceptAny>,
+        count: usize,
+        serve: impl Fn(ServerAssociation<TcpStream>) + Send + 'static,
+    ) -> (String, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            for _ in 0..count {
+                let (stream, _) = listener.accept().unwrap();
+                if let Ok(association) = scp.establish(stream) {
+                    serve(association);
+                }
+            }
+        });
+        (address, handle)
+    }
+
+    fn instance(transfer_syntax: &str) -> Vec<u8> {
+        let obj = InMemDicomObject::from_element_iter([
+            text(tags::SOP_CLASS_UID, VR::UI, uids::CT_IMAGE_STORAGE),
+            text(tags::SOP_INSTANCE_UID, VR::UI, INSTANCE),
+            text(tags::PATIENT_ID, VR::LO, "P1"),
+            text(tags::STUDY_INSTANCE_UID, VR::UI, STUDY),
+            text(tags::SERIES_INSTANCE_UID, VR::UI, SERIES),
+            text(tags::SERIES_NUMBER, VR::IS, "2"),
+            text(tags::MODALITY, VR::CS, "CT"),
+        ]);
+        dimse::encode_data(&obj, transfer_syntax).unwrap()
+    }
+
+    /// Send a C-STORE request for the test instance and return the response status.
+    fn store(link: &mut impl Link, context_id: u8) -> u16 {
+        let transfer_syntax = link.transfer_syntax(context_id).unwrap();
+        let command = dimse::command(
+            dimse::C_STORE_RQ,
+            vec![
+                dimse::uid(tags::AFFECTED_SOP_CLASS_UID, uids::CT_IMAGE_STORAGE),
+                dimse::ushort(tags::MESSAGE_ID, 1),
+                dimse::ushort(tags::PRIORITY, 0),
+                dimse::uid(tags::AFFECTED_SOP_INSTANCE_UID, INSTANCE),
+            ],
+            true,
+        )
+        .unwrap();
+        let data = instance(&transfer_syntax);
+        dimse::send(link, context_id, &command, Some(&data)).unwrap();
+        let Incoming::Message(response) = dimse::receive(link).unwrap() else {
+            panic!("no C-STORE response");
+        };
+        assert_eq!(response.field(), dimse::C_STORE_RSP);
+        response.uint(tags::STATUS).unwrap()
+    }
+
+    /// Answer a C-FIND with one study or series match, depending on the query level.
+    fn answer_find(association: &mut ServerAssociation<TcpStream>, request: &dimse::Message) {
+        let transfer_syntax = association.transfer_syntax(request.context_id).unwrap();
+        let keys = dimse::decode_data(request.data.as_deref().unwrap(), &transfer_syntax).unwrap();
+        let found = match value(&keys, tags::QUERY_RETRIEVE_LEVEL).as_str() {
+            "STUDY" => {
+                assert_eq!(value(&keys, tags::PATIENT_ID), "P1");
+                InMemDicomObject::from_element_iter([
+                    text(tags::PATIENT_ID, VR::LO, "P1"),
+                    text(tags::STUDY_INSTANCE_UID, VR::UI, STUDY),
+                ])
+            }
+            _ => {
+                assert_eq!(value(&keys, tags::STUDY_INSTANCE_UID), STUDY);
+                InMemDicomObject::from_element_iter([
+                    text(tags::STUDY_INSTANCE_UID, VR::UI, STUDY),
+                    text(tags::SERIES_INSTANCE_UID, VR::UI, SERIES),
+                ])
+            }
+        };
+        let data = dimse::encode_data(&found, &transfer_syntax).unwrap();
+        let pending = dimse::response(request, dimse::C_FIND_RSP, 0xFF00, true).unwrap();
+        dimse::send(association, request.context_id, &pending, Some(&data)).unwrap();
+        let done =
+            dimse::response(request, dimse::C_FIND_RSP, dimse::STATUS_SUCCESS, false).unwrap();
+        dimse::send(association, request.context_id, &done, None).unwrap();
+    }
+
+    fn retrieved(output: &Path) -> Vec<PathBuf> {
+        glob::glob(&format!("{}/**/*.dcm", output.display()))
+            .unwrap()
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn find_and_get() {
+        let scp = ServerAssociationOptions::new()
+            .ae_title("PACS")
+            .promiscuous(true);
+        let (peer, pacs) = pacs(scp, 2, |mut association| {
+            loop {
+                let Incoming::Message(request) = dimse::receive(&mut association).unwrap() else {
+                    return;
+                };
+                match request.field() {
+                    dimse::C_FIND_RQ => answer_find(&mut association, &request),
+                    dimse::C_GET_RQ => {
+                        // The storage contexts follow the C-GET context in proposal order
+                        let position = STORAGE_SOP_CLASSES
+                            .iter()
+                            .position(|uid| *uid == uids::CT_IMAGE_STORAGE)
+                            .unwrap();
+                        let status = store(&mut association, (2 * position + 3) as u8);
+                        assert_eq!(status, dimse::STATUS_SUCCESS);
+                        let done = dimse::response(
+                            &request,
+                            dimse::C_GET_RSP,
+                            dimse::STATUS_SUCCESS,
+                            false,
+                        )
+                        .unwrap();
+                        dimse::send(&mut association, request.context_id, &done, None).unwrap();
+                    }
+                    other => panic!("unexpected command 0x{:04X}", other),
+                }
+            }
+        });
+
+        let output = scratch_dir("fetch_get");
+        fetch(&options(&output, &peer, Method::Get, 0)).unwrap();
+        pacs.join().unwrap();
+
+        let files = retrieved(&output);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with(format!("{}.dcm", INSTANCE)));
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[test]
+    fn find_and_move() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let scp = ServerAssociationOptions::new()
+            .ae_title("PACS")
+            .promiscuous(true);
+        let (peer, pacs) = pacs(scp, 2, move |mut association| {
+            loop {
+                let Incoming::Message(request) = dimse::receive(&mut association).unwrap() else {
+                    return;
+                };
+                match request.field() {
+                    dimse::C_FIND_RQ => answer_find(&mut association, &request),
+                    dimse::C_MOVE_RQ => {
+                        assert_eq!(request.string(tags::MOVE_DESTINATION), "SCANNER");
+                        let proposal = Proposal {
+                            calling_ae_title: "PACS",
+                            called_ae_title: "SCANNER",
+                            abstract_syntaxes: vec![uids::CT_IMAGE_STORAGE],
+                            transfer_syntaxes: &TRANSFER_SYNTAXES,
+                            scp_roles: &[],
+                        };
+                        let mut sub =
+                            Requestor::establish(&format!("127.0.0.1:{}", port), &proposal)
+                                .unwrap();
+                        let (context_id, _) = sub.context(uids::CT_IMAGE_STORAGE).unwrap();
+                        assert_eq!(store(&mut sub, context_id), dimse::STATUS_SUCCESS);
+                        sub.release().unwrap();
+                        let done = dimse::response(
+                            &request,
+                            dimse::C_MOVE_RSP,
+                            dimse::STATUS_SUCCESS,
+                            false,
+                        )
+                        .unwrap();
+                        dimse::send(&mut association, request.context_id, &done, None).unwrap();
+                    }
+                    other => panic!("unexpected command 0x{:04X}", other),
+                }
+            }
+        });
+
+        let output = scratch_dir("fetch_move");
+        fetch(&options(&output, &peer, Method::Move, port)).unwrap();
+        pacs.join().unwrap();
+
+        assert_eq!(retrieved(&output).len(), 1);
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[test]
+    fn unaccepted_query_model_is_an_error() {
+        let scp = ServerAssociationOptions::new()
+            .ae_title("PACS")
+            .with_abstract_syntax(dimse::VERIFICATION_SOP_CLASS);
+        let (peer, pacs) = pacs(scp, 1, |mut association| {
+            let _ = dimse::receive(&mut association);
+        });
+
+        let output = scratch_dir("fetch_rejected");
+        let error = fetch(&options(&output, &peer, Method::Get, 0)).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains(uids::STUDY_ROOT_QUERY_RETRIEVE_INFORMATION_MODEL_FIND),
+            "{}",
+            error
+        );
+        pacs.join().unwrap();
+    }
+}
@@ -0,0 +1,473 @@
+// --- Series filter expressions ---
+//
+// `--filter` selects the DICOM files that are extracted, exported and searched for XProtocol:
+//
+//   Modality == "MR" && ImageType !~ "DERIVED" && SeriesDescription ~ "(?i)t1"
+//
+// Fields are the deep-scan attributes by DICOM keyword (`SeriesDescription`, `EchoTime`, ...,
+// case-insensitive; `TR`, `TE` and `TI` are accepted as in series_metadata.csv) plus `FileName`,
+// the entry name inside the archive. Comparisons:
+//
+//   ==  !=           string equality; numerically when both sides are numbers ("5.0" == 5)
+//   ~   !~           regular expression search (unanchored; `(?i)` for case-insensitive)
+//   <  <=  >  >=     numeric comparison; false when the value is missing or not a number
+//
+// combined with `&&`, `||`, `!` and parentheses. Literals are numbers or double-quoted strings
+// (`\"` and `\\` escape). Missing values compare as "N/A". The expression is evaluated per file,
+// so series attributes select whole series.
+
+use std::collections::HashSet;
+
+use regex::Regex;
+use tracing::info;
+
+use crate::DeepDicomCandidate;
+
+type Accessor = fn(&DeepDicomCandidate) -> &str;
+
+const FIELDS: &[(&str, Accessor)] = &[
+    ("FileName", |c| &c.name),
+    ("StudyInstanceUID", |c| &c.study_instance_uid),
+    ("SeriesInstanceUID", |c| &c.series_instance_uid),
+    ("SOPInstanceUID", |c| &c.sop_instance_uid),
+    ("Manufacturer", |c| &c.manufacturer),
+    ("Modality", |c| &c.modality),
+    ("PatientID", |c| &c.patient_id),
+    ("PatientName", |c| &c.patient_name),
+    ("StudyDate", |c| &c.study_date),
+    ("AccessionNumber", |c| &c.accession_number),
+    ("StudyDescription", |c| &c.study_description),
+    ("SeriesDescription", |c| &c.series_description),
+    ("SeriesNumber", |c| &c.series_number),
+    ("ProtocolName", |c| &c.protocol_name),
+    ("AcquisitionType", |c| &c.acquisition_type),
+    ("PixelSpacing", |c| &c.pixel_spacing),
+    ("SliceThickness", |c| &c.slice_thickness),
+    ("AcquisitionTime", |c| &c.acquisition_time),
+    ("Rows", |c| &c.rows),
+    ("Columns", |c| &c.columns),
+    ("RepetitionTime", |c| &c.repetition_time),
+    ("TR", |c| &c.repetition_time),
+    ("EchoTime", |c| &c.echo_time),
+    ("TE", |c| &c.echo_time),
+    ("InversionTime", |c| &c.inversion_time),
+    ("TI", |c| &c.inversion_time),
+    ("DerivationDescription", |c| &c.derivation_description),
+    ("ReferencedSeriesUID", |c| &c.referenced_series_uid),
+    ("AcquisitionDuration", |c| &c.acquisition_duration),
+    ("FlipAngle", |c| &c.flip_angle),
+    ("NumberOfAverages", |c| &c.number_of_averages),
+    ("EchoTrainLength", |c| &c.echo_train_length),
+    ("ParallelImagingFactor", |c| &c.parallel_imaging_factor),
+    ("MagneticFieldStrength", |c| &c.magnetic_field_strength),
+    ("SpacingBetweenSlices", |c| &c.spacing_between_slices),
+    ("ImageType", |c| &c.image_type),
+    ("InstanceNumber", |c| &c.instance_number),
+    ("PhaseEncodingDirection", |c| &c.phase_encoding_direction),
+    ("EffectiveEchoSpacing", |c| &c.effective_echo_spacing),
+    ("TotalReadoutTime", |c| &c.total_readout_time),
+    ("TransferSyntax", |c| &c.transfer_syntax),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Field(String),
+    Text(String),
+    Number(f64),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+enum Comparison {
+    Equal(String),
+    NotEqual(String),
+    Matches(Regex),
+    NotMatches(Regex),
+    Less(f64),
+    LessEqual(f64),
+    Greater(f64),
+    GreaterEqual(f64),
+}
+
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Accessor, Comparison),
+}
+
+pub struct Filter {
+    expr: Expr,
+    source: String,
+}
+
+impl Filter {
+    /// Parse a filter expression. Unknown fields, bad regexes and syntax errors are reported with
+    /// their position.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some((token, offset)) = parser.tokens.get(parser.pos) {
+            return Err(format!(
+                "Unexpected {} at column {} of filter",
+                describe(token),
+                offset + 1
+            ));
+        }
+        Ok(Filter {
+            expr,
+            source: source.trim().to_string(),
+        })
+    }
+
+    pub fn matches(&self, candidate: &DeepDicomCandidate) -> bool {
+        evaluate(&self.expr, candidate)
+    }
+
+    /// Keep the candidates that match and report how many that was.
+    pub fn apply(&self, candidates: &mut Vec<DeepDicomCandidate>) {
+        let total = candidates.len();
+        candidates.retain(|c| self.matches(c));
+        info!(
+            "Filter '{}' kept {} of {} DICOM files",
+            self.source,
+            candidates.len(),
+            total
+        );
+    }
+}
+
+/// Series Instance UIDs of the files that passed the filter
+pub fn series_uids(candidates: &[DeepDicomCandidate]) -> HashSet<String> {
+    candidates
+        .iter()
+        .map(|c| c.series_instance_uid.clone())
+        .collect()
+}
+
+fn evaluate(expr: &Expr, candidate: &DeepDicomCandidate) -> bool {
+    match expr {
+        Expr::Or(a, b) => evaluate(a, candidate) || evaluate(b, candidate),
+        Expr::And(a, b) => evaluate(a, candidate) && evaluate(b, candidate),
+        Expr::Not(a) => !evaluate(a, candidate),
+        Expr::Compare(field, comparison) => {
+            let value = field(candidate).trim();
+            let number = value.parse::<f64>().ok();
+            match comparison {
+                Comparison::Equal(text) => equal(value, text),
+                Comparison::NotEqual(text) => !equal(value, text),
+                Comparison::Matches(re) => re.is_match(value),
+                Comparison::NotMatches(re) => !re.is_match(value),
+                Comparison::Less(limit) => number.is_some_and(|n| n < *limit),
+                Comparison::LessEqual(limit) => number.is_some_and(|n| n <= *limit),
+                Comparison::Greater(limit) => number.is_some_and(|n| n > *limit),
+                Comparison::GreaterEqual(limit) => number.is_some_and(|n| n >= *limit),
+            }
+        }
+    }
+}
+
+fn equal(value: &str, text: &str) -> bool {
+    match (value.parse::<f64>(), text.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => value == text,
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Field(name) => format!("field '{}'", name),
+        Token::Text(text) => format!("string \"{}\"", text),
+        Token::Number(n) => format!("number {}", n),
+        Token::Op(op) => format!("'{}'", op),
+        Token::Open => "'('".to_string(),
+        Token::Close => "')'".to_string(),
+    }
+}
+
+/// Operators, longest first so that `<=` is not read as `<`
+const OPERATORS: &[&str] = &["&&", "||", "==", "!=", "!~", "<=", ">=", "~", "<", ">", "!"];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let (offset, c) = chars[i];
+        let rest = &source[offset..];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push((Token::Open, offset));
+            i += 1;
+        } else if c == ')' {
+            tokens.push((Token::Close, offset));
+            i += 1;
+        } else if c == '"' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => {
+                        return Err(format!(
+                            "Unterminated string at column {} of filter",
+                            offset + 1
+                        ));
+                    }
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => {
+                        match chars.get(i + 1) {
+                            Some((_, escaped @ ('"' | '\\'))) => text.push(*escaped),
+                            // Keep other escapes for the regex (`\d`, `\.`)
+                            Some((_, other)) => {
+                                text.push('\\');
+                                text.push(*other);
+                            }
+                            None => {}
+                        }
+                        i += 2;
+                        continue;
+                    }
+                    Some((_, other)) => text.push(*other),
+                }
+                i += 1;
+            }
+            tokens.push((Token::Text(text), offset));
+            i += 1;
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let len = rest
+                .find(|ch: char| !(ch.is_ascii_digit() || matches!(ch, '.' | '-' | 'e' | 'E')))
+                .unwrap_or(rest.len());
+            let number = rest[..len]
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid number at column {} of filter", offset + 1))?;
+            tokens.push((Token::Number(number), offset));
+            i += rest[..len].chars().count();
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .unwrap_or(rest.len());
+            tokens.push((Token::Field(rest[..len].to_string()), offset));
+            i += len;
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push((Token::Op(op), offset));
+            i += op.len();
+        } else {
+            return Err(format!(
+                "Unexpected '{}' at column {} of filter",
+                c,
+                offset + 1
+            ));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn next(&mut self) -> Result<(Token, usize), String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("Filter ends unexpectedly")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Op("||")) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::Op("&&")) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next()? {
+            (Token::Op("!"), _) => Ok(Expr::Not(Box::new(self.unary()?))),
+            (Token::Open, offset) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Ok((Token::Close, _)) => Ok(expr),
+                    _ => Err(format!("Unclosed '(' at column {} of filter", offset + 1)),
+                }
+            }
+            (Token::Field(name), offset) => self.comparison(&name, offset),
+            (token, offset) => Err(format!(
+                "Expected a field name but found {} at column {} of filter",
+                describe(&token),
+                offset + 1
+            )),
+        }
+    }
+
+    fn comparison(&mut self, name: &str, offset: usize) -> Result<Expr, String> {
+        let Some((_, field)) = FIELDS.iter().find(|(f, _)| f.eq_ignore_ascii_case(name)) else {
+            let known: Vec<&str> = FIELDS.iter().map(|(f, _)| *f).collect();
+            return Err(format!(
+                "Unknown field '{}' at column {} of filter (known fields: {})",
+                name,
+                offset + 1,
+                known.join(", ")
+            ));
+        };
+        let (op, op_offset) = match self.next()? {
+            (Token::Op(op), at) => (op, at),
+            (token, at) => {
+                return Err(format!(
+                    "Expected a comparison after '{}' but found {} at column {} of filter",
+                    name,
+                    describe(&token),
+                    at + 1
+                ));
+            }
+        };
+        let (value, value_offset) = self.next()?;
+
+        let column = value_offset + 1;
+        let text = || match &value {
+            Token::Text(text) => Ok(text.clone()),
+            Token::Number(n) => Ok(n.to_string()),
+            other => Err(format!(
+                "Expected a value but found {} at column {} of filter",
+                describe(other),
+                column
+            )),
+        };
+        let number = || match &value {
+            Token::Number(n) => Ok(*n),
+            Token::Text(text) => text.trim().parse::<f64>().map_err(|_| {
+                format!(
+                    "'{}' needs a number but found \"{}\" at column {} of filter",
+                    op, text, column
+                )
+            }),
+            other => Err(format!(
+                "Expected a number but found {} at column {} of filter",
+                describe(other),
+                column
+            )),
+        };
+        let regex = || {
+            let pattern = text()?;
+            Regex::new(&pattern).map_err(|e| {
+                format!(
+                    "Invalid regular expression at column {} of filter: {}",
+                    column, e
+                )
+            })
+        };
+
+        let comparison = match op {
+            "==" => Comparison::Equal(text()?),
+            "!=" => Comparison::NotEqual(text()?),
+            "~" => Comparison::Matches(regex()?),
+            "!~" => Comparison::NotMatches(regex()?),
+            "<" => Comparison::Less(number()?),
+            "<=" => Comparison::LessEqual(number()?),
+            ">" => Comparison::Greater(number()?),
+            ">=" => Comparison::GreaterEqual(number()?),
+            other => {
+                return Err(format!(
+                    "Expected a comparison after '{}' but found '{}' at column {} of filter",
+                    name,
+                    other,
+                    op_offset + 1
+                ));
+            }
+        };
+        Ok(Expr::Compare(*field, comparison))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(modality: &str, description: &str, echo_time: &str) -> DeepDicomCandidate {
+        DeepDicomCandidate {
+            modality: modality.to_string(),
+            series_description: description.to_string(),
+            echo_time: echo_time.to_string(),
+            image_type: "ORIGINAL\\PRIMARY".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn keeps(filter: &str, candidate: &DeepDicomCandidate) -> bool {
+        Filter::parse(filter).unwrap().matches(candidate)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let ct = candidate("CT", "Head", "N/A");
+        assert!(keeps(
+            r#"Modality == "CT" || Modality == "MR" && TE > 50"#,
+            &ct
+        ));
+        assert!(!keeps(
+            r#"(Modality == "CT" || Modality == "MR") && TE > 50"#,
+            &ct
+        ));
+        assert!(keeps(r#"!Modality == "MR" && !(TE < 10)"#, &ct));
+    }
+
+    #[test]
+    fn regex_comparisons() {
+        let t1 = candidate("MR", "T1_MPRAGE sag", "2.3");
+        assert!(keeps(r#"SeriesDescription ~ "(?i)mprage""#, &t1));
+        assert!(!keeps(r#"SeriesDescription ~ "^sag""#, &t1));
+        assert!(keeps(r#"ImageType !~ "DERIVED""#, &t1));
+        assert!(Filter::parse(r#"SeriesDescription ~ "(""#).is_err());
+    }
+
+    #[test]
+    fn numeric_comparisons() {
+        let mr = candidate("MR", "T2", "90.0");
+        assert!(keeps("TE == 90", &mr));
+        assert!(keeps(r#"EchoTime == "90""#, &mr));
+        assert!(keeps("TE >= 90 && TE < 100", &mr));
+        assert!(!keeps("TE > 90", &mr));
+        // Missing values are never in range and compare as "N/A"
+        let missing = candidate("MR", "T2", "N/A");
+        assert!(!keeps("TE < 100", &missing));
+        assert!(!keeps("TE >= 100", &missing));
+        assert!(keeps(r#"TE == "N/A""#, &missing));
+        assert!(Filter::parse(r#"TE < "short""#).is_err());
+    }
+
+    #[test]
+    fn syntax_errors_name_the_column() {
+        let err = Filter::parse(r#"Modality == "MR" && Colour == "red""#)
+            .err()
+            .unwrap();
+        assert!(
+            err.contains("Unknown field 'Colour' at column 21"),
+            "{}",
+            err
+        );
+        assert!(Filter::parse(r#"Modality == "MR" )"#).is_err());
+        assert!(Filter::parse(r#"Modality "MR""#).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+// --- Fuzzing entry points ---
+//
+// Only built with `--cfg fuzzing`, which cargo-fuzz sets for the targets in fuzz/, so the
+// extraction paths that are private to the crate can be fuzzed. Each runs one path on a ZIP
+// archive, writing below a scratch directory that is emptied afterwards.
+
+use std::path::PathBuf;
+
+use crate::{deep_scan_dicom_candidates_parallel, extract_and_organize_dicoms};
+
+/// Scratch directory for one target, unique to the fuzzing process
+fn scratch(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "dicom_scanner_fuzz_{}_{}",
+        name,
+        std::process::id()
+    ))
+}
+
+/// Deep scan, then organize into the default Study/Series layout.
+pub fn organize(data: &[u8]) {
+    let Ok(scan) = deep_scan_dicom_candidates_parallel(data) else {
+        return;
+    };
+    let output = scratch("organize");
+    let _ = extract_and_organize_dicoms(data, &scan.candidates, &output, None, None);
+    let _ = std::fs::remove_dir_all(&output);
+}
+
+/// Write the XProtocol of every Siemens series.
+pub fn xprot(data: &[u8]) {
+    let output = scratch("xprot");
+    let _ = crate::extract_xprotocol_from_zip(data, &output, None);
+    let _ = std::fs::remove_dir_all(&output);
+}
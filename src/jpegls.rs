@@ -0,0 +1,589 @@
+// --- JPEG-LS lossless encoder ---
+//
+// A baseline ITU-T T.87 encoder (NEAR = 0, default thresholds, no interleaving) used when
+// transcoding extracted files to JPEG-LS Lossless. Each component is written as its own scan,
+// so a frame of an RGB image produces three scans in one codestream.
+
+/// Default context reset interval (T.87 C.2.4.1.1)
+const RESET: i32 = 64;
+
+/// Run-length order table (T.87 A.7.1.1)
+const J: [u32; 32] = [
+    0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 9, 10, 11, 12, 13,
+    14, 15,
+];
+
+/// Encode one frame as a JPEG-LS codestream (SOI ... EOI).
+///
+/// `components` holds one plane per sample, each `width * height` values below 2^`precision`.
+pub fn encode(
+    components: &[Vec<u16>],
+    width: usize,
+    height: usize,
+    precision: u8,
+) -> Result<Vec<u8>, String> {
+    if !(2..=16).contains(&precision) {
+        return Err(format!(
+            "JPEG-LS needs 2 to 16 bits per sample, not {}",
+            precision
+        ));
+    }
+    if width == 0 || height == 0 || width > 0xFFFF || height > 0xFFFF {
+        return Err(format!(
+            "JPEG-LS cannot encode a {}x{} image",
+            width, height
+        ));
+    }
+
+    let mut out = vec![0xFF, 0xD8];
+
+    // SOF55: frame header
+    out.extend_from_slice(&[0xFF, 0xF7]);
+    out.extend_from_slice(&(8 + 3 * components.len() as u16).to_be_bytes());
+    out.push(precision);
+    out.extend_from_slice(&(height as u16).to_be_bytes());
+    out.extend_from_slice(&(width as u16).to_be_bytes());
+    out.push(components.len() as u8);
+    for id in 1..=components.len() as u8 {
+        out.extend_from_slice(&[id, 0x11, 0]);
+    }
+
+    for (id, plane) in (1..).zip(components) {
+        // SOS: one component, no mapping table, NEAR = 0, ILV = 0, no point transform
+        out.extend_from_slice(&[0xFF, 0xDA, 0, 8, 1, id, 0, 0, 0, 0]);
+        let mut scan = ScanEncoder::new(precision);
+        scan.encode_plane(plane, width, height);
+        out.extend(scan.finish());
+    }
+
+    out.extend_from_slice(&[0xFF, 0xD9]);
+    Ok(out)
+}
+
+/// Bit writer with the JPEG-LS marker stuffing: a zero bit follows every 0xFF byte.
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    filled: u8,
+    capacity: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            current: 0,
+            filled: 0,
+            capacity: 8,
+        }
+    }
+
+    fn bit(&mut self, bit: bool) {
+        self.current = (self.current << 1) | bit as u8;
+        self.filled += 1;
+        if self.filled == self.capacity {
+            self.bytes.push(self.current);
+            self.capacity = if self.current == 0xFF { 7 } else { 8 };
+            self.current = 0;
+            self.filled = 0;
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        for shift in (0..count).rev() {
+            self.bit((value >> shift) & 1 == 1);
+        }
+    }
+
+    fn zeros(&mut self, count: u32) {
+        for _ in 0..count {
+            self.bit(false);
+        }
+    }
+
+    /// Pad the last byte with zeros; a trailing 0xFF gets a stuffed byte so it cannot be read
+    /// as the start of the next marker.
+    fn finish(mut self) -> Vec<u8> {
+        while self.filled != 0 {
+            self.bit(false);
+        }
+        if self.bytes.last() == Some(&0xFF) {
+            self.bytes.push(0);
+        }
+        self.bytes
+    }
+}
+
+/// Coding state of one scan
+struct ScanEncoder {
+    writer: BitWriter,
+    max_value: i32,
+    range: i32,
+    qbpp: u32,
+    limit: u32,
+    thresholds: (i32, i32, i32),
+    // 365 regular contexts followed by the two run interruption contexts
+    a: [i32; 367],
+    b: [i32; 367],
+    c: [i32; 367],
+    n: [i32; 367],
+    nn: [i32; 367],
+    run_index: usize,
+}
+
+impl ScanEncoder {
+    fn new(precision: u8) -> Self {
+        let max_value = (1i32 << precision) - 1;
+        let range = max_value + 1;
+        let qbpp = precision as u32;
+        let bpp = qbpp.max(2);
+        let initial_a = ((range + 32) / 64).max(2);
+        ScanEncoder {
+            writer: BitWriter::new(),
+            max_value,
+            range,
+            qbpp,
+            limit: 2 * (bpp + bpp.max(8)),
+            thresholds: default_thresholds(max_value),
+            a: [initial_a; 367],
+            b: [0; 367],
+            c: [0; 367],
+            n: [1; 367],
+            nn: [0; 367],
+            run_index: 0,
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.writer.finish()
+    }
+
+    fn encode_plane(&mut self, plane: &[u16], width: usize, height: usize) {
+        // Lines padded with one sample on each side: index 0 is x = -1, index width + 1 is x = width
+        let mut previous = vec![0i32; width + 2];
+        let mut current = vec![0i32; width + 2];
+        for y in 0..height {
+            let line = &plane[y * width..(y + 1) * width];
+            previous[width + 1] = previous[width];
+            current[0] = previous[1];
+
+            let mut x = 0;
+            while x < width {
+                let i = x + 1;
+                let (ra, rb, rc, rd) = (
+                    current[i - 1],
+                    previous[i],
+                    previous[i - 1],
+                    previous[i + 1],
+                );
+                let sample = line[x] as i32;
+                if rd == rb && rb == rc && rc == ra {
+                    x = self.encode_run(line, x, ra, &previous, &mut current);
+                } else {
+                    self.encode_regular(sample, ra, rb, rc, rd);
+                    current[i] = sample;
+                    x += 1;
+                }
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+    }
+
+    fn quantize(&self, d: i32) -> i32 {
+        let (t1, t2, t3) = self.thresholds;
+        match d {
+            d if d <= -t3 => -4,
+            d if d <= -t2 => -3,
+            d if d <= -t1 => -2,
+            d if d < 0 => -1,
+            0 => 0,
+            d if d < t1 => 1,
+            d if d < t2 => 2,
+            d if d < t3 => 3,
+            _ => 4,
+        }
+    }
+
+    fn reduce(&self, mut error: i32) -> i32 {
+        if error < 0 {
+            error += self.range;
+        }
+        if error >= (self.range + 1) / 2 {
+            error -= self.range;
+        }
+        error
+    }
+
+    /// Limited-length Golomb code (T.87 A.5.3)
+    fn golomb(&mut self, value: i32, k: u32, limit: u32) {
+        let value = value as u32;
+        let high = value >> k;
+        if high < limit - self.qbpp - 1 {
+            self.writer.zeros(high);
+            self.writer.bit(true);
+            self.writer.bits(value & ((1 << k) - 1), k);
+        } else {
+            self.writer.zeros(limit - self.qbpp - 1);
+            self.writer.bit(true);
+            self.writer.bits(value - 1, self.qbpp);
+        }
+    }
+
+    fn encode_regular(&mut self, sample: i32, ra: i32, rb: i32, rc: i32, rd: i32) {
+        let (mut q1, mut q2, mut q3) = (
+            self.quantize(rd - rb),
+            self.quantize(rb - rc),
+            self.quantize(rc - ra),
+        );
+        let negative = q1 < 0 || (q1 == 0 && (q2 < 0 || (q2 == 0 && q3 < 0)));
+        if negative {
+            (q1, q2, q3) = (-q1, -q2, -q3);
+        }
+        let q = ((q1 * 9 + q2) * 9 + q3) as usize;
+
+        // Median edge detector plus the context's bias correction
+        let mut prediction = if rc >= ra.max(rb) {
+            ra.min(rb)
+        } else if rc <= ra.min(rb) {
+            ra.max(rb)
+        } else {
+            ra + rb - rc
+        };
+        prediction += if negative { -self.c[q] } else { self.c[q] };
+        prediction = prediction.clamp(0, self.max_value);
+
+        let mut error = sample - prediction;
+        if negative {
+            error = -error;
+        }
+        let error = self.reduce(error);
+
+        let mut k = 0;
+        while (self.n[q] << k) < self.a[q] {
+            k += 1;
+        }
+        let mapped = if k == 0 && 2 * self.b[q] <= -self.n[q] {
+            if error >= 0 {
+                2 * error + 1
+            } else {
+                -2 * (error + 1)
+            }
+        } else if error >= 0 {
+            2 * error
+        } else {
+            -2 * error - 1
+        };
+        self.golomb(mapped, k, self.limit);
+
+        // Context update and bias cancellation (T.87 A.6)
+        self.b[q] += error;
+        self.a[q] += error.abs();
+        if self.n[q] == RESET {
+            self.a[q] >>= 1;
+            self.b[q] >>= 1;
+            self.n[q] >>= 1;
+        }
+        self.n[q] += 1;
+        if self.b[q] <= -self.n[q] {
+            self.b[q] += self.n[q];
+            if self.c[q] > -128 {
+                self.c[q] -= 1;
+            }
+            if self.b[q] <= -self.n[q] {
+                self.b[q] = -self.n[q] + 1;
+            }
+        } else if self.b[q] > 0 {
+            self.b[q] -= self.n[q];
+            if self.c[q] < 127 {
+                self.c[q] += 1;
+            }
+            if self.b[q] > 0 {
+                self.b[q] = 0;
+            }
+        }
+    }
+
+    /// Encode a run of samples equal to `ra` starting at `x`, plus the interrupting sample.
+    /// Returns the next x to code.
+    fn encode_run(
+        &mut self,
+        line: &[u16],
+        start: usize,
+        ra: i32,
+        previous: &[i32],
+        current: &mut [i32],
+    ) -> usize {
+        let width = line.len();
+        let mut x = start;
+        while x < width && line[x] as i32 == ra {
+            current[x + 1] = ra;
+            x += 1;
+        }
+
+        let mut count = x - start;
+        while count >= 1 << J[self.run_index] {
+            self.writer.bit(true);
+            count -= 1 << J[self.run_index];
+            if self.run_index < 31 {
+                self.run_index += 1;
+            }
+        }
+        if x == width {
+            if count > 0 {
+                self.writer.bit(true);
+            }
+            return x;
+        }
+        self.writer.bit(false);
+        self.writer.bits(count as u32, J[self.run_index]);
+
+        // Run interruption sample (T.87 A.7.2)
+        let i = x + 1;
+        let (ra, rb) = (current[i - 1], previous[i]);
+        let sample = line[x] as i32;
+        let same = ra == rb;
+        let mut error = sample - if same { ra } else { rb };
+        if !same && ra > rb {
+            error = -error;
+        }
+        let error = self.reduce(error);
+
+        let q = if same { 366 } else { 365 };
+        let ri_type = same as i32;
+        let temp = if same {
+            self.a[q] + (self.n[q] >> 1)
+        } else {
+            self.a[q]
+        };
+        let mut k = 0;
+        while (self.n[q] << k) < temp {
+            k += 1;
+        }
+        let map = (k == 0 && error > 0 && 2 * self.nn[q] < self.n[q])
+            || (error < 0 && 2 * self.nn[q] >= self.n[q])
+            || (error < 0 && k != 0);
+        let mapped = 2 * error.abs() - ri_type - map as i32;
+        self.golomb(mapped, k, self.limit - J[self.run_index] - 1);
+
+        if error < 0 {
+            self.nn[q] += 1;
+        }
+        self.a[q] += (mapped + 1 - ri_type) >> 1;
+        if self.n[q] == RESET {
+            self.a[q] >>= 1;
+            self.n[q] >>= 1;
+            self.nn[q] >>= 1;
+        }
+        self.n[q] += 1;
+        if self.run_index > 0 {
+            self.run_index -= 1;
+        }
+
+        current[i] = sample;
+        x + 1
+    }
+}
+
+/// Default gradient thresholds T1, T2, T3 for NEAR = 0 (T.87 C.2.4.1.1.1)
+fn default_thresholds(max_value: i32) -> (i32, i32, i32) {
+    if max_value >= 128 {
+        let factor = (max_value.min(4095) + 128) / 256;
+        let t1 = (factor + 2).clamp(1, max_value);
+        let t2 = (factor * 4 + 3).clamp(t1, max_value);
+        let t3 = (factor * 17 + 4).clamp(t2, max_value);
+        (t1, t2, t3)
+    } else {
+        let factor = 256 / (max_value + 1);
+        let t1 = (3 / factor).max(2).clamp(1, max_value);
+        let t2 = (7 / factor).max(3).clamp(t1, max_value);
+        let t3 = (21 / factor).max(4).clamp(t2, max_value);
+        (t1, t2, t3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode;
+    #[cfg(feature = "charls")]
+    use charls::{CharLS, FrameInfo};
+
+    /// Codestreams written by CharLS 2.4 (ILV none, planar input) for the `golden` planes below.
+    /// For 16 bits CharLS also writes an LSE segment restating the default parameters; it is
+    /// left out here, as this encoder does not write it.
+    const GOLDEN: &[(usize, usize, u8, usize, &str)] = &[
+        (
+            8,
+            4,
+            8,
+            1,
+            "ffd8fff7000b080004000801011100ffda0008010100000000000001fc8000003c22cf5ec416c830c300\
+             0000d200000a0000030b856300018000028099aa560000006e801dffd9",
+        ),
+        (
+            6,
+            3,
+            12,
+            1,
+            "ffd8fff7000b0c0003000601011100ffda0008010100000000000000001ffc80000000003fc334000000\
+             00669494b0204e9c00078000000001d45d36600000000aac5cffd9",
+        ),
+        (
+            5,
+            4,
+            16,
+            1,
+            "ffd8fff7000b100004000501011100ffda0008010100000000000000000001ff7e000000000000ff7be0\
+             6800001419cca10020740e00004410000000473801032066c8fae60082a07000000000000fe078000000\
+             00000ee488ffd9",
+        ),
+        (
+            4,
+            3,
+            8,
+            3,
+            "ffd8fff70011080003000403011100021100031100ffda0008010100000000000001fc000001ef000001\
+             796c83000000fb800000d84600000714ffda0008010200000000000001fc000001ef000001fc19a0c000\
+             a00000361180003c000003f2ffda0008010300000000000001fc000001ef000001762ec1800000670000\
+             007863000000a540ffd9",
+        ),
+    ];
+
+    /// A plane with a flat band (run mode), a gradient and noise (regular mode)
+    fn plane(width: usize, height: usize, precision: u8, seed: u32) -> Vec<u16> {
+        let max = (1_u32 << precision) - 1;
+        let mut state = seed;
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let value = if x < width / 3 {
+                    max / 2
+                } else if x < 2 * width / 3 {
+                    ((x + y) as u32 * 7) % (max + 1)
+                } else {
+                    (state >> 8) % (max + 1)
+                };
+                value as u16
+            })
+            .collect()
+    }
+
+    /// The planes of a GOLDEN entry: one `plane` per component, seeded from the precision
+    fn golden(width: usize, height: usize, precision: u8, components: usize) -> Vec<Vec<u16>> {
+        (1..=components as u32)
+            .map(|seed| plane(width, height, precision, seed + precision as u32))
+            .collect()
+    }
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn golden_codestreams() {
+        for &(width, height, precision, components, codestream) in GOLDEN {
+            let planes = golden(width, height, precision, components);
+            let encoded = encode(&planes, width, height, precision).unwrap();
+            assert_eq!(
+                encoded,
+                hex(codestream),
+                "{}x{} {}-bit, {} component(s)",
+                width,
+                height,
+                precision,
+                components
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert!(encode(&[vec![0; 4]], 2, 2, 1).is_err());
+        assert!(encode(&[vec![0; 4]], 2, 2, 17).is_err());
+        assert!(encode(&[Vec::new()], 0, 2, 8).is_err());
+    }
+
+    /// The golden codestreams are what CharLS itself writes for the same samples.
+    #[cfg(feature = "charls")]
+    #[test]
+    fn golden_codestreams_match_charls() {
+        for &(width, height, precision, components, codestream) in GOLDEN {
+            let source: Vec<u8> = golden(width, height, precision, components)
+                .iter()
+                .flatten()
+                .flat_map(|&v| {
+                    if precision <= 8 {
+                        vec![v as u8]
+                    } else {
+                        v.to_le_bytes().to_vec()
+                    }
+                })
+                .collect();
+            let frame = FrameInfo {
+                width: width as u32,
+                height: height as u32,
+                bits_per_sample: precision as i32,
+                component_count: components as i32,
+            };
+            let mut written = CharLS::default().encode(frame, 0, &source).unwrap();
+            // Drop CharLS's LSE segment (FF F8, 15 bytes after the frame header)
+            if let Some(at) = written.windows(2).position(|w| w == [0xFF, 0xF8]) {
+                written.drain(at..at + 15);
+            }
+            assert_eq!(written, hex(codestream));
+        }
+    }
+
+    /// Encode, decode with CharLS and compare with the input samples.
+    #[cfg(feature = "charls")]
+    fn round_trip(planes: &[Vec<u16>], width: usize, height: usize, precision: u8) {
+        let encoded = encode(planes, width, height, precision).unwrap();
+        let decoded = CharLS::default().decode(&encoded).unwrap();
+        let expected: Vec<u8> = planes
+            .iter()
+            .flatten()
+            .flat_map(|&v| {
+                if precision <= 8 {
+                    vec![v as u8]
+                } else {
+                    v.to_le_bytes().to_vec()
+                }
+            })
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[cfg(feature = "charls")]
+    #[test]
+    fn eight_bit() {
+        round_trip(&[plane(37, 23, 8, 1)], 37, 23, 8);
+    }
+
+    #[cfg(feature = "charls")]
+    #[test]
+    fn eight_bit_rgb() {
+        let planes: Vec<_> = (1..=3).map(|seed| plane(20, 9, 8, seed)).collect();
+        round_trip(&planes, 20, 9, 8);
+    }
+
+    #[cfg(feature = "charls")]
+    #[test]
+    fn twelve_bit() {
+        round_trip(&[plane(64, 17, 12, 2)], 64, 17, 12);
+    }
+
+    #[cfg(feature = "charls")]
+    #[test]
+    fn sixteen_bit() {
+        round_trip(&[plane(33, 31, 16, 3)], 33, 31, 16);
+    }
+
+    #[cfg(feature = "charls")]
+    #[test]
+    fn single_column() {
+        round_trip(&[plane(1, 50, 8, 4)], 1, 50, 8);
+    }
+}
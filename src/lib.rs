@@ -0,0 +1,4261 @@
+use std::{
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use dicom::core::VR;
+use dicom::core::value::Value;
+use dicom::object::mem::InMemDicomObject;
+use dicom::{
+    core::Tag,
+    dictionary_std::tags::{self, PATIENT_ID},
+};
+
+use dicom::object::StandardDataDictionary;
+use rayon::prelude::*;
+use zip::ZipArchive;
+
+use clap::{Parser, Subcommand};
+use tracing::{debug, info, trace, warn};
+
+use dicom::object::{FileDicomObject, OpenFileOptions};
+
+mod acquisition;
+mod batch;
+mod bids;
+mod dicomdir;
+mod dicomweb;
+pub mod dimse;
+mod entry_error;
+mod fetch;
+mod filter;
+#[cfg(fuzzing)]
+pub mod fuzzing;
+mod jpegls;
+mod listen;
+mod logging;
+mod nifti;
+mod parquet_export;
+mod preview;
+mod scan;
+mod scan_index;
+mod serve;
+mod sort;
+mod sqlite_export;
+mod template;
+#[cfg(test)]
+mod test_support;
+pub mod transcode;
+mod watch;
+
+use entry_error::{CrcReader, EntryError};
+use scan_index::{EntryCache, ScanIndex};
+use template::PathTemplate;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// More diagnostics on stderr: -v adds per-file header details, -vv everything
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Fewer diagnostics on stderr: -q keeps warnings and errors, -qq errors only
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "verbose")]
+    quiet: u8,
+
+    /// Format of the diagnostics on stderr
+    #[arg(long, value_enum, default_value_t = logging::LogFormat::Text, global = true)]
+    log_format: logging::LogFormat,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Archives to process; shared by the subcommands that scan archives
+#[derive(clap::Args, Debug)]
+struct InputArgs {
+    /// ZIP archives; several archives or a glob ("exports/*.zip") are processed as a batch
+    #[arg(required_unless_present = "file_list")]
+    files: Vec<PathBuf>,
+
+    /// Text file listing one archive path or glob per line (batch mode)
+    #[arg(long)]
+    file_list: Option<PathBuf>,
+
+    /// Number of archives processed concurrently in batch mode
+    #[arg(long, default_value_t = 4)]
+    jobs: usize,
+
+    /// SQLite scan index; unchanged archives and entries are loaded from it instead of parsed
+    #[arg(long)]
+    index: Option<PathBuf>,
+
+    /// Only use the files matching this expression, e.g.
+    /// 'Modality == "MR" && ImageType !~ "DERIVED" && SeriesDescription ~ "(?i)t1"'
+    #[arg(long)]
+    filter: Option<String>,
+}
+
+impl InputArgs {
+    /// Expand the archive list; the second value tells whether this is a batch.
+    fn resolve(&self) -> Result<(Vec<PathBuf>, bool), Box<dyn std::error::Error>> {
+        let inputs = batch::resolve_inputs(&self.files, self.file_list.as_deref())?;
+        if inputs.is_empty() {
+            return Err("No input archives found".into());
+        }
+        let batch_mode = inputs.len() != 1 || self.file_list.is_some();
+        Ok((inputs, batch_mode))
+    }
+}
+
+/// How extracted files are laid out and post-processed; shared by `organize` and `watch`
+#[derive(clap::Args, Debug)]
+struct OrganizeArgs {
+    /// Path template for organized files, e.g.
+    /// "{PatientID}/{StudyDate}_{StudyDescription}/{SeriesNumber:04}_{SeriesDescription}/{InstanceNumber:05}.dcm"
+    #[arg(long, requires = "output")]
+    template: Option<String>,
+
+    /// Maximum length of a single path component rendered from --template
+    #[arg(long, default_value_t = template::DEFAULT_MAX_COMPONENT_LEN, requires = "template")]
+    max_component_len: usize,
+
+    /// Rewrite the extracted files in this transfer syntax (pixel data is decoded and re-encoded
+    /// losslessly; all other attributes are kept)
+    #[arg(long, value_enum, requires = "output", conflicts_with = "dicom_media")]
+    transcode: Option<transcode::Target>,
+
+    /// Write the output as a DICOM media file-set (8-character names plus a DICOMDIR)
+    #[arg(long, requires = "output", conflicts_with = "template")]
+    dicom_media: bool,
+
+    /// Write PNG thumbnails of each series and a contact sheet per study to <output>/previews
+    #[arg(long, requires = "output", conflicts_with = "dicom_media")]
+    preview: bool,
+
+    /// Number of evenly spaced slices to preview per series
+    #[arg(long, default_value_t = 1, requires = "preview")]
+    preview_slices: usize,
+
+    /// Convert the extracted series to NIfTI-1 (.nii.gz with a JSON sidecar) in this directory
+    #[arg(long, requires = "output")]
+    nifti: Option<PathBuf>,
+
+    /// Convert the extracted series into a BIDS dataset (sub-*/ses-*/anat|func|dwi|fmap)
+    #[arg(long, requires = "output")]
+    bids: Option<PathBuf>,
+
+    /// JSON file with the series-to-BIDS rules (replaces the built-in rules)
+    #[arg(long, requires = "bids")]
+    bids_rules: Option<PathBuf>,
+
+    /// Tab-separated PatientID and subject label per line (default: hashed PatientIDs)
+    #[arg(long, requires = "bids")]
+    bids_subjects: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the DICOM files of archives (tab-separated on stdout; statistics on stderr)
+    Scan {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Print the MRN (PatientID) of each archive
+    Mrn {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Extract and organize the DICOM files of archives by study and series
+    Organize {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Output directory; in batch mode each archive gets a sub-folder
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[command(flatten)]
+        organize: OrganizeArgs,
+
+        /// Directory for series_catalog.csv and failures.csv in batch mode (default: --output)
+        #[arg(long)]
+        report_dir: Option<PathBuf>,
+    },
+    /// Extract the XProtocol of Siemens series
+    Xprot {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Output directory for .xprot files; in batch mode each archive gets a sub-folder
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Analyze derivation relationships between the series of an archive or directory
+    Derivations {
+        /// ZIP archive, directory or DICOM file
+        input: PathBuf,
+    },
+    /// List an archive from its DICOMDIR (ZIP, directory or DICOMDIR file) without deep-parsing
+    Dicomdir {
+        /// ZIP archive, directory or DICOMDIR file
+        input: PathBuf,
+    },
+    /// Export the scanned metadata to SQLite, Parquet or CSV
+    Export {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Add the patient/study/series/instance hierarchy, derivations and protocols to a SQLite
+        /// database (created if missing; archives accumulate across runs)
+        #[arg(long, required_unless_present_any = ["parquet", "report_dir"])]
+        sqlite: Option<PathBuf>,
+
+        /// Directory for instances.parquet and series.parquet (typed columns)
+        #[arg(long)]
+        parquet: Option<PathBuf>,
+
+        /// Directory for series_catalog.csv and failures.csv
+        #[arg(long)]
+        report_dir: Option<PathBuf>,
+    },
+    /// Sort ZIP archives into folders by MRN
+    Sort {
+        /// Directory to recursively search for ZIP files
+        input: PathBuf,
+
+        /// Directory where sorted ZIP files are placed
+        output: PathBuf,
+
+        /// Destination path template, e.g. "{PatientID}/{StudyDate}_{PatientID}.zip"
+        #[arg(long, default_value = sort::DEFAULT_SORT_LAYOUT)]
+        layout: String,
+
+        /// Move archives instead of copying them
+        #[arg(long = "move")]
+        move_files: bool,
+    },
+    /// Query a scan index (--index) without opening the archives
+    Index {
+        /// Path to the index database
+        db: PathBuf,
+
+        /// List indexed archives instead of series
+        #[arg(long)]
+        archives: bool,
+
+        /// Only series of this PatientID
+        #[arg(long)]
+        patient_id: Option<String>,
+
+        /// Only series of this StudyInstanceUID
+        #[arg(long)]
+        study_uid: Option<String>,
+
+        /// Only series of this Modality
+        #[arg(long)]
+        modality: Option<String>,
+
+        /// Only archives whose path contains this text
+        #[arg(long)]
+        archive: Option<String>,
+    },
+    /// Convert a directory of extracted DICOM files (e.g. an --output tree) to NIfTI-1
+    Nifti {
+        /// Directory to recursively search for DICOM files
+        input: PathBuf,
+
+        /// Directory where .nii.gz files and JSON sidecars are written
+        output: PathBuf,
+
+        /// Write the output directory as a BIDS dataset
+        #[arg(long)]
+        bids: bool,
+
+        /// JSON file with the series-to-BIDS rules (replaces the built-in rules)
+        #[arg(long, requires = "bids")]
+        rules: Option<PathBuf>,
+
+        /// Tab-separated PatientID and subject label per line (default: hashed PatientIDs)
+        #[arg(long, requires = "bids")]
+        subjects: Option<PathBuf>,
+    },
+    /// Receive instances over DICOM (C-STORE) and organize them as they arrive
+    Listen {
+        /// Directory where received instances are organized
+        output: PathBuf,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+
+        /// TCP port to listen on
+        #[arg(long, default_value_t = 11112)]
+        port: u16,
+
+        /// AE title of this receiver; calls addressed to another title are rejected
+        #[arg(long, default_value = "DICOM_SCANNER")]
+        ae_title: String,
+
+        /// Calling AE title allowed to send (repeatable); other callers are rejected
+        #[arg(long = "allow", required_unless_present = "allow_any")]
+        allowed: Vec<String>,
+
+        /// Accept associations from any calling AE title
+        #[arg(long, conflicts_with = "allowed")]
+        allow_any: bool,
+
+        /// Seconds without new instances after which a study is complete and the series
+        /// metadata is exported
+        #[arg(long, default_value_t = 30)]
+        idle_timeout: u64,
+
+        /// Path template for organized files (see --template)
+        #[arg(long)]
+        template: Option<String>,
+
+        /// Maximum length of a single path component rendered from --template
+        #[arg(long, default_value_t = template::DEFAULT_MAX_COMPONENT_LEN, requires = "template")]
+        max_component_len: usize,
+    },
+    /// Query a PACS (C-FIND) and retrieve the matching studies or series (C-GET/C-MOVE)
+    Fetch {
+        /// Directory where retrieved instances are organized
+        output: PathBuf,
+
+        /// PACS address, host:port
+        #[arg(long)]
+        host: String,
+
+        /// AE title of the PACS
+        #[arg(long)]
+        called_ae_title: String,
+
+        /// AE title of this client (and C-MOVE destination)
+        #[arg(long, default_value = "DICOM_SCANNER")]
+        ae_title: String,
+
+        /// Match studies of this PatientID (MRN)
+        #[arg(long, alias = "mrn")]
+        patient_id: Option<String>,
+
+        /// Match StudyDate: YYYYMMDD or a range YYYYMMDD-YYYYMMDD (either end may be omitted)
+        #[arg(long)]
+        study_date: Option<String>,
+
+        /// Match AccessionNumber
+        #[arg(long)]
+        accession: Option<String>,
+
+        /// Retrieve whole studies or list and retrieve individual series
+        #[arg(long, value_enum, default_value = "study")]
+        level: fetch::Level,
+
+        /// Retrieve with C-GET or C-MOVE
+        #[arg(long, value_enum, default_value = "get")]
+        method: fetch::Method,
+
+        /// Port receiving C-MOVE sub-operations
+        #[arg(long, default_value_t = 11112)]
+        port: u16,
+
+        /// Path template for organized files (see --template)
+        #[arg(long)]
+        template: Option<String>,
+
+        /// Maximum length of a single path component rendered from --template
+        #[arg(long, default_value_t = template::DEFAULT_MAX_COMPONENT_LEN, requires = "template")]
+        max_component_len: usize,
+
+        /// Only list the C-FIND matches
+        #[arg(long)]
+        query_only: bool,
+    },
+    /// Query a DICOMweb service (QIDO-RS) and retrieve the matching series (WADO-RS)
+    DicomwebFetch {
+        /// Directory where retrieved instances are organized
+        output: PathBuf,
+
+        /// DICOMweb service root, e.g. https://pacs.example.org/dicom-web
+        #[arg(long)]
+        url: String,
+
+        /// Bearer token sent with every request
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Match studies of this PatientID (MRN)
+        #[arg(long, alias = "mrn")]
+        patient_id: Option<String>,
+
+        /// Match StudyDate: YYYYMMDD or a range YYYYMMDD-YYYYMMDD (either end may be omitted)
+        #[arg(long)]
+        study_date: Option<String>,
+
+        /// Match AccessionNumber
+        #[arg(long)]
+        accession: Option<String>,
+
+        /// List whole studies or individual series
+        #[arg(long, value_enum, default_value = "study")]
+        level: fetch::Level,
+
+        /// Path template for organized files (see --template)
+        #[arg(long)]
+        template: Option<String>,
+
+        /// Maximum length of a single path component rendered from --template
+        #[arg(long, default_value_t = template::DEFAULT_MAX_COMPONENT_LEN, requires = "template")]
+        max_component_len: usize,
+
+        /// Only list the QIDO-RS matches
+        #[arg(long)]
+        query_only: bool,
+    },
+    /// Organize, export and extract the XProtocol of ZIPs, folders and files dropped into
+    /// watched folders
+    Watch {
+        /// Folders to watch
+        #[arg(required = true)]
+        dirs: Vec<PathBuf>,
+
+        /// Output directory; each input gets a sub-folder
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        organize: OrganizeArgs,
+
+        /// Output directory for .xprot files; each input gets a sub-folder
+        #[arg(long)]
+        xprot: Option<PathBuf>,
+
+        /// Add every input to this SQLite hierarchy database
+        #[arg(long)]
+        sqlite: Option<PathBuf>,
+
+        /// SQLite scan index; unchanged archives and entries are loaded from it instead of parsed
+        #[arg(long)]
+        index: Option<PathBuf>,
+
+        /// Only use the files matching this expression (see `organize --filter`)
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// Directory for series_catalog.csv and failures.csv (default: --output or .)
+        #[arg(long)]
+        report_dir: Option<PathBuf>,
+
+        /// Folder for processed inputs (default: <watched folder>/done)
+        #[arg(long)]
+        done: Option<PathBuf>,
+
+        /// Folder for inputs that failed (default: <watched folder>/failed)
+        #[arg(long)]
+        failed: Option<PathBuf>,
+
+        /// Processing journal (default: <report dir>/watch_journal.jsonl)
+        #[arg(long)]
+        journal: Option<PathBuf>,
+
+        /// Seconds an input must stay unchanged before it is processed
+        #[arg(long, default_value_t = 10)]
+        settle: u64,
+
+        /// Seconds between scans of the watched folders
+        #[arg(long, default_value_t = 5)]
+        poll: u64,
+
+        /// Exit once everything in the folders has been processed (e.g. when run from cron)
+        #[arg(long)]
+        once: bool,
+    },
+    /// Serve a directory of DICOM files (e.g. an --output tree) over QIDO-RS and WADO-RS
+    DicomwebServe {
+        /// Directory to recursively search for DICOM files
+        input: PathBuf,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+
+        /// TCP port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+
+        /// Origin allowed to call the server from a browser (no cross-origin access by default)
+        #[arg(long)]
+        cors_origin: Option<String>,
+    },
+    /// Serve an HTTP JSON API for submitting archives and querying their scan results
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+
+        /// TCP port to listen on
+        #[arg(long, default_value_t = 8081)]
+        port: u16,
+
+        /// Only accept submitted paths inside this directory
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+
+        /// Number of archives scanned at the same time
+        #[arg(long, default_value_t = 2)]
+        workers: usize,
+
+        /// Largest accepted upload, in MB
+        #[arg(long, default_value_t = 2048)]
+        max_upload_mb: u64,
+
+        /// Origin allowed to call the API from a browser (no cross-origin access by default)
+        #[arg(long)]
+        cors_origin: Option<String>,
+
+        /// Minutes a finished job and its results are kept
+        #[arg(long, default_value_t = 60)]
+        keep_minutes: u64,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct DicomCandidate {
+    pub index: usize,
+    pub name: String,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct DeepDicomCandidate {
+    pub index: usize,
+    pub name: String,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub study_instance_uid: String,
+    pub series_instance_uid: String,
+    pub sop_instance_uid: String,
+    pub manufacturer: String,
+    pub modality: String,
+    pub patient_id: String,
+    pub patient_name: String,
+    pub study_date: String,
+    pub accession_number: String,
+    pub study_description: String,
+    pub series_description: String,
+    pub series_number: String,
+    pub protocol_name: String,
+    pub acquisition_type: String, // 2D or 3D
+    pub pixel_spacing: String,
+    pub slice_thickness: String,
+    pub acquisition_time: String,
+    pub rows: String,
+    pub columns: String,
+    pub repetition_time: String, // TR
+    pub echo_time: String,       // TE
+    pub inversion_time: String,  // TI
+    pub derivation_description: String,
+    pub referenced_series_uid: String, // For tracking derived series relationships
+    pub acquisition_duration: String,  // Protocol duration in seconds
+    // MR-specific technical parameters
+    pub flip_angle: String,
+    pub number_of_averages: String, // NEX
+    pub echo_train_length: String,
+    pub parallel_imaging_factor: String,
+    pub magnetic_field_strength: String,
+    // Image set information
+    pub spacing_between_slices: String,
+    pub image_type: String,
+    pub instance_number: String,
+    pub phase_encoding_direction: String, // BIDS i / i- / j / j-
+    pub effective_echo_spacing: String,   // seconds
+    pub total_readout_time: String,       // seconds
+    pub slice_timing: String,             // ms, one value per slice in this file
+    pub slice_position: String,           // mm along the slice normal
+    pub transfer_syntax: String,          // UID from the file meta group
+}
+
+#[derive(Debug)]
+struct DicomAcqMatrix {
+    values: [u32; 4],
+}
+
+impl DicomAcqMatrix {
+    /// Returns the two non-zero values, based on the valid DICOM acquisition matrix pattern.
+    pub fn extract_pair(&self) -> (u32, u32) {
+        let [a, b, c, d] = self.values;
+        if a == 0 && d == 0 {
+            (b, c)
+        } else if b == 0 && c == 0 {
+            (a, d)
+        } else {
+            unreachable!(
+                "Struct invariant violated: DicomAcqMatrix is only constructed when valid"
+            );
+        }
+    }
+}
+
+impl std::str::FromStr for DicomAcqMatrix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('\\').collect();
+
+        if parts.len() != 4 {
+            return Err(format!("Expected 4 values, got {}", parts.len()));
+        }
+
+        let mut values = [0u32; 4];
+        for (i, part) in parts.iter().enumerate() {
+            values[i] = part
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| format!("Failed to parse '{}' as u32: {}", part, e))?;
+        }
+
+        let [a, b, c, d] = values;
+        if !((a == 0 && d == 0) || (b == 0 && c == 0)) {
+            return Err(format!(
+                "Invalid acquisition matrix: must have (1st and 4th == 0) or (2nd and 3rd == 0), got: {:?}",
+                values
+            ));
+        }
+
+        Ok(DicomAcqMatrix { values })
+    }
+}
+
+// Calculate the acquistion resolution based on DICOM tags as best as we can
+pub fn calculate_acq_resolution(
+    acq_mtx: String,
+    dcm_rows: String,
+    dcm_cols: String,
+    pixel_spacing: String,
+) -> String {
+    // Handle cases where acquisition matrix is invalid
+    let acq_matrix = match DicomAcqMatrix::from_str(&acq_mtx) {
+        Ok(matrix) => matrix,
+        Err(_) => {
+            // If parsing fails, return N/A
+            return "N/A".to_string();
+        }
+    };
+    let (rows, cols) = acq_matrix.extract_pair();
+
+    let pixel_spacing = pixel_spacing
+        .split('\\')
+        .map(|s| s.parse::<f32>().unwrap_or(0.0))
+        .collect::<Vec<f32>>();
+    let [spacing_x, spacing_y, ..] = pixel_spacing[..] else {
+        return "N/A".to_string();
+    };
+
+    let dcm_rows = dcm_rows.parse::<f32>().unwrap_or(0.0);
+
+    let dcm_cols = dcm_cols.parse::<f32>().unwrap_or(0.0);
+
+    let fov_x = spacing_x * dcm_rows;
+    let fov_y = spacing_y * dcm_cols;
+
+    let resolution_x = fov_x / rows as f32;
+    let resolution_y: f32 = fov_y / cols as f32;
+
+    format!("{} x {} mm", resolution_x, resolution_y)
+}
+
+/*
+fn get_tag_string<D: DicomObject>(dcm: &D, tag: Tag) -> String {
+    dcm.element(tag)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "N/A".to_string())
+}
+
+fn get_tag_float<D: DicomObject>(dcm: &D, tag: Tag) -> f32 {
+    dcm.element(tag)
+        .ok()
+        .and_then(|e| e.value().to_float32().ok())
+        .unwrap_or(f32::NAN)
+}
+
+
+fn get_element_str(obj: &impl dicom::object::DicomObject, tag: Tag) -> Option<String> {
+    obj.element(tag)
+        .ok()?
+        .value()
+        .to_str()
+        .ok()
+        .map(|s| s.to_string())
+}
+*/
+
+pub fn scan_gems_parm_01(dcm_object: &InMemDicomObject<StandardDataDictionary>) {
+    // VR: LO
+    let gehc_private_creator_id = dcm_object
+        .element(Tag(0x0043, 0x0010))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _bitmap_of_prescan_options = dcm_object
+        .element(Tag(0x0043, 0x1001))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _gradient_offset_x = dcm_object
+        .element(Tag(0x0043, 0x1002))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _gradient_offset_y = dcm_object
+        .element(Tag(0x0043, 0x1003))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _gradient_offset_z = dcm_object
+        .element(Tag(0x0043, 0x1004))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS, no longer used in DV26
+    let _image_is_original = dcm_object
+        .element(Tag(0x0043, 0x1005))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _number_of_epi_shots = dcm_object
+        .element(Tag(0x0043, 0x1006))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _views_per_segment = dcm_object
+        .element(Tag(0x0043, 0x1007))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _respiratory_rate_bpm = dcm_object
+        .element(Tag(0x0043, 0x1008))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _respiratory_trigger_point = dcm_object
+        .element(Tag(0x0043, 0x1009))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _type_of_receiver_used = dcm_object
+        .element(Tag(0x0043, 0x100A))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS
+    let peak_dbdt = dcm_object
+        .element(Tag(0x0043, 0x100B))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS
+    let dbdt_limits_percent = dcm_object
+        .element(Tag(0x0043, 0x100C))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS
+    let psd_estimatated_limit = dcm_object
+        .element(Tag(0x0043, 0x100D))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS
+    let psd_estimated_limit_tps = dcm_object
+        .element(Tag(0x0043, 0x100E))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS, no longer used in DV26
+    let sar_avg_head = dcm_object
+        .element(Tag(0x0043, 0x100F))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: US, no longer used in DV26
+    let _window_value = dcm_object
+        .element(Tag(0x0043, 0x1010))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _ge_image_integrity = dcm_object
+        .element(Tag(0x0043, 0x101C))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS, no longer used in DV26
+    let _level_value = dcm_object
+        .element(Tag(0x0043, 0x101D))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB, no longer used in DV26
+    let _unique_image_identifier = dcm_object
+        .element(Tag(0x0043, 0x1028))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB
+    let _histogram_tables = dcm_object
+        .element(Tag(0x0043, 0x1029))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB
+    let _user_defined_data = dcm_object
+        .element(Tag(0x0043, 0x102A))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS[4], no longer used in DV26
+    let _private_scan_options = dcm_object
+        .element(Tag(0x0043, 0x102B))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _effective_echo_spacing = dcm_object
+        .element(Tag(0x0043, 0x102C))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SH
+    // (String slop field 1 in legacy GE MR images)
+    let _filter_mode = dcm_object
+        .element(Tag(0x0043, 0x102D))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SH
+    let _string_slop_field_2 = dcm_object
+        .element(Tag(0x0043, 0x102E))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS (image_type)
+    let _raw_data_type = dcm_object
+        .element(Tag(0x0043, 0x102F))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _vas_collapse_flag = dcm_object
+        .element(Tag(0x0043, 0x1030))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[2], not used in DV26
+    let _ra_coord_of_target_recon_center = dcm_object
+        .element(Tag(0x0043, 0x1031))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _vas_flags = dcm_object
+        .element(Tag(0x0043, 0x1032))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: FL
+    let _neg_scanspacing = dcm_object
+        .element(Tag(0x0043, 0x1033))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: IS
+    let _offset_frequency = dcm_object
+        .element(Tag(0x0043, 0x1034))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: UL
+    let _user_usage_tag = dcm_object
+        .element(Tag(0x0043, 0x1035))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: UL
+    let _user_fill_map_msw = dcm_object
+        .element(Tag(0x0043, 0x1036))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: UL
+    let _user_fill_map_lsw = dcm_object
+        .element(Tag(0x0043, 0x1037))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: FL[24]
+    let _user_data25_48 = dcm_object
+        .element(Tag(0x0043, 0x1038))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: IS[4]
+    let _slop_int_6_9 = dcm_object
+        .element(Tag(0x0043, 0x1039))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: IS[8]
+    let _slop_int_10_17 = dcm_object
+        .element(Tag(0x0043, 0x1060))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SH, not used in DV26
+    let _scanner_study_id = dcm_object
+        .element(Tag(0x0043, 0x1062))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VS: DS[3 or 4]
+    // 3 on single gradient coil systems, on multiple gradient coil systems the 4th value is the selected gradient coil
+    let _scanner_table_entry = dcm_object
+        .element(Tag(0x0043, 0x106f))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: ST
+    let _paradigm_description = dcm_object
+        .element(Tag(0x0043, 0x1071))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: UI
+    let _paradigm_uid = dcm_object
+        .element(Tag(0x0043, 0x1072))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: US
+    let _experiment_type = dcm_object
+        .element(Tag(0x0043, 0x1073))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: US
+    let _number_of_rest_volumes = dcm_object
+        .element(Tag(0x0043, 0x1074))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: US
+    let _number_of_active_volumes = dcm_object
+        .element(Tag(0x0043, 0x1075))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: US
+    let _number_of_dummy_scans = dcm_object
+        .element(Tag(0x0043, 0x1076))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SH
+    let _application_name = dcm_object
+        .element(Tag(0x0043, 0x1077))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SH
+    let _application_version = dcm_object
+        .element(Tag(0x0043, 0x1078))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: US
+    let _slices_per_volume = dcm_object
+        .element(Tag(0x0043, 0x1079))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: US
+    let _expected_time_points = dcm_object
+        .element(Tag(0x0043, 0x107A))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: FL[1...n]
+    let _regressor_values = dcm_object
+        .element(Tag(0x0043, 0x107B))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: FL
+    let _delay_after_slice_group = dcm_object
+        .element(Tag(0x0043, 0x107C))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: US
+    let _recon_mode_flag_word = dcm_object
+        .element(Tag(0x0043, 0x107D))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1...n]
+    let _pacc_specific_information = dcm_object
+        .element(Tag(0x0043, 0x107E))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1...n]
+    let _private_data = dcm_object
+        .element(Tag(0x0043, 0x107F))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1...n]
+    let _coil_id_data = dcm_object
+        .element(Tag(0x0043, 0x1080))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO
+    let _ge_coil_name = dcm_object
+        .element(Tag(0x0043, 0x1081))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1...n]
+    let _system_configuration_information = dcm_object
+        .element(Tag(0x0043, 0x1082))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[2]
+    let _asset_r_factors = dcm_object
+        .element(Tag(0x0043, 0x1083))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[5]
+    let _additional_asset_data = dcm_object
+        .element(Tag(0x0043, 0x1084))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: UT
+    let _debug_data_text = dcm_object
+        .element(Tag(0x0043, 0x1085))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB
+    let _debug_data_bin = dcm_object
+        .element(Tag(0x0043, 0x1086))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: UT
+    let _software_version_long = dcm_object
+        .element(Tag(0x0043, 0x1087))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: UI
+    let _pure_cal_series_uid = dcm_object
+        .element(Tag(0x0043, 0x1088))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[3]
+    let _gov_body_dbdt_sar_def = dcm_object
+        .element(Tag(0x0043, 0x1089))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: CS
+    let _private_inplace_pe_dir = dcm_object
+        .element(Tag(0x0043, 0x108A))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB, not used in DV26
+    let _fmri_binary_data_block = dcm_object
+        .element(Tag(0x0043, 0x108B))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[6]
+    let _voxel_location = dcm_object
+        .element(Tag(0x0043, 0x108C))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    //VR: DS[7n]
+    let _sat_band_locations = dcm_object
+        .element(Tag(0x0043, 0x108D))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[3]
+    let _spectro_prescan_values = dcm_object
+        .element(Tag(0x0043, 0x108E))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[3]
+    let _spectro_parameters = dcm_object
+        .element(Tag(0x0043, 0x108F))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1..n]
+    let _sar_definition = dcm_object
+        .element(Tag(0x0043, 0x1090))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1..n]
+    let _sar_value = dcm_object
+        .element(Tag(0x0043, 0x1091))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO
+    let _image_error_text = dcm_object
+        .element(Tag(0x0043, 0x1092))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1..n]
+    let _spectro_quantitation_values = dcm_object
+        .element(Tag(0x0043, 0x1093))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1..n]
+    let _spectro_ratio_values = dcm_object
+        .element(Tag(0x0043, 0x1094))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO
+    let _prescan_reuse_string = dcm_object
+        .element(Tag(0x0043, 0x1095))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: CS
+    let _content_qualification = dcm_object
+        .element(Tag(0x0043, 0x1096))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[8]
+    let _image_filtering_parameters = dcm_object
+        .element(Tag(0x0043, 0x1097))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: UI
+    let _asset_acquisition_calibration_uid = dcm_object
+        .element(Tag(0x0043, 0x1098))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1..n]
+    let _extended_options = dcm_object
+        .element(Tag(0x0043, 0x1099))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: IS
+    let _rx_stack_identification = dcm_object
+        .element(Tag(0x0043, 0x109A))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS
+    let _npw_factor = dcm_object
+        .element(Tag(0x0043, 0x109B))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB
+    let _research_tag_1 = dcm_object
+        .element(Tag(0x0043, 0x109C))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB
+    let _research_tag_2 = dcm_object
+        .element(Tag(0x0043, 0x109D))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB
+    let _research_tag_3 = dcm_object
+        .element(Tag(0x0043, 0x109E))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: OB
+    let _research_tag_4 = dcm_object
+        .element(Tag(0x0043, 0x109F))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SQ
+    let _spectroscopy_pixel_sequence = dcm_object
+        .element(Tag(0x0043, 0x10A0))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SQ
+    let _spectroscopy_default_display_sequence = dcm_object
+        .element(Tag(0x0043, 0x10A1))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VS: DS[1..n]
+    let _mef_data = dcm_object
+        .element(Tag(0x0043, 0x10A2))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: CS
+    let _asl_contrast_technique = dcm_object
+        .element(Tag(0x0043, 0x10A3))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO
+    let _detailed_text_for_asl_labeling = dcm_object
+        .element(Tag(0x0043, 0x10A4))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: IS
+    let _duration_of_label_or_ctrl_pulse = dcm_object
+        .element(Tag(0x0043, 0x10A5))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS, not used in DV26
+    let _offset_frequency_fastb1map = dcm_object
+        .element(Tag(0x0043, 0x10A6))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS
+    let _motion_encoding_factor = dcm_object
+        .element(Tag(0x0043, 0x10A7))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[3]
+    let _dual_drive_mode_amplitude_attenuation_phase_offset = dcm_object
+        .element(Tag(0x0043, 0x10A8))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1..n]
+    let _threed_cal_data = dcm_object
+        .element(Tag(0x0043, 0x10A9))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1..n]
+    let _additional_filtering_parameters = dcm_object
+        .element(Tag(0x0043, 0x10AA))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1..n]
+    let _silenz_data = dcm_object
+        .element(Tag(0x0043, 0x10AB))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1..n], reserved for future use
+    let _qmap_delay_data = dcm_object
+        .element(Tag(0x0043, 0x10AC))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1..n]
+    let _other_recovery_times_values = dcm_object
+        .element(Tag(0x0043, 0x10AD))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[1..n]
+    let _other_recovery_times_labels = dcm_object
+        .element(Tag(0x0043, 0x10AE))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1..n]
+    let _additional_echo_times = dcm_object
+        .element(Tag(0x0043, 0x10AF))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: FL
+    let _rescan_time_in_acquisition = dcm_object
+        .element(Tag(0x0043, 0x10B0))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _excitation_mode = dcm_object
+        .element(Tag(0x0043, 0x10B1))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1..n]
+    let _advanced_eddy_correction = dcm_object
+        .element(Tag(0x0043, 0x10B3))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: SS
+    let _mrf_transmit_gain = dcm_object
+        .element(Tag(0x0043, 0x10B4))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO
+    let _mr_table_position_information = dcm_object
+        .element(Tag(0x0043, 0x10B2))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[7]
+    let _multiband_parameters = dcm_object
+        .element(Tag(0x0043, 0x10B6))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO[4]
+    let _compressed_sensing_parameters = dcm_object
+        .element(Tag(0x0043, 0x10B7))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS
+    let _grad_comp_parameters = dcm_object
+        .element(Tag(0x0043, 0x10B8))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO
+    let _parallel_transmit_information = dcm_object
+        .element(Tag(0x0043, 0x10B9))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS
+    let _echo_spacing = dcm_object
+        .element(Tag(0x0043, 0x10BA))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO
+    let _pixel_information = dcm_object
+        .element(Tag(0x0043, 0x10BB))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: IS
+    let _heart_beats_pattern = dcm_object
+        .element(Tag(0x0043, 0x10BC))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: LO
+    let _hyper_kat_factor = dcm_object
+        .element(Tag(0x0043, 0x10BD))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    // VR: DS[1..n]
+    let _delta_transmit_gain = dcm_object
+        .element(Tag(0x0043, 0x10BF))
+        .ok()
+        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+        .unwrap_or_else(|| "N/A".to_string());
+
+    debug!(
+        gehc_private_creator_id,
+        peak_dbdt,
+        dbdt_limits_percent,
+        psd_estimatated_limit,
+        psd_estimated_limit_tps,
+        sar_avg_head,
+        "GE PARM_01 parameters"
+    );
+}
+
+// --- Siemens XProtocol extraction and recursive SQ traversal ---
+
+pub fn is_csa_header(data: &[u8]) -> bool {
+    data.len() >= 4 && &data[0..4] == b"SV10"
+}
+
+/// Walk the elements of a Siemens CSA header (SV10 layout), returning each element name with
+/// its item payloads. A truncated header yields the elements read so far.
+fn csa_elements(data: &[u8]) -> Vec<(String, Vec<&[u8]>)> {
+    let mut elements = Vec::new();
+    if !is_csa_header(data) {
+        return elements;
+    }
+
+    let mut offset = 8; // skip SV10 signature (4 bytes) + unused (4 bytes)
+
+    if data.len() < offset + 4 {
+        return elements;
+    }
+
+    let num_elements = u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ]) as usize;
+    offset += 4;
+
+    // skip unused 4 bytes after num_elements
+    offset += 4;
+
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ]) as usize
+    };
+
+    for _ in 0..num_elements {
+        if data.len() < offset + 64 + 4 + 4 + 4 + 4 + 4 {
+            break;
+        }
+
+        let name_bytes = &data[offset..offset + 64];
+        let name = String::from_utf8_lossy(name_bytes)
+            .trim_end_matches('\0')
+            .to_string();
+        offset += 64;
+
+        // skip VM (4 bytes), VR (4 bytes), SyngoDT (4 bytes)
+        offset += 12;
+
+        let num_items = read_u32(offset);
+        // item count plus the sync pattern (4 bytes) that follows it
+        offset += 8;
+
+        let mut items = Vec::new();
+        for _ in 0..num_items {
+            if data.len() < offset + 4 {
+                break;
+            }
+            let item_len = read_u32(offset);
+            offset += 4;
+
+            // skip 3 unused u32 fields
+            if data.len() < offset + 12 {
+                break;
+            }
+            offset += 12;
+
+            if item_len > 0 && offset + item_len <= data.len() {
+                items.push(&data[offset..offset + item_len]);
+            }
+
+            // items are padded to 4-byte boundaries
+            let padded_len = (item_len + 3) & !3;
+            if offset + padded_len > data.len() {
+                break;
+            }
+            offset += padded_len;
+        }
+
+        elements.push((name, items));
+    }
+
+    elements
+}
+
+/// Values of a named CSA element as trimmed strings; empty items are dropped.
+pub fn csa_values(data: &[u8], name: &str) -> Option<Vec<String>> {
+    let (_, items) = csa_elements(data).into_iter().find(|(n, _)| n == name)?;
+    let values: Vec<String> = items
+        .iter()
+        .map(|item| {
+            String::from_utf8_lossy(item)
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string()
+        })
+        .filter(|v| !v.is_empty())
+        .collect();
+    (!values.is_empty()).then_some(values)
+}
+
+/// The Siemens CSA image header (0029,1010) of an object, if present
+pub fn csa_image_header(obj: &InMemDicomObject<StandardDataDictionary>) -> Option<Vec<u8>> {
+    let bytes = obj.element(Tag(0x0029, 0x1010)).ok()?.to_bytes().ok()?;
+    is_csa_header(&bytes).then(|| bytes.to_vec())
+}
+
+pub fn parse_csa_header(data: &[u8]) -> Option<String> {
+    for (name, items) in csa_elements(data) {
+        let element_data = items.concat();
+
+        if name.contains("Protocol") || name == "MrPhoenixProtocol" {
+            if let Some(start_pos) = element_data
+                .windows(10)
+                .position(|w| w.starts_with(b"### ASCCONV") || w.starts_with(b"<XProtocol>"))
+            {
+                let content = &element_data[start_pos..];
+                let trimmed_len = content
+                    .iter()
+                    .rposition(|&b| b != 0)
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                return Some(String::from_utf8_lossy(&content[..trimmed_len]).to_string());
+            } else if !element_data.is_empty() {
+                let trimmed_len = element_data
+                    .iter()
+                    .rposition(|&b| b != 0)
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                if trimmed_len > 0 {
+                    return Some(String::from_utf8_lossy(&element_data[..trimmed_len]).to_string());
+                }
+            }
+        }
+    }
+
+    None
+}
+
+pub fn parse_dicom_sequence(data: &[u8]) -> Option<String> {
+    let mut offset = 0;
+
+    if data.len() < 8 || data[0] != 0xFE || data[1] != 0xFF || data[2] != 0x00 || data[3] != 0xE0 {
+        return None;
+    }
+    offset += 4;
+
+    let item_length = u32::from_le_bytes(data[offset..offset + 4].try_into().ok()?) as usize;
+    offset += 4;
+
+    let item_end = if item_length == 0xFFFFFFFF {
+        data.len()
+    } else {
+        offset + item_length
+    };
+
+    while offset + 8 <= item_end && offset + 8 <= data.len() {
+        let group = u16::from_le_bytes(data[offset..offset + 2].try_into().ok()?);
+        let element = u16::from_le_bytes(data[offset + 2..offset + 4].try_into().ok()?);
+        offset += 4;
+
+        if group == 0xFFFE && (element == 0xE00D || element == 0xE0DD) {
+            offset += 4;
+            continue;
+        }
+
+        let vr_bytes = if offset + 2 <= data.len() {
+            [data[offset], data[offset + 1]]
+        } else {
+            break;
+        };
+        let vr = String::from_utf8_lossy(&vr_bytes).to_string();
+        offset += 2;
+
+        let valid_vr = vr_bytes[0].is_ascii_uppercase() && vr_bytes[1].is_ascii_uppercase();
+
+        let value_length = if !valid_vr {
+            offset -= 2;
+            if offset + 4 > data.len() {
+                break;
+            }
+            let len = u32::from_le_bytes(data[offset..offset + 4].try_into().ok()?) as usize;
+            offset += 4;
+            len
+        } else if vr == "OB" || vr == "OW" || vr == "SQ" || vr == "UN" || vr == "UT" {
+            offset += 2;
+            if offset + 4 > data.len() {
+                break;
+            }
+            u32::from_le_bytes(data[offset..offset + 4].try_into().ok()?) as usize
+        } else {
+            if offset + 2 > data.len() {
+                break;
+            }
+            u16::from_le_bytes(data[offset..offset + 2].try_into().ok()?) as usize
+        };
+
+        if !valid_vr {
+            // offset already advanced above
+        } else if vr == "OB" || vr == "OW" || vr == "SQ" || vr == "UN" || vr == "UT" {
+            offset += 4;
+        } else {
+            offset += 2;
+        }
+
+        if offset + value_length <= data.len() {
+            let tag_data = &data[offset..offset + value_length];
+
+            if let Some(xprot_pos) = tag_data
+                .windows(11)
+                .position(|window| window.starts_with(b"<XProtocol>"))
+            {
+                let content = &tag_data[xprot_pos..];
+                let trimmed_len = content
+                    .iter()
+                    .rposition(|&b| b != 0)
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                return Some(String::from_utf8_lossy(&content[..trimmed_len]).to_string());
+            }
+        }
+
+        offset += value_length;
+    }
+
+    None
+}
+
+pub fn extract_tag_recursive(
+    obj: &InMemDicomObject<StandardDataDictionary>,
+    tag_to_find: Tag,
+) -> Option<String> {
+    if let Ok(tag_element) = obj.element(tag_to_find)
+        && let Ok(bytes) = tag_element.to_bytes()
+    {
+        if tag_to_find == Tag(0x0021, 0x10fe) {
+            if bytes.len() >= 4
+                && bytes[0] == 0xFE
+                && bytes[1] == 0xFF
+                && bytes[2] == 0x00
+                && bytes[3] == 0xE0
+            {
+                if let Some(parsed) = parse_dicom_sequence(&bytes) {
+                    return Some(parsed);
+                }
+            } else if is_csa_header(&bytes)
+                && let Some(parsed) = parse_csa_header(&bytes)
+            {
+                return Some(parsed);
+            }
+        }
+        let trimmed_len = bytes
+            .iter()
+            .rposition(|&b| b != 0)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        return Some(String::from_utf8_lossy(&bytes[..trimmed_len]).to_string());
+    }
+
+    for element in obj.iter() {
+        if element.vr() == VR::SQ
+            && let Value::Sequence(sequence) = element.value()
+        {
+            for item in sequence.items() {
+                if let Some(value) = extract_tag_recursive(item, tag_to_find) {
+                    return Some(value);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+pub fn get_tag_string(obj: &InMemDicomObject<StandardDataDictionary>, tag: Tag) -> String {
+    if let Ok(element) = obj.element(tag)
+        && let Ok(s) = element.value().to_str()
+    {
+        return s.to_string();
+    }
+
+    for element in obj.iter() {
+        if element.vr() == VR::SQ
+            && let Value::Sequence(sequence) = element.value()
+        {
+            for item in sequence.items() {
+                let result = get_tag_string(item, tag);
+                if result != "N/A" {
+                    return result;
+                }
+            }
+        }
+    }
+
+    "N/A".to_string()
+}
+
+#[derive(Debug, Clone)]
+struct DerivationCode {
+    code_value: String,
+    coding_scheme: String,
+    code_meaning: String,
+}
+
+#[derive(Debug, Clone)]
+struct SeriesDerivationInfo {
+    series_instance_uid: String,
+    series_description: String,
+    series_number: String,
+    modality: String,
+    image_type: String,
+    is_derived: bool,
+    file_count: usize,
+    derivation_description: String,
+    derivation_codes: Vec<DerivationCode>,
+    frame_of_reference_uid: String,
+    referenced_series_uids: Vec<String>,
+    source_sop_uids: Vec<String>,
+    referenced_image_sop_uids: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct DerivationEdge {
+    source_series_uid: String,
+    evidence_tags: Vec<&'static str>,
+}
+
+fn extract_all_from_sq(
+    obj: &InMemDicomObject<StandardDataDictionary>,
+    sq_tag: Tag,
+    inner_tag: Tag,
+) -> Vec<String> {
+    let mut results = Vec::new();
+    if let Ok(sq_element) = obj.element(sq_tag)
+        && let Value::Sequence(sequence) = sq_element.value()
+    {
+        for item in sequence.items() {
+            if let Ok(inner_element) = item.element(inner_tag)
+                && let Ok(s) = inner_element.value().to_str()
+            {
+                let trimmed = s.trim().to_string();
+                if !trimmed.is_empty() {
+                    results.push(trimmed);
+                }
+            }
+        }
+    }
+    results
+}
+
+fn extract_all_from_sq_deep(
+    obj: &InMemDicomObject<StandardDataDictionary>,
+    sq_tag: Tag,
+    inner_tag: Tag,
+) -> Vec<String> {
+    let mut results = Vec::new();
+    if let Ok(sq_element) = obj.element(sq_tag)
+        && let Value::Sequence(sequence) = sq_element.value()
+    {
+        for item in sequence.items() {
+            if let Ok(inner_element) = item.element(inner_tag)
+                && let Ok(s) = inner_element.value().to_str()
+            {
+                let trimmed = s.trim().to_string();
+                if !trimmed.is_empty() {
+                    results.push(trimmed);
+                }
+            }
+        }
+    }
+    for element in obj.iter() {
+        if element.vr() == VR::SQ
+            && let Value::Sequence(sequence) = element.value()
+        {
+            for item in sequence.items() {
+                results.extend(extract_all_from_sq_deep(item, sq_tag, inner_tag));
+            }
+        }
+    }
+    results
+}
+
+fn extract_derivation_codes(obj: &InMemDicomObject<StandardDataDictionary>) -> Vec<DerivationCode> {
+    let mut codes = Vec::new();
+    if let Ok(sq_element) = obj.element(tags::DERIVATION_CODE_SEQUENCE)
+        && let Value::Sequence(sequence) = sq_element.value()
+    {
+        for item in sequence.items() {
+            let code_value = item
+                .element(tags::CODE_VALUE)
+                .map_or("N/A".to_string(), |e| {
+                    e.value().to_str().unwrap_or_default().trim().to_string()
+                });
+            let coding_scheme = item
+                .element(tags::CODING_SCHEME_DESIGNATOR)
+                .map_or("N/A".to_string(), |e| {
+                    e.value().to_str().unwrap_or_default().trim().to_string()
+                });
+            let code_meaning = item
+                .element(tags::CODE_MEANING)
+                .map_or("N/A".to_string(), |e| {
+                    e.value().to_str().unwrap_or_default().trim().to_string()
+                });
+            codes.push(DerivationCode {
+                code_value,
+                coding_scheme,
+                code_meaning,
+            });
+        }
+    }
+    for element in obj.iter() {
+        if element.vr() == VR::SQ
+            && let Value::Sequence(sequence) = element.value()
+        {
+            for item in sequence.items() {
+                codes.extend(extract_derivation_codes(item));
+            }
+        }
+    }
+    codes
+}
+
+fn format_series_label(
+    uid: &str,
+    descriptions: &std::collections::HashMap<String, String>,
+    numbers: &std::collections::HashMap<String, String>,
+) -> String {
+    let desc = descriptions.get(uid).map(|s| s.as_str()).unwrap_or("N/A");
+    let num = numbers.get(uid).map(|s| s.as_str()).unwrap_or("?");
+    format!("Series {} \"{}\" [...{}]", num, desc, short_uid(uid, 8))
+}
+
+type DicomObjects = Vec<InMemDicomObject<StandardDataDictionary>>;
+
+/// Parse DICOM objects from either a ZIP archive or a directory of DICOM files.
+/// Returns the parsed objects and the entries or files that were skipped.
+fn load_dicom_objects(
+    input_path: &Path,
+) -> Result<(DicomObjects, Vec<EntryError>), Box<dyn std::error::Error>> {
+    let mut objects = Vec::new();
+    let mut skipped = Vec::new();
+
+    if input_path.is_dir() {
+        fn visit_dir(
+            root: &Path,
+            dir: &Path,
+            objects: &mut DicomObjects,
+            skipped: &mut Vec<EntryError>,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if path.is_dir() {
+                    visit_dir(root, &path, objects, skipped)?;
+                    continue;
+                }
+                match OpenFileOptions::new()
+                    .read_until(tags::PIXEL_DATA)
+                    .open_file(&path)
+                {
+                    Ok(obj) => objects.push(obj.into_inner()),
+                    Err(e) => {
+                        let name = path.strip_prefix(root).unwrap_or(&path);
+                        skipped.push(EntryError::from_dicom(name.display().to_string(), &e));
+                    }
+                }
+            }
+            Ok(())
+        }
+        visit_dir(input_path, input_path, &mut objects, &mut skipped)?;
+    } else {
+        // Try as ZIP first, fall back to single DICOM file
+        let file_data = map_archive(input_path)?;
+
+        if let Ok(loaded) = load_zip_objects(&file_data) {
+            (objects, skipped) = loaded;
+        } else {
+            match OpenFileOptions::new()
+                .read_until(tags::PIXEL_DATA)
+                .open_file(input_path)
+            {
+                Ok(obj) => objects.push(obj.into_inner()),
+                Err(e) => {
+                    skipped.push(EntryError::from_dicom(input_path.display().to_string(), &e))
+                }
+            }
+        }
+    }
+
+    Ok((objects, skipped))
+}
+
+/// Parse the DICOM headers of every entry in an in-memory ZIP archive.
+fn load_zip_objects(
+    zip_bytes: &[u8],
+) -> Result<(DicomObjects, Vec<EntryError>), Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+    let mut names = archive.clone();
+    let mut objects = Vec::new();
+    let mut skipped = Vec::new();
+    for i in 0..archive.len() {
+        let file = match archive.by_index(i) {
+            Ok(f) => f,
+            Err(e) => {
+                skipped.push(EntryError::from_zip(entry_name(&mut names, i), &e));
+                continue;
+            }
+        };
+        if file.is_dir() {
+            continue;
+        }
+        if file.size() < 132 {
+            skipped.push(EntryError::too_short(file.name(), file.size()));
+            continue;
+        }
+        let name = file.name().to_string();
+        match OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .from_reader(file)
+        {
+            Ok(obj) => objects.push(obj.into_inner()),
+            Err(e) => skipped.push(EntryError::from_dicom(name, &e)),
+        }
+    }
+    Ok((objects, skipped))
+}
+
+/// Name of an entry that cannot be opened, from its central directory record
+fn entry_name<R: std::io::Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+) -> String {
+    archive
+        .by_index_raw(index)
+        .map_or_else(|_| format!("#{}", index), |f| f.name().to_string())
+}
+
+/// Sibling series that reference a common set of SOP instances missing from the dataset
+struct PhantomSourceGroup {
+    series_uids: Vec<String>,
+    shared_sops: usize,
+    total_unique_sops: usize,
+}
+
+/// Result of the derivation analysis, shared by the text report and the SQLite export
+struct DerivationAnalysis {
+    derivation_infos: Vec<SeriesDerivationInfo>,
+    /// derived series UID → edges to its source series
+    derivation_graph: std::collections::HashMap<String, Vec<DerivationEdge>>,
+    unresolved_sops: std::collections::HashMap<String, Vec<String>>,
+    unresolved_sets: std::collections::HashMap<String, std::collections::HashSet<String>>,
+    phantom_groups: Vec<PhantomSourceGroup>,
+    pairwise_overlaps: std::collections::HashMap<(String, String), (usize, usize, usize)>,
+    series_descriptions: std::collections::HashMap<String, String>,
+    series_numbers: std::collections::HashMap<String, String>,
+}
+
+pub fn analyze_derivations(input_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Loading DICOM files from {}", input_path.display());
+    let (objects, skipped) = load_dicom_objects(input_path)?;
+    info!("Parsed {} DICOM files", objects.len());
+    entry_error::report(&skipped);
+
+    let analysis = compute_derivations(&objects);
+    info!(
+        "Found {} series across {} files",
+        analysis.series_descriptions.len(),
+        objects.len()
+    );
+
+    print_derivation_report(analysis);
+    Ok(())
+}
+
+/// Build the derivation graph and phantom source groups for a set of parsed objects.
+fn compute_derivations(objects: &[InMemDicomObject<StandardDataDictionary>]) -> DerivationAnalysis {
+    use std::collections::{HashMap, HashSet};
+
+    // Pass 1: Build SOP→Series map and collect representative per series
+    let mut sop_to_series: HashMap<String, String> = HashMap::new();
+    let mut series_file_counts: HashMap<String, usize> = HashMap::new();
+    let mut series_representatives: HashMap<String, usize> = HashMap::new(); // series_uid → index in objects
+    let mut series_descriptions: HashMap<String, String> = HashMap::new();
+    let mut series_numbers: HashMap<String, String> = HashMap::new();
+
+    for (idx, dcm_object) in objects.iter().enumerate() {
+        let sop_uid = get_tag_string(dcm_object, tags::SOP_INSTANCE_UID);
+        let series_uid = get_tag_string(dcm_object, tags::SERIES_INSTANCE_UID);
+
+        if sop_uid != "N/A" && series_uid != "N/A" {
+            sop_to_series.insert(sop_uid, series_uid.clone());
+        }
+
+        *series_file_counts.entry(series_uid.clone()).or_insert(0) += 1;
+
+        if let std::collections::hash_map::Entry::Vacant(e) =
+            series_representatives.entry(series_uid.clone())
+        {
+            series_descriptions.insert(
+                series_uid.clone(),
+                get_tag_string(dcm_object, tags::SERIES_DESCRIPTION),
+            );
+            series_numbers.insert(series_uid, get_tag_string(dcm_object, tags::SERIES_NUMBER));
+            e.insert(idx);
+        }
+    }
+
+    // Build series → file indices map for aggregation
+    let mut series_files: HashMap<String, Vec<usize>> = HashMap::new();
+    for (idx, dcm_object) in objects.iter().enumerate() {
+        let series_uid = get_tag_string(dcm_object, tags::SERIES_INSTANCE_UID);
+        if series_uid != "N/A" {
+            series_files.entry(series_uid).or_default().push(idx);
+        }
+    }
+
+    // Pass 2: Extract derivation info per series (aggregate across ALL files)
+    let mut derivation_infos: Vec<SeriesDerivationInfo> = Vec::new();
+
+    for (series_uid, &obj_idx) in &series_representatives {
+        let dcm_object = &objects[obj_idx];
+
+        let image_type = get_tag_string(dcm_object, tags::IMAGE_TYPE);
+        let is_derived = image_type
+            .split('\\')
+            .next()
+            .map(|s| s.trim().eq_ignore_ascii_case("DERIVED"))
+            .unwrap_or(false);
+
+        let derivation_description = get_tag_string(dcm_object, tags::DERIVATION_DESCRIPTION);
+        let derivation_codes = extract_derivation_codes(dcm_object);
+        let frame_of_reference_uid = get_tag_string(dcm_object, tags::FRAME_OF_REFERENCE_UID);
+        let modality = get_tag_string(dcm_object, tags::MODALITY);
+
+        let mut referenced_series_uids = Vec::new();
+        let mut source_sop_uids = Vec::new();
+        let mut referenced_image_sop_uids = Vec::new();
+
+        let fallback = vec![obj_idx];
+        let file_indices = series_files
+            .get(series_uid.as_str())
+            .map(|v| v.as_slice())
+            .unwrap_or(&fallback);
+
+        for &fidx in file_indices {
+            let obj = &objects[fidx];
+            referenced_series_uids.extend(extract_all_from_sq(
+                obj,
+                tags::REFERENCED_SERIES_SEQUENCE,
+                tags::SERIES_INSTANCE_UID,
+            ));
+            source_sop_uids.extend(extract_all_from_sq_deep(
+                obj,
+                tags::SOURCE_IMAGE_SEQUENCE,
+                tags::REFERENCED_SOP_INSTANCE_UID,
+            ));
+            referenced_image_sop_uids.extend(extract_all_from_sq(
+                obj,
+                tags::REFERENCED_IMAGE_SEQUENCE,
+                tags::REFERENCED_SOP_INSTANCE_UID,
+            ));
+        }
+
+        referenced_series_uids.sort();
+        referenced_series_uids.dedup();
+        source_sop_uids.sort();
+        source_sop_uids.dedup();
+        referenced_image_sop_uids.sort();
+        referenced_image_sop_uids.dedup();
+
+        derivation_infos.push(SeriesDerivationInfo {
+            series_instance_uid: series_uid.clone(),
+            series_description: series_descriptions
+                .get(series_uid)
+                .cloned()
+                .unwrap_or_default(),
+            series_number: series_numbers.get(series_uid).cloned().unwrap_or_default(),
+            modality,
+            image_type,
+            is_derived,
+            file_count: *series_file_counts.get(series_uid).unwrap_or(&0),
+            derivation_description,
+            derivation_codes,
+            frame_of_reference_uid,
+            referenced_series_uids,
+            source_sop_uids,
+            referenced_image_sop_uids,
+        });
+    }
+
+    // Sort by series number
+    derivation_infos
+        .sort_by_key(|info| info.series_number.trim().parse::<i32>().unwrap_or(i32::MAX));
+
+    // Phase 3: Build derivation graph
+    let mut derivation_graph: HashMap<String, Vec<DerivationEdge>> = HashMap::new();
+    let mut unresolved_sops: HashMap<String, Vec<String>> = HashMap::new();
+
+    for info in &derivation_infos {
+        let mut edges: Vec<DerivationEdge> = Vec::new();
+
+        // Direct series references
+        for ref_series_uid in &info.referenced_series_uids {
+            if ref_series_uid != &info.series_instance_uid {
+                edges.push(DerivationEdge {
+                    source_series_uid: ref_series_uid.clone(),
+                    evidence_tags: vec!["ReferencedSeriesSequence (0008,1115)"],
+                });
+            }
+        }
+
+        // SOP → Series for SourceImageSequence
+        let mut source_series: HashSet<String> = HashSet::new();
+        let mut unresolved: Vec<String> = Vec::new();
+        for sop_uid in &info.source_sop_uids {
+            if let Some(series_uid) = sop_to_series.get(sop_uid) {
+                if series_uid != &info.series_instance_uid {
+                    source_series.insert(series_uid.clone());
+                }
+            } else {
+                unresolved.push(sop_uid.clone());
+            }
+        }
+        for series_uid in source_series {
+            if let Some(existing) = edges.iter_mut().find(|e| e.source_series_uid == series_uid) {
+                if !existing
+                    .evidence_tags
+                    .contains(&"SourceImageSequence (0008,2112)")
+                {
+                    existing
+                        .evidence_tags
+                        .push("SourceImageSequence (0008,2112)");
+                }
+            } else {
+                edges.push(DerivationEdge {
+                    source_series_uid: series_uid,
+                    evidence_tags: vec!["SourceImageSequence (0008,2112)"],
+                });
+            }
+        }
+
+        // SOP → Series for ReferencedImageSequence
+        let mut ref_img_series: HashSet<String> = HashSet::new();
+        for sop_uid in &info.referenced_image_sop_uids {
+            if let Some(series_uid) = sop_to_series.get(sop_uid) {
+                if series_uid != &info.series_instance_uid {
+                    ref_img_series.insert(series_uid.clone());
+                }
+            } else {
+                unresolved.push(sop_uid.clone());
+            }
+        }
+        for series_uid in ref_img_series {
+            if let Some(existing) = edges.iter_mut().find(|e| e.source_series_uid == series_uid) {
+                if !existing
+                    .evidence_tags
+                    .contains(&"ReferencedImageSequence (0008,1140)")
+                {
+                    existing
+                        .evidence_tags
+                        .push("ReferencedImageSequence (0008,1140)");
+                }
+            } else {
+                edges.push(DerivationEdge {
+                    source_series_uid: series_uid,
+                    evidence_tags: vec!["ReferencedImageSequence (0008,1140)"],
+                });
+            }
+        }
+
+        if !unresolved.is_empty() {
+            unresolved_sops.insert(info.series_instance_uid.clone(), unresolved);
+        }
+
+        if !edges.is_empty() {
+            derivation_graph.insert(info.series_instance_uid.clone(), edges);
+        }
+    }
+
+    // Phase 4: Phantom source analysis — cluster unresolved SOPs to find sibling series
+    // Build reverse index: unresolved SOP → set of series that reference it
+    let mut sop_to_referencing_series: HashMap<String, Vec<String>> = HashMap::new();
+    for (series_uid, sops) in &unresolved_sops {
+        for sop in sops {
+            sop_to_referencing_series
+                .entry(sop.clone())
+                .or_default()
+                .push(series_uid.clone());
+        }
+    }
+
+    // Build a per-series set of unresolved SOPs for fast intersection
+    let unresolved_sets: HashMap<String, HashSet<String>> = unresolved_sops
+        .iter()
+        .map(|(uid, sops)| (uid.clone(), sops.iter().cloned().collect()))
+        .collect();
+
+    // Find sibling groups: series pairs that share unresolved source SOPs
+    // For each pair of series with unresolved SOPs, compute intersection size
+    let series_with_unresolved: Vec<&String> = unresolved_sops.keys().collect();
+    let mut sibling_edges: Vec<(String, String, usize, usize, usize)> = Vec::new();
+
+    for (i, a) in series_with_unresolved.iter().enumerate() {
+        let set_a = &unresolved_sets[*a];
+        for b in series_with_unresolved.iter().skip(i + 1) {
+            let set_b = &unresolved_sets[*b];
+            let intersection = set_a.intersection(set_b).count();
+            if intersection > 0 {
+                sibling_edges.push((
+                    (*a).clone(),
+                    (*b).clone(),
+                    intersection,
+                    set_a.len(),
+                    set_b.len(),
+                ));
+            }
+        }
+    }
+
+    // Group series into clusters of shared phantom sources
+    // Use union-find approach: if A shares SOPs with B, and B with C, all three are in a cluster
+    let mut cluster_map: HashMap<String, usize> = HashMap::new();
+    let mut clusters: Vec<HashSet<String>> = Vec::new();
+
+    for (a, b, _shared, _size_a, _size_b) in &sibling_edges {
+        let cluster_a = cluster_map.get(a).copied();
+        let cluster_b = cluster_map.get(b).copied();
+        match (cluster_a, cluster_b) {
+            (Some(ca), Some(cb)) if ca == cb => {}
+            (Some(ca), Some(cb)) => {
+                let (keep, merge) = if ca < cb { (ca, cb) } else { (cb, ca) };
+                let merged: HashSet<String> = clusters[merge].drain().collect();
+                for uid in &merged {
+                    cluster_map.insert(uid.clone(), keep);
+                }
+                clusters[keep].extend(merged);
+            }
+            (Some(ca), None) => {
+                clusters[ca].insert(b.clone());
+                cluster_map.insert(b.clone(), ca);
+            }
+            (None, Some(cb)) => {
+                clusters[cb].insert(a.clone());
+                cluster_map.insert(a.clone(), cb);
+            }
+            (None, None) => {
+                let idx = clusters.len();
+                let mut set = HashSet::new();
+                set.insert(a.clone());
+                set.insert(b.clone());
+                clusters.push(set);
+                cluster_map.insert(a.clone(), idx);
+                cluster_map.insert(b.clone(), idx);
+            }
+        }
+    }
+
+    // Collect non-empty clusters and compute the shared SOP pool for each
+    let mut phantom_groups: Vec<PhantomSourceGroup> = Vec::new();
+    let mut seen_clusters: HashSet<usize> = HashSet::new();
+    let unique_cluster_ids: HashSet<usize> = cluster_map.values().copied().collect();
+
+    for idx in unique_cluster_ids {
+        if seen_clusters.contains(&idx) || clusters[idx].is_empty() {
+            continue;
+        }
+        seen_clusters.insert(idx);
+
+        let members: Vec<String> = {
+            let mut v: Vec<String> = clusters[idx].iter().cloned().collect();
+            v.sort_by_key(|uid| {
+                series_numbers
+                    .get(uid)
+                    .and_then(|n| n.trim().parse::<i32>().ok())
+                    .unwrap_or(i32::MAX)
+            });
+            v
+        };
+
+        if members.len() < 2 {
+            continue;
+        }
+
+        // Find shared SOPs across all members (intersection)
+        let mut all_sops: HashSet<String> = HashSet::new();
+        let mut shared = unresolved_sets
+            .get(&members[0])
+            .cloned()
+            .unwrap_or_default();
+        for uid in &members {
+            if let Some(set) = unresolved_sets.get(uid) {
+                all_sops.extend(set.iter().cloned());
+                shared = shared.intersection(set).cloned().collect();
+            }
+        }
+
+        phantom_groups.push(PhantomSourceGroup {
+            series_uids: members,
+            shared_sops: shared.len(),
+            total_unique_sops: all_sops.len(),
+        });
+    }
+
+    phantom_groups.sort_by_key(|g| {
+        g.series_uids
+            .first()
+            .and_then(|uid| series_numbers.get(uid))
+            .and_then(|n| n.trim().parse::<i32>().ok())
+            .unwrap_or(i32::MAX)
+    });
+
+    // Also collect pairwise overlap details for the report
+    let mut pairwise_overlaps: HashMap<(String, String), (usize, usize, usize)> = HashMap::new();
+    for (a, b, shared, size_a, size_b) in &sibling_edges {
+        pairwise_overlaps.insert((a.clone(), b.clone()), (*shared, *size_a, *size_b));
+        pairwise_overlaps.insert((b.clone(), a.clone()), (*shared, *size_b, *size_a));
+    }
+
+    DerivationAnalysis {
+        derivation_infos,
+        derivation_graph,
+        unresolved_sops,
+        unresolved_sets,
+        phantom_groups,
+        pairwise_overlaps,
+        series_descriptions,
+        series_numbers,
+    }
+}
+
+/// Print the derivation report for `--derivations`.
+fn print_derivation_report(analysis: DerivationAnalysis) {
+    use std::collections::HashMap;
+
+    let DerivationAnalysis {
+        derivation_infos,
+        derivation_graph,
+        unresolved_sops,
+        unresolved_sets,
+        phantom_groups,
+        pairwise_overlaps,
+        series_descriptions,
+        series_numbers,
+    } = analysis;
+
+    println!("=== DICOM Derivation Analysis ===\n");
+
+    // Series overview
+    println!("--- Series Overview ---");
+    for info in &derivation_infos {
+        let tag = if info.is_derived { "D" } else { "O" };
+        let label = format_series_label(
+            &info.series_instance_uid,
+            &series_descriptions,
+            &series_numbers,
+        );
+        println!(
+            "  [{}] {}  ({} files, {})",
+            tag, label, info.file_count, info.modality
+        );
+        println!("      ImageType: {}", info.image_type);
+        if info.derivation_description != "N/A" && !info.derivation_description.is_empty() {
+            println!(
+                "      DerivationDescription (0008,2111): {}",
+                info.derivation_description
+            );
+        }
+        for code in &info.derivation_codes {
+            println!(
+                "      DerivationCode (0008,9215): {} ({}) \"{}\"",
+                code.code_value, code.coding_scheme, code.code_meaning
+            );
+        }
+        if !info.source_sop_uids.is_empty() {
+            println!(
+                "      SourceImageSequence (0008,2112): {} SOP references",
+                info.source_sop_uids.len()
+            );
+        }
+        if !info.referenced_image_sop_uids.is_empty() {
+            println!(
+                "      ReferencedImageSequence (0008,1140): {} SOP references",
+                info.referenced_image_sop_uids.len()
+            );
+        }
+        if !info.referenced_series_uids.is_empty() {
+            println!(
+                "      ReferencedSeriesSequence (0008,1115): {:?}",
+                info.referenced_series_uids
+            );
+        }
+        if info.frame_of_reference_uid != "N/A" {
+            let short_for = info
+                .frame_of_reference_uid
+                .split('.')
+                .next_back()
+                .unwrap_or(&info.frame_of_reference_uid);
+            println!("      FrameOfReference: ...{}", short_for);
+        }
+        println!();
+    }
+
+    // Derivation graph (derived → sources)
+    let has_any_derived = derivation_infos.iter().any(|i| i.is_derived);
+    if has_any_derived {
+        println!("--- Derivation Map (derived <- source) ---");
+        for info in &derivation_infos {
+            if !info.is_derived {
+                continue;
+            }
+            let label = format_series_label(
+                &info.series_instance_uid,
+                &series_descriptions,
+                &series_numbers,
+            );
+            println!("  {}", label);
+
+            if let Some(edges) = derivation_graph.get(&info.series_instance_uid) {
+                for edge in edges {
+                    let source_label = format_series_label(
+                        &edge.source_series_uid,
+                        &series_descriptions,
+                        &series_numbers,
+                    );
+                    println!("    <- {}", source_label);
+                    println!("       via: {}", edge.evidence_tags.join(", "));
+                }
+            }
+
+            if let Some(unresolved) = unresolved_sops.get(&info.series_instance_uid) {
+                println!(
+                    "    <- {} unresolved external SOP reference(s) not in this dataset",
+                    unresolved.len()
+                );
+                println!(
+                    "       via: SourceImageSequence (0008,2112) / ReferencedImageSequence (0008,1140)"
+                );
+            }
+
+            let total_refs = info.source_sop_uids.len() + info.referenced_image_sop_uids.len();
+            if total_refs == 0
+                && info.referenced_series_uids.is_empty()
+                && !unresolved_sops.contains_key(&info.series_instance_uid)
+            {
+                println!("    (no explicit derivation references found in DICOM tags)");
+                println!("    ImageType indicates DERIVED — likely inline reconstruction");
+            }
+
+            println!();
+        }
+    }
+
+    // Dependency tree (source → derived)
+    println!("--- Dependency Tree (source -> derived) ---");
+    let mut source_to_derived: HashMap<String, Vec<(String, Vec<&'static str>)>> = HashMap::new();
+    for (derived_uid, edges) in &derivation_graph {
+        for edge in edges {
+            source_to_derived
+                .entry(edge.source_series_uid.clone())
+                .or_default()
+                .push((derived_uid.clone(), edge.evidence_tags.clone()));
+        }
+    }
+
+    for info in &derivation_infos {
+        let has_sources = derivation_graph.contains_key(&info.series_instance_uid);
+        let has_derivatives = source_to_derived.contains_key(&info.series_instance_uid);
+
+        if has_derivatives {
+            let label = format_series_label(
+                &info.series_instance_uid,
+                &series_descriptions,
+                &series_numbers,
+            );
+            let tag = if info.is_derived {
+                "DERIVED"
+            } else {
+                "ORIGINAL"
+            };
+            println!("  {} [{}]", label, tag);
+            if let Some(derivatives) = source_to_derived.get(&info.series_instance_uid) {
+                let mut sorted_derivs = derivatives.clone();
+                sorted_derivs.sort_by_key(|(uid, _)| {
+                    series_numbers
+                        .get(uid)
+                        .and_then(|n| n.trim().parse::<i32>().ok())
+                        .unwrap_or(i32::MAX)
+                });
+                for (derived_uid, evidence) in &sorted_derivs {
+                    let derived_label =
+                        format_series_label(derived_uid, &series_descriptions, &series_numbers);
+                    println!("    -> {} via {}", derived_label, evidence.join(", "));
+                }
+            }
+        } else if !has_sources {
+            let label = format_series_label(
+                &info.series_instance_uid,
+                &series_descriptions,
+                &series_numbers,
+            );
+            let tag = if info.is_derived {
+                "DERIVED"
+            } else {
+                "ORIGINAL"
+            };
+            println!("  {} [{}] (no derivation links)", label, tag);
+        }
+    }
+
+    // Shared Phantom Sources — sibling series that reference common unresolved SOPs
+    if !phantom_groups.is_empty() {
+        println!("--- Shared Phantom Sources (sibling series from common acquisition) ---");
+        for (group_idx, group) in phantom_groups.iter().enumerate() {
+            println!(
+                "  Phantom Source Group {} ({} shared SOPs, {} total unique SOPs):",
+                group_idx + 1,
+                group.shared_sops,
+                group.total_unique_sops
+            );
+            for uid in &group.series_uids {
+                let label = format_series_label(uid, &series_descriptions, &series_numbers);
+                let own_count = unresolved_sets.get(uid).map(|s| s.len()).unwrap_or(0);
+                let tag = if derivation_infos
+                    .iter()
+                    .find(|i| &i.series_instance_uid == uid)
+                    .map(|i| i.is_derived)
+                    .unwrap_or(false)
+                {
+                    "D"
+                } else {
+                    "O"
+                };
+                println!("    [{}] {} ({} source SOPs)", tag, label, own_count);
+            }
+
+            // Show pairwise overlap between first few members
+            if group.series_uids.len() <= 10 {
+                let mut shown = false;
+                for i in 0..group.series_uids.len() {
+                    for j in (i + 1)..group.series_uids.len() {
+                        let a = &group.series_uids[i];
+                        let b = &group.series_uids[j];
+                        if let Some((shared, size_a, _size_b)) =
+                            pairwise_overlaps.get(&(a.clone(), b.clone()))
+                        {
+                            if !shown {
+                                println!("    Pairwise overlap:");
+                                shown = true;
+                            }
+                            let num_a = series_numbers.get(a).map(|s| s.trim()).unwrap_or("?");
+                            let num_b = series_numbers.get(b).map(|s| s.trim()).unwrap_or("?");
+                            let pct = (*shared * 100).checked_div(*size_a).unwrap_or(0);
+                            println!(
+                                "      S{} <-> S{}: {} shared SOPs ({}%)",
+                                num_a, num_b, shared, pct
+                            );
+                        }
+                    }
+                }
+            }
+            println!();
+        }
+    }
+
+    // Frame of Reference groups
+    println!("--- Frame of Reference Groups ---");
+    let mut for_groups: HashMap<String, Vec<String>> = HashMap::new();
+    for info in &derivation_infos {
+        if info.frame_of_reference_uid != "N/A" {
+            for_groups
+                .entry(info.frame_of_reference_uid.clone())
+                .or_default()
+                .push(info.series_instance_uid.clone());
+        }
+    }
+    for (for_uid, series_uids) in &for_groups {
+        let short_for = for_uid.split('.').next_back().unwrap_or(for_uid);
+        let labels: Vec<String> = series_uids
+            .iter()
+            .map(|uid| {
+                let num = series_numbers.get(uid).map(|s| s.as_str()).unwrap_or("?");
+                format!("S{}", num.trim())
+            })
+            .collect();
+        println!("  FoR ...{}: {}", short_for, labels.join(", "));
+    }
+
+    println!();
+}
+
+/// Siemens XProtocol text from (0021,1019) and (0021,10FE), separated by a blank line
+fn xprotocol_text(obj: &InMemDicomObject<StandardDataDictionary>) -> Option<String> {
+    let tag_1019 = extract_tag_recursive(obj, Tag(0x0021, 0x1019));
+    let tag_10fe = extract_tag_recursive(obj, Tag(0x0021, 0x10fe));
+    match (tag_1019, tag_10fe) {
+        (Some(a), Some(b)) => Some(format!("{}\n\n{}", a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Write the XProtocol of each Siemens series, restricted to `only_series` when given.
+/// Returns how many were written and the entries that could not be parsed.
+pub fn extract_xprotocol_from_zip(
+    zip_bytes: &[u8],
+    output_dir: &Path,
+    only_series: Option<&std::collections::HashSet<String>>,
+) -> Result<(usize, Vec<EntryError>), Box<dyn std::error::Error>> {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io::Write;
+
+    fs::create_dir_all(output_dir)?;
+
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+    let mut names = archive.clone();
+    let mut seen_series: HashSet<String> = HashSet::new();
+    let mut extracted_count = 0;
+    let mut skipped = Vec::new();
+
+    for i in 0..archive.len() {
+        let file = match archive.by_index(i) {
+            Ok(f) => f,
+            Err(e) => {
+                skipped.push(EntryError::from_zip(entry_name(&mut names, i), &e));
+                continue;
+            }
+        };
+        if file.is_dir() {
+            continue;
+        }
+        if file.size() < 132 {
+            skipped.push(EntryError::too_short(file.name(), file.size()));
+            continue;
+        }
+        let name = file.name().to_string();
+
+        // Parse straight from the entry; nothing past the header is decompressed
+        let dcm_object = match OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .from_reader(file)
+        {
+            Ok(obj) => obj,
+            Err(e) => {
+                skipped.push(EntryError::from_dicom(name, &e));
+                continue;
+            }
+        };
+
+        let manufacturer = dcm_object
+            .element(tags::MANUFACTURER)
+            .map_or(String::new(), |e| {
+                e.value().to_str().unwrap_or_default().to_uppercase()
+            });
+
+        if !manufacturer.contains("SIEMENS") {
+            continue;
+        }
+
+        let series_uid = dcm_object
+            .element(tags::SERIES_INSTANCE_UID)
+            .map_or("unknown".to_string(), |e| {
+                e.value().to_str().unwrap_or_default().to_string()
+            });
+
+        if seen_series.contains(&series_uid)
+            || only_series.is_some_and(|only| !only.contains(&series_uid))
+        {
+            continue;
+        }
+
+        let Some(xprotocol) = xprotocol_text(&dcm_object) else {
+            seen_series.insert(series_uid);
+            continue;
+        };
+
+        let series_desc =
+            dcm_object
+                .element(tags::SERIES_DESCRIPTION)
+                .map_or("Unknown".to_string(), |e| {
+                    let s = e.value().to_str().unwrap_or_default().trim().to_string();
+                    if s.is_empty() {
+                        "Unknown".to_string()
+                    } else {
+                        s
+                    }
+                });
+
+        let series_num = dcm_object
+            .element(tags::SERIES_NUMBER)
+            .map_or(String::new(), |e| {
+                e.value().to_str().unwrap_or_default().trim().to_string()
+            });
+
+        let uid_suffix = short_uid(&series_uid, 8);
+
+        let filename = if series_num.is_empty() {
+            format!("{}_{}.xprot", sanitize_filename(&series_desc), uid_suffix)
+        } else {
+            format!(
+                "{:04}_{}_{}.xprot",
+                series_num.parse::<i32>().unwrap_or(0),
+                sanitize_filename(&series_desc),
+                uid_suffix
+            )
+        };
+
+        let output_path = output_dir.join(&filename);
+        let mut out_file = fs::File::create(&output_path)?;
+
+        write!(out_file, "{}", xprotocol)?;
+
+        debug!(series = %series_uid, "Extracted XProtocol: {} ({})", filename, series_desc);
+        extracted_count += 1;
+        seen_series.insert(series_uid);
+    }
+
+    Ok((extracted_count, skipped))
+}
+
+/// Result of a deep scan: the DICOM instances, and why every other entry was left out
+#[derive(Default)]
+pub struct DeepScan {
+    pub candidates: Vec<DeepDicomCandidate>,
+    pub skipped: Vec<EntryError>,
+}
+
+pub fn deep_scan_dicom_candidates_parallel(
+    zip_bytes: &[u8],
+) -> Result<DeepScan, Box<dyn std::error::Error>> {
+    let (scan, _) = deep_scan_dicom_candidates_cached(zip_bytes, &EntryCache::new())?;
+    Ok(scan)
+}
+
+/// Deep scan that reuses `cache` results for entries whose name, CRC and size are unchanged.
+/// Returns the scan and how many of its candidates came from the cache.
+pub fn deep_scan_dicom_candidates_cached(
+    zip_bytes: &[u8],
+    cache: &EntryCache,
+) -> Result<(DeepScan, usize), Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+    // Second handle for naming entries that cannot be opened
+    let mut names = archive.clone();
+    let mut all_candidates = Vec::new();
+    let mut skipped = Vec::new();
+    let mut reused = 0;
+
+    for i in 0..archive.len() {
+        let file = match archive.by_index(i) {
+            Ok(f) => f,
+            Err(e) => {
+                skipped.push(EntryError::from_zip(entry_name(&mut names, i), &e));
+                continue;
+            }
+        };
+
+        if file.is_dir() {
+            continue;
+        }
+        if file.size() < 132 {
+            skipped.push(EntryError::too_short(file.name(), file.size()));
+            continue;
+        }
+
+        let name = file.name().to_string();
+        let compressed_size = file.compressed_size();
+        let uncompressed_size = file.size();
+
+        if let Some(cached) = cache.get(&(name.clone(), file.crc32(), uncompressed_size)) {
+            all_candidates.push(DeepDicomCandidate {
+                index: i,
+                compressed_size,
+                ..cached.clone()
+            });
+            reused += 1;
+            continue;
+        }
+
+        // If this succeeds, we have a DICOM file, I suppose
+        let dcm_result = OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .from_reader(file);
+
+        let dcm_object = match dcm_result {
+            Ok(obj) => obj,
+            Err(e) => {
+                skipped.push(EntryError::from_dicom(name, &e));
+                continue;
+            }
+        };
+
+        if let Some(candidate) =
+            deep_candidate_from_object(dcm_object, i, name, compressed_size, uncompressed_size)
+        {
+            all_candidates.push(candidate);
+        }
+    }
+    Ok((
+        DeepScan {
+            candidates: all_candidates,
+            skipped,
+        },
+        reused,
+    ))
+}
+
+/// Build the deep-scan record for one parsed DICOM header.
+/// Returns None for objects that are not listed as instances (DICOMDIR, enhanced MR).
+fn deep_candidate_from_object(
+    dcm_object: FileDicomObject<InMemDicomObject<StandardDataDictionary>>,
+    index: usize,
+    name: String,
+    compressed_size: u64,
+    uncompressed_size: u64,
+) -> Option<DeepDicomCandidate> {
+    // A DICOMDIR is an index, not an instance; it is handled by the dicomdir module
+    if dicomdir::is_dicomdir(&dcm_object) {
+        return None;
+    }
+
+    let study_instance_uid = get_tag_string(&dcm_object, tags::STUDY_INSTANCE_UID);
+    let series_instance_uid = get_tag_string(&dcm_object, tags::SERIES_INSTANCE_UID);
+    let patient_id = get_tag_string(&dcm_object, PATIENT_ID);
+    let patient_name = get_tag_string(&dcm_object, tags::PATIENT_NAME);
+    let study_date = get_tag_string(&dcm_object, tags::STUDY_DATE);
+    let accession_number = get_tag_string(&dcm_object, tags::ACCESSION_NUMBER);
+    let sop_instance_uid = get_tag_string(&dcm_object, tags::SOP_INSTANCE_UID);
+    let instance_number = get_tag_string(&dcm_object, tags::INSTANCE_NUMBER);
+    let modality = get_tag_string(&dcm_object, tags::MODALITY);
+    let _file = tracing::debug_span!("file", name = %name, series = %series_instance_uid).entered();
+
+    let protocol_name = get_tag_string(&dcm_object, tags::PROTOCOL_NAME);
+    let study_description = get_tag_string(&dcm_object, tags::STUDY_DESCRIPTION);
+    let series_description = get_tag_string(&dcm_object, tags::SERIES_DESCRIPTION);
+    let _series_date = get_tag_string(&dcm_object, tags::SERIES_DATE);
+    let series_number = get_tag_string(&dcm_object, tags::SERIES_NUMBER);
+    let _series_time = get_tag_string(&dcm_object, tags::SERIES_TIME);
+    let manufacturer = get_tag_string(&dcm_object, tags::MANUFACTURER);
+
+    let acquisition_type = get_tag_string(&dcm_object, tags::MR_ACQUISITION_TYPE);
+    let acquisition_time = get_tag_string(&dcm_object, tags::ACQUISITION_TIME);
+    let pixel_spacing = get_tag_string(&dcm_object, tags::PIXEL_SPACING);
+    let slice_thickness = get_tag_string(&dcm_object, tags::SLICE_THICKNESS);
+    let rows = get_tag_string(&dcm_object, tags::ROWS);
+    let columns = get_tag_string(&dcm_object, tags::COLUMNS);
+    let repetition_time = get_tag_string(&dcm_object, tags::REPETITION_TIME);
+    let echo_time = get_tag_string(&dcm_object, tags::ECHO_TIME);
+    let inversion_time = get_tag_string(&dcm_object, tags::INVERSION_TIME);
+    let derivation_description = get_tag_string(&dcm_object, tags::DERIVATION_DESCRIPTION);
+
+    // Check for referenced series (source series for derived images)
+    let referenced_series_uid =
+        if let Ok(ref_series_seq) = dcm_object.element(tags::REFERENCED_SERIES_SEQUENCE) {
+            // Get the first item in the sequence
+            if let dicom::core::value::Value::Sequence(seq) = ref_series_seq.value() {
+                if let Some(first_item) = seq.items().first() {
+                    // Get the Series Instance UID from the referenced series
+                    first_item
+                        .element(tags::SERIES_INSTANCE_UID)
+                        .ok()
+                        .and_then(|e| e.value().to_str().ok().map(|s| s.to_string()))
+                        .unwrap_or_else(|| "N/A".to_string())
+                } else {
+                    "N/A".to_string()
+                }
+            } else {
+                "N/A".to_string()
+            }
+        } else {
+            "N/A".to_string()
+        };
+
+    let mut acquisition_duration = get_tag_string(&dcm_object, Tag(0x0018, 0x9073));
+    let flip_angle = get_tag_string(&dcm_object, tags::FLIP_ANGLE);
+    let number_of_averages = get_tag_string(&dcm_object, tags::NUMBER_OF_AVERAGES);
+    let echo_train_length = get_tag_string(&dcm_object, tags::ECHO_TRAIN_LENGTH);
+
+    // Parallel imaging factor - try vendor-specific tags in priority order
+    let mut parallel_imaging_factor = {
+        let val = get_tag_string(&dcm_object, Tag(0x0018, 0x9069));
+        if val != "N/A" {
+            val
+        } else {
+            let val = get_tag_string(&dcm_object, Tag(0x0051, 0x1011));
+            if val != "N/A" {
+                val
+            } else {
+                get_tag_string(&dcm_object, Tag(0x0018, 0x9078))
+            }
+        }
+    };
+
+    let magnetic_field_strength = get_tag_string(&dcm_object, tags::MAGNETIC_FIELD_STRENGTH);
+    let spacing_between_slices = get_tag_string(&dcm_object, tags::SPACING_BETWEEN_SLICES);
+    let image_type = get_tag_string(&dcm_object, tags::IMAGE_TYPE);
+    let sop_class_uid = get_tag_string(&dcm_object, tags::SOP_CLASS_UID);
+
+    // Phase-encoding polarity and readout timing for distortion correction
+    let csa = csa_image_header(&dcm_object);
+    let mosaic = acquisition::mosaic_images(&dcm_object, csa.as_deref());
+    let phase_encoding_polarity =
+        acquisition::phase_encoding_direction(&dcm_object, csa.as_deref())
+            .unwrap_or_else(|| "N/A".to_string());
+    let (effective_echo_spacing, total_readout_time) =
+        match acquisition::readout_time(&dcm_object, csa.as_deref(), mosaic) {
+            Some((ees, trt)) => (ees.to_string(), trt.to_string()),
+            None => ("N/A".to_string(), "N/A".to_string()),
+        };
+    let slice_timing = acquisition::slice_times(&dcm_object, csa.as_deref())
+        .map(|times| acquisition::format_slice_timing(&times))
+        .unwrap_or_else(|| "N/A".to_string());
+    let slice_position = acquisition::slice_position(&dcm_object)
+        .map(|p| p.to_string())
+        .unwrap_or_else(|| "N/A".to_string());
+    let transfer_syntax = dcm_object
+        .meta()
+        .transfer_syntax()
+        .trim_end_matches('\0')
+        .to_string();
+
+    trace!(sop_class_uid, modality, "Parsed header");
+
+    if sop_class_uid == *"1.2.840.10008.5.1.4.1.1.4.1" && modality == *"MR" {
+        debug!("Enhanced MR image; not listed as an instance");
+
+        let _acquisition_number = get_tag_string(&dcm_object, tags::ACQUISITION_NUMBER);
+        let _acquisiton_date_time = get_tag_string(&dcm_object, tags::ACQUISITION_DATE_TIME);
+        let _content_qualification = get_tag_string(&dcm_object, tags::CONTENT_QUALIFICATION);
+        let _resonant_nucleus = get_tag_string(&dcm_object, tags::RESONANT_NUCLEUS);
+        let _kspace_filtering = get_tag_string(&dcm_object, tags::K_SPACE_FILTERING);
+        let _magnetic_field_strength = get_tag_string(&dcm_object, tags::MAGNETIC_FIELD_STRENGTH);
+        let _applicable_safety_standard_agency =
+            get_tag_string(&dcm_object, tags::APPLICABLE_SAFETY_STANDARD_AGENCY);
+        let _applicable_safety_standard_description =
+            get_tag_string(&dcm_object, tags::APPLICABLE_SAFETY_STANDARD_DESCRIPTION);
+        let _image_comments = get_tag_string(&dcm_object, tags::IMAGE_COMMENTS);
+        let _isocenter_position = get_tag_string(&dcm_object, tags::ISOCENTER_POSITION);
+        let _b1rms = get_tag_string(&dcm_object, tags::B1RMS);
+        let _acquisition_contrast = get_tag_string(&dcm_object, tags::ACQUISITION_CONTRAST);
+        let mr_fov_geometry_sequence = get_tag_string(&dcm_object, tags::MRFOV_GEOMETRY_SEQUENCE);
+        let _inplane_phase_encoding_direction =
+            get_tag_string(&dcm_object, tags::IN_PLANE_PHASE_ENCODING_DIRECTION);
+        let mr_acquisition_frequency_encoding_steps =
+            get_tag_string(&dcm_object, tags::MR_ACQUISITION_FREQUENCY_ENCODING_STEPS);
+        let mr_acquisition_phase_encoding_steps_inplane = get_tag_string(
+            &dcm_object,
+            tags::MR_ACQUISITION_PHASE_ENCODING_STEPS_IN_PLANE,
+        );
+        let mr_acquisition_phase_encoding_steps_outofplane = get_tag_string(
+            &dcm_object,
+            tags::MR_ACQUISITION_PHASE_ENCODING_STEPS_OUT_OF_PLANE,
+        );
+        let _percent_sampling = get_tag_string(&dcm_object, tags::PERCENT_SAMPLING);
+        let _percent_phase_field_of_view =
+            get_tag_string(&dcm_object, tags::PERCENT_PHASE_FIELD_OF_VIEW);
+
+        debug!(
+            mr_fov_geometry_sequence,
+            frequency_steps = mr_acquisition_frequency_encoding_steps,
+            phase_steps = mr_acquisition_phase_encoding_steps_inplane,
+            kz_steps = mr_acquisition_phase_encoding_steps_outofplane,
+            "Enhanced MR geometry"
+        );
+        if slice_timing != "N/A" {
+            let times: Vec<f64> = slice_timing
+                .split('\\')
+                .filter_map(|t| t.parse::<f64>().ok())
+                .collect();
+            debug!(
+                slice_timing,
+                multiband = acquisition::multiband_factor(&times)
+                    .map_or("N/A".to_string(), |f| f.to_string()),
+                "Enhanced MR slice timing (ms)"
+            );
+        }
+
+        return None;
+    }
+
+    // If the Modality is "MR", get some additional information
+    if modality == "MR" {
+        let te = get_tag_string(&dcm_object, tags::ECHO_TIME);
+        let tr = get_tag_string(&dcm_object, tags::REPETITION_TIME);
+        let sar = get_tag_string(&dcm_object, tags::SAR);
+        let _db_dt = get_tag_string(&dcm_object, tags::D_BDT);
+        let _isocenter_position = get_tag_string(&dcm_object, tags::ISOCENTER_POSITION);
+        let receive_coil_name = get_tag_string(&dcm_object, tags::RECEIVE_COIL_NAME);
+        let pixel_bandwidth = get_tag_string(&dcm_object, tags::PIXEL_BANDWIDTH);
+        let _number_pe = get_tag_string(&dcm_object, tags::NUMBER_OF_PHASE_ENCODING_STEPS);
+        let acq_matrix = get_tag_string(&dcm_object, tags::ACQUISITION_MATRIX);
+        let phase_encoding_direction =
+            get_tag_string(&dcm_object, tags::IN_PLANE_PHASE_ENCODING_DIRECTION);
+        let reconstruction_diameter = get_tag_string(&dcm_object, tags::RECONSTRUCTION_DIAMETER);
+        let pixel_spacing = get_tag_string(&dcm_object, tags::PIXEL_SPACING);
+        let rows = get_tag_string(&dcm_object, tags::ROWS);
+        let columns = get_tag_string(&dcm_object, tags::COLUMNS);
+
+        let _b1_rms = get_tag_string(&dcm_object, tags::B1RMS);
+        let _bits_allocated = get_tag_string(&dcm_object, tags::BITS_ALLOCATED);
+        let _bits_stored = get_tag_string(&dcm_object, tags::BITS_STORED);
+        let _high_bit = get_tag_string(&dcm_object, tags::HIGH_BIT);
+        let scanning_sequence = get_tag_string(&dcm_object, tags::SCANNING_SEQUENCE);
+        let sequence_variant = get_tag_string(&dcm_object, tags::SEQUENCE_VARIANT);
+        let scan_options = get_tag_string(&dcm_object, tags::SCAN_OPTIONS);
+        let mr_acquisition_type = get_tag_string(&dcm_object, tags::MR_ACQUISITION_TYPE);
+        let _inversion_time = get_tag_string(&dcm_object, tags::INVERSION_TIME);
+
+        let _sequence_name = get_tag_string(&dcm_object, tags::SEQUENCE_NAME);
+        let center_to_center_slice_gap = get_tag_string(&dcm_object, tags::SPACING_BETWEEN_SLICES);
+        let percent_sampling = get_tag_string(&dcm_object, tags::PERCENT_SAMPLING);
+        let percent_phase_fov = get_tag_string(&dcm_object, tags::PERCENT_PHASE_FIELD_OF_VIEW);
+        let flip_angle = get_tag_string(&dcm_object, tags::FLIP_ANGLE);
+
+        let _variable_flip_flag = get_tag_string(&dcm_object, tags::VARIABLE_FLIP_ANGLE_FLAG);
+        let slice_thickness = get_tag_string(&dcm_object, tags::SLICE_THICKNESS);
+
+        // Seems like GE does not report:
+        // - dbdt
+        // - isocenter position
+        // - number_pe
+        // - sequence_name
+
+        // image matrix
+
+        debug!(
+            series_number,
+            series_description,
+            scanning_sequence,
+            sequence_variant,
+            scan_options,
+            mr_acquisition_type,
+            sar,
+            receive_coil_name,
+            pixel_bandwidth,
+            te,
+            tr,
+            flip_angle,
+            acq_matrix,
+            phase_encoding_direction,
+            phase_encoding_polarity,
+            total_readout_time,
+            reconstruction_diameter,
+            percent_phase_fov,
+            percent_sampling,
+            pixel_spacing,
+            rows,
+            columns,
+            slice_thickness,
+            center_to_center_slice_gap,
+            acq_resolution = calculate_acq_resolution(
+                acq_matrix.clone(),
+                rows.clone(),
+                columns.clone(),
+                pixel_spacing.clone(),
+            ),
+            "MR acquisition"
+        );
+
+        if manufacturer == "GE MEDICAL SYSTEMS" {
+            let internal_sequence_name = get_tag_string(&dcm_object, Tag(0x0019, 0x109E));
+
+            // this tag is "FL" as VR (single float)
+            let ge_acquisition_duration = dcm_object
+                .element(Tag(0x0019, 0x105A))
+                .ok()
+                .and_then(|e| e.value().to_float32().ok())
+                .unwrap_or(f32::NAN);
+
+            // If we didn't get acquisition duration from standard tag, use GE-specific one
+            if acquisition_duration == "N/A" && !ge_acquisition_duration.is_nan() {
+                acquisition_duration = format!("{:.2}", ge_acquisition_duration);
+            }
+
+            let number_of_echoes = get_tag_string(&dcm_object, Tag(0x0019, 0x107E));
+            let _table_delta = get_tag_string(&dcm_object, Tag(0x0019, 0x107F));
+            let _gehc_private_creator_id = get_tag_string(&dcm_object, Tag(0x0043, 0x0010));
+            let _bitmap_of_prescan_options = get_tag_string(&dcm_object, Tag(0x0043, 0x1001));
+            let _gradient_offset_x = get_tag_string(&dcm_object, Tag(0x0043, 0x1002));
+            let _gradient_offset_y = get_tag_string(&dcm_object, Tag(0x0043, 0x1003));
+            let _gradient_offset_z = get_tag_string(&dcm_object, Tag(0x0043, 0x1004));
+
+            let _image_is_original = get_tag_string(&dcm_object, Tag(0x0043, 0x1005));
+            let _number_of_epi_shots = get_tag_string(&dcm_object, Tag(0x0043, 0x1006));
+            let _views_per_segment = get_tag_string(&dcm_object, Tag(0x0043, 0x1007));
+            let _respiratory_rate_bpm = get_tag_string(&dcm_object, Tag(0x0043, 0x1008));
+            let _respiratory_trigger_point = get_tag_string(&dcm_object, Tag(0x0043, 0x1009));
+            let _type_of_receiver_used = get_tag_string(&dcm_object, Tag(0x0043, 0x100A));
+            let _peak_dbdt = get_tag_string(&dcm_object, Tag(0x0043, 0x100B));
+            let _dbdt_limits_percent = get_tag_string(&dcm_object, Tag(0x0043, 0x100C));
+            let _psd_estimatated_limit = get_tag_string(&dcm_object, Tag(0x0043, 0x100D));
+            let _psd_estimated_limit_tps = get_tag_string(&dcm_object, Tag(0x0043, 0x100E));
+            let _sar_avg_head = get_tag_string(&dcm_object, Tag(0x0043, 0x100F));
+            let _application_name = get_tag_string(&dcm_object, Tag(0x0043, 0x1077));
+            let _application_version = get_tag_string(&dcm_object, Tag(0x0043, 0x1078));
+            let _slices_per_volume = get_tag_string(&dcm_object, Tag(0x0043, 0x1079));
+            let asset_r_factors = get_tag_string(&dcm_object, Tag(0x0043, 0x1083));
+
+            // Use GE Asset R factors for parallel imaging factor if not already set
+            if parallel_imaging_factor == "N/A" && asset_r_factors != "N/A" {
+                parallel_imaging_factor = asset_r_factors.clone();
+            }
+
+            // note the acquisition duration is in micro seconds
+            debug!(
+                internal_sequence_name,
+                acquisition_duration = ?Duration::from_micros(ge_acquisition_duration as u64),
+                number_of_echoes,
+                asset_r_factors,
+                "GE acquisition"
+            );
+
+            //scan_gems_parm_01(&dcm_object);
+        }
+    }
+    Some(DeepDicomCandidate {
+        index,
+        name,
+        compressed_size,
+        uncompressed_size,
+        study_instance_uid,
+        series_instance_uid,
+        sop_instance_uid,
+        modality,
+        manufacturer,
+        patient_id,
+        patient_name,
+        study_date,
+        accession_number,
+        study_description,
+        series_description,
+        series_number,
+        protocol_name,
+        acquisition_type,
+        pixel_spacing,
+        slice_thickness,
+        acquisition_time,
+        rows,
+        columns,
+        repetition_time,
+        echo_time,
+        inversion_time,
+        derivation_description,
+        referenced_series_uid,
+        acquisition_duration,
+        flip_angle,
+        number_of_averages,
+        echo_train_length,
+        parallel_imaging_factor,
+        magnetic_field_strength,
+        spacing_between_slices,
+        image_type,
+        instance_number,
+        phase_encoding_direction: phase_encoding_polarity,
+        effective_echo_spacing,
+        total_readout_time,
+        slice_timing,
+        slice_position,
+        transfer_syntax,
+    })
+}
+
+pub fn scan_dicom_candidates_parallel(
+    zip_bytes: &[u8],
+) -> Result<Vec<DicomCandidate>, Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+    let mut all_candidates = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut file = match archive.by_index(i) {
+            Ok(f) => f,
+            Err(_) => continue,
+        };
+
+        if file.size() < 132 {
+            continue;
+        }
+
+        // get the first 132 bytes of the dcm_object
+        let mut header = [0u8; 132];
+        if file.read_exact(&mut header).is_ok() {
+            all_candidates.push((
+                i,
+                file.name().to_string(),
+                file.compressed_size(),
+                file.size(),
+                header,
+            ));
+        }
+    }
+
+    let results: Vec<_> = all_candidates
+        .into_par_iter()
+        .filter_map(|(i, name, compressed, uncompressed, header)| {
+            if &header[128..132] == b"DICM" {
+                Some(DicomCandidate {
+                    index: i,
+                    name,
+                    compressed_size: compressed,
+                    uncompressed_size: uncompressed,
+                })
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    Ok(results)
+}
+
+/// Column names of series_metadata.csv
+const SERIES_METADATA_HEADER: &str = "SeriesDescription,ProtocolName,SeriesNumber,Modality,SeriesInstanceUID,AcquisitionType,PixelSpacing,SliceThickness,SpacingBetweenSlices,FOV,TR,TE,TI,FlipAngle,NumberOfAverages,EchoTrainLength,ParallelImagingFactor,MagneticFieldStrength,ImageType,AcquisitionTime,AcquisitionDuration,PhaseEncodingDirection,EffectiveEchoSpacing,TotalReadoutTime,SliceTiming,MultibandFactor,TransferSyntax,DerivationDescription,ReferencedSeriesUID,FileCount";
+
+/// Escape fields that might contain commas or quotes
+fn escape_csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// One row per series (values already CSV-escaped), sorted by study and series UID
+fn series_metadata_rows(deep_candidates: &[DeepDicomCandidate]) -> Vec<Vec<String>> {
+    // Group files by series to get one row per series
+    let mut series_map: std::collections::HashMap<(String, String), &DeepDicomCandidate> =
+        std::collections::HashMap::new();
+
+    // For each series, we'll use the first file's metadata as representative
+    for candidate in deep_candidates {
+        let key = (
+            candidate.study_instance_uid.clone(),
+            candidate.series_instance_uid.clone(),
+        );
+        series_map.entry(key).or_insert(candidate);
+    }
+
+    // Files per series (counted, and combined for the slice timing)
+    let mut series_files: std::collections::HashMap<(String, String), Vec<&DeepDicomCandidate>> =
+        std::collections::HashMap::new();
+    for candidate in deep_candidates {
+        let key = (
+            candidate.study_instance_uid.clone(),
+            candidate.series_instance_uid.clone(),
+        );
+        series_files.entry(key).or_default().push(candidate);
+    }
+
+    // Sort series by study and series for consistent output
+    let mut sorted_series: Vec<_> = series_map.into_iter().collect();
+    sorted_series.sort_by(|a, b| match a.0.0.cmp(&b.0.0) {
+        std::cmp::Ordering::Equal => a.0.1.cmp(&b.0.1),
+        other => other,
+    });
+
+    let mut rows = Vec::with_capacity(sorted_series.len());
+    for ((study_uid, series_uid), candidate) in sorted_series {
+        let files = series_files
+            .get(&(study_uid.clone(), series_uid.clone()))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let file_count = files.len();
+        let slice_timing = acquisition::series_slice_timing(files);
+        let multiband_factor = slice_timing
+            .as_deref()
+            .and_then(acquisition::multiband_factor)
+            .map_or("N/A".to_string(), |f| f.to_string());
+        let slice_timing = slice_timing
+            .as_deref()
+            .map_or("N/A".to_string(), acquisition::format_slice_timing);
+        let transfer_syntax = series_transfer_syntax(files);
+
+        // Calculate FOV if pixel spacing and matrix size are available
+        let fov = if candidate.pixel_spacing != "N/A"
+            && candidate.rows != "N/A"
+            && candidate.columns != "N/A"
+        {
+            // Parse pixel spacing (format: "value1\\value2")
+            let spacing_parts: Vec<&str> = candidate.pixel_spacing.split('\\').collect();
+            if spacing_parts.len() >= 2 {
+                if let (Ok(spacing_x), Ok(spacing_y), Ok(rows), Ok(cols)) = (
+                    spacing_parts[0].parse::<f32>(),
+                    spacing_parts[1].parse::<f32>(),
+                    candidate.rows.parse::<f32>(),
+                    candidate.columns.parse::<f32>(),
+                ) {
+                    format!("{:.1}x{:.1}", spacing_x * cols, spacing_y * rows)
+                } else {
+                    "N/A".to_string()
+                }
+            } else {
+                "N/A".to_string()
+            }
+        } else {
+            "N/A".to_string()
+        };
+
+        rows.push(vec![
+            escape_csv_field(&candidate.series_description),
+            escape_csv_field(&candidate.protocol_name),
+            escape_csv_field(&candidate.series_number),
+            escape_csv_field(&candidate.modality),
+            escape_csv_field(&series_uid),
+            escape_csv_field(&candidate.acquisition_type),
+            escape_csv_field(&candidate.pixel_spacing),
+            escape_csv_field(&candidate.slice_thickness),
+            escape_csv_field(&candidate.spacing_between_slices),
+            escape_csv_field(&fov),
+            escape_csv_field(&candidate.repetition_time),
+            escape_csv_field(&candidate.echo_time),
+            escape_csv_field(&candidate.inversion_time),
+            escape_csv_field(&candidate.flip_angle),
+            escape_csv_field(&candidate.number_of_averages),
+            escape_csv_field(&candidate.echo_train_length),
+            escape_csv_field(&candidate.parallel_imaging_factor),
+            escape_csv_field(&candidate.magnetic_field_strength),
+            escape_csv_field(&candidate.image_type),
+            escape_csv_field(&candidate.acquisition_time),
+            escape_csv_field(&candidate.acquisition_duration),
+            escape_csv_field(&candidate.phase_encoding_direction),
+            escape_csv_field(&candidate.effective_echo_spacing),
+            escape_csv_field(&candidate.total_readout_time),
+            escape_csv_field(&slice_timing),
+            escape_csv_field(&multiband_factor),
+            escape_csv_field(&transfer_syntax),
+            escape_csv_field(&candidate.derivation_description),
+            escape_csv_field(&candidate.referenced_series_uid),
+            file_count.to_string(),
+        ]);
+    }
+
+    rows
+}
+
+/// Transfer syntax UIDs of a series' files, backslash-joined when they differ
+fn series_transfer_syntax(files: &[&DeepDicomCandidate]) -> String {
+    let mut uids: Vec<&str> = files.iter().map(|c| c.transfer_syntax.as_str()).collect();
+    uids.sort_unstable();
+    uids.dedup();
+    if uids.is_empty() {
+        "N/A".to_string()
+    } else {
+        uids.join("\\")
+    }
+}
+
+/// Export series metadata to CSV file
+fn export_series_metadata_csv(
+    deep_candidates: &[DeepDicomCandidate],
+    output_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_series_metadata_csv(&series_metadata_rows(deep_candidates), output_dir)
+}
+
+/// Write series_metadata.csv from rows built by [`series_metadata_rows`].
+fn write_series_metadata_csv(
+    rows: &[Vec<String>],
+    output_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::fs::File;
+    use std::io::Write;
+
+    // Create CSV file
+    let csv_path = output_dir.join("series_metadata.csv");
+    let mut csv_file = File::create(&csv_path)?;
+
+    // Write CSV header
+    writeln!(csv_file, "{}", SERIES_METADATA_HEADER)?;
+
+    // Write data rows
+    for row in rows {
+        writeln!(csv_file, "{}", row.join(","))?;
+    }
+
+    info!("Metadata exported to: {}", csv_path.display());
+    Ok(())
+}
+
+/// The first `len` characters of the last UID component. Characters, not bytes: a malformed UID
+/// read as ISO-8859-1 may hold multibyte characters.
+fn short_uid(uid: &str, len: usize) -> String {
+    uid.split('.')
+        .next_back()
+        .unwrap_or(uid)
+        .chars()
+        .take(len)
+        .collect()
+}
+
+/// Build the default output path: Study_[Description]_[ShortUID] / Series_[Number]_[Description]_[ShortUID] / [original name]
+fn default_organized_path(
+    study_uid: &str,
+    series_uid: &str,
+    original_name: &str,
+    study_desc: &str,
+    series_desc: &str,
+    series_num: &str,
+    index: usize,
+) -> PathBuf {
+    let study_folder_name = if study_desc != "N/A" && !study_desc.is_empty() {
+        format!(
+            "Study_{}_{}",
+            sanitize_filename(study_desc),
+            short_uid(study_uid, 8)
+        )
+    } else {
+        format!("Study_{}", short_uid(study_uid, 16))
+    };
+
+    let series_folder_name = if series_desc != "N/A" && !series_desc.is_empty() {
+        let series_prefix = if series_num != "N/A" && !series_num.is_empty() {
+            format!(
+                "Series_{:04}_{}",
+                series_num.parse::<i32>().unwrap_or(0),
+                sanitize_filename(series_desc)
+            )
+        } else {
+            format!("Series_{}", sanitize_filename(series_desc))
+        };
+        format!("{}_{}", series_prefix, short_uid(series_uid, 8))
+    } else {
+        format!("Series_{}", short_uid(series_uid, 16))
+    };
+
+    // Determine output filename (preserve original name if possible)
+    let file_name = PathBuf::from(original_name)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| format!("dicom_{:04}.dcm", index));
+
+    PathBuf::from(study_folder_name)
+        .join(series_folder_name)
+        .join(file_name)
+}
+
+/// Append `_1`, `_2`, ... to the file stem until the path has not been used in this run
+fn unique_output_path(path: PathBuf, used: &mut std::collections::HashSet<PathBuf>) -> PathBuf {
+    if used.insert(path.clone()) {
+        return path;
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut counter = 1;
+    loop {
+        let candidate = path.with_file_name(format!("{}_{}{}", stem, counter, ext));
+        if used.insert(candidate.clone()) {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+/// Extract and organize DICOM files from ZIP archive by series
+fn extract_and_organize_dicoms(
+    zip_bytes: &[u8],
+    deep_candidates: &[DeepDicomCandidate],
+    output_dir: &Path,
+    template: Option<&PathTemplate>,
+    transcode: Option<transcode::Target>,
+) -> Result<Vec<EntryError>, Box<dyn std::error::Error>> {
+    use std::fs;
+    use std::io::Write;
+
+    // Create output directory if it doesn't exist
+    fs::create_dir_all(output_dir)?;
+
+    // Create a map of file indices to their study/series info
+    let mut file_organization = std::collections::HashMap::new();
+    for candidate in deep_candidates {
+        file_organization.insert(
+            candidate.index,
+            (
+                candidate.study_instance_uid.clone(),
+                candidate.series_instance_uid.clone(),
+                candidate.name.clone(),
+                candidate.study_description.clone(),
+                candidate.series_description.clone(),
+                candidate.series_number.clone(),
+            ),
+        );
+    }
+
+    let archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+
+    // Resolve destinations in parallel; only the header is read when a template needs it
+    let mut entries: Vec<_> = file_organization.into_iter().collect();
+    entries.sort_by_key(|(index, _)| *index);
+    let relative_paths: Vec<Result<PathBuf, String>> = entries
+        .par_iter()
+        .map_init(
+            || archive.clone(),
+            |archive,
+             (
+                index,
+                (study_uid, series_uid, original_name, study_desc, series_desc, series_num),
+            )| {
+                match template {
+                    Some(template) => {
+                        let zip_file = archive.by_index(*index).map_err(|e| e.to_string())?;
+                        let dcm_object = OpenFileOptions::new()
+                            .read_until(tags::PIXEL_DATA)
+                            .from_reader(zip_file)
+                            .map_err(|e| format!("{}: {}", original_name, e))?;
+                        Ok(template.render_object(&dcm_object, original_name, *index))
+                    }
+                    None => Ok(default_organized_path(
+                        study_uid,
+                        series_uid,
+                        original_name,
+                        study_desc,
+                        series_desc,
+                        series_num,
+                        *index,
+                    )),
+                }
+            },
+        )
+        .collect();
+
+    // Collisions are resolved in archive order so the numbering is stable between runs
+    let mut used_paths = std::collections::HashSet::new();
+    let mut created_dirs = std::collections::HashSet::new();
+    let mut jobs = Vec::with_capacity(entries.len());
+    for ((index, (_, series_uid, original_name, ..)), relative_path) in
+        entries.iter().zip(relative_paths)
+    {
+        let output_path = unique_output_path(output_dir.join(relative_path?), &mut used_paths);
+        if let Some(parent) = output_path.parent()
+            && created_dirs.insert(parent.to_path_buf())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        jobs.push((*index, series_uid, original_name, output_path));
+    }
+
+    // Stream every entry to disk; each worker decompresses through its own archive handle
+    // and logs inside a series span under the caller's span. An entry that cannot be read is
+    // skipped; an output file that cannot be written fails the archive.
+    let span = tracing::Span::current();
+    let skipped: Vec<EntryError> = jobs
+        .par_iter()
+        .map_init(
+            || archive.clone(),
+            |archive,
+             (index, series_uid, original_name, output_path)|
+             -> Result<Option<EntryError>, String> {
+                let _series =
+                    tracing::info_span!(parent: &span, "series", uid = %series_uid).entered();
+                let (mut zip_file, size) = match archive.by_index(*index) {
+                    Ok(f) => {
+                        let (crc, size) = (f.crc32(), f.size());
+                        (CrcReader::new(f, crc, size), size)
+                    }
+                    Err(e) => return Ok(Some(EntryError::from_zip(original_name.as_str(), &e))),
+                };
+                let mut output_file = fs::File::create(output_path)
+                    .map_err(|e| format!("{}: {}", output_path.display(), e))?;
+                let copied = if let Some(target) = transcode {
+                    // Files that cannot be transcoded are kept in their original syntax
+                    let mut bytes = Vec::with_capacity(size as usize);
+                    zip_file
+                        .read_to_end(&mut bytes)
+                        .map_err(CopyError::Read)
+                        .map(|_| {
+                            transcode::transcode(&bytes, target).unwrap_or_else(|e| {
+                                warn!("Transcode: kept {} as stored: {}", original_name, e);
+                                bytes
+                            })
+                        })
+                        .and_then(|bytes| output_file.write_all(&bytes).map_err(CopyError::Write))
+                } else {
+                    copy_entry(&mut zip_file, &mut output_file)
+                };
+                match copied {
+                    Ok(()) => {
+                        debug!("Extracted: {} -> {}", original_name, output_path.display());
+                        Ok(None)
+                    }
+                    Err(CopyError::Read(e)) => {
+                        drop(output_file);
+                        let _ = fs::remove_file(output_path);
+                        Ok(Some(EntryError::from_io(original_name.as_str(), &e)))
+                    }
+                    Err(CopyError::Write(e)) => Err(format!("{}: {}", output_path.display(), e)),
+                }
+            },
+        )
+        .collect::<Result<Vec<Option<EntryError>>, String>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    info!(
+        files = jobs.len() - skipped.len(),
+        "Files organized in: {}",
+        output_dir.display()
+    );
+
+    // Print summary of organization with descriptive names
+    type SeriesMap = std::collections::HashMap<String, (String, String)>;
+    type StudyMap = std::collections::HashMap<String, (String, SeriesMap)>;
+    let mut study_info: StudyMap = StudyMap::new();
+
+    for candidate in deep_candidates {
+        let series_info = study_info
+            .entry(candidate.study_instance_uid.clone())
+            .or_insert_with(|| {
+                (
+                    candidate.study_description.clone(),
+                    std::collections::HashMap::new(),
+                )
+            });
+
+        series_info.1.insert(
+            candidate.series_instance_uid.clone(),
+            (
+                candidate.series_description.clone(),
+                candidate.series_number.clone(),
+            ),
+        );
+    }
+
+    info!("Organized {} studies", study_info.len());
+    for (study_uid, (study_desc, series_map)) in &study_info {
+        let study_display = if study_desc != "N/A" && !study_desc.is_empty() {
+            study_desc.clone()
+        } else {
+            format!("UID: {}", short_uid(study_uid, 16))
+        };
+        info!("Study [{}]: {} series", study_display, series_map.len());
+    }
+
+    Ok(skipped)
+}
+
+enum CopyError {
+    /// The archive entry could not be decompressed
+    Read(std::io::Error),
+    /// The output file could not be written
+    Write(std::io::Error),
+}
+
+/// Stream an archive entry into a file, keeping read and write failures apart.
+fn copy_entry(entry: &mut impl Read, out: &mut impl std::io::Write) -> Result<(), CopyError> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = match entry.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CopyError::Read(e)),
+        };
+        out.write_all(&buffer[..n]).map_err(CopyError::Write)?;
+    }
+}
+
+/// Memory-map an archive read-only.
+/// Entries are decompressed from the mapping on demand instead of loading the whole file.
+fn map_archive(path: &Path) -> Result<memmap2::Mmap, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    // SAFETY: the mapping is read-only and only handed out as a byte slice. Nothing stops another
+    // process from truncating the file meanwhile, and touching pages past the new end then raises
+    // SIGBUS instead of an error; inputs that may still be written go through `SettledArchive`
+    let mmap = unsafe { memmap2::Mmap::map(&file) }
+        .map_err(|e| format!("Failed to map {}: {}", path.display(), e))?;
+    Ok(mmap)
+}
+
+/// An archive that another process may still be writing (watch folders, serve paths), mapped
+/// under a shared lock once its size and modification time have held still for the settle time.
+/// Writers that lock the file are kept out until this is dropped.
+struct SettledArchive {
+    mmap: memmap2::Mmap,
+    /// Holds the shared lock
+    _file: std::fs::File,
+}
+
+impl SettledArchive {
+    fn open(path: &Path, settle: Duration) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        file.try_lock_shared()
+            .map_err(|e| format!("{} is locked by a writer: {}", path.display(), e))?;
+        let before = file.metadata()?;
+        if !settle.is_zero() {
+            std::thread::sleep(settle);
+        }
+        let after = file.metadata()?;
+        if before.len() != after.len() || before.modified().ok() != after.modified().ok() {
+            return Err(format!("{} is still being written", path.display()).into());
+        }
+        // SAFETY: as in `map_archive`; the lock and the settle check keep out cooperating writers
+        // and files still being copied, not a process that truncates the file regardless
+        let mmap = unsafe { memmap2::Mmap::map(&file) }
+            .map_err(|e| format!("Failed to map {}: {}", path.display(), e))?;
+        Ok(SettledArchive { mmap, _file: file })
+    }
+}
+
+impl std::ops::Deref for SettledArchive {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.mmap
+    }
+}
+
+/// Sanitize a string to be used as a filename
+fn sanitize_filename(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Parsed templates, rules and opened databases of the pipeline subcommands
+struct Pipeline {
+    template: Option<PathTemplate>,
+    filter: Option<filter::Filter>,
+    bids_rules: Vec<bids::Rule>,
+    bids_subjects: bids::Subjects,
+    scan_index: Option<std::sync::Mutex<ScanIndex>>,
+    hierarchy_db: Option<std::sync::Mutex<sqlite_export::HierarchyDb>>,
+}
+
+impl Pipeline {
+    /// Validate the options and open the databases before doing any expensive work.
+    fn open(
+        organize: Option<&OrganizeArgs>,
+        filter: Option<&str>,
+        index: Option<&Path>,
+        sqlite: Option<&Path>,
+    ) -> Result<Pipeline, Box<dyn std::error::Error>> {
+        let template = organize
+            .and_then(|o| o.template.as_deref().map(|t| (t, o.max_component_len)))
+            .map(|(t, max_len)| PathTemplate::parse(t).map(|t| t.with_max_component_len(max_len)))
+            .transpose()?;
+        if let Some(o) = organize
+            && o.transcode.is_some_and(|target| !target.is_readable())
+            && (o.nifti.is_some() || o.bids.is_some())
+        {
+            return Err(
+                "--nifti and --bids cannot read deflated files, nor JPEG-LS files without the charls feature; use --transcode explicit or rle"
+                    .into(),
+            );
+        }
+        let (bids_rules, bids_subjects) = match organize {
+            Some(o) if o.bids.is_some() => (
+                bids::load_rules(o.bids_rules.as_deref())?,
+                bids::load_subjects(o.bids_subjects.as_deref())?,
+            ),
+            _ => (Vec::new(), bids::Subjects::default()),
+        };
+        Ok(Pipeline {
+            template,
+            filter: filter.map(filter::Filter::parse).transpose()?,
+            bids_rules,
+            bids_subjects,
+            scan_index: index
+                .map(ScanIndex::open)
+                .transpose()?
+                .map(std::sync::Mutex::new),
+            hierarchy_db: sqlite
+                .map(sqlite_export::HierarchyDb::open)
+                .transpose()?
+                .map(std::sync::Mutex::new),
+        })
+    }
+
+    fn options<'a>(&'a self, organize: Option<&'a OrganizeArgs>) -> batch::BatchOptions<'a> {
+        batch::BatchOptions {
+            template: self.template.as_ref(),
+            scan_index: self.scan_index.as_ref(),
+            hierarchy_db: self.hierarchy_db.as_ref(),
+            filter: self.filter.as_ref(),
+            bids_rules: &self.bids_rules,
+            bids_subjects: Some(&self.bids_subjects),
+            dicom_media: organize.is_some_and(|o| o.dicom_media),
+            transcode: organize.and_then(|o| o.transcode),
+            preview: organize.filter(|o| o.preview).map(|o| o.preview_slices),
+            nifti: organize.and_then(|o| o.nifti.as_deref()),
+            bids: organize.and_then(|o| o.bids.as_deref()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Parse the command line and run the chosen subcommand.
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    logging::init(args.verbose, args.quiet, args.log_format);
+
+    match &args.command {
+        Command::Scan { input } => {
+            let pipeline =
+                Pipeline::open(None, input.filter.as_deref(), input.index.as_deref(), None)?;
+            let (inputs, _) = input.resolve()?;
+            let options = batch::BatchOptions {
+                jobs: input.jobs,
+                ..pipeline.options(None)
+            };
+            scan::scan_archives(&inputs, &options)
+        }
+
+        Command::Mrn { input } => {
+            let pipeline =
+                Pipeline::open(None, input.filter.as_deref(), input.index.as_deref(), None)?;
+            let (inputs, batch_mode) = input.resolve()?;
+            let options = batch::BatchOptions {
+                jobs: input.jobs,
+                ..pipeline.options(None)
+            };
+            scan::print_mrns(&inputs, batch_mode, &options)
+        }
+
+        Command::Organize {
+            input,
+            output,
+            organize,
+            report_dir,
+        } => {
+            let pipeline = Pipeline::open(
+                Some(organize),
+                input.filter.as_deref(),
+                input.index.as_deref(),
+                None,
+            )?;
+            let (inputs, batch_mode) = input.resolve()?;
+            let report_dir = report_dir.as_deref().unwrap_or(output);
+            batch::run(
+                &inputs,
+                batch_mode,
+                &batch::BatchOptions {
+                    output: Some(output),
+                    report_dir: Some(report_dir),
+                    jobs: input.jobs,
+                    ..pipeline.options(Some(organize))
+                },
+            )
+        }
+
+        Command::Xprot { input, output } => {
+            let pipeline =
+                Pipeline::open(None, input.filter.as_deref(), input.index.as_deref(), None)?;
+            let (inputs, batch_mode) = input.resolve()?;
+            batch::run(
+                &inputs,
+                batch_mode,
+                &batch::BatchOptions {
+                    xprot: Some(output),
+                    jobs: input.jobs,
+                    ..pipeline.options(None)
+                },
+            )
+        }
+
+        Command::Derivations { input } => analyze_derivations(input),
+
+        Command::Dicomdir { input } => {
+            match dicomdir::load_dicomdir_index(input)? {
+                Some((index, present)) => {
+                    let check = index.cross_check(&present);
+                    dicomdir::print_dicomdir_listing(&index, &check);
+                }
+                None => warn!("No DICOMDIR found in {}", input.display()),
+            }
+            Ok(())
+        }
+
+        Command::Export {
+            input,
+            sqlite,
+            parquet,
+            report_dir,
+        } => {
+            let pipeline = Pipeline::open(
+                None,
+                input.filter.as_deref(),
+                input.index.as_deref(),
+                sqlite.as_deref(),
+            )?;
+            let (inputs, _) = input.resolve()?;
+            // Exports are keyed by archive, so even a single archive is run as a batch
+            batch::run(
+                &inputs,
+                true,
+                &batch::BatchOptions {
+                    parquet: parquet.as_deref(),
+                    report_dir: report_dir.as_deref(),
+                    jobs: input.jobs,
+                    ..pipeline.options(None)
+                },
+            )
+        }
+
+        Command::Sort {
+            input,
+            output,
+            layout,
+            move_files,
+        } => {
+            let layout = PathTemplate::parse(layout)?;
+            sort::sort_archives(input, output, &layout, *move_files)
+        }
+
+        Command::Index {
+            db,
+            archives,
+            patient_id,
+            study_uid,
+            modality,
+            archive,
+        } => {
+            let query = scan_index::IndexQuery {
+                patient_id: patient_id.clone(),
+                study_uid: study_uid.clone(),
+                modality: modality.clone(),
+                archive: archive.clone(),
+            };
+            scan_index::print_index_query(db, &query, *archives)
+        }
+
+        Command::Nifti {
+            input,
+            output,
+            bids,
+            rules,
+            subjects,
+        } => {
+            if *bids {
+                let rules = bids::load_rules(rules.as_deref())?;
+                let subjects = bids::load_subjects(subjects.as_deref())?;
+                return bids::convert_directory(input, output, &rules, &subjects);
+            }
+            nifti::convert_directory(input, output)
+        }
+
+        Command::Listen {
+            output,
+            bind,
+            port,
+            ae_title,
+            allowed,
+            allow_any,
+            idle_timeout,
+            template,
+            max_component_len,
+        } => {
+            let template = template
+                .as_deref()
+                .map(|t| {
+                    PathTemplate::parse(t).map(|t| t.with_max_component_len(*max_component_len))
+                })
+                .transpose()?;
+            listen::listen(&listen::ListenOptions {
+                output,
+                bind,
+                port: *port,
+                ae_title,
+                allowed,
+                allow_any: *allow_any,
+                idle_timeout: Duration::from_secs(*idle_timeout),
+                template: template.as_ref(),
+            })
+        }
+
+        Command::Fetch {
+            output,
+            host,
+            called_ae_title,
+            ae_title,
+            patient_id,
+            study_date,
+            accession,
+            level,
+            method,
+            port,
+            template,
+            max_component_len,
+            query_only,
+        } => {
+            let template = template
+                .as_deref()
+                .map(|t| {
+                    PathTemplate::parse(t).map(|t| t.with_max_component_len(*max_component_len))
+                })
+                .transpose()?;
+            fetch::fetch(&fetch::FetchOptions {
+                output,
+                peer: host,
+                called_ae_title,
+                ae_title,
+                patient_id: patient_id.as_deref(),
+                study_date: study_date.as_deref(),
+                accession: accession.as_deref(),
+                level: *level,
+                method: *method,
+                port: *port,
+                template: template.as_ref(),
+                query_only: *query_only,
+            })
+        }
+
+        Command::DicomwebFetch {
+            output,
+            url,
+            token,
+            patient_id,
+            study_date,
+            accession,
+            level,
+            template,
+            max_component_len,
+            query_only,
+        } => {
+            let template = template
+                .as_deref()
+                .map(|t| {
+                    PathTemplate::parse(t).map(|t| t.with_max_component_len(*max_component_len))
+                })
+                .transpose()?;
+            dicomweb::fetch(&dicomweb::WebFetchOptions {
+                output,
+                url,
+                token: token.as_deref(),
+                patient_id: patient_id.as_deref(),
+                study_date: study_date.as_deref(),
+                accession: accession.as_deref(),
+                level: *level,
+                template: template.as_ref(),
+                query_only: *query_only,
+            })
+        }
+
+        Command::DicomwebServe {
+            input,
+            bind,
+            port,
+            cors_origin,
+        } => dicomweb::serve(&dicomweb::WebServeOptions {
+            input,
+            bind,
+            port: *port,
+            cors_origin: cors_origin.as_deref(),
+        }),
+
+        Command::Serve {
+            bind,
+            port,
+            root,
+            workers,
+            max_upload_mb,
+            cors_origin,
+            keep_minutes,
+        } => serve::serve(&serve::ServeOptions {
+            bind,
+            port: *port,
+            root,
+            workers: *workers,
+            max_upload: max_upload_mb
+                .checked_mul(1024 * 1024)
+                .ok_or("--max-upload-mb is too large")?,
+            cors_origin: cors_origin.as_deref(),
+            keep: Duration::from_secs(keep_minutes.saturating_mul(60)),
+        }),
+
+        Command::Watch {
+            dirs,
+            output,
+            organize,
+            xprot,
+            sqlite,
+            index,
+            filter,
+            report_dir,
+            done,
+            failed,
+            journal,
+            settle,
+            poll,
+            once,
+        } => {
+            if organize.bids.is_some() {
+                return Err("--bids covers a whole run and is not supported by watch".into());
+            }
+            let pipeline = Pipeline::open(
+                Some(organize),
+                filter.as_deref(),
+                index.as_deref(),
+                sqlite.as_deref(),
+            )?;
+            let report_dir = report_dir
+                .clone()
+                .or_else(|| output.clone())
+                .unwrap_or_else(|| PathBuf::from("."));
+            let journal = journal
+                .clone()
+                .unwrap_or_else(|| report_dir.join("watch_journal.jsonl"));
+            watch::watch(
+                &watch::WatchOptions {
+                    dirs,
+                    done: done.as_deref(),
+                    failed: failed.as_deref(),
+                    journal: &journal,
+                    report_dir: &report_dir,
+                    settle: Duration::from_secs(*settle),
+                    poll: Duration::from_secs(*poll),
+                    once: *once,
+                },
+                &batch::BatchOptions {
+                    output: output.as_deref(),
+                    xprot: xprot.as_deref(),
+                    ..pipeline.options(Some(organize))
+                },
+            )
+        }
+    }
+}
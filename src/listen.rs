@@ -0,0 +1,459 @@
+// --- DICOM storage receiver ---
+//
+// `listen` runs a Storage SCP: peers on the allow list send instances with C-STORE, each one is
+// written to the organized layout (default Study_*/Series_* folders or --template) and its
+// deep-scan record is kept. A study counts as complete once nothing new arrived for the idle
+// timeout; series_metadata.csv is then rewritten for everything received so far. `fetch` uses the
+// same storage for the instances it retrieves.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use dicom::dictionary_std::tags;
+use dicom::object::{FileMetaTableBuilder, OpenFileOptions};
+use dicom::ul::ServerAssociation;
+use dicom::ul::association::server::{AccessControl, ServerAssociationOptions};
+use dicom::ul::pdu::{AssociationRJServiceUserReason, UserIdentity};
+use tracing::{debug, error, info, warn};
+
+use crate::dimse::{self, Incoming, Link, Message};
+use crate::template::PathTemplate;
+use crate::{
+    DeepDicomCandidate, deep_candidate_from_object, default_organized_path, get_tag_string,
+    series_metadata_rows, unique_output_path, write_series_metadata_csv,
+};
+
+pub struct ListenOptions<'a> {
+    pub output: &'a Path,
+    /// Address to listen on
+    pub bind: &'a str,
+    pub port: u16,
+    pub ae_title: &'a str,
+    /// Calling AE titles allowed to associate
+    pub allowed: &'a [String],
+    /// Accept any calling AE title instead of `allowed`
+    pub allow_any: bool,
+    pub idle_timeout: Duration,
+    pub template: Option<&'a PathTemplate>,
+}
+
+/// Accepts associations addressed to our AE title from the configured callers.
+pub struct AllowedCallers<'a> {
+    /// Calling AE titles accepted; `None` accepts any
+    titles: Option<&'a [String]>,
+}
+
+impl AccessControl for AllowedCallers<'_> {
+    fn check_access(
+        &self,
+        this_ae_title: &str,
+        calling_ae_title: &str,
+        called_ae_title: &str,
+        _user_identity: Option<&UserIdentity>,
+    ) -> Result<(), AssociationRJServiceUserReason> {
+        if called_ae_title.trim() != this_ae_title {
+            return Err(AssociationRJServiceUserReason::CalledAETitleNotRecognized);
+        }
+        if let Some(titles) = self.titles
+            && !titles.iter().any(|t| t == calling_ae_title.trim())
+        {
+            return Err(AssociationRJServiceUserReason::CallingAETitleNotRecognized);
+        }
+        Ok(())
+    }
+}
+
+/// Storage SCP accepting any storage SOP class and C-ECHO from the `allowed` calling AE titles
+/// (any caller when `None`)
+pub fn storage_scp<'a>(
+    ae_title: &str,
+    allowed: Option<&'a [String]>,
+) -> ServerAssociationOptions<'a, AllowedCallers<'a>> {
+    ServerAssociationOptions::new()
+        .ae_access_control(AllowedCallers { titles: allowed })
+        .ae_title(ae_title.to_string())
+        .with_abstract_syntax(dimse::VERIFICATION_SOP_CLASS)
+        .promiscuous(true)
+}
+
+/// Organized output for received instances, and everything received so far
+pub struct Storage<'a> {
+    output: &'a Path,
+    template: Option<&'a PathTemplate>,
+    received: Mutex<Received>,
+}
+
+#[derive(Default)]
+struct Received {
+    /// SOPInstanceUID -> file; a resent instance replaces its file
+    instances: HashMap<String, PathBuf>,
+    used_paths: HashSet<PathBuf>,
+    studies: HashMap<String, StudyActivity>,
+    /// series_metadata.csv rows of the exported studies, by StudyInstanceUID
+    rows: BTreeMap<String, Vec<Vec<String>>>,
+}
+
+struct StudyActivity {
+    last_instance: Instant,
+    /// Instances received since the metadata was last exported
+    pending: usize,
+    files: BTreeSet<PathBuf>,
+}
+
+impl<'a> Storage<'a> {
+    pub fn new(output: &'a Path, template: Option<&'a PathTemplate>) -> Self {
+        Storage {
+            output,
+            template,
+            received: Mutex::new(Received::default()),
+        }
+    }
+
+    fn received(&self) -> Result<MutexGuard<'_, Received>, Box<dyn std::error::Error>> {
+        self.received
+            .lock()
+            .map_err(|_| "receiver state poisoned".into())
+    }
+
+    /// Store the data set of a C-STORE request and return the response status.
+    pub fn answer(&self, message: &Message, transfer_syntax: &str) -> u16 {
+        match self.store(message, transfer_syntax) {
+            Ok(path) => {
+                debug!("Received: {}", path.display());
+                dimse::STATUS_SUCCESS
+            }
+            Err(e) => {
+                error!(
+                    "Store of {} failed: {}",
+                    message.string(tags::AFFECTED_SOP_INSTANCE_UID),
+                    e
+                );
+                dimse::STATUS_OUT_OF_RESOURCES
+            }
+        }
+    }
+
+    /// Write one received data set as a DICOM file in the organized layout.
+    fn store(
+        &self,
+        message: &Message,
+        transfer_syntax: &str,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let data = message
+            .data
+            .as_deref()
+            .ok_or("C-STORE without a data set")?;
+        let sop_class = message.string(tags::AFFECTED_SOP_CLASS_UID);
+        let sop_instance = message.string(tags::AFFECTED_SOP_INSTANCE_UID);
+
+        let meta = FileMetaTableBuilder::new()
+            .media_storage_sop_class_uid(sop_class.as_str())
+            .media_storage_sop_instance_uid(sop_instance.as_str())
+            .transfer_syntax(transfer_syntax)
+            .build()?;
+        let mut file = vec![0u8; 128];
+        file.extend_from_slice(b"DICM");
+        meta.write(&mut file)?;
+        file.extend_from_slice(data);
+        self.store_file(file)
+    }
+
+    /// Write one DICOM file (e.g. a WADO-RS part) in the organized layout.
+    pub fn store_file(&self, file: Vec<u8>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let header = OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .from_reader(&file[..])?;
+        let sop_instance = header
+            .meta()
+            .media_storage_sop_instance_uid()
+            .trim_end_matches('\0')
+            .to_string();
+        let name = format!("{}.dcm", sop_instance);
+        let study_uid = get_tag_string(&header, tags::STUDY_INSTANCE_UID);
+
+        // The path is built without the lock and only claimed under it, so a header that fails
+        // to render cannot poison it; the file is written without it too
+        let (existing, index) = {
+            let received = self.received()?;
+            (
+                received.instances.get(&sop_instance).cloned(),
+                received.instances.len(),
+            )
+        };
+        let path = match existing {
+            Some(path) => path,
+            None => {
+                let relative = match self.template {
+                    Some(template) => template.render_object(&header, &name, index),
+                    None => default_organized_path(
+                        &study_uid,
+                        &get_tag_string(&header, tags::SERIES_INSTANCE_UID),
+                        &name,
+                        &get_tag_string(&header, tags::STUDY_DESCRIPTION),
+                        &get_tag_string(&header, tags::SERIES_DESCRIPTION),
+                        &get_tag_string(&header, tags::SERIES_NUMBER),
+                        index,
+                    ),
+                };
+                let mut received = self.received()?;
+                // Another association may have stored the same instance in the meantime
+                match received.instances.get(&sop_instance) {
+                    Some(path) => path.clone(),
+                    None => {
+                        let path = unique_output_path(
+                            self.output.join(relative),
+                            &mut received.used_paths,
+                        );
+                        received.instances.insert(sop_instance, path.clone());
+                        path
+                    }
+                }
+            }
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &file)?;
+
+        let mut received = self.received()?;
+        let study = received
+            .studies
+            .entry(study_uid)
+            .or_insert_with(|| StudyActivity {
+                last_instance: Instant::now(),
+                pending: 0,
+                files: BTreeSet::new(),
+            });
+        study.last_instance = Instant::now();
+        study.pending += 1;
+        study.files.insert(path.clone());
+        Ok(path)
+    }
+
+    /// Rewrite the series metadata once a study with new instances has been idle long enough.
+    fn export_idle_studies(&self, idle_timeout: Duration) {
+        let completed: Vec<(String, Vec<PathBuf>)> = {
+            let Ok(mut received) = self.received() else {
+                return;
+            };
+            received
+                .studies
+                .iter_mut()
+                .filter(|(_, study)| {
+                    study.pending > 0 && study.last_instance.elapsed() >= idle_timeout
+                })
+                .map(|(study_uid, study)| {
+                    study.pending = 0;
+                    (study_uid.clone(), study.files.iter().cloned().collect())
+                })
+                .collect()
+        };
+        if completed.is_empty() {
+            return;
+        }
+
+        for (study_uid, files) in &completed {
+            info!(
+                "Study complete: {} ({} instance(s) received)",
+                study_uid,
+                files.len()
+            );
+        }
+        if let Err(e) = self.export(completed) {
+            error!("Metadata export failed: {}", e);
+        }
+    }
+
+    /// Number of instances stored so far
+    pub fn instance_count(&self) -> usize {
+        self.received().map_or(0, |r| r.instances.len())
+    }
+
+    /// Write series_metadata.csv for everything received.
+    pub fn export_metadata(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let studies = self
+            .received()?
+            .studies
+            .iter_mut()
+            .map(|(study_uid, study)| {
+                study.pending = 0;
+                (study_uid.clone(), study.files.iter().cloned().collect())
+            })
+            .collect();
+        self.export(studies)
+    }
+
+    /// Rebuild the series rows of the given studies from their files, then rewrite
+    /// series_metadata.csv with the rows of every exported study.
+    fn export(
+        &self,
+        studies: Vec<(String, Vec<PathBuf>)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let rebuilt: Vec<(String, Vec<Vec<String>>)> = studies
+            .into_iter()
+            .map(|(study_uid, files)| (study_uid, study_rows(&files)))
+            .collect();
+        let rows: Vec<Vec<String>> = {
+            let mut received = self.received()?;
+            received.rows.extend(rebuilt);
+            received.rows.values().flatten().cloned().collect()
+        };
+        write_series_metadata_csv(&rows, self.output)
+    }
+}
+
+/// Series rows of one study, from the headers of its stored files
+fn study_rows(files: &[PathBuf]) -> Vec<Vec<String>> {
+    let candidates: Vec<DeepDicomCandidate> = files
+        .iter()
+        .enumerate()
+        .filter_map(|(index, path)| {
+            let size = fs::metadata(path).ok()?.len();
+            let header = match OpenFileOptions::new()
+                .read_until(tags::PIXEL_DATA)
+                .open_file(path)
+            {
+                Ok(header) => header,
+                Err(e) => {
+                    warn!("Cannot re-read {}: {}", path.display(), e);
+                    return None;
+                }
+            };
+            let name = path.file_name()?.to_string_lossy().to_string();
+            deep_candidate_from_object(header, index, name, size, size)
+        })
+        .collect();
+    series_metadata_rows(&candidates)
+}
+
+/// Run the receiver until the process is stopped.
+pub fn listen(options: &ListenOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if options.allowed.is_empty() && !options.allow_any {
+        return Err("listen needs --allow <calling AE title> (or --allow-any)".into());
+    }
+    fs::create_dir_all(options.output)?;
+    let listener = TcpListener::bind((options.bind, options.port))?;
+    let scp = storage_scp(
+        options.ae_title,
+        (!options.allow_any).then_some(options.allowed),
+    );
+    let storage = Storage::new(options.output, options.template);
+
+    info!(
+        "Listening on {}:{} as {} (study idle timeout {}s)",
+        options.bind,
+        options.port,
+        options.ae_title,
+        options.idle_timeout.as_secs()
+    );
+    if options.allow_any {
+        info!("Accepting calls from any AE title");
+    } else {
+        info!("Accepting calls from: {}", options.allowed.join(", "));
+    }
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            loop {
+                std::thread::sleep(Duration::from_secs(1));
+                storage.export_idle_studies(options.idle_timeout);
+            }
+        });
+        accept(&listener, &scp, &[], &storage, &AtomicBool::new(false));
+    });
+    Ok(())
+}
+
+/// Serve associations on `listener`, each on its own thread, until `stop` is set. Returns once
+/// every association has ended. Connections from outside `peers` are closed (empty accepts any).
+pub fn accept(
+    listener: &TcpListener,
+    scp: &ServerAssociationOptions<AllowedCallers>,
+    peers: &[IpAddr],
+    storage: &Storage,
+    stop: &AtomicBool,
+) {
+    if let Err(e) = listener.set_nonblocking(true) {
+        error!("Listener setup failed: {}", e);
+        return;
+    }
+    std::thread::scope(|scope| {
+        while !stop.load(Ordering::Relaxed) {
+            let (stream, address) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Connection failed: {}", e);
+                    continue;
+                }
+            };
+            if !peers.is_empty() && !peers.contains(&address.ip()) {
+                warn!("Connection from {} refused: address not allowed", address);
+                continue;
+            }
+            let peer = address.to_string();
+            scope.spawn(move || {
+                let established = stream
+                    .set_nonblocking(false)
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| scp.establish(stream).map_err(|e| e.to_string()));
+                match established {
+                    Ok(association) => serve(association, storage),
+                    Err(e) => warn!("Association from {} refused: {}", peer, e),
+                }
+            });
+        }
+    });
+}
+
+/// Answer requests on one association until it is released or aborted.
+fn serve(mut association: ServerAssociation<TcpStream>, storage: &Storage) {
+    let calling = association.client_ae_title().trim().to_string();
+    info!("Association from {}", calling);
+    let mut stored = 0;
+
+    loop {
+        let message = match dimse::receive(&mut association) {
+            Ok(Incoming::Message(message)) => message,
+            Ok(Incoming::Released) | Ok(Incoming::Aborted) => break,
+            Err(e) => {
+                warn!("Association from {}: {}", calling, e);
+                return;
+            }
+        };
+
+        let (field, status) = match message.field() {
+            dimse::C_ECHO_RQ => (dimse::C_ECHO_RSP, dimse::STATUS_SUCCESS),
+            dimse::C_STORE_RQ => {
+                let transfer_syntax = association
+                    .transfer_syntax(message.context_id)
+                    .unwrap_or_default();
+                let status = storage.answer(&message, &transfer_syntax);
+                if status == dimse::STATUS_SUCCESS {
+                    stored += 1;
+                }
+                (dimse::C_STORE_RSP, status)
+            }
+            other => (other | 0x8000, dimse::STATUS_UNRECOGNIZED_OPERATION),
+        };
+        let sent = dimse::response(&message, field, status, false)
+            .and_then(|rsp| dimse::send(&mut association, message.context_id, &rsp, None));
+        if let Err(e) = sent {
+            warn!("Association from {}: {}", calling, e);
+            return;
+        }
+    }
+    info!(
+        "Association from {} closed ({} instance(s))",
+        calling, stored
+    );
+}
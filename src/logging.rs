@@ -0,0 +1,56 @@
+// --- Logging ---
+//
+// Diagnostics go to stderr through `tracing`; stdout stays reserved for command output.
+// Work is wrapped in spans (archive → series → file) so every event carries its context.
+
+use std::io::IsTerminal;
+
+use tracing_subscriber::EnvFilter;
+
+/// Format of the diagnostics written to stderr
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One human-readable line per event
+    Text,
+    /// One JSON object per event, with its enclosing spans and a timestamp
+    Json,
+}
+
+/// Map -v/-q counts to a level: -qq error, -q warn, default info, -v debug, -vv trace.
+fn level(verbose: u8, quiet: u8) -> &'static str {
+    match i16::from(verbose) - i16::from(quiet) {
+        ..=-2 => "error",
+        -1 => "warn",
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    }
+}
+
+/// Install the stderr subscriber. RUST_LOG, when set, replaces the level chosen by -v/-q.
+pub fn init(verbose: u8, quiet: u8, format: LogFormat) {
+    let level = level(verbose, quiet);
+    // Dependencies only report warnings unless RUST_LOG asks for more
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        let deps = if level == "error" { "error" } else { "warn" };
+        EnvFilter::new(format!("{deps},dicom_scanner={level}"))
+    });
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_target(false);
+
+    match format {
+        LogFormat::Text => builder
+            .without_time()
+            .with_ansi(std::io::stderr().is_terminal())
+            .init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .init(),
+    }
+}
@@ -0,0 +1,1270 @@
+// --- NIfTI-1 conversion ---
+//
+// Converts extracted DICOM files into one .nii.gz per series (and per echo), replacing the
+// external dcm2niix step. Slices are ordered along the slice normal by ImagePositionPatient,
+// repeated positions become volumes, Siemens mosaics are unpacked, and the orientation is
+// written to both sform and qform in NIfTI's RAS+ convention. Stored values are kept with
+// RescaleSlope/Intercept in scl_slope/scl_inter unless the rescale differs between slices.
+// Diffusion series also get FSL .bval/.bvec files.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use dicom::dictionary_std::tags;
+use dicom::object::OpenFileOptions;
+use dicom::pixeldata::{ConvertOptions, ModalityLutOption, PixelDecoder};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rayon::prelude::*;
+use serde_json::{Map, Value, json};
+use tracing::{Span, info, info_span, warn};
+
+use crate::acquisition;
+use crate::{
+    DeepDicomCandidate, csa_image_header, csa_values, deep_candidate_from_object, get_tag_string,
+    sanitize_filename,
+};
+
+/// Positions closer than this along the slice normal are treated as the same slice (mm)
+const POSITION_TOLERANCE: f64 = 0.01;
+
+/// Header fields of one extracted DICOM file needed for conversion
+pub struct Slice {
+    path: PathBuf,
+    pub candidate: DeepDicomCandidate,
+    position: Option<[f64; 3]>,
+    orientation: Option<[f64; 6]>,
+    /// Row spacing, column spacing (PixelSpacing order)
+    spacing: [f64; 2],
+    rows: usize,
+    columns: usize,
+    frames: usize,
+    samples_per_pixel: usize,
+    bits_allocated: usize,
+    signed: bool,
+    slope: f64,
+    intercept: f64,
+    echo: String,
+    phase: bool,
+    acquisition_number: i64,
+    temporal_position: i64,
+    instance_number: i64,
+    /// Number of tiles when the file is a Siemens mosaic
+    mosaic: Option<usize>,
+    /// CSA SliceNormalVector, which gives the tile order of a mosaic
+    slice_normal: Option<[f64; 3]>,
+    /// b-value and gradient direction of a diffusion-weighted image
+    diffusion: Option<(f64, Option<[f64; 3]>)>,
+    pub scanning_sequence: String,
+    pub sequence_variant: String,
+    pub sequence_name: String,
+    pub patient_sex: String,
+    pub patient_age: String,
+    /// Derived acquisition fields for the sidecar (phase encoding, readout and slice timing)
+    pub acquisition: Map<String, Value>,
+}
+
+/// A run of slices that becomes one NIfTI file
+struct Volume<'a> {
+    /// slices[t][z]: volume t, slice z (mosaics hold one file per volume)
+    slices: Vec<Vec<&'a Slice>>,
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    /// LPS affine: columns i, j, k and the origin
+    affine: [[f64; 4]; 3],
+}
+
+enum Outcome {
+    Written(PathBuf, [usize; 4]),
+    Skipped(String, String),
+}
+
+/// The slices of one series that become one NIfTI file (a single echo, magnitude or phase)
+pub struct SeriesGroup<'a> {
+    pub slices: Vec<&'a Slice>,
+    /// 1-based echo index in TE order, when the series has more than one echo
+    pub echo_number: Option<usize>,
+    pub phase: bool,
+}
+
+/// A group with its output path (without extension) and extra sidecar fields
+pub struct ConversionJob<'a> {
+    pub group: &'a SeriesGroup<'a>,
+    pub base: PathBuf,
+    pub sidecar: Map<String, Value>,
+}
+
+/// Convert every series found below `input_dir` into `.nii.gz` + `.json` files in `output_dir`.
+pub fn convert_directory(
+    input_dir: &Path,
+    output_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let slices = read_slices(input_dir)?;
+    let groups = group_series(&slices);
+
+    let mut used_names = HashSet::new();
+    let mut jobs = Vec::new();
+    for group in &groups {
+        let c = &group.slices[0].candidate;
+        let mut name = format!(
+            "{}_{}",
+            c.series_number.trim().parse::<i64>().unwrap_or(0),
+            if c.series_description != "N/A" {
+                &c.series_description
+            } else {
+                &c.protocol_name
+            }
+        )
+        .replace(' ', "_");
+        if let Some(echo_number) = group.echo_number {
+            name.push_str(&format!("_e{}", echo_number));
+        }
+        if group.phase {
+            name.push_str("_ph");
+        }
+        let subject = if c.patient_id != "N/A" {
+            c.patient_id.trim()
+        } else {
+            "unknown"
+        };
+        let base = PathBuf::from(sanitize_filename(subject)).join(sanitize_filename(&name));
+
+        let mut unique = base.clone();
+        let mut counter = 1;
+        while !used_names.insert(unique.clone()) {
+            unique = PathBuf::from(format!("{}_{}", base.display(), counter));
+            counter += 1;
+        }
+        jobs.push(ConversionJob {
+            group,
+            base: unique,
+            sidecar: Map::new(),
+        });
+    }
+
+    let (written, skipped) = write_series(&jobs, output_dir);
+    info!(
+        "NIfTI conversion: {} file(s) written to {}, {} series skipped",
+        written,
+        output_dir.display(),
+        skipped
+    );
+
+    Ok(())
+}
+
+/// Read the header of every DICOM file below `input_dir`, skipping repeated SOP instances.
+pub fn read_slices(input_dir: &Path) -> Result<Vec<Slice>, Box<dyn std::error::Error>> {
+    if !input_dir.is_dir() {
+        return Err(format!("Input path does not exist: {}", input_dir.display()).into());
+    }
+
+    let mut paths = Vec::new();
+    let mut stack = vec![input_dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+
+    let mut slices: Vec<Slice> = paths
+        .par_iter()
+        .filter_map(|path| read_slice(path, input_dir))
+        .collect();
+
+    // The same instance extracted twice (e.g. from overlapping archives) is only used once
+    let mut seen = HashSet::new();
+    slices.retain(|s| {
+        let uid = &s.candidate.sop_instance_uid;
+        uid == "N/A" || seen.insert(uid.clone())
+    });
+    Ok(slices)
+}
+
+/// Group slices by patient, study and series, then by echo and magnitude / phase.
+pub fn group_series(slices: &[Slice]) -> Vec<SeriesGroup<'_>> {
+    type GroupKey = (String, String, String, bool);
+    let mut groups: BTreeMap<GroupKey, Vec<&Slice>> = BTreeMap::new();
+    for slice in slices {
+        let c = &slice.candidate;
+        let series_number = c.series_number.trim().parse::<i64>().unwrap_or(0);
+        groups
+            .entry((
+                format!(
+                    "{}\t{}\t{:010}",
+                    c.patient_id, c.study_instance_uid, series_number
+                ),
+                c.series_instance_uid.clone(),
+                slice.echo.clone(),
+                slice.phase,
+            ))
+            .or_default()
+            .push(slice);
+    }
+
+    // Echoes are numbered 1.. in TE order; numbers are only needed for multi-echo series
+    let mut echoes_per_series: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (_, series_uid, echo, _) in groups.keys() {
+        let echoes = echoes_per_series.entry(series_uid).or_default();
+        if !echoes.contains(&echo.as_str()) {
+            echoes.push(echo);
+        }
+    }
+    for echoes in echoes_per_series.values_mut() {
+        echoes.sort_by(|a, b| {
+            let key = |e: &str| e.parse::<f64>().unwrap_or(f64::MAX);
+            key(a).total_cmp(&key(b))
+        });
+    }
+
+    groups
+        .iter()
+        .map(|((_, series_uid, echo, phase), slices)| {
+            let echoes = &echoes_per_series[series_uid.as_str()];
+            SeriesGroup {
+                slices: slices.clone(),
+                echo_number: (echoes.len() > 1)
+                    .then(|| echoes.iter().position(|e| e == echo).map(|i| i + 1))
+                    .flatten(),
+                phase: *phase,
+            }
+        })
+        .collect()
+}
+
+/// Write the jobs in parallel and report each one; returns (written, skipped).
+pub fn write_series(jobs: &[ConversionJob], output_dir: &Path) -> (usize, usize) {
+    let span = Span::current();
+    let outcomes: Vec<Outcome> = jobs
+        .par_iter()
+        .map(|job| {
+            let c = &job.group.slices[0].candidate;
+            let _series =
+                info_span!(parent: &span, "series", uid = %c.series_instance_uid).entered();
+            let label = format!("{} ({})", c.series_description, c.series_instance_uid);
+            let outcome = match convert_group(job, &output_dir.join(&job.base)) {
+                Ok(outcome) => outcome,
+                Err(e) => Outcome::Skipped(label, e.to_string()),
+            };
+            match &outcome {
+                Outcome::Written(path, dims) => info!(
+                    "NIfTI: {} ({}x{}x{}x{})",
+                    path.display(),
+                    dims[0],
+                    dims[1],
+                    dims[2],
+                    dims[3]
+                ),
+                Outcome::Skipped(label, reason) => warn!("NIfTI: skipped {}: {}", label, reason),
+            }
+            outcome
+        })
+        .collect();
+
+    let written = outcomes
+        .iter()
+        .filter(|o| matches!(o, Outcome::Written(..)))
+        .count();
+    (written, outcomes.len() - written)
+}
+
+fn read_slice(path: &Path, input_dir: &Path) -> Option<Slice> {
+    let obj = OpenFileOptions::new()
+        .read_until(tags::PIXEL_DATA)
+        .open_file(path)
+        .ok()?;
+
+    let number = |tag| get_tag_string(&obj, tag).trim().parse::<f64>().ok();
+    let position = numbers(&get_tag_string(&obj, tags::IMAGE_POSITION_PATIENT))
+        .and_then(|v| <[f64; 3]>::try_from(v).ok());
+    let orientation = numbers(&get_tag_string(&obj, tags::IMAGE_ORIENTATION_PATIENT))
+        .and_then(|v| <[f64; 6]>::try_from(v).ok());
+    let spacing = numbers(&get_tag_string(&obj, tags::PIXEL_SPACING))
+        .filter(|v| v.len() == 2)
+        .map(|v| [v[0], v[1]])
+        .unwrap_or([1.0, 1.0]);
+
+    let image_type = get_tag_string(&obj, tags::IMAGE_TYPE).to_uppercase();
+    let image_type: Vec<&str> = image_type.split('\\').map(str::trim).collect();
+    let csa = csa_image_header(&obj);
+    let mosaic = acquisition::mosaic_images(&obj, csa.as_deref());
+    let slice_normal = csa
+        .as_deref()
+        .and_then(|h| csa_values(h, "SliceNormalVector"))
+        .and_then(|v| {
+            v.iter()
+                .map(|s| s.parse::<f64>().ok())
+                .collect::<Option<Vec<_>>>()
+        })
+        .and_then(|v| <[f64; 3]>::try_from(v).ok());
+
+    let echo = match get_tag_string(&obj, tags::ECHO_NUMBERS).trim() {
+        "N/A" | "" => get_tag_string(&obj, tags::ECHO_TIME).trim().to_string(),
+        echo => echo.to_string(),
+    };
+
+    let rows = number(tags::ROWS).unwrap_or(0.0) as usize;
+    let columns = number(tags::COLUMNS).unwrap_or(0.0) as usize;
+    let frames = number(tags::NUMBER_OF_FRAMES).unwrap_or(1.0).max(1.0) as usize;
+    let samples_per_pixel = number(tags::SAMPLES_PER_PIXEL).unwrap_or(1.0) as usize;
+    let bits_allocated = number(tags::BITS_ALLOCATED).unwrap_or(16.0) as usize;
+    let signed = number(tags::PIXEL_REPRESENTATION) == Some(1.0);
+    let slope = number(tags::RESCALE_SLOPE).unwrap_or(1.0);
+    let intercept = number(tags::RESCALE_INTERCEPT).unwrap_or(0.0);
+    let acquisition_number = number(tags::ACQUISITION_NUMBER).unwrap_or(0.0) as i64;
+    let temporal_position = number(tags::TEMPORAL_POSITION_IDENTIFIER).unwrap_or(0.0) as i64;
+    let instance_number = number(tags::INSTANCE_NUMBER).unwrap_or(0.0) as i64;
+    let phase = image_type.contains(&"P") || image_type.contains(&"PHASE");
+    let scanning_sequence = get_tag_string(&obj, tags::SCANNING_SEQUENCE);
+    let sequence_variant = get_tag_string(&obj, tags::SEQUENCE_VARIANT);
+    let sequence_name = get_tag_string(&obj, tags::SEQUENCE_NAME);
+    let patient_sex = get_tag_string(&obj, tags::PATIENT_SEX);
+    let patient_age = get_tag_string(&obj, tags::PATIENT_AGE);
+    let acquisition = acquisition::bids_fields(&obj, csa.as_deref(), mosaic);
+    let diffusion = acquisition::diffusion(&obj, csa.as_deref());
+
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let name = path
+        .strip_prefix(input_dir)
+        .unwrap_or(path)
+        .display()
+        .to_string();
+    let candidate = deep_candidate_from_object(obj, 0, name, size, size)?;
+
+    Some(Slice {
+        path: path.to_path_buf(),
+        candidate,
+        position,
+        orientation,
+        spacing,
+        rows,
+        columns,
+        frames,
+        samples_per_pixel,
+        bits_allocated,
+        signed,
+        slope,
+        intercept,
+        echo,
+        phase,
+        acquisition_number,
+        temporal_position,
+        instance_number,
+        mosaic,
+        slice_normal,
+        diffusion,
+        scanning_sequence,
+        sequence_variant,
+        sequence_name,
+        patient_sex,
+        patient_age,
+        acquisition,
+    })
+}
+
+/// Order a group of slices into volumes and write the NIfTI file and its sidecar.
+fn convert_group(job: &ConversionJob, base: &Path) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let group = &job.group.slices[..];
+    let first = group[0];
+    if first.rows == 0 || first.columns == 0 {
+        return Err("no image data".into());
+    }
+    if first.samples_per_pixel != 1 {
+        return Err("colour images are not converted".into());
+    }
+    if first.frames > 1 {
+        return Err("multi-frame objects are not converted".into());
+    }
+    let (Some(_), Some(orientation)) = (first.position, first.orientation) else {
+        return Err("missing ImagePositionPatient/ImageOrientationPatient".into());
+    };
+    let consistent = group.iter().all(|s| {
+        s.rows == first.rows
+            && s.columns == first.columns
+            && s.position.is_some()
+            && s.orientation.is_some_and(|o| {
+                o.iter()
+                    .zip(orientation.iter())
+                    .all(|(a, b)| (a - b).abs() < 1e-4)
+            })
+    });
+    if !consistent {
+        return Err("slices differ in size or orientation".into());
+    }
+
+    let volume = match first.mosaic {
+        Some(tiles) => mosaic_volume(group, tiles)?,
+        None => slice_volume(group)?,
+    };
+    let nt = volume.slices.len();
+
+    let nifti_path = PathBuf::from(format!("{}.nii.gz", base.display()));
+    if let Some(parent) = nifti_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_nifti(&nifti_path, &volume)?;
+    write_diffusion(base, &volume)?;
+
+    let sidecar_path = PathBuf::from(format!("{}.json", base.display()));
+    let mut sidecar = sidecar(&first.candidate);
+    sidecar.extend(first.acquisition.clone());
+    if let Some(echo_number) = job.group.echo_number {
+        sidecar.insert("EchoNumber".to_string(), json!(echo_number));
+    }
+    let candidates: Vec<&DeepDicomCandidate> = group.iter().map(|s| &s.candidate).collect();
+    if let Some(slice_timing) = acquisition::series_slice_timing(&candidates)
+        && slice_timing.len() == volume.nz
+    {
+        sidecar.insert("SliceTiming".to_string(), json!(slice_timing));
+    }
+    sidecar.extend(job.sidecar.clone());
+    fs::write(
+        &sidecar_path,
+        serde_json::to_string_pretty(&Value::Object(sidecar))?,
+    )?;
+
+    Ok(Outcome::Written(
+        nifti_path,
+        [volume.nx, volume.ny, volume.nz, nt],
+    ))
+}
+
+/// Single-slice files: distinct positions along the normal are slices, repeats are volumes.
+fn slice_volume<'a>(group: &[&'a Slice]) -> Result<Volume<'a>, Box<dyn std::error::Error>> {
+    let first = group[0];
+    let o = first.orientation.unwrap_or_default();
+    let (row, col) = ([o[0], o[1], o[2]], [o[3], o[4], o[5]]);
+    let normal = cross(row, col);
+    let distance = |s: &Slice| dot(s.position.unwrap_or_default(), normal);
+
+    let mut sorted: Vec<&Slice> = group.to_vec();
+    sorted.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+
+    let mut positions: Vec<Vec<&Slice>> = Vec::new();
+    for slice in sorted {
+        match positions.last_mut() {
+            Some(last) if (distance(slice) - distance(last[0])).abs() < POSITION_TOLERANCE => {
+                last.push(slice)
+            }
+            _ => positions.push(vec![slice]),
+        }
+    }
+    let nz = positions.len();
+    let nt = positions[0].len();
+    if positions.iter().any(|p| p.len() != nt) {
+        return Err(format!(
+            "{} files do not divide into complete volumes of {} slices",
+            group.len(),
+            nz
+        )
+        .into());
+    }
+    for position in &mut positions {
+        position.sort_by_key(|s| (s.temporal_position, s.acquisition_number, s.instance_number));
+    }
+
+    let origin = positions[0][0].position.unwrap_or_default();
+    let step = if nz > 1 {
+        let last = positions[nz - 1][0].position.unwrap_or_default();
+        scale(sub(last, origin), 1.0 / (nz - 1) as f64)
+    } else {
+        scale(normal, slice_spacing(&first.candidate))
+    };
+
+    Ok(Volume {
+        slices: (0..nt)
+            .map(|t| positions.iter().map(|p| p[t]).collect())
+            .collect(),
+        nx: first.columns,
+        ny: first.rows,
+        nz,
+        affine: affine(row, col, first.spacing, step, origin),
+    })
+}
+
+/// Siemens mosaics: every file is a volume whose slices are tiled row by row.
+fn mosaic_volume<'a>(
+    group: &[&'a Slice],
+    tiles: usize,
+) -> Result<Volume<'a>, Box<dyn std::error::Error>> {
+    let first = group[0];
+    let per_row = (tiles as f64).sqrt().ceil() as usize;
+    let (nx, ny) = (first.columns / per_row, first.rows / per_row);
+    if nx == 0
+        || ny == 0
+        || !first.columns.is_multiple_of(per_row)
+        || !first.rows.is_multiple_of(per_row)
+    {
+        return Err(format!(
+            "{}x{} mosaic cannot hold {} tiles",
+            first.columns, first.rows, tiles
+        )
+        .into());
+    }
+
+    let o = first.orientation.unwrap_or_default();
+    let (row, col) = ([o[0], o[1], o[2]], [o[3], o[4], o[5]]);
+    let normal = first.slice_normal.unwrap_or_else(|| cross(row, col));
+
+    // ImagePositionPatient refers to the corner of the whole mosaic, not of the first tile
+    let [row_spacing, column_spacing] = first.spacing;
+    let origin = add(
+        first.position.unwrap_or_default(),
+        add(
+            scale(row, column_spacing * (first.columns - nx) as f64 / 2.0),
+            scale(col, row_spacing * (first.rows - ny) as f64 / 2.0),
+        ),
+    );
+    let step = scale(normal, slice_spacing(&first.candidate));
+
+    let mut volumes: Vec<&Slice> = group.to_vec();
+    volumes.sort_by_key(|s| (s.acquisition_number, s.instance_number));
+
+    Ok(Volume {
+        slices: volumes.into_iter().map(|s| vec![s]).collect(),
+        nx,
+        ny,
+        nz: tiles,
+        affine: affine(row, col, first.spacing, step, origin),
+    })
+}
+
+fn slice_spacing(candidate: &DeepDicomCandidate) -> f64 {
+    [
+        &candidate.spacing_between_slices,
+        &candidate.slice_thickness,
+    ]
+    .iter()
+    .find_map(|v| v.trim().parse::<f64>().ok().filter(|v| *v > 0.0))
+    .unwrap_or(1.0)
+}
+
+fn affine(
+    row: [f64; 3],
+    col: [f64; 3],
+    spacing: [f64; 2],
+    step: [f64; 3],
+    origin: [f64; 3],
+) -> [[f64; 4]; 3] {
+    let i = scale(row, spacing[1]);
+    let j = scale(col, spacing[0]);
+    let mut m = [[0.0; 4]; 3];
+    for axis in 0..3 {
+        m[axis] = [i[axis], j[axis], step[axis], origin[axis]];
+    }
+    m
+}
+
+fn write_nifti(path: &Path, volume: &Volume) -> Result<(), Box<dyn std::error::Error>> {
+    let all: Vec<&Slice> = volume.slices.iter().flatten().copied().collect();
+    let first = all[0];
+    let uniform_rescale = all.iter().all(|s| {
+        (s.slope - first.slope).abs() < 1e-9 && (s.intercept - first.intercept).abs() < 1e-9
+    });
+
+    // Stored values keep the scanner's integer type; differing rescales force float32
+    let (datatype, bitpix): (i16, i16) = match (uniform_rescale, first.bits_allocated, first.signed)
+    {
+        (false, ..) => (16, 32),
+        (true, 8, false) => (2, 8),
+        (true, 8, true) => (256, 8),
+        (true, 16, false) => (512, 16),
+        (true, 16, true) => (4, 16),
+        (true, _, false) => (768, 32),
+        (true, _, true) => (8, 32),
+    };
+    let (scl_slope, scl_inter) = if uniform_rescale {
+        (first.slope, first.intercept)
+    } else {
+        (1.0, 0.0)
+    };
+
+    let nt = volume.slices.len();
+    let repetition_time = first
+        .candidate
+        .repetition_time
+        .trim()
+        .parse::<f64>()
+        .map(|ms| ms / 1000.0)
+        .unwrap_or(0.0);
+    let header = nifti_header(
+        [volume.nx, volume.ny, volume.nz, nt],
+        repetition_time,
+        datatype,
+        bitpix,
+        (scl_slope, scl_inter),
+        &volume.affine,
+        &first.candidate.series_description,
+    )?;
+
+    let mut out = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    out.write_all(&header)?;
+    // No extensions
+    out.write_all(&[0u8; 4])?;
+
+    let options = ConvertOptions::new().with_modality_lut(ModalityLutOption::None);
+    let mut buffer = Vec::with_capacity(volume.nx * volume.ny * bitpix as usize / 8);
+    for files in &volume.slices {
+        for file in files {
+            let mut obj = dicom::object::open_file(&file.path)?;
+            crate::transcode::decode_rle(&mut obj)?;
+            let pixels: Vec<f64> = obj
+                .decode_pixel_data()?
+                .to_vec_frame_with_options(0, &options)
+                .map_err(|e| format!("{}: {}", file.path.display(), e))?;
+            if pixels.len() < file.rows * file.columns {
+                return Err(format!(
+                    "{}: {} pixel values for a {}x{} image",
+                    file.path.display(),
+                    pixels.len(),
+                    file.columns,
+                    file.rows
+                )
+                .into());
+            }
+
+            let tiles = if file.mosaic.is_some() { volume.nz } else { 1 };
+            let per_row = if file.mosaic.is_some() {
+                file.columns / volume.nx
+            } else {
+                1
+            };
+            for tile in 0..tiles {
+                let (tile_row, tile_col) = (tile / per_row, tile % per_row);
+                buffer.clear();
+                for y in 0..volume.ny {
+                    let start = (tile_row * volume.ny + y) * file.columns + tile_col * volume.nx;
+                    for &value in &pixels[start..start + volume.nx] {
+                        let value = if uniform_rescale {
+                            value
+                        } else {
+                            value * file.slope + file.intercept
+                        };
+                        match datatype {
+                            2 => buffer.push(value as u8),
+                            256 => buffer.push(value as i8 as u8),
+                            512 => buffer.extend_from_slice(&(value as u16).to_le_bytes()),
+                            4 => buffer.extend_from_slice(&(value as i16).to_le_bytes()),
+                            768 => buffer.extend_from_slice(&(value as u32).to_le_bytes()),
+                            8 => buffer.extend_from_slice(&(value as i32).to_le_bytes()),
+                            _ => buffer.extend_from_slice(&(value as f32).to_le_bytes()),
+                        }
+                    }
+                }
+                out.write_all(&buffer)?;
+            }
+        }
+    }
+    out.finish()?.flush()?;
+    Ok(())
+}
+
+/// Write .bval/.bvec when every volume has a b-value. Gradients are given along the voxel
+/// axes, with x negated when the grid is right-handed (FSL's convention).
+fn write_diffusion(base: &Path, volume: &Volume) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(diffusion) = volume
+        .slices
+        .iter()
+        .map(|files| files[0].diffusion)
+        .collect::<Option<Vec<_>>>()
+        .filter(|d| d.len() > 1)
+    else {
+        return Ok(());
+    };
+
+    let axis = |c: usize| {
+        normalize([
+            volume.affine[0][c],
+            volume.affine[1][c],
+            volume.affine[2][c],
+        ])
+    };
+    let (i, j, k) = (axis(0), axis(1), axis(2));
+    let flip_x = if dot(cross(i, j), k) > 0.0 { -1.0 } else { 1.0 };
+    // Rounded, and -0 printed as 0
+    let format = |v: f64| format!("{}", (v * 1e6).round() / 1e6 + 0.0);
+
+    let mut bvec = vec![Vec::new(); 3];
+    for (b_value, direction) in &diffusion {
+        let g = match direction {
+            Some(g) if *b_value > 0.0 => normalize(*g),
+            _ => [0.0; 3],
+        };
+        for (row, value) in bvec
+            .iter_mut()
+            .zip([flip_x * dot(g, i), dot(g, j), dot(g, k)])
+        {
+            row.push(format(value));
+        }
+    }
+    let bval: Vec<String> = diffusion.iter().map(|(b, _)| format(*b)).collect();
+    fs::write(format!("{}.bval", base.display()), bval.join(" ") + "\n")?;
+    let bvec: Vec<String> = bvec.iter().map(|row| row.join(" ")).collect();
+    fs::write(format!("{}.bvec", base.display()), bvec.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Build the 348-byte NIfTI-1 header from an LPS affine.
+fn nifti_header(
+    dims: [usize; 4],
+    repetition_time: f64,
+    datatype: i16,
+    bitpix: i16,
+    (scl_slope, scl_inter): (f64, f64),
+    lps: &[[f64; 4]; 3],
+    description: &str,
+) -> Result<Vec<u8>, String> {
+    // DICOM patient coordinates are LPS, NIfTI world coordinates RAS
+    let mut m = *lps;
+    for row in &mut m[..2] {
+        for value in row.iter_mut() {
+            *value = -*value;
+        }
+    }
+
+    let column = |c: usize| [m[0][c], m[1][c], m[2][c]];
+    let voxel = [norm(column(0)), norm(column(1)), norm(column(2))];
+    let (qfac, quaternion) = quaternion([
+        normalize(column(0)),
+        normalize(column(1)),
+        normalize(column(2)),
+    ]);
+
+    let mut h = vec![0u8; 348];
+    let put_i16 =
+        |h: &mut Vec<u8>, at: usize, v: i16| h[at..at + 2].copy_from_slice(&v.to_le_bytes());
+    let put_f32 = |h: &mut Vec<u8>, at: usize, v: f64| {
+        h[at..at + 4].copy_from_slice(&(v as f32).to_le_bytes())
+    };
+
+    h[0..4].copy_from_slice(&348i32.to_le_bytes());
+    h[38] = b'r';
+    let ndim = if dims[3] > 1 { 4 } else { 3 };
+    let dim = [ndim, dims[0], dims[1], dims[2], dims[3], 1, 1, 1];
+    for (i, d) in dim.iter().enumerate() {
+        let d = i16::try_from(*d)
+            .map_err(|_| format!("dimension {} exceeds the NIfTI-1 limit of {}", d, i16::MAX))?;
+        put_i16(&mut h, 40 + 2 * i, d);
+    }
+    put_i16(&mut h, 70, datatype);
+    put_i16(&mut h, 72, bitpix);
+    let pixdim = [
+        qfac,
+        voxel[0],
+        voxel[1],
+        voxel[2],
+        repetition_time,
+        0.0,
+        0.0,
+        0.0,
+    ];
+    for (i, p) in pixdim.iter().enumerate() {
+        put_f32(&mut h, 76 + 4 * i, *p);
+    }
+    put_f32(&mut h, 108, 352.0); // vox_offset
+    put_f32(&mut h, 112, scl_slope);
+    put_f32(&mut h, 116, scl_inter);
+    h[123] = 2 | 8; // mm, seconds
+
+    let description = description.as_bytes();
+    let length = description.len().min(79);
+    h[148..148 + length].copy_from_slice(&description[..length]);
+
+    put_i16(&mut h, 252, 1); // qform_code: scanner anatomical
+    put_i16(&mut h, 254, 1); // sform_code: scanner anatomical
+    for (i, q) in quaternion.iter().enumerate() {
+        put_f32(&mut h, 256 + 4 * i, *q);
+    }
+    for (i, row) in m.iter().enumerate() {
+        put_f32(&mut h, 268 + 4 * i, row[3]);
+    }
+    for (r, row) in m.iter().enumerate() {
+        for (c, value) in row.iter().enumerate() {
+            put_f32(&mut h, 280 + 16 * r + 4 * c, *value);
+        }
+    }
+    h[344..348].copy_from_slice(b"n+1\0");
+    Ok(h)
+}
+
+/// qfac and quaternion (b, c, d) for a rotation given as its columns.
+/// A left-handed matrix gets qfac -1 with its third column flipped.
+fn quaternion(mut columns: [[f64; 3]; 3]) -> (f64, [f64; 3]) {
+    let det = dot(cross(columns[0], columns[1]), columns[2]);
+    let qfac = if det < 0.0 {
+        columns[2] = scale(columns[2], -1.0);
+        -1.0
+    } else {
+        1.0
+    };
+    let r = |i: usize, j: usize| columns[j][i];
+
+    let trace = r(0, 0) + r(1, 1) + r(2, 2) + 1.0;
+    let (a, b, c, d) = if trace > 0.5 {
+        let a = 0.5 * trace.sqrt();
+        (
+            a,
+            0.25 * (r(2, 1) - r(1, 2)) / a,
+            0.25 * (r(0, 2) - r(2, 0)) / a,
+            0.25 * (r(1, 0) - r(0, 1)) / a,
+        )
+    } else {
+        let xd = 1.0 + r(0, 0) - (r(1, 1) + r(2, 2));
+        let yd = 1.0 + r(1, 1) - (r(0, 0) + r(2, 2));
+        let zd = 1.0 + r(2, 2) - (r(0, 0) + r(1, 1));
+        if xd > 1.0 {
+            let b = 0.5 * xd.sqrt();
+            (
+                0.25 * (r(2, 1) - r(1, 2)) / b,
+                b,
+                0.25 * (r(0, 1) + r(1, 0)) / b,
+                0.25 * (r(0, 2) + r(2, 0)) / b,
+            )
+        } else if yd > 1.0 {
+            let c = 0.5 * yd.sqrt();
+            (
+                0.25 * (r(0, 2) - r(2, 0)) / c,
+                0.25 * (r(0, 1) + r(1, 0)) / c,
+                c,
+                0.25 * (r(1, 2) + r(2, 1)) / c,
+            )
+        } else {
+            let d = 0.5 * zd.sqrt();
+            (
+                0.25 * (r(1, 0) - r(0, 1)) / d,
+                0.25 * (r(0, 2) + r(2, 0)) / d,
+                0.25 * (r(1, 2) + r(2, 1)) / d,
+                d,
+            )
+        }
+    };
+    let sign = if a < 0.0 { -1.0 } else { 1.0 };
+    (qfac, [sign * b, sign * c, sign * d])
+}
+
+/// BIDS-style sidecar from the deep-scan metadata; times are converted to seconds.
+pub fn sidecar(c: &DeepDicomCandidate) -> Map<String, Value> {
+    let mut map = Map::new();
+    let mut text = |key: &str, value: &str| {
+        let value = value.trim();
+        if !value.is_empty() && value != "N/A" {
+            map.insert(key.to_string(), json!(value));
+        }
+    };
+    text("Modality", &c.modality);
+    text("Manufacturer", &c.manufacturer);
+    text("SeriesDescription", &c.series_description);
+    text("ProtocolName", &c.protocol_name);
+    text("MRAcquisitionType", &c.acquisition_type);
+    text("SeriesInstanceUID", &c.series_instance_uid);
+    text("StudyInstanceUID", &c.study_instance_uid);
+
+    let number = |value: &str| value.trim().parse::<f64>().ok();
+    let mut numeric = |key: &str, value: Option<f64>| {
+        if let Some(value) = value {
+            map.insert(key.to_string(), json!(value));
+        }
+    };
+    numeric("MagneticFieldStrength", number(&c.magnetic_field_strength));
+    numeric("SliceThickness", number(&c.slice_thickness));
+    numeric("SpacingBetweenSlices", number(&c.spacing_between_slices));
+    numeric(
+        "RepetitionTime",
+        number(&c.repetition_time).map(|ms| ms / 1000.0),
+    );
+    numeric("EchoTime", number(&c.echo_time).map(|ms| ms / 1000.0));
+    numeric(
+        "InversionTime",
+        number(&c.inversion_time).map(|ms| ms / 1000.0),
+    );
+    numeric("FlipAngle", number(&c.flip_angle));
+    numeric("AcquisitionDuration", number(&c.acquisition_duration));
+
+    if let Ok(series_number) = c.series_number.trim().parse::<i64>() {
+        map.insert("SeriesNumber".to_string(), json!(series_number));
+    }
+    if let Ok(echo_train_length) = c.echo_train_length.trim().parse::<i64>() {
+        map.insert("EchoTrainLength".to_string(), json!(echo_train_length));
+    }
+    if c.image_type != "N/A" {
+        let image_type: Vec<&str> = c.image_type.split('\\').map(str::trim).collect();
+        map.insert("ImageType".to_string(), json!(image_type));
+    }
+    if let Some(time) = bids_time(&c.acquisition_time) {
+        map.insert("AcquisitionTime".to_string(), json!(time));
+    }
+    map.insert("ConversionSoftware".to_string(), json!("dicom_scanner"));
+    map
+}
+
+/// DICOM TM (HHMMSS.FFFFFF) as HH:MM:SS.FFFFFF
+fn bids_time(value: &str) -> Option<String> {
+    let value = value.trim();
+    let (hms, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if hms.len() != 6 || !hms.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut time = format!("{}:{}:{}", &hms[0..2], &hms[2..4], &hms[4..6]);
+    if !fraction.is_empty() {
+        time.push('.');
+        time.push_str(fraction);
+    }
+    Some(time)
+}
+
+fn numbers(value: &str) -> Option<Vec<f64>> {
+    value
+        .split('\\')
+        .map(|v| v.trim().parse::<f64>().ok())
+        .collect()
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f64; 3], factor: f64) -> [f64; 3] {
+    [a[0] * factor, a[1] * factor, a[2] * factor]
+}
+
+fn norm(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: [f64; 3]) -> [f64; 3] {
+    let length = norm(a);
+    if length > 0.0 {
+        scale(a, 1.0 / length)
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use dicom::core::VR;
+    use dicom::dictionary_std::tags;
+    use dicom::object::InMemDicomObject;
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::test_support::{
+        csa_header, file_bytes, instance, put_csa_image_header, put_pixels, scratch_dir,
+    };
+
+    /// One MR image with the given geometry (PixelSpacing is row spacing \ column spacing).
+    fn image(
+        sop: &str,
+        orientation: &str,
+        position: &str,
+        spacing: &str,
+        (rows, columns): (u16, u16),
+        pixels: Vec<u8>,
+    ) -> InMemDicomObject {
+        let mut obj = instance("P1", "1.2.3", "1.2.3.4", sop);
+        obj.put_str(tags::MODALITY, VR::CS, "MR");
+        obj.put_str(tags::SERIES_DESCRIPTION, VR::LO, "test");
+        obj.put_str(tags::IMAGE_ORIENTATION_PATIENT, VR::DS, orientation);
+        obj.put_str(tags::IMAGE_POSITION_PATIENT, VR::DS, position);
+        obj.put_str(tags::PIXEL_SPACING, VR::DS, spacing);
+        put_pixels(&mut obj, rows, columns, 1, pixels);
+        obj
+    }
+
+    /// Convert `files` as one series and return the NIfTI header and voxel data.
+    fn convert(name: &str, files: Vec<InMemDicomObject>) -> (Vec<u8>, Vec<u8>) {
+        let dir = scratch_dir(name);
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        fs::create_dir_all(&input).unwrap();
+        for (i, obj) in files.into_iter().enumerate() {
+            fs::write(input.join(format!("{}.dcm", i)), file_bytes(obj)).unwrap();
+        }
+
+        let slices = read_slices(&input).unwrap();
+        let groups = group_series(&slices);
+        assert_eq!(groups.len(), 1);
+        let job = ConversionJob {
+            group: &groups[0],
+            base: PathBuf::from("series"),
+            sidecar: Map::new(),
+        };
+        assert_eq!(write_series(&[job], &output), (1, 0));
+
+        let mut bytes = Vec::new();
+        GzDecoder::new(File::open(output.join("series.nii.gz")).unwrap())
+            .read_to_end(&mut bytes)
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let data = bytes.split_off(352);
+        (bytes, data)
+    }
+
+    fn i16_at(h: &[u8], at: usize) -> i16 {
+        i16::from_le_bytes([h[at], h[at + 1]])
+    }
+
+    fn f32_at(h: &[u8], at: usize) -> f64 {
+        f32::from_le_bytes([h[at], h[at + 1], h[at + 2], h[at + 3]]) as f64
+    }
+
+    fn dims(h: &[u8]) -> Vec<i16> {
+        (0..5).map(|i| i16_at(h, 40 + 2 * i)).collect()
+    }
+
+    fn pixdim(h: &[u8]) -> Vec<f64> {
+        (0..5).map(|i| f32_at(h, 76 + 4 * i)).collect()
+    }
+
+    fn sform(h: &[u8]) -> [[f64; 4]; 3] {
+        let mut m = [[0.0; 4]; 3];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = f32_at(h, 280 + 16 * r + 4 * c);
+            }
+        }
+        m
+    }
+
+    /// qfac, quaternion (b, c, d) and qoffset
+    fn qform(h: &[u8]) -> (f64, [f64; 3], [f64; 3]) {
+        (
+            f32_at(h, 76),
+            [f32_at(h, 256), f32_at(h, 260), f32_at(h, 264)],
+            [f32_at(h, 268), f32_at(h, 272), f32_at(h, 276)],
+        )
+    }
+
+    fn assert_close<const N: usize>(actual: [f64; N], expected: [f64; N]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    // The expected matrices below are worked out by hand from the DICOM geometry: column i is
+    // the row direction times the column spacing, j the column direction times the row
+    // spacing, k the step between slices, and the x and y rows are negated for LPS -> RAS.
+    // dcm2niix writes the same voxel-to-world mapping, though it also flips the rows of 2D
+    // images, so its j column and offset differ by that flip.
+
+    #[test]
+    fn axial_slices_are_ordered_by_position() {
+        // 4 columns x 3 rows, files written out of slice order
+        let files = [4, 0, 2]
+            .iter()
+            .map(|&z| {
+                let pixels = (0..12).map(|p| z as u8 * 16 + p).collect();
+                image(
+                    &format!("1.2.3.4.{}", z),
+                    "1\\0\\0\\0\\1\\0",
+                    &format!("-10\\20\\{}", z),
+                    "0.5\\0.75",
+                    (3, 4),
+                    pixels,
+                )
+            })
+            .collect();
+        let (h, data) = convert("nifti_axial", files);
+
+        assert_eq!(dims(&h), [3, 4, 3, 3, 1]);
+        assert_eq!(i16_at(&h, 70), 2); // uint8
+        assert_close(
+            <[f64; 4]>::try_from(&pixdim(&h)[..4]).unwrap(),
+            [1.0, 0.75, 0.5, 2.0],
+        );
+        let m = sform(&h);
+        assert_close(m[0], [-0.75, 0.0, 0.0, 10.0]);
+        assert_close(m[1], [0.0, -0.5, 0.0, -20.0]);
+        assert_close(m[2], [0.0, 0.0, 2.0, 0.0]);
+        // A 180 degree turn about z: (-1, -1, 1) on the diagonal
+        let (qfac, quaternion, offset) = qform(&h);
+        assert_eq!(qfac, 1.0);
+        assert_close(quaternion, [0.0, 0.0, 1.0]);
+        assert_close(offset, [10.0, -20.0, 0.0]);
+
+        // Slices ascend in z; within a slice x runs fastest, as in the DICOM rows
+        let expected: Vec<u8> = [0u8, 2, 4]
+            .iter()
+            .flat_map(|&z| (0..12).map(move |p| z * 16 + p))
+            .collect();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn sagittal_repeats_become_volumes() {
+        // Rows run anterior to posterior, columns head to foot; two time points of 3 slices
+        let mut files = Vec::new();
+        for t in 0..2u16 {
+            for (z, x) in [6, 10, 8].iter().enumerate() {
+                let pixels = (0..4u16)
+                    .flat_map(|p| (t * 100 + *x as u16 * 10 + p).to_le_bytes())
+                    .collect();
+                let mut obj = image(
+                    &format!("1.2.3.4.{}.{}", t, z),
+                    "0\\1\\0\\0\\0\\-1",
+                    &format!("{}\\-5\\30", x),
+                    "1\\2",
+                    (2, 2),
+                    pixels,
+                );
+                obj.put_str(
+                    tags::TEMPORAL_POSITION_IDENTIFIER,
+                    VR::IS,
+                    (t + 1).to_string(),
+                );
+                obj.put_str(tags::REPETITION_TIME, VR::DS, "2000");
+                files.push(obj);
+            }
+        }
+        let (h, data) = convert("nifti_sagittal", files);
+
+        assert_eq!(dims(&h), [4, 2, 2, 3, 2]);
+        assert_eq!(i16_at(&h, 70), 512); // uint16
+        assert_close(
+            <[f64; 5]>::try_from(pixdim(&h)).unwrap(),
+            [1.0, 2.0, 1.0, 2.0, 2.0],
+        );
+        // The slice normal (row x column) points right, so slices run from x = 10 down to 6
+        let m = sform(&h);
+        assert_close(m[0], [0.0, 0.0, 2.0, -10.0]);
+        assert_close(m[1], [-2.0, 0.0, 0.0, 5.0]);
+        assert_close(m[2], [0.0, -1.0, 0.0, 30.0]);
+        // Rotation columns (0,-1,0), (0,0,-1), (1,0,0): a = 1/2, (b, c, d) = (-1/2, 1/2, -1/2)
+        let (qfac, quaternion, offset) = qform(&h);
+        assert_eq!(qfac, 1.0);
+        assert_close(quaternion, [-0.5, 0.5, -0.5]);
+        assert_close(offset, [-10.0, 5.0, 30.0]);
+
+        let values: Vec<u16> = data
+            .chunks(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        let expected: Vec<u16> = (0..2u16)
+            .flat_map(|t| [10u16, 8, 6].map(move |x| (t, x)))
+            .flat_map(|(t, x)| (0..4).map(move |p| t * 100 + x * 10 + p))
+            .collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn mosaic_tiles_become_slices() {
+        // A 6x6 mosaic of 3x3 tiles holding 3 slices, stored descending (normal -z)
+        let csa = csa_header(&[
+            ("NumberOfImagesInMosaic", &["3"]),
+            ("SliceNormalVector", &["0", "0", "-1"]),
+        ]);
+        let files = (0..2u8)
+            .map(|volume| {
+                let pixels = (0..36).map(|p| volume * 100 + p).collect();
+                let mut obj = image(
+                    &format!("1.2.3.4.{}", volume),
+                    "1\\0\\0\\0\\1\\0",
+                    "-100\\-80\\10",
+                    "2\\2",
+                    (6, 6),
+                    pixels,
+                );
+                obj.put_str(tags::IMAGE_TYPE, VR::CS, "ORIGINAL\\PRIMARY\\M\\MOSAIC");
+                obj.put_str(tags::SLICE_THICKNESS, VR::DS, "3");
+                obj.put_str(tags::ACQUISITION_NUMBER, VR::IS, (volume + 1).to_string());
+                put_csa_image_header(&mut obj, csa.clone());
+                obj
+            })
+            .collect();
+        let (h, data) = convert("nifti_mosaic", files);
+
+        assert_eq!(dims(&h), [4, 3, 3, 3, 2]);
+        // ImagePositionPatient is the mosaic corner: the first tile starts (6 - 3) / 2
+        // columns and rows of 2 mm further along, at (-97, -77, 10)
+        let m = sform(&h);
+        assert_close(m[0], [-2.0, 0.0, 0.0, 97.0]);
+        assert_close(m[1], [0.0, -2.0, 0.0, 77.0]);
+        assert_close(m[2], [0.0, 0.0, -3.0, 10.0]);
+        // diag(-1, -1, -1) is left-handed: qfac -1 and a 180 degree turn about z
+        let (qfac, quaternion, offset) = qform(&h);
+        assert_eq!(qfac, -1.0);
+        assert_close(quaternion, [0.0, 0.0, 1.0]);
+        assert_close(offset, [97.0, 77.0, 10.0]);
+
+        // Tiles are read row by row: tile k starts at mosaic row 3 * (k / 2), column 3 * (k % 2)
+        let mut expected = Vec::new();
+        for volume in 0..2u8 {
+            for tile in 0..3u8 {
+                for y in 0..3 {
+                    for x in 0..3 {
+                        expected.push(volume * 100 + ((tile / 2) * 3 + y) * 6 + (tile % 2) * 3 + x);
+                    }
+                }
+            }
+        }
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn short_pixel_data_is_skipped() {
+        let square = |size: u16| {
+            let pixels = vec![0; size as usize * size as usize];
+            image(
+                "1.2.3.4.1",
+                "1\\0\\0\\0\\1\\0",
+                "0\\0\\0",
+                "1\\1",
+                (size, size),
+                pixels,
+            )
+        };
+        let dir = scratch_dir("nifti_short");
+        fs::create_dir_all(dir.join("in")).unwrap();
+        fs::write(dir.join("in/0.dcm"), file_bytes(square(4))).unwrap();
+        let slices = read_slices(&dir.join("in")).unwrap();
+
+        // The file is replaced by a smaller image after its header was read
+        fs::write(dir.join("in/0.dcm"), file_bytes(square(2))).unwrap();
+        let groups = group_series(&slices);
+        let job = ConversionJob {
+            group: &groups[0],
+            base: PathBuf::from("series"),
+            sidecar: Map::new(),
+        };
+        assert_eq!(write_series(&[job], &dir.join("out")), (0, 1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dimensions_beyond_i16_are_rejected() {
+        let lps = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+        ];
+        let header = |nx| nifti_header([nx, 1, 1, 1], 0.0, 2, 8, (1.0, 0.0), &lps, "");
+        assert!(header(32767).is_ok());
+        assert!(header(32768).unwrap_err().contains("32768"));
+    }
+}
@@ -0,0 +1,456 @@
+// --- Apache Parquet export ---
+//
+// Writes instances.parquet (one row per DICOM file) and series.parquet (one row per series) with
+// real types instead of the "N/A" strings of series_metadata.csv: sizes and counts are INT64,
+// physical values DOUBLE, StudyDate a DATE, acquisition start a TIMESTAMP, and multi-valued
+// attributes (ImageType, PixelSpacing) LIST columns. Missing values are nulls.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use tracing::info;
+
+use crate::{DeepDicomCandidate, acquisition};
+
+enum Column {
+    Utf8(Vec<Option<String>>),
+    Int64(Vec<Option<i64>>),
+    Double(Vec<Option<f64>>),
+    /// Days since 1970-01-01
+    Date(Vec<Option<i32>>),
+    /// Milliseconds since 1970-01-01, scanner local time (DICOM carries no time zone)
+    Timestamp(Vec<Option<i64>>),
+    Utf8List(Vec<Option<Vec<String>>>),
+    DoubleList(Vec<Option<Vec<f64>>>),
+}
+
+struct Table {
+    columns: Vec<(&'static str, Column)>,
+}
+
+/// Write instances.parquet and series.parquet for `(source archive, candidate)` rows.
+pub fn export_parquet(
+    rows: &[(String, &DeepDicomCandidate)],
+    output_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(output_dir)?;
+
+    let instances_path = output_dir.join("instances.parquet");
+    write_table(&instances_path, &instance_table(rows))?;
+
+    // One representative per series, in the same order as series_metadata.csv
+    let mut series_files: HashMap<(&str, &str, &str), Vec<&DeepDicomCandidate>> = HashMap::new();
+    let mut representatives: Vec<(String, &DeepDicomCandidate)> = Vec::new();
+    for (source, candidate) in rows {
+        let key = (
+            source.as_str(),
+            candidate.study_instance_uid.as_str(),
+            candidate.series_instance_uid.as_str(),
+        );
+        let files = series_files.entry(key).or_default();
+        if files.is_empty() {
+            representatives.push((source.clone(), candidate));
+        }
+        files.push(candidate);
+    }
+    representatives.sort_by(|a, b| {
+        (&a.0, &a.1.study_instance_uid, &a.1.series_instance_uid).cmp(&(
+            &b.0,
+            &b.1.study_instance_uid,
+            &b.1.series_instance_uid,
+        ))
+    });
+
+    let mut series = instance_table(&representatives);
+    series.columns.retain(|(name, _)| {
+        !matches!(
+            *name,
+            "entry_name"
+                | "entry_index"
+                | "compressed_size"
+                | "uncompressed_size"
+                | "sop_instance_uid"
+                | "instance_number"
+        )
+    });
+    let files: Vec<&[&DeepDicomCandidate]> = representatives
+        .iter()
+        .map(|(source, c)| {
+            series_files[&(
+                source.as_str(),
+                c.study_instance_uid.as_str(),
+                c.series_instance_uid.as_str(),
+            )]
+                .as_slice()
+        })
+        .collect();
+    let slice_timing: Vec<Option<Vec<f64>>> = files
+        .iter()
+        .map(|files| acquisition::series_slice_timing(files))
+        .collect();
+    series.columns.push((
+        "file_count",
+        Column::Int64(files.iter().map(|f| Some(f.len() as i64)).collect()),
+    ));
+    series.columns.push((
+        "multiband_factor",
+        Column::Int64(
+            slice_timing
+                .iter()
+                .map(|t| acquisition::multiband_factor(t.as_deref()?).map(|f| f as i64))
+                .collect(),
+        ),
+    ));
+    series
+        .columns
+        .push(("slice_timing_s", Column::DoubleList(slice_timing)));
+    series.columns.push((
+        "fov_mm",
+        Column::DoubleList(
+            representatives
+                .iter()
+                .map(|(_, c)| field_of_view(c))
+                .collect(),
+        ),
+    ));
+    let series_path = output_dir.join("series.parquet");
+    write_table(&series_path, &series)?;
+
+    info!(
+        "Parquet exported to: {} ({} rows), {} ({} rows)",
+        instances_path.display(),
+        rows.len(),
+        series_path.display(),
+        representatives.len()
+    );
+    Ok(())
+}
+
+fn instance_table(rows: &[(String, &DeepDicomCandidate)]) -> Table {
+    let utf8 = |f: fn(&DeepDicomCandidate) -> &str| {
+        Column::Utf8(rows.iter().map(|(_, c)| text(f(c))).collect())
+    };
+    let double = |f: fn(&DeepDicomCandidate) -> &str| {
+        Column::Double(rows.iter().map(|(_, c)| double(f(c))).collect())
+    };
+    let int64 = |f: fn(&DeepDicomCandidate) -> &str| {
+        Column::Int64(rows.iter().map(|(_, c)| integer(f(c))).collect())
+    };
+
+    Table {
+        columns: vec![
+            (
+                "source_archive",
+                Column::Utf8(rows.iter().map(|(s, _)| Some(s.clone())).collect()),
+            ),
+            ("entry_name", utf8(|c| &c.name)),
+            (
+                "entry_index",
+                Column::Int64(rows.iter().map(|(_, c)| Some(c.index as i64)).collect()),
+            ),
+            (
+                "compressed_size",
+                Column::Int64(
+                    rows.iter()
+                        .map(|(_, c)| Some(c.compressed_size as i64))
+                        .collect(),
+                ),
+            ),
+            (
+                "uncompressed_size",
+                Column::Int64(
+                    rows.iter()
+                        .map(|(_, c)| Some(c.uncompressed_size as i64))
+                        .collect(),
+                ),
+            ),
+            ("patient_id", utf8(|c| &c.patient_id)),
+            ("patient_name", utf8(|c| &c.patient_name)),
+            ("study_instance_uid", utf8(|c| &c.study_instance_uid)),
+            (
+                "study_date",
+                Column::Date(
+                    rows.iter()
+                        .map(|(_, c)| dicom_date(&c.study_date))
+                        .collect(),
+                ),
+            ),
+            ("accession_number", utf8(|c| &c.accession_number)),
+            ("study_description", utf8(|c| &c.study_description)),
+            ("series_instance_uid", utf8(|c| &c.series_instance_uid)),
+            ("series_number", int64(|c| &c.series_number)),
+            ("series_description", utf8(|c| &c.series_description)),
+            ("protocol_name", utf8(|c| &c.protocol_name)),
+            ("modality", utf8(|c| &c.modality)),
+            ("manufacturer", utf8(|c| &c.manufacturer)),
+            ("sop_instance_uid", utf8(|c| &c.sop_instance_uid)),
+            ("instance_number", int64(|c| &c.instance_number)),
+            (
+                "image_type",
+                Column::Utf8List(rows.iter().map(|(_, c)| text_list(&c.image_type)).collect()),
+            ),
+            ("acquisition_type", utf8(|c| &c.acquisition_type)),
+            (
+                "acquisition_datetime",
+                Column::Timestamp(
+                    rows.iter()
+                        .map(|(_, c)| dicom_datetime(&c.study_date, &c.acquisition_time))
+                        .collect(),
+                ),
+            ),
+            (
+                "acquisition_duration_s",
+                double(|c| &c.acquisition_duration),
+            ),
+            ("repetition_time_ms", double(|c| &c.repetition_time)),
+            ("echo_time_ms", double(|c| &c.echo_time)),
+            ("inversion_time_ms", double(|c| &c.inversion_time)),
+            ("flip_angle_deg", double(|c| &c.flip_angle)),
+            ("number_of_averages", double(|c| &c.number_of_averages)),
+            ("echo_train_length", int64(|c| &c.echo_train_length)),
+            (
+                "parallel_imaging_factor",
+                utf8(|c| &c.parallel_imaging_factor),
+            ),
+            (
+                "magnetic_field_strength_t",
+                double(|c| &c.magnetic_field_strength),
+            ),
+            (
+                "pixel_spacing_mm",
+                Column::DoubleList(
+                    rows.iter()
+                        .map(|(_, c)| double_list(&c.pixel_spacing))
+                        .collect(),
+                ),
+            ),
+            ("slice_thickness_mm", double(|c| &c.slice_thickness)),
+            (
+                "spacing_between_slices_mm",
+                double(|c| &c.spacing_between_slices),
+            ),
+            ("rows", int64(|c| &c.rows)),
+            ("columns", int64(|c| &c.columns)),
+            (
+                "phase_encoding_direction",
+                utf8(|c| &c.phase_encoding_direction),
+            ),
+            (
+                "effective_echo_spacing_s",
+                double(|c| &c.effective_echo_spacing),
+            ),
+            ("total_readout_time_s", double(|c| &c.total_readout_time)),
+            ("transfer_syntax", utf8(|c| &c.transfer_syntax)),
+            (
+                "derivation_description",
+                utf8(|c| &c.derivation_description),
+            ),
+            ("referenced_series_uid", utf8(|c| &c.referenced_series_uid)),
+        ],
+    }
+}
+
+fn write_table(path: &Path, table: &Table) -> Result<(), Box<dyn std::error::Error>> {
+    let fields: Vec<String> = table
+        .columns
+        .iter()
+        .map(|(name, column)| match column {
+            Column::Utf8(_) => format!("optional binary {} (STRING);", name),
+            Column::Int64(_) => format!("optional int64 {};", name),
+            Column::Double(_) => format!("optional double {};", name),
+            Column::Date(_) => format!("optional int32 {} (DATE);", name),
+            Column::Timestamp(_) => {
+                format!("optional int64 {} (TIMESTAMP(MILLIS,false));", name)
+            }
+            Column::Utf8List(_) => format!(
+                "optional group {} (LIST) {{ repeated group list {{ required binary element (STRING); }} }}",
+                name
+            ),
+            Column::DoubleList(_) => format!(
+                "optional group {} (LIST) {{ repeated group list {{ required double element; }} }}",
+                name
+            ),
+        })
+        .collect();
+    let schema = Arc::new(parse_message_type(&format!(
+        "message dicom {{ {} }}",
+        fields.join(" ")
+    ))?);
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+
+    let mut writer = SerializedFileWriter::new(File::create(path)?, schema, props)?;
+    let mut row_group = writer.next_row_group()?;
+    let mut columns = table.columns.iter();
+    while let Some(mut column_writer) = row_group.next_column()? {
+        let (_, column) = columns
+            .next()
+            .ok_or("Parquet schema has more columns than the table")?;
+        match column {
+            Column::Utf8(values) => {
+                let (values, def) = flat(values, |v| ByteArray::from(v.as_str()));
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&def), None)?;
+            }
+            Column::Int64(values) | Column::Timestamp(values) => {
+                let (values, def) = flat(values, |v| *v);
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, Some(&def), None)?;
+            }
+            Column::Double(values) => {
+                let (values, def) = flat(values, |v| *v);
+                column_writer
+                    .typed::<DoubleType>()
+                    .write_batch(&values, Some(&def), None)?;
+            }
+            Column::Date(values) => {
+                let (values, def) = flat(values, |v| *v);
+                column_writer
+                    .typed::<Int32Type>()
+                    .write_batch(&values, Some(&def), None)?;
+            }
+            Column::Utf8List(lists) => {
+                let (values, def, rep) = nested(lists, |v| ByteArray::from(v.as_str()));
+                column_writer.typed::<ByteArrayType>().write_batch(
+                    &values,
+                    Some(&def),
+                    Some(&rep),
+                )?;
+            }
+            Column::DoubleList(lists) => {
+                let (values, def, rep) = nested(lists, |v| *v);
+                column_writer
+                    .typed::<DoubleType>()
+                    .write_batch(&values, Some(&def), Some(&rep))?;
+            }
+        }
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Values and definition levels for an optional primitive column
+fn flat<T, U>(values: &[Option<T>], convert: impl Fn(&T) -> U) -> (Vec<U>, Vec<i16>) {
+    let def = values.iter().map(|v| i16::from(v.is_some())).collect();
+    let values = values.iter().flatten().map(convert).collect();
+    (values, def)
+}
+
+/// Values, definition and repetition levels for an optional LIST of required elements.
+/// Null lists have definition level 0, empty lists 1, elements 2.
+fn nested<T, U>(
+    lists: &[Option<Vec<T>>],
+    convert: impl Fn(&T) -> U,
+) -> (Vec<U>, Vec<i16>, Vec<i16>) {
+    let mut values = Vec::new();
+    let mut def = Vec::new();
+    let mut rep = Vec::new();
+    for list in lists {
+        match list {
+            None => {
+                def.push(0);
+                rep.push(0);
+            }
+            Some(items) if items.is_empty() => {
+                def.push(1);
+                rep.push(0);
+            }
+            Some(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    values.push(convert(item));
+                    def.push(2);
+                    rep.push(i16::from(i > 0));
+                }
+            }
+        }
+    }
+    (values, def, rep)
+}
+
+fn text(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty() && value != "N/A").then(|| value.to_string())
+}
+
+fn double(value: &str) -> Option<f64> {
+    text(value).and_then(|v| v.parse::<f64>().ok())
+}
+
+fn integer(value: &str) -> Option<i64> {
+    text(value).and_then(|v| {
+        v.parse::<i64>()
+            .ok()
+            .or_else(|| v.parse::<f64>().ok().map(|f| f as i64))
+    })
+}
+
+fn text_list(value: &str) -> Option<Vec<String>> {
+    text(value).map(|v| v.split('\\').map(|s| s.trim().to_string()).collect())
+}
+
+fn double_list(value: &str) -> Option<Vec<f64>> {
+    text(value).and_then(|v| {
+        v.split('\\')
+            .map(|s| s.trim().parse::<f64>().ok())
+            .collect::<Option<Vec<f64>>>()
+    })
+}
+
+/// Field of view (columns × column spacing, rows × row spacing) in mm
+fn field_of_view(c: &DeepDicomCandidate) -> Option<Vec<f64>> {
+    let spacing = double_list(&c.pixel_spacing)?;
+    let (rows, cols) = (double(&c.rows)?, double(&c.columns)?);
+    (spacing.len() >= 2).then(|| vec![spacing[1] * cols, spacing[0] * rows])
+}
+
+/// DICOM DA (YYYYMMDD) as days since the Unix epoch
+fn dicom_date(value: &str) -> Option<i32> {
+    let value = text(value)?;
+    if value.len() != 8 || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let year: i64 = value[0..4].parse().ok()?;
+    let month: i64 = value[4..6].parse().ok()?;
+    let day: i64 = value[6..8].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days from civil date (proleptic Gregorian calendar)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    i32::try_from(era * 146_097 + doe - 719_468).ok()
+}
+
+/// DICOM DA + TM (HHMMSS.FFFFFF, components optional from the right) as epoch milliseconds
+fn dicom_datetime(date: &str, time: &str) -> Option<i64> {
+    let days = i64::from(dicom_date(date)?);
+    let time = text(time)?;
+    let (hms, fraction) = time.split_once('.').unwrap_or((&time, ""));
+    if hms.len() < 2 || hms.len() % 2 != 0 || !hms.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let part = |i: usize| -> i64 { hms.get(i..i + 2).and_then(|s| s.parse().ok()).unwrap_or(0) };
+    let millis = format!("{:0<3}", fraction.chars().take(3).collect::<String>())
+        .parse::<i64>()
+        .unwrap_or(0);
+    Some(((days * 24 + part(0)) * 60 + part(2)) * 60_000 + part(4) * 1000 + millis)
+}
@@ -0,0 +1,410 @@
+// --- Series previews ---
+//
+// Decodes the pixel data of the middle slice (or a few evenly spaced slices) of every series and
+// writes PNG thumbnails plus one contact sheet per study under <output>/previews, using the same
+// Study_* / Series_* names as the organized files. Monochrome images are windowed with
+// WindowCenter/WindowWidth when present and otherwise auto-windowed to the 1st-99th percentile.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+
+use dicom::dictionary_std::tags;
+use dicom::object::OpenFileOptions;
+use dicom::pixeldata::PixelDecoder;
+use rayon::prelude::*;
+use tracing::{Span, info, info_span, warn};
+use zip::ZipArchive;
+
+use crate::{DeepDicomCandidate, default_organized_path, get_tag_string};
+
+/// Longest side of a thumbnail and size of a contact sheet cell (pixels)
+const THUMBNAIL_SIZE: usize = 256;
+
+/// Contact sheet layout
+const SHEET_COLUMNS: usize = 6;
+const SHEET_GAP: usize = 4;
+
+/// Series number labels: 3x5 glyphs for 0-9, scaled up, drawn in yellow
+const LABEL_SCALE: usize = 3;
+const LABEL_COLOR: [u8; 3] = [255, 220, 0];
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// An 8-bit RGB image
+struct Rgb {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Rgb {
+    /// An image of `width` x `height` pixels, when `data` holds exactly that many RGB triples
+    fn new(width: usize, height: usize, data: Vec<u8>) -> Result<Rgb, String> {
+        if width == 0 || height == 0 || data.len() != width * height * 3 {
+            return Err(format!(
+                "{} bytes of RGB data for a {}x{} image",
+                data.len(),
+                width,
+                height
+            ));
+        }
+        Ok(Rgb {
+            width,
+            height,
+            data,
+        })
+    }
+}
+
+/// One series to preview: the picked files, in slice order
+struct SeriesPreview<'a> {
+    study_folder: PathBuf,
+    series_folder: PathBuf,
+    series_number: String,
+    picks: Vec<&'a DeepDicomCandidate>,
+}
+
+/// Write PNG thumbnails of `slices` slices per series and a contact sheet per study.
+pub fn write_previews(
+    zip_bytes: &[u8],
+    deep_candidates: &[DeepDicomCandidate],
+    output_dir: &Path,
+    slices: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut series: BTreeMap<(&str, &str), Vec<&DeepDicomCandidate>> = BTreeMap::new();
+    for candidate in deep_candidates {
+        series
+            .entry((
+                &candidate.study_instance_uid,
+                &candidate.series_instance_uid,
+            ))
+            .or_default()
+            .push(candidate);
+    }
+
+    let mut previews: Vec<SeriesPreview> = series
+        .into_values()
+        .map(|mut files| {
+            files.sort_by(|a, b| {
+                let key = |c: &DeepDicomCandidate| {
+                    (
+                        c.slice_position.trim().parse::<f64>().unwrap_or(0.0),
+                        c.instance_number.trim().parse::<i64>().unwrap_or(0),
+                    )
+                };
+                let (a, b) = (key(a), key(b));
+                a.0.total_cmp(&b.0).then(a.1.cmp(&b.1))
+            });
+            // Evenly spaced slices; a single slice is the middle one
+            let count = slices.clamp(1, files.len());
+            let mut picks: Vec<&DeepDicomCandidate> = (0..count)
+                .map(|k| files[(2 * k + 1) * files.len() / (2 * count)])
+                .collect();
+            picks.dedup_by_key(|c| c.index);
+
+            let first = files[0];
+            let organized = default_organized_path(
+                &first.study_instance_uid,
+                &first.series_instance_uid,
+                &first.name,
+                &first.study_description,
+                &first.series_description,
+                &first.series_number,
+                first.index,
+            );
+            let series_folder = organized.parent().unwrap_or(Path::new("")).to_path_buf();
+            SeriesPreview {
+                study_folder: series_folder
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf(),
+                series_folder,
+                series_number: first.series_number.trim().to_string(),
+                picks,
+            }
+        })
+        .collect();
+    previews.sort_by_key(|p| {
+        (
+            p.study_folder.clone(),
+            p.series_number.parse::<i64>().unwrap_or(i64::MAX),
+        )
+    });
+
+    let preview_dir = output_dir.join("previews");
+    let archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+    let span = Span::current();
+
+    // (study folder, series number, thumbnail for the contact sheet)
+    let thumbnails: Vec<(PathBuf, String, Option<Rgb>)> = previews
+        .par_iter()
+        .map_init(
+            || archive.clone(),
+            |archive, preview| {
+                let _series = info_span!(
+                    parent: &span,
+                    "series",
+                    uid = %preview.picks[0].series_instance_uid
+                )
+                .entered();
+                let mut sheet_thumbnail = None;
+                let middle = preview.picks.len() / 2;
+                for (k, candidate) in preview.picks.iter().enumerate() {
+                    let thumbnail = match render_entry(archive, candidate) {
+                        Ok(image) => fit(&image, THUMBNAIL_SIZE),
+                        Err(e) => {
+                            warn!("Preview: skipped {}: {}", candidate.name, e);
+                            continue;
+                        }
+                    };
+                    let name = if preview.picks.len() > 1 {
+                        format!("{}_{}.png", preview.series_folder.display(), k + 1)
+                    } else {
+                        format!("{}.png", preview.series_folder.display())
+                    };
+                    let path = preview_dir.join(name);
+                    if let Err(e) = write_png(&path, &thumbnail) {
+                        warn!("Preview: cannot write {}: {}", path.display(), e);
+                    }
+                    if k == middle {
+                        sheet_thumbnail = Some(thumbnail);
+                    }
+                }
+                (
+                    preview.study_folder.clone(),
+                    preview.series_number.clone(),
+                    sheet_thumbnail,
+                )
+            },
+        )
+        .collect();
+
+    let mut studies: BTreeMap<&Path, Vec<(&str, &Rgb)>> = BTreeMap::new();
+    for (study_folder, series_number, thumbnail) in &thumbnails {
+        if let Some(thumbnail) = thumbnail {
+            studies
+                .entry(study_folder)
+                .or_default()
+                .push((series_number, thumbnail));
+        }
+    }
+    for (study_folder, cells) in &studies {
+        let path = preview_dir.join(format!("{}.png", study_folder.display()));
+        write_png(&path, &contact_sheet(cells))?;
+    }
+
+    info!(
+        "Previews written to: {} ({} series, {} contact sheet(s))",
+        preview_dir.display(),
+        thumbnails.iter().filter(|(_, _, t)| t.is_some()).count(),
+        studies.len()
+    );
+    Ok(())
+}
+
+/// Decode the middle frame of one archive entry into a display image.
+fn render_entry(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    candidate: &DeepDicomCandidate,
+) -> Result<Rgb, Box<dyn std::error::Error>> {
+    let entry = archive.by_index(candidate.index)?;
+    let mut obj = OpenFileOptions::new().from_reader(entry)?;
+    crate::transcode::decode_rle(&mut obj)?;
+    let pixels = obj.decode_pixel_data()?;
+    let width = pixels.columns() as usize;
+    let height = pixels.rows() as usize;
+    let frame = pixels.number_of_frames() / 2;
+
+    if pixels.samples_per_pixel() == 3 {
+        return Ok(Rgb::new(width, height, pixels.to_vec_frame::<u8>(frame)?)?);
+    }
+    if pixels.samples_per_pixel() != 1 {
+        return Err(format!("{} samples per pixel", pixels.samples_per_pixel()).into());
+    }
+
+    // Modality LUT (rescale) applied, then the VOI window
+    let values: Vec<f64> = pixels.to_vec_frame::<f64>(frame)?;
+    let first = |tag| {
+        get_tag_string(&obj, tag)
+            .split('\\')
+            .next()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+    };
+    let (center, width_) = match (first(tags::WINDOW_CENTER), first(tags::WINDOW_WIDTH)) {
+        (Some(center), Some(window)) if window >= 1.0 => (center, window),
+        _ => auto_window(&values),
+    };
+    let invert = get_tag_string(&obj, tags::PHOTOMETRIC_INTERPRETATION).trim() == "MONOCHROME1";
+
+    let mut data = Vec::with_capacity(width * height * 3);
+    for value in values {
+        // Linear VOI function from PS3.3 C.11.2.1.2.1
+        let mut level = ((value - (center - 0.5)) / (width_ - 1.0).max(1.0) + 0.5).clamp(0.0, 1.0);
+        if invert {
+            level = 1.0 - level;
+        }
+        let grey = (level * 255.0).round() as u8;
+        data.extend_from_slice(&[grey, grey, grey]);
+    }
+    Ok(Rgb::new(width, height, data)?)
+}
+
+/// Window (center, width) spanning the 1st to 99th percentile of the values
+fn auto_window(values: &[f64]) -> (f64, f64) {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    if sorted.is_empty() {
+        return (0.0, 1.0);
+    }
+    sorted.sort_by(f64::total_cmp);
+    let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+    let (low, high) = (percentile(0.01), percentile(0.99));
+    ((low + high) / 2.0, (high - low).max(1.0) + 1.0)
+}
+
+/// Scale an image to fit a size x size box, averaging when shrinking.
+fn fit(image: &Rgb, size: usize) -> Rgb {
+    let scale = size as f64 / image.width.max(image.height).max(1) as f64;
+    let width = ((image.width as f64 * scale).round() as usize).max(1);
+    let height = ((image.height as f64 * scale).round() as usize).max(1);
+
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        let y0 = y * image.height / height;
+        let y1 = ((y + 1) * image.height / height).max(y0 + 1);
+        for x in 0..width {
+            let x0 = x * image.width / width;
+            let x1 = ((x + 1) * image.width / width).max(x0 + 1);
+            let mut sum = [0usize; 3];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let offset = (sy * image.width + sx) * 3;
+                    for (c, total) in sum.iter_mut().enumerate() {
+                        *total += image.data[offset + c] as usize;
+                    }
+                }
+            }
+            let count = (y1 - y0) * (x1 - x0);
+            data.extend(sum.iter().map(|total| (total / count) as u8));
+        }
+    }
+    Rgb {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Grid of thumbnails, each centred in its cell and labelled with the series number.
+fn contact_sheet(cells: &[(&str, &Rgb)]) -> Rgb {
+    let columns = cells.len().clamp(1, SHEET_COLUMNS);
+    let rows = cells.len().div_ceil(columns).max(1);
+    let cell = THUMBNAIL_SIZE + SHEET_GAP;
+    let mut sheet = Rgb {
+        width: columns * cell + SHEET_GAP,
+        height: rows * cell + SHEET_GAP,
+        data: Vec::new(),
+    };
+    sheet.data = vec![0; sheet.width * sheet.height * 3];
+
+    for (i, (label, thumbnail)) in cells.iter().enumerate() {
+        let left = SHEET_GAP + (i % columns) * cell + (THUMBNAIL_SIZE - thumbnail.width) / 2;
+        let top = SHEET_GAP + (i / columns) * cell + (THUMBNAIL_SIZE - thumbnail.height) / 2;
+        for y in 0..thumbnail.height {
+            let source = y * thumbnail.width * 3;
+            let target = ((top + y) * sheet.width + left) * 3;
+            sheet.data[target..target + thumbnail.width * 3]
+                .copy_from_slice(&thumbnail.data[source..source + thumbnail.width * 3]);
+        }
+        draw_label(
+            &mut sheet,
+            SHEET_GAP + (i % columns) * cell + LABEL_SCALE,
+            SHEET_GAP + (i / columns) * cell + LABEL_SCALE,
+            label,
+        );
+    }
+    sheet
+}
+
+/// Draw the digits of `text` (other characters are skipped) with a dark outline.
+fn draw_label(image: &mut Rgb, left: usize, top: usize, text: &str) {
+    let digits: Vec<usize> = text
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| d as usize)
+        .collect();
+    for (shadow, color) in [(true, [0, 0, 0]), (false, LABEL_COLOR)] {
+        for (n, digit) in digits.iter().enumerate() {
+            for (row, bits) in DIGITS[*digit].iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    let x = left + (n * 4 + column) * LABEL_SCALE + shadow as usize;
+                    let y = top + row * LABEL_SCALE + shadow as usize;
+                    for dy in 0..LABEL_SCALE {
+                        for dx in 0..LABEL_SCALE {
+                            let (px, py) = (x + dx, y + dy);
+                            if px < image.width && py < image.height {
+                                let offset = (py * image.width + px) * 3;
+                                image.data[offset..offset + 3].copy_from_slice(&color);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn write_png(path: &Path, image: &Rgb) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width as u32,
+        image.height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_match_their_size() {
+        assert!(Rgb::new(2, 2, vec![0; 12]).is_ok());
+        assert!(Rgb::new(2, 2, vec![0; 9]).is_err());
+        assert!(Rgb::new(0, 2, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn fit_averages_blocks() {
+        // 4x2 image: left half black, right half white
+        let data = (0..8)
+            .flat_map(|i| if i % 4 < 2 { [0; 3] } else { [255; 3] })
+            .collect();
+        let image = Rgb::new(4, 2, data).unwrap();
+        let small = fit(&image, 2);
+        assert_eq!((small.width, small.height), (2, 1));
+        assert_eq!(small.data, [0, 0, 0, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,230 @@
+// --- scan and mrn subcommands ---
+//
+// `scan` lists the DICOM files of each archive on stdout, one tab-separated line per file: entry
+// name, Modality, Manufacturer, StudyInstanceUID and SeriesInstanceUID, preceded by the archive
+// path when several archives are scanned. Timings, sizes and the study summary are logged.
+// `mrn` prints the PatientIDs only. stdout carries nothing else, so both can be piped.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use rayon::prelude::*;
+use tracing::{error, info, info_span};
+
+use crate::batch::{self, BatchOptions};
+use crate::entry_error;
+use crate::{dicomdir, map_archive, scan_dicom_candidates_parallel};
+
+/// List every archive. Several archives are scanned `--jobs` at a time and listed in input
+/// order; a failed one is reported and the rest are scanned.
+pub fn scan_archives(
+    inputs: &[PathBuf],
+    options: &BatchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let [input] = inputs {
+        let _archive = info_span!("archive", path = %input.display()).entered();
+        for row in scan_archive(input, options)? {
+            println!("{}", row);
+        }
+        return Ok(());
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.max(1))
+        .build()?;
+    // Each archive span is entered only for its own scan and hangs under the caller's span, so a
+    // worker that steals another archive while waiting on the nested deep scan does not nest it
+    let span = tracing::Span::current();
+    let results: Vec<Result<Vec<String>, String>> = pool.install(|| {
+        inputs
+            .par_iter()
+            .map(|input| {
+                info_span!(parent: &span, "archive", path = %input.display())
+                    .in_scope(|| scan_archive(input, options).map_err(|e| e.to_string()))
+            })
+            .collect()
+    });
+
+    let mut failed = 0;
+    for (input, result) in inputs.iter().zip(results) {
+        match result {
+            Ok(rows) => {
+                for row in rows {
+                    println!("{}\t{}", input.display(), row);
+                }
+            }
+            Err(e) => {
+                error!(archive = %input.display(), "Scan failed: {}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} archive(s) failed", failed, inputs.len()).into());
+    }
+    Ok(())
+}
+
+/// Scan one archive and return its listing rows; the statistics are logged.
+fn scan_archive(
+    input: &Path,
+    options: &BatchOptions,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    // Map the ZIP archive; pages are read on demand, so archives larger than RAM work
+    let zip_data = map_archive(input)?;
+    let fr_duration = start.elapsed();
+
+    let candidates = scan_dicom_candidates_parallel(&zip_data)?;
+    let duration = start.elapsed();
+
+    let scan = batch::deep_scan(input, &zip_data, true, options)?;
+    let mut deep_candidates = scan.candidates;
+    let deep_duration = start.elapsed();
+    if let Some(filter) = options.filter {
+        filter.apply(&mut deep_candidates);
+    }
+
+    let rows = deep_candidates
+        .iter()
+        .map(|cand| {
+            [
+                cand.name.as_str(),
+                &cand.modality,
+                &cand.manufacturer,
+                &cand.study_instance_uid,
+                &cand.series_instance_uid,
+            ]
+            .join("\t")
+        })
+        .collect();
+
+    let total_compressed: u64 = candidates.iter().map(|c| c.compressed_size).sum();
+    let total_uncompressed: u64 = candidates.iter().map(|c| c.uncompressed_size).sum();
+    let compression_ratio = if total_compressed > 0 {
+        format!(
+            "{:.3} %",
+            total_uncompressed as f64 / total_compressed as f64 * 100.0
+        )
+    } else {
+        "N/A".to_string()
+    };
+
+    info!(
+        files = candidates.len(),
+        zip_read = ?fr_duration,
+        detection = ?(duration - fr_duration),
+        total = ?duration,
+        files_per_sec = (candidates.len() as f64 / duration.as_secs_f64()).round(),
+        "Found {} DICOM files in archive",
+        candidates.len()
+    );
+    info!(
+        compressed_bytes = total_compressed,
+        uncompressed_bytes = total_uncompressed,
+        compression_ratio,
+        threads = rayon::current_num_threads(),
+        "Archive size"
+    );
+    info!(
+        elapsed = ?(deep_duration - duration),
+        "Deep scan found {} DICOM files",
+        deep_candidates.len()
+    );
+    entry_error::report(&scan.skipped);
+
+    // Archives burned from CD/DVD usually carry a DICOMDIR; report whether it matches the content
+    if let Ok(Some((index, present))) = dicomdir::load_dicomdir_index(input) {
+        let check = index.cross_check(&present);
+        info!(
+            "DICOMDIR present: {} referenced file(s), {} missing, {} unreferenced (use the dicomdir subcommand for details)",
+            check.referenced,
+            check.missing.len(),
+            check.unreferenced.len()
+        );
+    }
+
+    // Distinct series per study
+    let mut study_series: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for cand in &deep_candidates {
+        study_series
+            .entry(&cand.study_instance_uid)
+            .or_default()
+            .insert(&cand.series_instance_uid);
+    }
+    for (study_instance_uid, series) in study_series {
+        info!(
+            "Study Instance UID: {} has {} distinct series",
+            study_instance_uid,
+            series.len()
+        );
+    }
+
+    Ok(rows)
+}
+
+/// Print the PatientIDs of every archive, prefixed with the archive path in batch mode.
+pub fn print_mrns(
+    inputs: &[PathBuf],
+    batch_mode: bool,
+    options: &BatchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.max(1))
+        .build()?;
+    // As in `scan_archives`: one span per archive, under the caller's span
+    let span = tracing::Span::current();
+    let results: Vec<(&PathBuf, Result<BTreeSet<String>, String>)> = pool.install(|| {
+        inputs
+            .par_iter()
+            .map(|input| {
+                let result = info_span!(parent: &span, "archive", path = %input.display())
+                    .in_scope(|| archive_mrns(input, options).map_err(|e| e.to_string()));
+                (input, result)
+            })
+            .collect()
+    });
+
+    let mut failed = 0;
+    for (input, result) in results {
+        match result {
+            Ok(patient_ids) => {
+                for patient_id in patient_ids {
+                    if batch_mode {
+                        println!("{}\t{}", input.display(), patient_id);
+                    } else {
+                        println!("{}", patient_id);
+                    }
+                }
+            }
+            Err(e) if !batch_mode => return Err(e.into()),
+            Err(e) => {
+                error!(archive = %input.display(), "Scan failed: {}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} archive(s) failed", failed, inputs.len()).into());
+    }
+    Ok(())
+}
+
+fn archive_mrns(
+    input: &Path,
+    options: &BatchOptions,
+) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let zip_data = map_archive(input)?;
+    let scan = batch::deep_scan(input, &zip_data, true, options)?;
+    entry_error::report(&scan.skipped);
+    let mut deep_candidates = scan.candidates;
+    if let Some(filter) = options.filter {
+        filter.apply(&mut deep_candidates);
+    }
+    Ok(deep_candidates
+        .into_iter()
+        .filter(|c| c.patient_id != "N/A")
+        .map(|c| c.patient_id)
+        .collect())
+}
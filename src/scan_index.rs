@@ -0,0 +1,466 @@
+// --- Persistent scan index ---
+//
+// A SQLite database that remembers deep-scan results between runs. Archives are identified by
+// canonical path, size and modification time; entries by name, CRC-32 and uncompressed size.
+// An unchanged archive is not opened for parsing at all, and in a changed archive only new or
+// modified entries are parsed. The stored results can be queried without the archives. Skipped
+// entries are stored with their reason, so an unchanged archive still reports them.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+use tracing::debug;
+use zip::ZipArchive;
+
+use crate::entry_error::EntryError;
+use crate::{DeepDicomCandidate, DeepScan, deep_scan_dicom_candidates_cached};
+
+/// Cached deep-scan results keyed by (entry name, CRC-32, uncompressed size)
+pub type EntryCache = HashMap<(String, u32, u64), DeepDicomCandidate>;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS archives (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    size INTEGER NOT NULL,
+    mtime_ns INTEGER NOT NULL,
+    scanned_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS entries (
+    archive_id INTEGER NOT NULL REFERENCES archives(id) ON DELETE CASCADE,
+    entry_index INTEGER NOT NULL,
+    entry_name TEXT NOT NULL,
+    crc32 INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    patient_id TEXT NOT NULL,
+    study_instance_uid TEXT NOT NULL,
+    series_instance_uid TEXT NOT NULL,
+    series_number TEXT NOT NULL,
+    series_description TEXT NOT NULL,
+    modality TEXT NOT NULL,
+    candidate TEXT NOT NULL,
+    PRIMARY KEY (archive_id, entry_index)
+);
+CREATE TABLE IF NOT EXISTS skipped (
+    archive_id INTEGER NOT NULL REFERENCES archives(id) ON DELETE CASCADE,
+    entry_name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    detail TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS entries_patient ON entries(patient_id);
+CREATE INDEX IF NOT EXISTS entries_series ON entries(study_instance_uid, series_instance_uid);
+CREATE INDEX IF NOT EXISTS skipped_archive ON skipped(archive_id);
+";
+
+/// Layout version in `PRAGMA user_version`, for migrating indexes when the layout changes
+const SCHEMA_VERSION: i64 = 1;
+
+/// Identity of an archive on disk
+pub struct ArchiveStamp {
+    path: String,
+    size: u64,
+    mtime_ns: i64,
+}
+
+impl ArchiveStamp {
+    pub fn of(archive: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = std::fs::canonicalize(archive)?;
+        let metadata = std::fs::metadata(&path)?;
+        let mtime_ns = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or(0);
+        Ok(ArchiveStamp {
+            path: path.to_string_lossy().to_string(),
+            size: metadata.len(),
+            mtime_ns,
+        })
+    }
+}
+
+pub enum CachedArchive {
+    /// Same path, size and mtime as the last scan: the stored results are current
+    Unchanged(DeepScan),
+    /// New or modified archive, with whatever entries were stored for it before
+    Changed(EntryCache),
+}
+
+pub struct ScanIndex {
+    conn: Connection,
+}
+
+impl ScanIndex {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(30))?;
+        conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")?;
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(ScanIndex { conn })
+    }
+
+    /// Open an existing index for queries only: nothing is created, migrated or switched to WAL.
+    pub fn open_read_only(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(std::time::Duration::from_secs(30))?;
+        Ok(ScanIndex { conn })
+    }
+
+    pub fn lookup(
+        &self,
+        stamp: &ArchiveStamp,
+    ) -> Result<CachedArchive, Box<dyn std::error::Error>> {
+        let archive: Option<(i64, i64, i64)> = self
+            .conn
+            .query_row(
+                "SELECT id, size, mtime_ns FROM archives WHERE path = ?1",
+                params![stamp.path],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        let Some((archive_id, size, mtime_ns)) = archive else {
+            return Ok(CachedArchive::Changed(EntryCache::new()));
+        };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT entry_name, crc32, size, candidate FROM entries WHERE archive_id = ?1",
+        )?;
+        let rows = stmt.query_map(params![archive_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut stored = Vec::new();
+        let mut outdated = false;
+        for row in rows {
+            let (name, crc32, entry_size, json) = row?;
+            // Entries written by an older layout of DeepDicomCandidate are simply re-parsed
+            match serde_json::from_str::<DeepDicomCandidate>(&json) {
+                Ok(candidate) => stored.push(((name, crc32, entry_size as u64), candidate)),
+                Err(_) => outdated = true,
+            }
+        }
+
+        if !outdated && size as u64 == stamp.size && mtime_ns == stamp.mtime_ns {
+            // Every stored entry, including entries that share a name
+            let mut candidates: Vec<DeepDicomCandidate> =
+                stored.into_iter().map(|(_, candidate)| candidate).collect();
+            candidates.sort_by_key(|c| c.index);
+            let mut stmt = self.conn.prepare(
+                "SELECT entry_name, kind, detail FROM skipped WHERE archive_id = ?1 ORDER BY rowid",
+            )?;
+            let skipped = stmt
+                .query_map(params![archive_id], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                })?
+                .filter_map(|row| {
+                    let (name, kind, detail) = row.ok()?;
+                    Some(EntryError::new(name, kind.parse().ok()?, detail))
+                })
+                .collect();
+            Ok(CachedArchive::Unchanged(DeepScan {
+                candidates,
+                skipped,
+            }))
+        } else {
+            Ok(CachedArchive::Changed(stored.into_iter().collect()))
+        }
+    }
+
+    /// Replace everything stored for the archive with the given scan results.
+    pub fn store(
+        &mut self,
+        stamp: &ArchiveStamp,
+        zip_bytes: &[u8],
+        scan: &DeepScan,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let candidates = &scan.candidates;
+        // CRCs come from the central directory; nothing is decompressed here
+        let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+        let mut crcs = Vec::with_capacity(candidates.len());
+        for candidate in candidates {
+            crcs.push(archive.by_index_raw(candidate.index)?.crc32());
+        }
+
+        let scanned_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO archives (path, size, mtime_ns, scanned_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(path) DO UPDATE SET size = excluded.size, mtime_ns = excluded.mtime_ns,
+                 scanned_at = excluded.scanned_at",
+            params![stamp.path, stamp.size as i64, stamp.mtime_ns, scanned_at],
+        )?;
+        let archive_id: i64 = tx.query_row(
+            "SELECT id FROM archives WHERE path = ?1",
+            params![stamp.path],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "DELETE FROM entries WHERE archive_id = ?1",
+            params![archive_id],
+        )?;
+        tx.execute(
+            "DELETE FROM skipped WHERE archive_id = ?1",
+            params![archive_id],
+        )?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO entries (archive_id, entry_index, entry_name, crc32, size, patient_id,
+                     study_instance_uid, series_instance_uid, series_number, series_description,
+                     modality, candidate)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            )?;
+            for (candidate, crc32) in candidates.iter().zip(crcs) {
+                insert.execute(params![
+                    archive_id,
+                    candidate.index as i64,
+                    candidate.name,
+                    crc32,
+                    candidate.uncompressed_size as i64,
+                    candidate.patient_id,
+                    candidate.study_instance_uid,
+                    candidate.series_instance_uid,
+                    candidate.series_number,
+                    candidate.series_description,
+                    candidate.modality,
+                    serde_json::to_string(candidate)?,
+                ])?;
+            }
+            let mut insert = tx.prepare(
+                "INSERT INTO skipped (archive_id, entry_name, kind, detail)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for e in &scan.skipped {
+                insert.execute(params![archive_id, e.name, e.kind.as_str(), e.detail])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Deep scan an archive through the index: unchanged archives come straight from the database,
+/// changed ones only parse new or modified entries, and the results are written back.
+pub fn deep_scan_with_index(
+    index: &Mutex<ScanIndex>,
+    archive_path: &Path,
+    zip_bytes: &[u8],
+) -> Result<DeepScan, Box<dyn std::error::Error>> {
+    let stamp = ArchiveStamp::of(archive_path)?;
+    let cached = {
+        let index = index.lock().unwrap_or_else(|e| e.into_inner());
+        index.lookup(&stamp)?
+    };
+
+    let cache = match cached {
+        CachedArchive::Unchanged(scan) => {
+            debug!(
+                "Index: archive unchanged, {} entries loaded from the index",
+                scan.candidates.len()
+            );
+            return Ok(scan);
+        }
+        CachedArchive::Changed(cache) => cache,
+    };
+
+    let (scan, reused) = deep_scan_dicom_candidates_cached(zip_bytes, &cache)?;
+    debug!(
+        "Index: {} entries reused, {} parsed",
+        reused,
+        scan.candidates.len() - reused
+    );
+
+    let mut index = index.lock().unwrap_or_else(|e| e.into_inner());
+    index.store(&stamp, zip_bytes, &scan)?;
+    Ok(scan)
+}
+
+/// Filters for querying the index
+#[derive(Debug, Default)]
+pub struct IndexQuery {
+    pub patient_id: Option<String>,
+    pub study_uid: Option<String>,
+    pub modality: Option<String>,
+    pub archive: Option<String>,
+}
+
+/// Print the indexed archives, or the indexed series matching the filters.
+pub fn print_index_query(
+    db_path: &Path,
+    query: &IndexQuery,
+    list_archives: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !db_path.exists() {
+        return Err(format!("Index not found: {}", db_path.display()).into());
+    }
+    let index = ScanIndex::open_read_only(db_path)?;
+
+    if list_archives {
+        let mut stmt = index.conn.prepare(
+            "SELECT a.path, a.size, a.scanned_at, COUNT(e.entry_name)
+             FROM archives a LEFT JOIN entries e ON e.archive_id = a.id
+             WHERE (?1 IS NULL OR a.path LIKE '%' || ?1 || '%')
+             GROUP BY a.id ORDER BY a.path",
+        )?;
+        let rows = stmt.query_map(params![query.archive], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+        println!("Archive\tSize\tScannedAt\tDicomFiles");
+        for row in rows {
+            let (path, size, scanned_at, files) = row?;
+            println!("{}\t{}\t{}\t{}", path, size, scanned_at, files);
+        }
+        return Ok(());
+    }
+
+    let mut stmt = index.conn.prepare(
+        "SELECT a.path, e.patient_id, e.study_instance_uid, e.series_instance_uid,
+                e.series_number, e.modality, e.series_description, COUNT(*)
+         FROM entries e JOIN archives a ON a.id = e.archive_id
+         WHERE (?1 IS NULL OR e.patient_id = ?1)
+           AND (?2 IS NULL OR e.study_instance_uid = ?2)
+           AND (?3 IS NULL OR e.modality = ?3)
+           AND (?4 IS NULL OR a.path LIKE '%' || ?4 || '%')
+         GROUP BY a.id, e.study_instance_uid, e.series_instance_uid
+         ORDER BY a.path, e.patient_id, e.study_instance_uid,
+                  CAST(e.series_number AS INTEGER), e.series_instance_uid",
+    )?;
+    let rows = stmt.query_map(
+        params![
+            query.patient_id,
+            query.study_uid,
+            query.modality,
+            query.archive
+        ],
+        |row| {
+            Ok([
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, String>(6)?,
+                row.get::<_, i64>(7)?.to_string(),
+            ])
+        },
+    )?;
+
+    println!(
+        "Archive\tPatientID\tStudyInstanceUID\tSeriesInstanceUID\tSeriesNumber\tModality\tSeriesDescription\tFileCount"
+    );
+    for row in rows {
+        println!("{}", row?.join("\t"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::core::VR;
+    use dicom::dictionary_std::tags;
+    use dicom::object::{FileMetaTableBuilder, InMemDicomObject};
+    use dicom::transfer_syntax::entries;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn instance(series_uid: &str, sop_uid: &str) -> Vec<u8> {
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put_str(tags::SOP_CLASS_UID, VR::UI, "1.2.840.10008.5.1.4.1.1.7");
+        obj.put_str(tags::SOP_INSTANCE_UID, VR::UI, sop_uid);
+        obj.put_str(tags::PATIENT_ID, VR::LO, "P1");
+        obj.put_str(tags::STUDY_INSTANCE_UID, VR::UI, "1.2.3");
+        obj.put_str(tags::SERIES_INSTANCE_UID, VR::UI, series_uid);
+        obj.put_str(tags::MODALITY, VR::CS, "OT");
+        let file = obj
+            .with_meta(
+                FileMetaTableBuilder::new()
+                    .transfer_syntax(entries::EXPLICIT_VR_LITTLE_ENDIAN.uid())
+                    .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+                    .media_storage_sop_instance_uid(sop_uid),
+            )
+            .unwrap();
+        let mut out = Vec::new();
+        file.write_all(&mut out).unwrap();
+        out
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dicom_scanner_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn duplicate_entry_names_are_kept() {
+        let dir = scratch_dir("index_duplicates");
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (series, sop) in [("1.2.3.1", "1.2.3.1.1"), ("1.2.3.2", "1.2.3.2.1")] {
+            zip.start_file("DICOM/IM0001", zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(&instance(series, sop)).unwrap();
+        }
+        let bytes = zip.finish().unwrap().into_inner();
+        let archive = dir.join("duplicates.zip");
+        std::fs::write(&archive, &bytes).unwrap();
+
+        let index = Mutex::new(ScanIndex::open(&dir.join("index.db")).unwrap());
+        let scanned = deep_scan_with_index(&index, &archive, &bytes).unwrap();
+        assert_eq!(scanned.candidates.len(), 2);
+        // The second scan comes from the index alone
+        let stamp = ArchiveStamp::of(&archive).unwrap();
+        let CachedArchive::Unchanged(stored) = index.lock().unwrap().lookup(&stamp).unwrap() else {
+            panic!("archive not recognized as unchanged");
+        };
+        let series: Vec<&str> = stored
+            .candidates
+            .iter()
+            .map(|c| c.series_instance_uid.as_str())
+            .collect();
+        assert_eq!(series, ["1.2.3.1", "1.2.3.2"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_only_open_changes_nothing() {
+        let dir = scratch_dir("index_read_only");
+        let missing = dir.join("missing.db");
+        assert!(ScanIndex::open_read_only(&missing).is_err());
+        assert!(!missing.exists());
+
+        let path = dir.join("index.db");
+        drop(ScanIndex::open(&path).unwrap());
+        let index = ScanIndex::open_read_only(&path).unwrap();
+        assert!(index.conn.execute("DELETE FROM archives", []).is_err());
+        print_index_query(&path, &IndexQuery::default(), true).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
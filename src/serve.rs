@@ -0,0 +1,750 @@
+// --- HTTP JSON API ---
+//
+// `serve` lets other programs ask "what's in this archive?" over HTTP. A job is submitted either
+// as a path on the server (ZIP archive, folder or single DICOM file) or by uploading a ZIP as the
+// request body, which is spooled to a temporary file. Jobs are scanned in the background by a
+// small pool of workers, with at most MAX_QUEUED waiting. Every header is read once, without the
+// pixel data: it gives the series metadata (the same columns as series_metadata.csv), the
+// derivation analysis gives the series graph, and the Siemens XProtocol is kept per series.
+// Results stay in memory until the job is deleted or has been finished for longer than
+// --keep-minutes.
+//
+// Submitted paths must lie inside --root. Browsers only get cross-origin access for the origin
+// given with --cors-origin.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dicom::dictionary_std::tags;
+use dicom::object::OpenFileOptions;
+use serde_json::{Value, json};
+use tracing::{error, info, info_span, warn};
+use zip::ZipArchive;
+
+use crate::entry_error::{self, EntryError};
+use crate::watch::{Packed, pack, spool};
+use crate::{
+    DeepScan, DerivationAnalysis, DicomObjects, SERIES_METADATA_HEADER, SettledArchive,
+    compute_derivations, deep_candidate_from_object, entry_name, get_tag_string,
+    series_metadata_rows, xprotocol_text,
+};
+
+/// How long a submitted archive must keep its size before it is scanned
+const SETTLE: Duration = Duration::from_secs(1);
+
+/// Jobs waiting for a worker; further submissions are refused until one starts
+const MAX_QUEUED: usize = 32;
+
+/// Largest JSON request body
+const MAX_JSON: u64 = 64 * 1024;
+
+pub struct ServeOptions<'a> {
+    pub bind: &'a str,
+    pub port: u16,
+    /// Only accept submitted paths inside this directory
+    pub root: &'a Path,
+    /// Number of jobs scanned at the same time
+    pub workers: usize,
+    /// Largest accepted upload in bytes
+    pub max_upload: u64,
+    /// Origin sent in Access-Control-Allow-Origin
+    pub cors_origin: Option<&'a str>,
+    /// How long finished jobs are kept
+    pub keep: Duration,
+}
+
+enum Input {
+    Path(PathBuf),
+    /// Uploaded ZIP, spooled to a temporary file
+    Upload(Packed),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl State {
+    fn name(self) -> &'static str {
+        match self {
+            State::Queued => "queued",
+            State::Running => "running",
+            State::Done => "done",
+            State::Failed => "failed",
+        }
+    }
+}
+
+struct Job {
+    source: String,
+    state: State,
+    error: Option<String>,
+    submitted: u64,
+    finished: Option<u64>,
+    result: Option<ScanResult>,
+}
+
+struct ScanResult {
+    files: usize,
+    /// One object per series, keyed by the series_metadata.csv columns
+    series: Vec<Value>,
+    derivations: Value,
+    /// Series Instance UID → XProtocol text
+    protocols: BTreeMap<String, String>,
+    /// Entries that are not scanned, with the reason
+    skipped: Vec<EntryError>,
+}
+
+struct Jobs {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+    queue: mpsc::SyncSender<(u64, Input)>,
+}
+
+struct Reply {
+    status: u16,
+    body: Value,
+}
+
+impl Reply {
+    fn ok(body: Value) -> Reply {
+        Reply { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Reply {
+        Reply {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+}
+
+/// Answer API requests until the process is stopped.
+pub fn serve(options: &ServeOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let root = options
+        .root
+        .canonicalize()
+        .map_err(|e| format!("--root {}: {}", options.root.display(), e))?;
+    if let Some(origin) = options.cors_origin
+        && tiny_http::Header::from_bytes("Access-Control-Allow-Origin", origin).is_err()
+    {
+        return Err(format!("--cors-origin {} is not a valid header value", origin).into());
+    }
+    let server = tiny_http::Server::http((options.bind, options.port))
+        .map_err(|e| format!("Cannot listen on {}:{}: {}", options.bind, options.port, e))?;
+    info!(
+        "Serving the scan API at http://{}:{}/",
+        options.bind, options.port
+    );
+
+    let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED);
+    let receiver = Mutex::new(receiver);
+    let jobs = Jobs {
+        jobs: Mutex::new(BTreeMap::new()),
+        next_id: AtomicU64::new(1),
+        queue: sender,
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..options.workers.max(1) {
+            scope.spawn(|| {
+                loop {
+                    let next = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    let Ok((id, input)) = next else {
+                        break;
+                    };
+                    run_job(&jobs, id, input);
+                }
+            });
+        }
+        for _ in 0..4 {
+            scope.spawn(|| {
+                while let Ok(request) = server.recv() {
+                    evict(&jobs, options.keep);
+                    answer(&jobs, &root, options, request);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Forget jobs that finished more than `keep` ago.
+fn evict(jobs: &Jobs, keep: Duration) {
+    let cutoff = now().saturating_sub(keep.as_secs());
+    let mut all = jobs.jobs.lock().unwrap_or_else(|e| e.into_inner());
+    all.retain(|_, job| job.finished.is_none_or(|finished| finished > cutoff));
+}
+
+fn run_job(jobs: &Jobs, id: u64, input: Input) {
+    let source = {
+        let mut all = jobs.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        match all.get_mut(&id) {
+            Some(job) => {
+                job.state = State::Running;
+                job.source.clone()
+            }
+            // Deleted while queued
+            None => return,
+        }
+    };
+
+    let outcome = info_span!("job", id, source = %source).in_scope(|| scan(&input));
+    drop(input);
+    match &outcome {
+        Ok(result) => info!(
+            "Job {} ({}): {} file(s), {} series",
+            id,
+            source,
+            result.files,
+            result.series.len()
+        ),
+        Err(e) => error!("Job {} ({}) failed: {}", id, source, e),
+    }
+
+    let mut all = jobs.jobs.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(job) = all.get_mut(&id) {
+        job.finished = Some(now());
+        match outcome {
+            Ok(result) => {
+                job.state = State::Done;
+                job.result = Some(result);
+            }
+            Err(e) => {
+                job.state = State::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Read the headers of one input and run the derivation analysis on them.
+fn scan(input: &Input) -> Result<ScanResult, Box<dyn std::error::Error>> {
+    let mapped;
+    let packed;
+    let zip_data: &[u8] = match input {
+        Input::Upload(upload) => upload,
+        Input::Path(path) if path.is_dir() => {
+            packed = pack(std::slice::from_ref(path), path)?;
+            &packed
+        }
+        Input::Path(path) => {
+            mapped = SettledArchive::open(path, SETTLE)?;
+            if ZipArchive::new(Cursor::new(&mapped[..])).is_ok() {
+                &mapped
+            } else {
+                packed = pack(
+                    std::slice::from_ref(path),
+                    path.parent().unwrap_or(Path::new("")),
+                )?;
+                &packed
+            }
+        }
+    };
+
+    let (
+        DeepScan {
+            candidates,
+            skipped,
+        },
+        objects,
+    ) = read_headers(zip_data)?;
+    entry_error::report(&skipped);
+    if candidates.is_empty() {
+        return Err("No DICOM files found".into());
+    }
+
+    let columns: Vec<&str> = SERIES_METADATA_HEADER.split(',').collect();
+    let series = series_metadata_rows(&candidates)
+        .into_iter()
+        .map(|row| {
+            let fields = columns
+                .iter()
+                .zip(&row)
+                .map(|(column, value)| (column.to_string(), text(value)))
+                .collect();
+            Value::Object(fields)
+        })
+        .collect();
+
+    let analysis = compute_derivations(&objects);
+    let mut protocols = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for obj in &objects {
+        let series_uid = get_tag_string(obj, tags::SERIES_INSTANCE_UID);
+        if series_uid == "N/A" || !seen.insert(series_uid.clone()) {
+            continue;
+        }
+        if let Some(protocol) = xprotocol_text(obj) {
+            protocols.insert(series_uid, protocol);
+        }
+    }
+
+    Ok(ScanResult {
+        files: candidates.len(),
+        series,
+        derivations: derivations_json(&analysis),
+        protocols,
+        skipped,
+    })
+}
+
+/// Read every entry up to the pixel data once: the deep scan, and the headers for the derivation
+/// analysis and XProtocol.
+fn read_headers(zip_data: &[u8]) -> Result<(DeepScan, DicomObjects), Box<dyn std::error::Error>> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_data))?;
+    let mut names = archive.clone();
+    let mut candidates = Vec::new();
+    let mut objects = Vec::new();
+    let mut skipped = Vec::new();
+    for i in 0..archive.len() {
+        let file = match archive.by_index(i) {
+            Ok(f) => f,
+            Err(e) => {
+                skipped.push(EntryError::from_zip(entry_name(&mut names, i), &e));
+                continue;
+            }
+        };
+        if file.is_dir() {
+            continue;
+        }
+        if file.size() < 132 {
+            skipped.push(EntryError::too_short(file.name(), file.size()));
+            continue;
+        }
+        let name = file.name().to_string();
+        let (compressed_size, size) = (file.compressed_size(), file.size());
+        match OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .from_reader(file)
+        {
+            Ok(obj) => {
+                objects.push((*obj).clone());
+                candidates.extend(deep_candidate_from_object(
+                    obj,
+                    i,
+                    name,
+                    compressed_size,
+                    size,
+                ));
+            }
+            Err(e) => skipped.push(EntryError::from_dicom(name, &e)),
+        }
+    }
+    Ok((
+        DeepScan {
+            candidates,
+            skipped,
+        },
+        objects,
+    ))
+}
+
+/// A metadata value, with "N/A" as null
+fn text(value: &str) -> Value {
+    if value == "N/A" {
+        Value::Null
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+fn derivations_json(analysis: &DerivationAnalysis) -> Value {
+    let series: Vec<Value> = analysis
+        .derivation_infos
+        .iter()
+        .map(|info| {
+            let codes: Vec<Value> = info
+                .derivation_codes
+                .iter()
+                .map(|code| {
+                    json!({
+                        "code_value": text(&code.code_value),
+                        "coding_scheme": text(&code.coding_scheme),
+                        "code_meaning": text(&code.code_meaning),
+                    })
+                })
+                .collect();
+            json!({
+                "series_instance_uid": info.series_instance_uid,
+                "series_number": text(&info.series_number),
+                "series_description": text(&info.series_description),
+                "modality": text(&info.modality),
+                "image_type": text(&info.image_type),
+                "is_derived": info.is_derived,
+                "file_count": info.file_count,
+                "derivation_description": text(&info.derivation_description),
+                "derivation_codes": codes,
+                "frame_of_reference_uid": text(&info.frame_of_reference_uid),
+                "unresolved_references": analysis
+                    .unresolved_sops
+                    .get(&info.series_instance_uid)
+                    .map_or(0, |sops| sops.len()),
+            })
+        })
+        .collect();
+
+    let mut graph: Vec<_> = analysis.derivation_graph.iter().collect();
+    graph.sort_by(|a, b| a.0.cmp(b.0));
+    let edges: Vec<Value> = graph
+        .into_iter()
+        .flat_map(|(derived_uid, edges)| {
+            edges.iter().map(move |edge| {
+                json!({
+                    "derived_series_uid": derived_uid,
+                    "source_series_uid": edge.source_series_uid,
+                    "evidence": edge.evidence_tags,
+                })
+            })
+        })
+        .collect();
+
+    let phantom_sources: Vec<Value> = analysis
+        .phantom_groups
+        .iter()
+        .map(|group| {
+            json!({
+                "series_uids": group.series_uids,
+                "shared_sops": group.shared_sops,
+                "total_unique_sops": group.total_unique_sops,
+            })
+        })
+        .collect();
+
+    json!({
+        "series": series,
+        "edges": edges,
+        "phantom_sources": phantom_sources,
+    })
+}
+
+fn job_json(id: u64, job: &Job) -> Value {
+    json!({
+        "id": id,
+        "source": job.source,
+        "status": job.state.name(),
+        "error": job.error,
+        "submitted": job.submitted,
+        "finished": job.finished,
+        "files": job.result.as_ref().map(|r| r.files),
+        "series_count": job.result.as_ref().map(|r| r.series.len()),
+        "skipped_count": job.result.as_ref().map(|r| r.skipped.len()),
+    })
+}
+
+fn answer(jobs: &Jobs, root: &Path, options: &ServeOptions, mut request: tiny_http::Request) {
+    let preflight = *request.method() == tiny_http::Method::Options;
+    let reply = if preflight && options.cors_origin.is_some() {
+        Reply {
+            status: 204,
+            body: Value::Null,
+        }
+    } else if preflight {
+        Reply::error(405, "cross-origin requests are not enabled")
+    } else {
+        route(jobs, root, options.max_upload, &mut request)
+            .unwrap_or_else(|e| Reply::error(500, &e.to_string()))
+    };
+
+    let header = |name: &str, value: &str| {
+        tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+    };
+    let body = if reply.status == 204 {
+        Vec::new()
+    } else {
+        reply.body.to_string().into_bytes()
+    };
+    let mut response = tiny_http::Response::from_data(body)
+        .with_status_code(reply.status)
+        .with_header(header("Content-Type", "application/json"));
+    if let Some(origin) = options.cors_origin {
+        response = response
+            .with_header(header("Access-Control-Allow-Origin", origin))
+            .with_header(header("Access-Control-Allow-Headers", "Content-Type"))
+            .with_header(header("Access-Control-Allow-Methods", "GET, POST, DELETE"))
+            .with_header(header("Vary", "Origin"));
+    }
+    if let Err(e) = request.respond(response) {
+        warn!("Response failed: {}", e);
+    }
+}
+
+fn route(
+    jobs: &Jobs,
+    root: &Path,
+    max_upload: u64,
+    request: &mut tiny_http::Request,
+) -> Result<Reply, Box<dyn std::error::Error>> {
+    use tiny_http::Method;
+
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let method = request.method().clone();
+
+    let id = match segments.as_slice() {
+        ["jobs"] => {
+            return Ok(match method {
+                Method::Get => {
+                    let all = jobs.jobs.lock().unwrap_or_else(|e| e.into_inner());
+                    Reply::ok(Value::Array(
+                        all.iter().map(|(id, job)| job_json(*id, job)).collect(),
+                    ))
+                }
+                Method::Post => submit(jobs, root, max_upload, request, query)?,
+                _ => Reply::error(405, "use GET or POST"),
+            });
+        }
+        ["jobs", id, ..] => match id.parse::<u64>() {
+            Ok(id) => id,
+            Err(_) => return Ok(Reply::error(404, "no such job")),
+        },
+        _ => return Ok(Reply::error(404, "unknown resource")),
+    };
+
+    let mut all = jobs.jobs.lock().unwrap_or_else(|e| e.into_inner());
+    if segments.len() == 2 && method == Method::Delete {
+        return Ok(match all.remove(&id) {
+            Some(job) => Reply::ok(job_json(id, &job)),
+            None => Reply::error(404, "no such job"),
+        });
+    }
+    if method != Method::Get {
+        return Ok(Reply::error(405, "use GET"));
+    }
+    let Some(job) = all.get(&id) else {
+        return Ok(Reply::error(404, "no such job"));
+    };
+    if segments.len() == 2 {
+        return Ok(Reply::ok(job_json(id, job)));
+    }
+    let Some(result) = &job.result else {
+        return Ok(Reply::error(
+            409,
+            &format!("job is {}, results are not available", job.state.name()),
+        ));
+    };
+
+    let series = |uid: &str| {
+        result
+            .series
+            .iter()
+            .find(|s| s["SeriesInstanceUID"].as_str() == Some(uid))
+    };
+    let reply = match segments[2..] {
+        ["series"] => Reply::ok(Value::Array(result.series.clone())),
+        ["series", uid] => match series(uid) {
+            Some(s) => Reply::ok(s.clone()),
+            None => Reply::error(404, "no such series"),
+        },
+        ["series", uid, "xprotocol"] => match (series(uid), result.protocols.get(uid)) {
+            (None, _) => Reply::error(404, "no such series"),
+            (Some(_), None) => Reply::error(404, "series has no XProtocol"),
+            (Some(_), Some(protocol)) => Reply::ok(json!({
+                "series_instance_uid": uid,
+                "xprotocol": protocol,
+            })),
+        },
+        ["derivations"] => Reply::ok(result.derivations.clone()),
+        ["skipped"] => Reply::ok(Value::Array(
+            result
+                .skipped
+                .iter()
+                .map(|e| json!({"entry": e.name, "kind": e.kind.as_str(), "detail": e.detail}))
+                .collect(),
+        )),
+        _ => Reply::error(404, "unknown resource"),
+    };
+    Ok(reply)
+}
+
+/// Queue a job for a path given as `{"path": ...}`, or for a ZIP uploaded as the body.
+fn submit(
+    jobs: &Jobs,
+    root: &Path,
+    max_upload: u64,
+    request: &mut tiny_http::Request,
+    query: &str,
+) -> Result<Reply, Box<dyn std::error::Error>> {
+    let is_json = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"));
+
+    let (source, input) = if is_json {
+        let mut body = Vec::new();
+        request
+            .as_reader()
+            .take(MAX_JSON + 1)
+            .read_to_end(&mut body)?;
+        if body.len() as u64 > MAX_JSON {
+            return Ok(Reply::error(413, "JSON body is too large"));
+        }
+        let submitted: Value = match serde_json::from_slice(&body) {
+            Ok(v) => v,
+            Err(e) => return Ok(Reply::error(400, &format!("invalid JSON: {}", e))),
+        };
+        let Some(path) = submitted["path"].as_str() else {
+            return Ok(Reply::error(400, "expected {\"path\": \"...\"}"));
+        };
+        let Ok(path) = Path::new(path).canonicalize() else {
+            return Ok(Reply::error(404, &format!("{} does not exist", path)));
+        };
+        if !path.starts_with(root) {
+            return Ok(Reply::error(403, "path is outside --root"));
+        }
+        (path.display().to_string(), Input::Path(path))
+    } else {
+        let Some(upload) = spool(request.as_reader(), max_upload)? else {
+            return Ok(Reply::error(413, "upload is larger than --max-upload-mb"));
+        };
+        if upload.is_empty() {
+            return Ok(Reply::error(400, "expected a ZIP body or a JSON path"));
+        }
+        let name = url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "name")
+            .map_or("upload".to_string(), |(_, v)| v.into_owned());
+        (name, Input::Upload(upload))
+    };
+
+    let id = jobs.next_id.fetch_add(1, Ordering::Relaxed);
+    let job = Job {
+        source,
+        state: State::Queued,
+        error: None,
+        submitted: now(),
+        finished: None,
+        result: None,
+    };
+    let reply = json!({ "id": id, "status": job.state.name() });
+    jobs.jobs
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(id, job);
+    if let Err(e) = jobs.queue.try_send((id, input)) {
+        jobs.jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id);
+        return match e {
+            mpsc::TrySendError::Full(_) => Ok(Reply::error(503, "too many jobs are queued")),
+            mpsc::TrySendError::Disconnected(_) => Err("the workers have stopped".into()),
+        };
+    }
+    Ok(Reply {
+        status: 202,
+        body: reply,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{file_bytes, http_exchange, instance, scratch_dir, zip_of};
+
+    /// Send one request to `answer` and return the status and JSON body of the response.
+    fn call(
+        jobs: &Jobs,
+        root: &Path,
+        options: &ServeOptions,
+        method: &str,
+        url: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> (u16, Value) {
+        let mut request = format!(
+            "{} {} HTTP/1.0\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            method,
+            url,
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(body);
+        let response = http_exchange(&request, |r| answer(jobs, root, options, r));
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn jobs_api() {
+        let root = scratch_dir("serve_api");
+        let options = ServeOptions {
+            bind: "127.0.0.1",
+            port: 0,
+            root: &root,
+            workers: 1,
+            max_upload: 4096,
+            cors_origin: None,
+            keep: Duration::from_secs(60),
+        };
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let jobs = Jobs {
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            queue: sender,
+        };
+        let call = |method, url, content_type, body: &[u8]| {
+            call(&jobs, &root, &options, method, url, content_type, body)
+        };
+        let zip = zip_of(&[(
+            "DICOM/IM0",
+            file_bytes(instance("P1", "1.2", "1.2.3", "1.2.3.4")),
+        )]);
+
+        let (status, reply) = call("POST", "/jobs?name=a.zip", "application/zip", &zip);
+        assert_eq!((status, reply["id"].as_u64()), (202, Some(1)));
+        // The queue holds one job
+        let (status, _) = call("POST", "/jobs", "application/zip", &zip);
+        assert_eq!(status, 503);
+        let (status, reply) = call("GET", "/jobs/1/series", "text/plain", b"");
+        assert_eq!((status, reply["error"].is_string()), (409, true));
+
+        let (id, input) = receiver.try_recv().unwrap();
+        run_job(&jobs, id, input);
+        let (status, reply) = call("GET", "/jobs/1", "text/plain", b"");
+        assert_eq!(status, 200);
+        assert_eq!(reply["status"], "done");
+        assert_eq!(reply["source"], "a.zip");
+        assert_eq!(reply["files"], 1);
+        let (_, reply) = call("GET", "/jobs/1/series/1.2.3", "text/plain", b"");
+        assert_eq!(reply["Modality"], "OT");
+        assert_eq!(reply["FileCount"], "1");
+        let (status, _) = call("GET", "/jobs/1/series/1.2.3/xprotocol", "text/plain", b"");
+        assert_eq!(status, 404);
+        let (_, reply) = call("GET", "/jobs/1/derivations", "text/plain", b"");
+        assert_eq!(reply["series"][0]["series_instance_uid"], "1.2.3");
+
+        let (status, _) = call("POST", "/jobs", "application/zip", &[0; 4097]);
+        assert_eq!(status, 413);
+        let (status, _) = call("POST", "/jobs", "application/zip", b"");
+        assert_eq!(status, 400);
+        let outside = format!(
+            "{{\"path\": {:?}}}",
+            std::env::temp_dir().display().to_string()
+        );
+        let (status, _) = call("POST", "/jobs", "application/json", outside.as_bytes());
+        assert_eq!(status, 403);
+
+        let (status, _) = call("DELETE", "/jobs/1", "text/plain", b"");
+        assert_eq!(status, 200);
+        let (status, _) = call("GET", "/jobs/1", "text/plain", b"");
+        assert_eq!(status, 404);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+// --- Sorting downloaded archives by patient ---
+//
+// Replaces scripts/sort_downloaded_zips.sh: every ZIP below the input directory (except the
+// output directory) is deep-scanned to find the patients it contains, and the archive is copied
+// (or moved) to a destination rendered from a path template per patient, using the header of the
+// patient's first instance. Archives holding several patients are copied to each patient's
+// destination.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use dicom::dictionary_std::tags;
+use dicom::object::OpenFileOptions;
+use dicom::object::mem::InMemDicomObject;
+use dicom::object::{FileDicomObject, StandardDataDictionary};
+use rayon::prelude::*;
+use tracing::{error, info, info_span, warn};
+use zip::ZipArchive;
+
+use crate::template::PathTemplate;
+use crate::{deep_scan_dicom_candidates_parallel, map_archive, unique_output_path};
+
+/// Default layout, matching the file names produced by the old shell script.
+pub const DEFAULT_SORT_LAYOUT: &str = "{PatientID}.zip";
+
+type HeaderObject = FileDicomObject<InMemDicomObject<StandardDataDictionary>>;
+
+enum SortOutcome {
+    Sorted(Vec<PathBuf>),
+    Skipped(String),
+    Failed(String),
+}
+
+/// Sort every ZIP archive found below `input_dir` into `output_dir`.
+pub fn sort_archives(
+    input_dir: &Path,
+    output_dir: &Path,
+    layout: &PathTemplate,
+    move_files: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !input_dir.is_dir() {
+        return Err(format!("Input path does not exist: {}", input_dir.display()).into());
+    }
+    fs::create_dir_all(output_dir)?;
+
+    let mut archives = find_zip_files(input_dir, &fs::canonicalize(output_dir)?)?;
+    archives.sort();
+
+    info!(
+        "Found {} ZIP file(s) in {}, sorting into {}",
+        archives.len(),
+        input_dir.display(),
+        output_dir.display()
+    );
+
+    // Destinations are reserved up front so parallel workers never pick the same name
+    let reserved = Mutex::new(HashSet::new());
+    let outcomes: Vec<(PathBuf, SortOutcome)> = archives
+        .par_iter()
+        .map(|archive| {
+            let outcome = sort_archive(archive, output_dir, layout, move_files, &reserved);
+            (archive.clone(), outcome)
+        })
+        .collect();
+
+    let mut processed = 0;
+    let mut skipped = 0;
+    let mut errors = 0;
+    for (archive, outcome) in &outcomes {
+        let _archive = info_span!("archive", path = %archive.display()).entered();
+        match outcome {
+            SortOutcome::Sorted(destinations) => {
+                processed += 1;
+                for destination in destinations {
+                    let action = if move_files { "Moved" } else { "Copied" };
+                    info!("{} to: {}", action, destination.display());
+                }
+            }
+            SortOutcome::Skipped(reason) => {
+                skipped += 1;
+                warn!("{}, skipping file", reason);
+            }
+            SortOutcome::Failed(reason) => {
+                errors += 1;
+                error!("{}", reason);
+            }
+        }
+    }
+
+    info!(processed, skipped, errors, "Sorting complete");
+
+    Ok(())
+}
+
+fn sort_archive(
+    archive: &Path,
+    output_dir: &Path,
+    layout: &PathTemplate,
+    move_files: bool,
+    reserved: &Mutex<HashSet<PathBuf>>,
+) -> SortOutcome {
+    let patients = match read_patient_headers(archive) {
+        Ok(patients) => patients,
+        Err(e) => return SortOutcome::Failed(format!("Failed to read archive: {}", e)),
+    };
+    if patients.is_empty() {
+        return SortOutcome::Skipped("No MRN found".to_string());
+    }
+
+    let archive_name = archive
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut destinations = Vec::new();
+    for (index, header) in patients.iter().enumerate() {
+        let relative = layout.render_object(header, &archive_name, index);
+        let destination = reserve_destination(output_dir.join(relative), reserved);
+
+        if let Some(parent) = destination.parent()
+            && let Err(e) = fs::create_dir_all(parent)
+        {
+            return SortOutcome::Failed(format!("Failed to create {}: {}", parent.display(), e));
+        }
+        if let Err(e) = fs::copy(archive, &destination) {
+            return SortOutcome::Failed(format!(
+                "Failed to copy to {}: {}",
+                destination.display(),
+                e
+            ));
+        }
+        destinations.push(destination);
+    }
+
+    // The source is only removed once every patient's copy exists
+    if move_files && let Err(e) = fs::remove_file(archive) {
+        return SortOutcome::Failed(format!("Copied but failed to remove source: {}", e));
+    }
+
+    SortOutcome::Sorted(destinations)
+}
+
+/// Pick a destination that is neither on disk nor taken by another worker.
+fn reserve_destination(path: PathBuf, reserved: &Mutex<HashSet<PathBuf>>) -> PathBuf {
+    let mut reserved = reserved.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        let candidate = unique_output_path(path.clone(), &mut reserved);
+        if !candidate.exists() {
+            return candidate;
+        }
+    }
+}
+
+/// Read the header of the first instance of every patient in the archive, in archive order.
+fn read_patient_headers(archive: &Path) -> Result<Vec<HeaderObject>, Box<dyn std::error::Error>> {
+    let zip_data = map_archive(archive)?;
+    let mut candidates = deep_scan_dicom_candidates_parallel(&zip_data)?.candidates;
+    candidates.sort_by_key(|c| c.index);
+
+    let mut zip = ZipArchive::new(Cursor::new(&zip_data[..]))?;
+    let mut seen = HashSet::new();
+    let mut headers = Vec::new();
+    for candidate in candidates {
+        let patient_id = candidate.patient_id.trim();
+        if patient_id.is_empty() || patient_id == "N/A" || !seen.insert(patient_id.to_string()) {
+            continue;
+        }
+        let header = OpenFileOptions::new()
+            .read_until(tags::PIXEL_DATA)
+            .from_reader(zip.by_index(candidate.index)?)?;
+        headers.push(header);
+    }
+
+    Ok(headers)
+}
+
+/// Every ZIP file below `dir`, except those in the `exclude` directory (the sort output).
+fn find_zip_files(dir: &Path, exclude: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut found = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if !fs::canonicalize(&path).is_ok_and(|p| p == exclude) {
+                    stack.push(path);
+                }
+            } else if path
+                .extension()
+                .map(|e| e.eq_ignore_ascii_case("zip"))
+                .unwrap_or(false)
+            {
+                found.push(path);
+            }
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{file_bytes, instance, scratch_dir, zip_of};
+
+    fn archive(patients: &[&str]) -> Vec<u8> {
+        let files: Vec<(String, Vec<u8>)> = patients
+            .iter()
+            .enumerate()
+            .map(|(i, patient)| {
+                let uid = format!("1.2.{}", i + 1);
+                let obj = instance(
+                    patient,
+                    &uid,
+                    &format!("{}.1", uid),
+                    &format!("{}.1.1", uid),
+                );
+                (format!("DICOM/IM{}", i), file_bytes(obj))
+            })
+            .collect();
+        let entries: Vec<(&str, Vec<u8>)> =
+            files.iter().map(|(n, d)| (n.as_str(), d.clone())).collect();
+        zip_of(&entries)
+    }
+
+    fn zip_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn archives_are_copied_per_patient() {
+        let dir = scratch_dir("sort_copy");
+        fs::create_dir_all(dir.join("downloads/more")).unwrap();
+        fs::write(dir.join("downloads/a.zip"), archive(&["P1"])).unwrap();
+        fs::write(
+            dir.join("downloads/more/b.zip"),
+            archive(&["P2", "P3", "P2"]),
+        )
+        .unwrap();
+        fs::write(dir.join("downloads/notes.txt"), "not an archive").unwrap();
+        let layout = PathTemplate::parse(DEFAULT_SORT_LAYOUT).unwrap();
+
+        // The output sits inside the input directory and is not sorted again
+        let output = dir.join("downloads/sorted");
+        sort_archives(&dir.join("downloads"), &output, &layout, false).unwrap();
+        assert_eq!(zip_names(&output), ["P1.zip", "P2.zip", "P3.zip"]);
+        sort_archives(&dir.join("downloads"), &output, &layout, false).unwrap();
+        assert_eq!(
+            zip_names(&output),
+            [
+                "P1.zip", "P1_1.zip", "P2.zip", "P2_1.zip", "P3.zip", "P3_1.zip"
+            ]
+        );
+        assert!(dir.join("downloads/a.zip").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn moved_archives_leave_the_input() {
+        let dir = scratch_dir("sort_move");
+        fs::create_dir_all(dir.join("in")).unwrap();
+        fs::write(dir.join("in/a.zip"), archive(&["P1"])).unwrap();
+        fs::write(
+            dir.join("in/empty.zip"),
+            zip_of(&[("readme.txt", b"x".to_vec())]),
+        )
+        .unwrap();
+        let layout = PathTemplate::parse("{PatientID}/{StudyInstanceUID}.zip").unwrap();
+
+        sort_archives(&dir.join("in"), &dir.join("out"), &layout, true).unwrap();
+        assert!(dir.join("out/P1/1.2.1.zip").exists());
+        assert!(!dir.join("in/a.zip").exists());
+        // Archives without DICOM files are skipped and stay where they are
+        assert!(dir.join("in/empty.zip").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,504 @@
+// --- SQLite export of the patient/study/series/instance hierarchy ---
+//
+// Unlike series_metadata.csv, which is written per extraction, the database accumulates: every
+// exported archive adds to the same normalized tables, so analysts can join across archives.
+// Re-exporting an archive replaces its instances. Numeric DICOM values are stored as REAL/INTEGER
+// and missing values ("N/A") as NULL.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dicom::dictionary_std::tags;
+use rusqlite::{Connection, Transaction, params};
+
+use crate::{
+    DeepDicomCandidate, DerivationAnalysis, acquisition, compute_derivations, get_tag_string,
+    load_dicom_objects, xprotocol_text,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS archive (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    exported_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS patient (
+    id INTEGER PRIMARY KEY,
+    patient_id TEXT NOT NULL UNIQUE,
+    patient_name TEXT
+);
+CREATE TABLE IF NOT EXISTS study (
+    id INTEGER PRIMARY KEY,
+    study_instance_uid TEXT NOT NULL UNIQUE,
+    patient_ref INTEGER NOT NULL REFERENCES patient(id),
+    study_date TEXT,
+    accession_number TEXT,
+    study_description TEXT
+);
+CREATE TABLE IF NOT EXISTS series (
+    id INTEGER PRIMARY KEY,
+    series_instance_uid TEXT NOT NULL UNIQUE,
+    study_ref INTEGER NOT NULL REFERENCES study(id),
+    series_number INTEGER,
+    modality TEXT,
+    manufacturer TEXT,
+    series_description TEXT,
+    protocol_name TEXT,
+    image_type TEXT,
+    is_derived INTEGER NOT NULL DEFAULT 0,
+    derivation_description TEXT,
+    frame_of_reference_uid TEXT,
+    acquisition_type TEXT,
+    acquisition_time TEXT,
+    acquisition_duration REAL,
+    repetition_time REAL,
+    echo_time REAL,
+    inversion_time REAL,
+    flip_angle REAL,
+    number_of_averages REAL,
+    echo_train_length INTEGER,
+    parallel_imaging_factor TEXT,
+    magnetic_field_strength REAL,
+    pixel_spacing TEXT,
+    slice_thickness REAL,
+    spacing_between_slices REAL,
+    rows INTEGER,
+    columns INTEGER,
+    phase_encoding_direction TEXT,
+    effective_echo_spacing REAL,
+    total_readout_time REAL,
+    slice_timing TEXT,
+    multiband_factor INTEGER,
+    transfer_syntax TEXT
+);
+CREATE TABLE IF NOT EXISTS instance (
+    id INTEGER PRIMARY KEY,
+    sop_instance_uid TEXT NOT NULL,
+    series_ref INTEGER NOT NULL REFERENCES series(id),
+    archive_ref INTEGER NOT NULL REFERENCES archive(id),
+    entry_index INTEGER NOT NULL,
+    entry_name TEXT NOT NULL,
+    instance_number INTEGER,
+    compressed_size INTEGER NOT NULL,
+    uncompressed_size INTEGER NOT NULL,
+    UNIQUE (archive_ref, entry_index)
+);
+CREATE TABLE IF NOT EXISTS derivation_edge (
+    derived_series_uid TEXT NOT NULL,
+    source_series_uid TEXT NOT NULL,
+    evidence TEXT NOT NULL,
+    PRIMARY KEY (derived_series_uid, source_series_uid)
+);
+CREATE TABLE IF NOT EXISTS protocol (
+    series_instance_uid TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    protocol TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS instance_series ON instance(series_ref);
+CREATE INDEX IF NOT EXISTS instance_sop ON instance(sop_instance_uid);
+CREATE VIEW IF NOT EXISTS series_overview AS
+    SELECT p.patient_id, st.study_instance_uid, st.study_date, st.study_description,
+           se.series_instance_uid, se.series_number, se.modality, se.series_description,
+           se.is_derived, COUNT(i.id) AS file_count
+    FROM series se
+    JOIN study st ON st.id = se.study_ref
+    JOIN patient p ON p.id = st.patient_ref
+    LEFT JOIN instance i ON i.series_ref = se.id
+    GROUP BY se.id;
+";
+
+/// Summary of one archive export
+pub struct ExportCounts {
+    pub instances: usize,
+    pub series: usize,
+    pub edges: usize,
+    pub protocols: usize,
+}
+
+pub struct HierarchyDb {
+    conn: Connection,
+}
+
+impl HierarchyDb {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(30))?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(HierarchyDb { conn })
+    }
+
+    /// Write one archive's instances, their series/study/patient rows, the derivation edges and
+    /// the Siemens protocols (series UID → XProtocol text).
+    pub fn export_archive(
+        &mut self,
+        source: &Path,
+        candidates: &[DeepDicomCandidate],
+        analysis: &DerivationAnalysis,
+        protocols: &[(String, String)],
+    ) -> Result<ExportCounts, Box<dyn std::error::Error>> {
+        let source = std::fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf());
+        let exported_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO archive (path, exported_at) VALUES (?1, ?2)
+             ON CONFLICT(path) DO UPDATE SET exported_at = excluded.exported_at",
+            params![source.to_string_lossy(), exported_at],
+        )?;
+        let archive_id: i64 = tx.query_row(
+            "SELECT id FROM archive WHERE path = ?1",
+            params![source.to_string_lossy()],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "DELETE FROM instance WHERE archive_ref = ?1",
+            params![archive_id],
+        )?;
+
+        let derivation_info: HashMap<&str, (bool, &str)> = analysis
+            .derivation_infos
+            .iter()
+            .map(|info| {
+                (
+                    info.series_instance_uid.as_str(),
+                    (info.is_derived, info.frame_of_reference_uid.as_str()),
+                )
+            })
+            .collect();
+
+        let mut series_ids: HashMap<&str, i64> = HashMap::new();
+        for candidate in candidates {
+            let series_id = match series_ids.get(candidate.series_instance_uid.as_str()) {
+                Some(id) => *id,
+                None => {
+                    let patient_id = upsert_patient(&tx, candidate)?;
+                    let study_id = upsert_study(&tx, candidate, patient_id)?;
+                    let (is_derived, frame_of_reference) = derivation_info
+                        .get(candidate.series_instance_uid.as_str())
+                        .copied()
+                        .unwrap_or((false, "N/A"));
+                    let id =
+                        upsert_series(&tx, candidate, study_id, is_derived, frame_of_reference)?;
+                    series_ids.insert(candidate.series_instance_uid.as_str(), id);
+                    id
+                }
+            };
+
+            tx.execute(
+                "INSERT INTO instance (sop_instance_uid, series_ref, archive_ref, entry_index,
+                     entry_name, instance_number, compressed_size, uncompressed_size)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    candidate.sop_instance_uid,
+                    series_id,
+                    archive_id,
+                    candidate.index as i64,
+                    candidate.name,
+                    integer(&candidate.instance_number),
+                    candidate.compressed_size as i64,
+                    candidate.uncompressed_size as i64,
+                ],
+            )?;
+        }
+
+        // Slice timing and transfer syntaxes combine all files of a series
+        let mut series_files: HashMap<&str, Vec<&DeepDicomCandidate>> = HashMap::new();
+        for candidate in candidates {
+            series_files
+                .entry(candidate.series_instance_uid.as_str())
+                .or_default()
+                .push(candidate);
+        }
+        for (series_uid, files) in &series_files {
+            let slice_timing = acquisition::series_slice_timing(files);
+            tx.execute(
+                "UPDATE series SET slice_timing = ?2, multiband_factor = ?3, transfer_syntax = ?4
+                 WHERE series_instance_uid = ?1",
+                params![
+                    series_uid,
+                    slice_timing
+                        .as_deref()
+                        .map(acquisition::format_slice_timing),
+                    slice_timing
+                        .as_deref()
+                        .and_then(acquisition::multiband_factor)
+                        .map(|f| f as i64),
+                    crate::series_transfer_syntax(files),
+                ],
+            )?;
+        }
+
+        let mut edges = 0;
+        for (derived_uid, derived_edges) in &analysis.derivation_graph {
+            for edge in derived_edges {
+                tx.execute(
+                    "INSERT OR REPLACE INTO derivation_edge
+                         (derived_series_uid, source_series_uid, evidence)
+                     VALUES (?1, ?2, ?3)",
+                    params![
+                        derived_uid,
+                        edge.source_series_uid,
+                        edge.evidence_tags.join(", ")
+                    ],
+                )?;
+                edges += 1;
+            }
+        }
+
+        for (series_uid, text) in protocols {
+            tx.execute(
+                "INSERT OR REPLACE INTO protocol (series_instance_uid, source, protocol)
+                 VALUES (?1, 'XProtocol', ?2)",
+                params![series_uid, text],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(ExportCounts {
+            instances: candidates.len(),
+            series: series_ids.len(),
+            edges,
+            protocols: protocols.len(),
+        })
+    }
+}
+
+fn upsert_patient(
+    tx: &Transaction,
+    candidate: &DeepDicomCandidate,
+) -> Result<i64, rusqlite::Error> {
+    tx.execute(
+        "INSERT INTO patient (patient_id, patient_name) VALUES (?1, ?2)
+         ON CONFLICT(patient_id) DO UPDATE SET
+             patient_name = COALESCE(excluded.patient_name, patient.patient_name)",
+        params![candidate.patient_id, text(&candidate.patient_name)],
+    )?;
+    tx.query_row(
+        "SELECT id FROM patient WHERE patient_id = ?1",
+        params![candidate.patient_id],
+        |row| row.get(0),
+    )
+}
+
+fn upsert_study(
+    tx: &Transaction,
+    candidate: &DeepDicomCandidate,
+    patient_ref: i64,
+) -> Result<i64, rusqlite::Error> {
+    tx.execute(
+        "INSERT INTO study (study_instance_uid, patient_ref, study_date, accession_number,
+             study_description)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(study_instance_uid) DO UPDATE SET
+             patient_ref = excluded.patient_ref,
+             study_date = COALESCE(excluded.study_date, study.study_date),
+             accession_number = COALESCE(excluded.accession_number, study.accession_number),
+             study_description = COALESCE(excluded.study_description, study.study_description)",
+        params![
+            candidate.study_instance_uid,
+            patient_ref,
+            text(&candidate.study_date),
+            text(&candidate.accession_number),
+            text(&candidate.study_description),
+        ],
+    )?;
+    tx.query_row(
+        "SELECT id FROM study WHERE study_instance_uid = ?1",
+        params![candidate.study_instance_uid],
+        |row| row.get(0),
+    )
+}
+
+fn upsert_series(
+    tx: &Transaction,
+    c: &DeepDicomCandidate,
+    study_ref: i64,
+    is_derived: bool,
+    frame_of_reference: &str,
+) -> Result<i64, rusqlite::Error> {
+    tx.execute(
+        "INSERT OR REPLACE INTO series (id, series_instance_uid, study_ref, series_number,
+             modality, manufacturer, series_description, protocol_name, image_type, is_derived,
+             derivation_description, frame_of_reference_uid, acquisition_type, acquisition_time,
+             acquisition_duration, repetition_time, echo_time, inversion_time, flip_angle,
+             number_of_averages, echo_train_length, parallel_imaging_factor,
+             magnetic_field_strength, pixel_spacing, slice_thickness, spacing_between_slices,
+             rows, columns, phase_encoding_direction, effective_echo_spacing, total_readout_time)
+         VALUES ((SELECT id FROM series WHERE series_instance_uid = ?1), ?1, ?2, ?3, ?4, ?5, ?6,
+             ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23,
+             ?24, ?25, ?26, ?27, ?28, ?29, ?30)",
+        params![
+            c.series_instance_uid,
+            study_ref,
+            integer(&c.series_number),
+            text(&c.modality),
+            text(&c.manufacturer),
+            text(&c.series_description),
+            text(&c.protocol_name),
+            text(&c.image_type),
+            is_derived,
+            text(&c.derivation_description),
+            text(frame_of_reference),
+            text(&c.acquisition_type),
+            text(&c.acquisition_time),
+            real(&c.acquisition_duration),
+            real(&c.repetition_time),
+            real(&c.echo_time),
+            real(&c.inversion_time),
+            real(&c.flip_angle),
+            real(&c.number_of_averages),
+            integer(&c.echo_train_length),
+            text(&c.parallel_imaging_factor),
+            real(&c.magnetic_field_strength),
+            text(&c.pixel_spacing),
+            real(&c.slice_thickness),
+            real(&c.spacing_between_slices),
+            integer(&c.rows),
+            integer(&c.columns),
+            text(&c.phase_encoding_direction),
+            real(&c.effective_echo_spacing),
+            real(&c.total_readout_time),
+        ],
+    )?;
+    tx.query_row(
+        "SELECT id FROM series WHERE series_instance_uid = ?1",
+        params![c.series_instance_uid],
+        |row| row.get(0),
+    )
+}
+
+fn text(value: &str) -> Option<&str> {
+    let value = value.trim();
+    (!value.is_empty() && value != "N/A").then_some(value)
+}
+
+fn real(value: &str) -> Option<f64> {
+    text(value).and_then(|v| v.parse::<f64>().ok())
+}
+
+fn integer(value: &str) -> Option<i64> {
+    text(value).and_then(|v| {
+        v.parse::<i64>()
+            .ok()
+            .or_else(|| v.parse::<f64>().ok().map(|f| f as i64))
+    })
+}
+
+/// Export an archive that has already been deep-scanned. The derivation analysis and protocol
+/// text need the full headers, so the archive's objects are parsed once more here. With
+/// `only_series`, derivation edges and protocols of other series are left out.
+pub fn export_to_sqlite(
+    db: &Mutex<HierarchyDb>,
+    archive_path: &Path,
+    candidates: &[DeepDicomCandidate],
+    only_series: Option<&HashSet<String>>,
+) -> Result<ExportCounts, Box<dyn std::error::Error>> {
+    // Entries that cannot be parsed were already reported by the deep scan
+    let (objects, _) = load_dicom_objects(archive_path)?;
+    let mut analysis = compute_derivations(&objects);
+    if let Some(only) = only_series {
+        analysis
+            .derivation_graph
+            .retain(|uid, _| only.contains(uid));
+    }
+
+    let mut protocols: Vec<(String, String)> = Vec::new();
+    let mut seen = HashSet::new();
+    for obj in &objects {
+        let series_uid = get_tag_string(obj, tags::SERIES_INSTANCE_UID);
+        if series_uid == "N/A"
+            || only_series.is_some_and(|only| !only.contains(&series_uid))
+            || !seen.insert(series_uid.clone())
+        {
+            continue;
+        }
+        if let Some(text) = xprotocol_text(obj) {
+            protocols.push((series_uid, text));
+        }
+    }
+
+    let mut db = db.lock().unwrap_or_else(|e| e.into_inner());
+    db.export_archive(archive_path, candidates, &analysis, &protocols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute_derivations;
+    use crate::test_support::scratch_dir;
+
+    fn candidate(index: usize, name: &str, series: &str) -> DeepDicomCandidate {
+        DeepDicomCandidate {
+            index,
+            name: name.to_string(),
+            patient_id: "P1".to_string(),
+            patient_name: "N/A".to_string(),
+            study_instance_uid: "1.2.3".to_string(),
+            series_instance_uid: series.to_string(),
+            sop_instance_uid: format!("{}.{}", series, index),
+            series_number: "3".to_string(),
+            instance_number: (index + 1).to_string(),
+            modality: "MR".to_string(),
+            echo_time: "2.5".to_string(),
+            repetition_time: "N/A".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn count(db: &HierarchyDb, sql: &str) -> i64 {
+        db.conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn archives_fill_the_hierarchy() {
+        let dir = scratch_dir("sqlite_export");
+        let archive = dir.join("study.zip");
+        std::fs::write(&archive, b"").unwrap();
+        let mut db = HierarchyDb::open(&dir.join("hierarchy.db")).unwrap();
+        // Two entries share a name; both are instances of the series
+        let candidates = [
+            candidate(0, "DICOM/IM1", "1.2.3.1"),
+            candidate(1, "DICOM/IM1", "1.2.3.1"),
+            candidate(2, "DICOM/IM2", "1.2.3.2"),
+        ];
+        let analysis = compute_derivations(&[]);
+        let counts = db
+            .export_archive(&archive, &candidates, &analysis, &[])
+            .unwrap();
+        assert_eq!((counts.instances, counts.series), (3, 2));
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM instance"), 3);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM patient"), 1);
+        assert_eq!(
+            count(
+                &db,
+                "SELECT file_count FROM series_overview WHERE series_instance_uid = '1.2.3.1'"
+            ),
+            2
+        );
+
+        // Re-exporting replaces the archive's instances instead of adding to them
+        db.export_archive(&archive, &candidates[..2], &analysis, &[])
+            .unwrap();
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM instance"), 2);
+
+        // Numbers are typed and missing values are NULL
+        let (echo_time, repetition_time, name): (Option<f64>, Option<f64>, Option<String>) = db
+            .conn
+            .query_row(
+                "SELECT se.echo_time, se.repetition_time, p.patient_name FROM series se
+                 JOIN study st ON st.id = se.study_ref JOIN patient p ON p.id = st.patient_ref
+                 WHERE se.series_instance_uid = '1.2.3.1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((echo_time, repetition_time, name), (Some(2.5), None, None));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,405 @@
+// --- Output path templates for the organizer ---
+//
+// A template is a relative path whose components mix literal text with
+// `{Field}` placeholders, e.g.
+//
+//   {PatientID}/{StudyDate}_{StudyDescription}/{SeriesNumber:04}_{SeriesDescription}/{InstanceNumber:05}.dcm
+//
+// Fields are DICOM keywords (`SeriesDescription`), tags (`0019,109E` or `(0019,109E)`)
+// or one of the pseudo fields `FileName` (entry name inside the archive) and `Index`
+// (entry index inside the archive). A field may carry a format spec after a colon:
+//
+//   {SeriesNumber:04}         zero-pad numeric values to 4 digits
+//   {StudyDescription:.20}    keep at most the first 20 characters
+//   {SeriesInstanceUID:-8}    keep only the last 8 characters (handy for UIDs)
+//
+// and a fallback after a pipe, used when the attribute is missing or empty:
+//
+//   {StudyDescription|NoDescription}
+
+use std::path::PathBuf;
+
+use dicom::core::Tag;
+use dicom::core::dictionary::DataDictionary;
+use dicom::object::StandardDataDictionary;
+use dicom::object::mem::InMemDicomObject;
+
+use crate::{get_tag_string, sanitize_filename};
+
+/// Default cap on the length of a single rendered path component.
+pub const DEFAULT_MAX_COMPONENT_LEN: usize = 64;
+
+/// Value used for fields that are missing and have no fallback.
+const MISSING_VALUE: &str = "UNKNOWN";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldSource {
+    Tag(Tag),
+    FileName,
+    Index,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FieldFormat {
+    zero_pad: Option<usize>,
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Field {
+        source: FieldSource,
+        format: FieldFormat,
+        fallback: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    components: Vec<Vec<Segment>>,
+    max_component_len: usize,
+}
+
+impl PathTemplate {
+    /// Parse a template string. Unknown keywords and malformed specs are reported as errors.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let template = template.trim();
+        if template.is_empty() {
+            return Err("Template is empty".to_string());
+        }
+        if template.starts_with('/') {
+            return Err("Template must be a relative path".to_string());
+        }
+
+        let mut components = Vec::new();
+        for raw_component in split_components(template)? {
+            if raw_component.is_empty() {
+                return Err(format!(
+                    "Template contains an empty path component: {}",
+                    template
+                ));
+            }
+            if raw_component == "." || raw_component == ".." {
+                return Err(format!(
+                    "Template must not contain '{}' components",
+                    raw_component
+                ));
+            }
+            components.push(parse_component(&raw_component)?);
+        }
+
+        Ok(PathTemplate {
+            components,
+            max_component_len: DEFAULT_MAX_COMPONENT_LEN,
+        })
+    }
+
+    pub fn with_max_component_len(mut self, max_component_len: usize) -> Self {
+        self.max_component_len = max_component_len.max(1);
+        self
+    }
+
+    /// Render the template, resolving each field with `resolve`.
+    /// Every component is sanitized and truncated, so the result is always a safe relative path.
+    pub fn render(&self, resolve: impl Fn(&FieldSource) -> Option<String>) -> PathBuf {
+        let mut path = PathBuf::new();
+        for component in &self.components {
+            let mut rendered = String::new();
+            for segment in component {
+                match segment {
+                    Segment::Literal(text) => rendered.push_str(text),
+                    Segment::Field {
+                        source,
+                        format,
+                        fallback,
+                    } => {
+                        let value = resolve(source)
+                            .map(|v| v.trim().trim_end_matches('\0').to_string())
+                            .filter(|v| !v.is_empty() && v != "N/A");
+                        let value = match value {
+                            Some(v) => apply_format(&v, format),
+                            None => fallback
+                                .clone()
+                                .unwrap_or_else(|| MISSING_VALUE.to_string()),
+                        };
+                        rendered.push_str(&value);
+                    }
+                }
+            }
+            path.push(sanitize_component(&rendered, self.max_component_len));
+        }
+        path
+    }
+
+    /// Render the template against a parsed DICOM object.
+    pub fn render_object(
+        &self,
+        obj: &InMemDicomObject<StandardDataDictionary>,
+        file_name: &str,
+        index: usize,
+    ) -> PathBuf {
+        self.render(|source| match source {
+            FieldSource::Tag(tag) => Some(get_tag_string(obj, *tag)),
+            FieldSource::FileName => Some(file_name.to_string()),
+            FieldSource::Index => Some(index.to_string()),
+        })
+    }
+}
+
+/// Split on '/' outside of braces, so fallbacks and specs are never mistaken for separators.
+fn split_components(template: &str) -> Result<Vec<String>, String> {
+    let mut components = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+
+    for c in template.chars() {
+        match c {
+            '{' => {
+                if depth > 0 {
+                    return Err(format!("Nested '{{' in template: {}", template));
+                }
+                depth += 1;
+                current.push(c);
+            }
+            '}' => {
+                if depth == 0 {
+                    return Err(format!("Unmatched '}}' in template: {}", template));
+                }
+                depth -= 1;
+                current.push(c);
+            }
+            '/' if depth == 0 => components.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+
+    if depth > 0 {
+        return Err(format!("Unclosed '{{' in template: {}", template));
+    }
+    components.push(current);
+    Ok(components)
+}
+
+fn parse_component(component: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut rest = component;
+
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .map(|i| start + i)
+            .ok_or_else(|| format!("Unclosed '{{' in template component: {}", component))?;
+        segments.push(parse_field(&rest[start + 1..end])?);
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
+    }
+
+    Ok(segments)
+}
+
+fn parse_field(field: &str) -> Result<Segment, String> {
+    let (field, fallback) = match field.split_once('|') {
+        Some((f, fb)) => (f, Some(sanitize_filename(fb.trim()))),
+        None => (field, None),
+    };
+
+    // Tags such as (0019,109E) contain no colon, so the first colon always starts the spec
+    let (name, spec) = match field.split_once(':') {
+        Some((n, s)) => (n.trim(), Some(s.trim())),
+        None => (field.trim(), None),
+    };
+
+    let source = parse_field_source(name)?;
+    let format = match spec {
+        Some(spec) => parse_format(spec).map_err(|e| format!("{} in field '{{{}}}'", e, field))?,
+        None => FieldFormat::default(),
+    };
+
+    Ok(Segment::Field {
+        source,
+        format,
+        fallback,
+    })
+}
+
+fn parse_field_source(name: &str) -> Result<FieldSource, String> {
+    match name {
+        "" => Err("Empty field name in template".to_string()),
+        "FileName" => Ok(FieldSource::FileName),
+        "Index" => Ok(FieldSource::Index),
+        _ => StandardDataDictionary
+            .parse_tag(name)
+            .map(FieldSource::Tag)
+            .ok_or_else(|| format!("Unknown DICOM attribute in template: '{}'", name)),
+    }
+}
+
+fn parse_format(spec: &str) -> Result<FieldFormat, String> {
+    let parse_len = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("Invalid format spec ':{}'", spec))
+    };
+
+    let mut format = FieldFormat::default();
+    if let Some(n) = spec.strip_prefix('.') {
+        format.head = Some(parse_len(n)?);
+    } else if let Some(n) = spec.strip_prefix('-') {
+        format.tail = Some(parse_len(n)?);
+    } else if spec.starts_with('0') && spec.len() > 1 {
+        format.zero_pad = Some(parse_len(&spec[1..])?);
+    } else {
+        return Err(format!("Invalid format spec ':{}'", spec));
+    }
+    Ok(format)
+}
+
+fn apply_format(value: &str, format: &FieldFormat) -> String {
+    let mut value = value.to_string();
+
+    if let Some(width) = format.zero_pad {
+        // IS/DS values may carry a fractional part or sign; pad only what parses as an integer
+        if let Ok(n) = value.parse::<i64>() {
+            value = format!("{:0width$}", n, width = width);
+        } else if let Ok(f) = value.parse::<f64>()
+            && f.fract() == 0.0
+        {
+            value = format!("{:0width$}", f as i64, width = width);
+        }
+    }
+    if let Some(n) = format.head {
+        value = value.chars().take(n).collect();
+    }
+    if let Some(n) = format.tail {
+        let len = value.chars().count();
+        value = value.chars().skip(len.saturating_sub(n)).collect();
+    }
+
+    value
+}
+
+/// Make a rendered component safe to use as a single path component.
+fn sanitize_component(component: &str, max_len: usize) -> String {
+    let cleaned: String = sanitize_filename(component)
+        .chars()
+        .map(|c| if c.is_control() { '_' } else { c })
+        .collect();
+
+    // Leading/trailing dots and spaces are troublesome on Windows and hide files on Unix
+    let trimmed = cleaned.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    let mut result = if trimmed.is_empty() {
+        MISSING_VALUE.to_string()
+    } else {
+        trimmed.to_string()
+    };
+
+    if result.chars().count() > max_len {
+        // Keep the extension of file names intact when truncating
+        let ext = std::path::Path::new(&result)
+            .extension()
+            .and_then(|e| e.to_str())
+            .filter(|e| e.len() < max_len / 2)
+            .map(|e| format!(".{}", e));
+        result = match ext {
+            Some(ext) => {
+                let stem: String = result.chars().take(max_len - ext.chars().count()).collect();
+                format!("{}{}", stem.trim_end_matches('.'), ext)
+            }
+            None => result.chars().take(max_len).collect(),
+        };
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::dictionary_std::tags;
+
+    fn render(template: &PathTemplate, values: &[(Tag, &str)]) -> PathBuf {
+        template.render(|source| match source {
+            FieldSource::Tag(tag) => values
+                .iter()
+                .find(|(t, _)| t == tag)
+                .map(|(_, v)| v.to_string()),
+            FieldSource::FileName => Some("IM0001".to_string()),
+            FieldSource::Index => Some("7".to_string()),
+        })
+    }
+
+    #[test]
+    fn renders_fields_formats_and_fallbacks() {
+        let template = PathTemplate::parse(
+            "{PatientID}/{SeriesNumber:04}_{SeriesInstanceUID:-4}/{StudyDescription|NoDesc}_{Index}.dcm",
+        )
+        .unwrap();
+        let path = render(
+            &template,
+            &[
+                (tags::PATIENT_ID, "P1"),
+                (tags::SERIES_NUMBER, "5"),
+                (tags::SERIES_INSTANCE_UID, "1.2.3.4567"),
+            ],
+        );
+        assert_eq!(path, PathBuf::from("P1/0005_4567/NoDesc_7.dcm"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_unterminated_braces() {
+        for template in [
+            "{NoSuchAttribute}/x.dcm",
+            "{PatientID/x.dcm",
+            "{PatientID}}/x.dcm",
+            "{PatientID:x}/x.dcm",
+            "{}/x.dcm",
+            "{PatientID}/../x.dcm",
+            "/{PatientID}",
+        ] {
+            assert!(PathTemplate::parse(template).is_err(), "{}", template);
+        }
+        assert!(PathTemplate::parse("{(0019,109E)}/{FileName}").is_ok());
+    }
+
+    #[test]
+    fn values_cannot_escape_the_output() {
+        let template =
+            PathTemplate::parse("{PatientID}/{StudyDescription}/{SeriesDescription}").unwrap();
+        let path = render(
+            &template,
+            &[
+                (tags::PATIENT_ID, ".."),
+                (tags::STUDY_DESCRIPTION, "../../etc"),
+                (tags::SERIES_DESCRIPTION, "a/b\\c"),
+            ],
+        );
+        assert_eq!(path.components().count(), 3);
+        for component in path.iter() {
+            let component = component.to_str().unwrap();
+            assert!(!component.contains(['/', '\\']), "{}", component);
+            assert!(!component.starts_with('.'), "{}", component);
+        }
+        assert_eq!(path.iter().next().unwrap(), MISSING_VALUE);
+    }
+
+    #[test]
+    fn components_are_truncated_to_the_maximum() {
+        let template = PathTemplate::parse("{StudyDescription}/{SeriesDescription}.dcm")
+            .unwrap()
+            .with_max_component_len(10);
+        let path = render(
+            &template,
+            &[
+                (tags::STUDY_DESCRIPTION, "Brain with and without contrast"),
+                (tags::SERIES_DESCRIPTION, "T1_MPRAGE_SAG_1mm_iso"),
+            ],
+        );
+        assert_eq!(path, PathBuf::from("Brain with/T1_MPR.dcm"));
+    }
+}
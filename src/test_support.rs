@@ -0,0 +1,160 @@
+// --- Fixtures shared by the unit tests ---
+//
+// Small DICOM files and ZIP archives built in memory, and scratch directories under the
+// system temp directory.
+
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+
+use dicom::core::{DataElement, PrimitiveValue, Tag, VR};
+use dicom::dictionary_std::tags;
+use dicom::object::{FileMetaTableBuilder, InMemDicomObject};
+use dicom::transfer_syntax::entries;
+
+/// Secondary Capture Image Storage
+pub const SECONDARY_CAPTURE: &str = "1.2.840.10008.5.1.4.1.1.7";
+
+/// An empty scratch directory, unique to the test process.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dicom_scanner_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The attributes every instance needs; add more with `put_str` or `put_u16`.
+pub fn instance(patient_id: &str, study: &str, series: &str, sop: &str) -> InMemDicomObject {
+    let mut obj = InMemDicomObject::new_empty();
+    obj.put_str(tags::SOP_CLASS_UID, VR::UI, SECONDARY_CAPTURE);
+    obj.put_str(tags::SOP_INSTANCE_UID, VR::UI, sop);
+    obj.put_str(tags::PATIENT_ID, VR::LO, patient_id);
+    obj.put_str(tags::STUDY_INSTANCE_UID, VR::UI, study);
+    obj.put_str(tags::SERIES_INSTANCE_UID, VR::UI, series);
+    obj.put_str(tags::MODALITY, VR::CS, "OT");
+    obj
+}
+
+pub fn put_u16(obj: &mut InMemDicomObject, tag: Tag, value: u16) {
+    obj.put(DataElement::new(tag, VR::US, PrimitiveValue::from(value)));
+}
+
+/// Add 8- or 16-bit monochrome native pixel data of `rows` x `columns` x `frames`.
+pub fn put_pixels(
+    obj: &mut InMemDicomObject,
+    rows: u16,
+    columns: u16,
+    frames: u32,
+    pixels: Vec<u8>,
+) {
+    let bits = (pixels.len() / (rows as usize * columns as usize * frames as usize) * 8) as u16;
+    obj.put_str(tags::PHOTOMETRIC_INTERPRETATION, VR::CS, "MONOCHROME2");
+    obj.put_str(tags::NUMBER_OF_FRAMES, VR::IS, frames.to_string());
+    for (tag, value) in [
+        (tags::SAMPLES_PER_PIXEL, 1),
+        (tags::ROWS, rows),
+        (tags::COLUMNS, columns),
+        (tags::BITS_ALLOCATED, bits),
+        (tags::BITS_STORED, bits),
+        (tags::HIGH_BIT, bits - 1),
+        (tags::PIXEL_REPRESENTATION, 0),
+    ] {
+        put_u16(obj, tag, value);
+    }
+    let vr = if bits == 8 { VR::OB } else { VR::OW };
+    obj.put(DataElement::new(
+        tags::PIXEL_DATA,
+        vr,
+        PrimitiveValue::from(pixels),
+    ));
+}
+
+/// A Siemens CSA header (SV10) with the given elements and their string items.
+pub fn csa_header(elements: &[(&str, &[&str])]) -> Vec<u8> {
+    let mut out = b"SV10\x04\x03\x02\x01".to_vec();
+    out.extend_from_slice(&(elements.len() as u32).to_le_bytes());
+    out.extend_from_slice(&77u32.to_le_bytes());
+    for (name, items) in elements {
+        let mut field = [0u8; 64];
+        field[..name.len()].copy_from_slice(name.as_bytes());
+        out.extend_from_slice(&field);
+        out.extend_from_slice(&(items.len() as u32).to_le_bytes()); // VM
+        out.extend_from_slice(b"DS\0\0");
+        out.extend_from_slice(&3u32.to_le_bytes()); // SyngoDT
+        out.extend_from_slice(&(items.len() as u32).to_le_bytes());
+        out.extend_from_slice(&77u32.to_le_bytes());
+        for item in *items {
+            let length = item.len() as u32 + 1;
+            for value in [length, length, 77, length] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(item.as_bytes());
+            out.push(0);
+            out.resize(out.len().next_multiple_of(4), 0);
+        }
+    }
+    out
+}
+
+/// Store a CSA image header in (0029,1010) of `obj`.
+pub fn put_csa_image_header(obj: &mut InMemDicomObject, csa: Vec<u8>) {
+    obj.put_str(Tag(0x0029, 0x0010), VR::LO, "SIEMENS CSA HEADER");
+    obj.put(DataElement::new(
+        Tag(0x0029, 0x1010),
+        VR::OB,
+        PrimitiveValue::from(csa),
+    ));
+}
+
+/// Encode as a Part 10 file in Explicit VR Little Endian.
+pub fn file_bytes(obj: InMemDicomObject) -> Vec<u8> {
+    let text = |tag| {
+        obj.element(tag)
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .map(|s| s.trim_end_matches('\0').to_string())
+            .unwrap_or_default()
+    };
+    let (sop_class, sop_instance) = (text(tags::SOP_CLASS_UID), text(tags::SOP_INSTANCE_UID));
+    let file = obj
+        .with_meta(
+            FileMetaTableBuilder::new()
+                .transfer_syntax(entries::EXPLICIT_VR_LITTLE_ENDIAN.uid())
+                .media_storage_sop_class_uid(sop_class)
+                .media_storage_sop_instance_uid(sop_instance),
+        )
+        .unwrap();
+    let mut out = Vec::new();
+    file.write_all(&mut out).unwrap();
+    out
+}
+
+/// A deflated ZIP archive with the given entries.
+pub fn zip_of(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in entries {
+        zip.start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+/// Send one raw HTTP request (head and body) to a server on a free port, let `answer` handle it
+/// and return the raw response.
+pub fn http_exchange(request: &[u8], answer: impl FnOnce(tiny_http::Request)) -> String {
+    use std::io::Read;
+
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    std::thread::scope(|scope| {
+        let client = scope.spawn(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            stream.write_all(request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        answer(server.recv().unwrap());
+        client.join().unwrap()
+    })
+}
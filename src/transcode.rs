@@ -0,0 +1,605 @@
+// --- Transcoding on extract ---
+//
+// Rewrites extracted files in another transfer syntax. Encapsulated pixel data is decoded first
+// by the pixel data crate: JPEG (baseline, extended, lossless), RLE Lossless and JPEG 2000 always,
+// JPEG-LS only when built with the `charls` feature. It is then re-encoded: RLE Lossless and
+// JPEG-LS Lossless are encoded here, Deflated Explicit VR Little Endian compresses the whole data
+// set. All data elements are kept; only the pixel data and the file meta transfer syntax change.
+//
+// RLE Lossless is decoded here rather than by the pixel data crate, which places 8-bit
+// single-sample data one byte late (its segment offset adds the samples per pixel).
+
+use std::io::Write;
+
+use dicom::core::value::{PixelFragmentSequence, Value};
+use dicom::core::{DataElement, VR};
+use dicom::dictionary_std::tags;
+use dicom::object::mem::InMemDicomObject;
+use dicom::object::{FileDicomObject, OpenFileOptions, StandardDataDictionary};
+use dicom::pixeldata::Transcode;
+use dicom::transfer_syntax::entries;
+
+use crate::jpegls;
+
+type DicomFile = FileDicomObject<InMemDicomObject<StandardDataDictionary>>;
+
+/// Transfer syntax for `--transcode`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Target {
+    /// Explicit VR Little Endian (uncompressed)
+    Explicit,
+    /// RLE Lossless
+    Rle,
+    /// JPEG-LS Lossless
+    JpegLs,
+    /// Deflated Explicit VR Little Endian
+    Deflate,
+}
+
+impl Target {
+    pub fn uid(self) -> &'static str {
+        match self {
+            Target::Explicit => entries::EXPLICIT_VR_LITTLE_ENDIAN.uid(),
+            Target::Rle => entries::RLE_LOSSLESS.uid(),
+            Target::JpegLs => entries::JPEG_LS_LOSSLESS_IMAGE_COMPRESSION.uid(),
+            Target::Deflate => entries::DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN.uid(),
+        }
+    }
+
+    /// Whether this tool can read files in this transfer syntax back (NIfTI/BIDS conversion)
+    pub fn is_readable(self) -> bool {
+        match self {
+            Target::Explicit | Target::Rle => true,
+            Target::JpegLs => cfg!(feature = "charls"),
+            Target::Deflate => false,
+        }
+    }
+}
+
+/// Transcode one DICOM file to `target`. Files already in the target syntax are returned as is.
+pub fn transcode(bytes: &[u8], target: Target) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut obj = OpenFileOptions::new().from_reader(bytes)?;
+    let source = obj
+        .meta()
+        .transfer_syntax()
+        .trim_end_matches('\0')
+        .to_string();
+    if source == target.uid() {
+        return Ok(bytes.to_vec());
+    }
+
+    if is_jpeg_ls(&source) && !cfg!(feature = "charls") {
+        return Err("JPEG-LS input needs a build with the charls feature".into());
+    }
+
+    // Native Explicit VR Little Endian first; decoding only happens for encapsulated pixel data
+    decode_rle(&mut obj)?;
+    obj.transcode(&entries::EXPLICIT_VR_LITTLE_ENDIAN.erased())?;
+    let samples_per_pixel = int(&obj, tags::SAMPLES_PER_PIXEL).unwrap_or(1);
+    if is_jpeg_process(&source)
+        && samples_per_pixel == 3
+        && crate::get_tag_string(&obj, tags::PHOTOMETRIC_INTERPRETATION).starts_with("YBR")
+    {
+        // The JPEG decoder returns RGB
+        obj.put(DataElement::new(
+            tags::PHOTOMETRIC_INTERPRETATION,
+            VR::CS,
+            "RGB",
+        ));
+        obj.put(DataElement::new(
+            tags::PLANAR_CONFIGURATION,
+            VR::US,
+            dicom::core::PrimitiveValue::from(0_u16),
+        ));
+    }
+
+    let mut out = Vec::with_capacity(bytes.len());
+    match target {
+        Target::Explicit => obj.write_all(&mut out)?,
+        Target::Rle | Target::JpegLs => {
+            encapsulate(&mut obj, target)?;
+            obj.write_all(&mut out)?;
+        }
+        Target::Deflate => {
+            // Meta group as usual, then the Explicit VR Little Endian data set as a raw deflate
+            // stream, padded to even length
+            let mut dataset = Vec::with_capacity(bytes.len());
+            obj.write_dataset_with_ts(&mut dataset, &entries::EXPLICIT_VR_LITTLE_ENDIAN.erased())?;
+            obj.update_meta(|meta| {
+                meta.set_transfer_syntax(&entries::DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN)
+            });
+            out.extend_from_slice(&[0; 128]);
+            out.extend_from_slice(b"DICM");
+            obj.write_meta(&mut out)?;
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(&mut out, flate2::Compression::default());
+            encoder.write_all(&dataset)?;
+            encoder.finish()?;
+            if out.len() % 2 == 1 {
+                out.push(0);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Replace RLE Lossless pixel data with native samples and switch the file to Explicit VR Little
+/// Endian. Files in any other transfer syntax are left alone.
+pub fn decode_rle(obj: &mut DicomFile) -> Result<(), Box<dyn std::error::Error>> {
+    if obj.meta().transfer_syntax().trim_end_matches('\0') != entries::RLE_LOSSLESS.uid() {
+        return Ok(());
+    }
+    if let Ok(pixel_data) = obj.element(tags::PIXEL_DATA) {
+        let Value::PixelSequence(sequence) = pixel_data.value() else {
+            return Err("RLE pixel data is not encapsulated".into());
+        };
+        let rows = int(obj, tags::ROWS).ok_or("missing Rows")?;
+        let columns = int(obj, tags::COLUMNS).ok_or("missing Columns")?;
+        let samples = int(obj, tags::SAMPLES_PER_PIXEL).unwrap_or(1);
+        let bits_allocated = int(obj, tags::BITS_ALLOCATED).ok_or("missing BitsAllocated")?;
+        let frames = int(obj, tags::NUMBER_OF_FRAMES).unwrap_or(1).max(1);
+        if bits_allocated % 8 != 0 || bits_allocated == 0 {
+            return Err(format!("{} bits allocated is not supported", bits_allocated).into());
+        }
+        // PS3.5 A.4: every RLE frame is exactly one fragment
+        if sequence.fragments().len() != frames {
+            return Err(format!(
+                "{} RLE fragments for {} frames",
+                sequence.fragments().len(),
+                frames
+            )
+            .into());
+        }
+
+        let pixels = rows * columns;
+        let bytes_per_sample = bits_allocated / 8;
+        let mut bytes = Vec::with_capacity(frames * pixels * samples * bytes_per_sample + 1);
+        for fragment in sequence.fragments() {
+            let segments = rle_segments(fragment, samples * bytes_per_sample, pixels)?;
+            // Little endian: least significant byte first, from the last segment of each sample
+            bytes.extend((0..pixels).flat_map(|p| {
+                let segments = &segments;
+                (0..samples).flat_map(move |c| {
+                    (0..bytes_per_sample)
+                        .rev()
+                        .map(move |b| segments[c * bytes_per_sample + b][p])
+                })
+            }));
+        }
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+
+        obj.put(DataElement::new(
+            tags::PIXEL_DATA,
+            if bits_allocated == 8 { VR::OB } else { VR::OW },
+            dicom::core::PrimitiveValue::from(bytes),
+        ));
+        if samples > 1 {
+            obj.put(DataElement::new(
+                tags::PLANAR_CONFIGURATION,
+                VR::US,
+                dicom::core::PrimitiveValue::from(0_u16),
+            ));
+        }
+    }
+    obj.update_meta(|meta| meta.set_transfer_syntax(&entries::EXPLICIT_VR_LITTLE_ENDIAN));
+    Ok(())
+}
+
+/// The `count` segments of one RLE frame (PS3.5 G.5), each unpacked to `len` bytes
+fn rle_segments(fragment: &[u8], count: usize, len: usize) -> Result<Vec<Vec<u8>>, String> {
+    if fragment.len() < 64 {
+        return Err(format!(
+            "RLE frame of {} bytes has no header",
+            fragment.len()
+        ));
+    }
+    let header: Vec<usize> = fragment[..64]
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .collect();
+    if header[0] != count {
+        return Err(format!("{} RLE segments, expected {}", header[0], count));
+    }
+
+    (0..count)
+        .map(|i| {
+            let start = header[i + 1];
+            let end = if i + 1 < count {
+                header[i + 2]
+            } else {
+                fragment.len()
+            };
+            let segment = fragment.get(start..end).ok_or_else(|| {
+                format!("RLE segment {} at {}..{} is out of range", i, start, end)
+            })?;
+            let mut out = unpack_bits(segment, len);
+            if out.len() < len {
+                return Err(format!(
+                    "RLE segment {} has {} of {} bytes",
+                    i,
+                    out.len(),
+                    len
+                ));
+            }
+            out.truncate(len);
+            Ok(out)
+        })
+        .collect()
+}
+
+/// Undo `pack_bits`, stopping once `len` bytes are out
+fn unpack_bits(mut data: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    while out.len() < len {
+        let Some((&header, rest)) = data.split_first() else {
+            break;
+        };
+        let header = header as i8;
+        if header >= 0 {
+            let count = (header as usize + 1).min(rest.len());
+            out.extend_from_slice(&rest[..count]);
+            data = &rest[count..];
+        } else if header != -128 {
+            let Some(&value) = rest.first() else {
+                break;
+            };
+            out.extend(std::iter::repeat_n(value, (1 - header as isize) as usize));
+            data = &rest[1..];
+        } else {
+            data = rest;
+        }
+    }
+    out
+}
+
+/// JPEG baseline and extended: the processes whose decoder converts YCbCr to RGB
+fn is_jpeg_process(uid: &str) -> bool {
+    uid == entries::JPEG_BASELINE.uid() || uid == entries::JPEG_EXTENDED.uid()
+}
+
+fn is_jpeg_ls(uid: &str) -> bool {
+    uid == entries::JPEG_LS_LOSSLESS_IMAGE_COMPRESSION.uid()
+        || uid == entries::JPEG_LS_LOSSY_IMAGE_COMPRESSION.uid()
+}
+
+fn int(obj: &DicomFile, tag: dicom::core::Tag) -> Option<usize> {
+    obj.element(tag)
+        .ok()?
+        .to_int::<u32>()
+        .ok()
+        .map(|v| v as usize)
+}
+
+/// Replace native pixel data with one RLE or JPEG-LS fragment per frame.
+fn encapsulate(obj: &mut DicomFile, target: Target) -> Result<(), Box<dyn std::error::Error>> {
+    if let Ok(pixel_data) = obj.element(tags::PIXEL_DATA) {
+        let rows = int(obj, tags::ROWS).ok_or("missing Rows")?;
+        let columns = int(obj, tags::COLUMNS).ok_or("missing Columns")?;
+        let samples = int(obj, tags::SAMPLES_PER_PIXEL).unwrap_or(1);
+        let bits_allocated = int(obj, tags::BITS_ALLOCATED).ok_or("missing BitsAllocated")?;
+        let bits_stored = int(obj, tags::BITS_STORED).unwrap_or(bits_allocated);
+        let planar = int(obj, tags::PLANAR_CONFIGURATION).unwrap_or(0);
+        let frames = int(obj, tags::NUMBER_OF_FRAMES).unwrap_or(1).max(1);
+        if bits_allocated != 8 && bits_allocated != 16 {
+            return Err(format!("{} bits allocated is not supported", bits_allocated).into());
+        }
+
+        let bytes = pixel_data.to_bytes()?;
+        let pixels = rows * columns;
+        let bytes_per_sample = bits_allocated / 8;
+        let frame_len = pixels * samples * bytes_per_sample;
+        if frame_len == 0 {
+            return Err(format!("{}x{} image with {} samples", columns, rows, samples).into());
+        }
+        let expected = frame_len
+            .checked_mul(frames)
+            .ok_or_else(|| format!("{} frames of {} bytes", frames, frame_len))?;
+        if bytes.len() < expected {
+            return Err(format!(
+                "pixel data has {} bytes, expected {}",
+                bytes.len(),
+                expected
+            )
+            .into());
+        }
+
+        let mut fragments = Vec::with_capacity(frames);
+        for frame in bytes.chunks_exact(frame_len).take(frames) {
+            // Byte offset of sample `c` of pixel `p`
+            let offset = |c: usize, p: usize| {
+                let index = if planar == 0 {
+                    p * samples + c
+                } else {
+                    c * pixels + p
+                };
+                index * bytes_per_sample
+            };
+            let mut fragment = match target {
+                Target::Rle => rle_frame(frame, rows, columns, samples, bytes_per_sample, offset)?,
+                _ => {
+                    let mask = ((1_u32 << bits_stored.min(16)) - 1) as u16;
+                    let planes: Vec<Vec<u16>> = (0..samples)
+                        .map(|c| {
+                            (0..pixels)
+                                .map(|p| {
+                                    let at = offset(c, p);
+                                    let value = if bytes_per_sample == 2 {
+                                        u16::from_le_bytes([frame[at], frame[at + 1]])
+                                    } else {
+                                        frame[at] as u16
+                                    };
+                                    value & mask
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    jpegls::encode(&planes, columns, rows, bits_stored as u8)?
+                }
+            };
+            if fragment.len() % 2 == 1 {
+                fragment.push(0);
+            }
+            fragments.push(fragment);
+        }
+
+        let offsets = basic_offset_table(&fragments).unwrap_or_default();
+        obj.put(DataElement::new(
+            tags::PIXEL_DATA,
+            VR::OB,
+            Value::PixelSequence(PixelFragmentSequence::new(offsets, fragments)),
+        ));
+        if target == Target::JpegLs && samples > 1 {
+            obj.put(DataElement::new(
+                tags::PLANAR_CONFIGURATION,
+                VR::US,
+                dicom::core::PrimitiveValue::from(0_u16),
+            ));
+        }
+    }
+
+    match target {
+        Target::Rle => obj.update_meta(|meta| meta.set_transfer_syntax(&entries::RLE_LOSSLESS)),
+        _ => obj.update_meta(|meta| {
+            meta.set_transfer_syntax(&entries::JPEG_LS_LOSSLESS_IMAGE_COMPRESSION)
+        }),
+    }
+    Ok(())
+}
+
+/// Byte offset of each frame's item from the first item, or None when the fragments do not fit
+/// the 32-bit table; the table is then left empty, which PS3.5 A.4 allows.
+fn basic_offset_table(fragments: &[Vec<u8>]) -> Option<Vec<u32>> {
+    let mut offsets = Vec::with_capacity(fragments.len());
+    let mut position = 0_u32;
+    for fragment in fragments {
+        offsets.push(position);
+        let item_len = u32::try_from(fragment.len()).ok()?.checked_add(8)?;
+        position = position.checked_add(item_len)?;
+    }
+    Some(offsets)
+}
+
+/// One RLE Lossless frame (PS3.5 Annex G): a 64-byte header and one PackBits segment per
+/// sample byte, most significant byte first, every row encoded separately.
+fn rle_frame(
+    frame: &[u8],
+    rows: usize,
+    columns: usize,
+    samples: usize,
+    bytes_per_sample: usize,
+    offset: impl Fn(usize, usize) -> usize,
+) -> Result<Vec<u8>, String> {
+    let segment_count = samples * bytes_per_sample;
+    if segment_count > 15 {
+        return Err(format!("RLE allows 15 segments, not {}", segment_count));
+    }
+
+    let mut segments = Vec::with_capacity(segment_count);
+    for c in 0..samples {
+        for byte in (0..bytes_per_sample).rev() {
+            let mut segment = Vec::new();
+            let mut row = Vec::with_capacity(columns);
+            for y in 0..rows {
+                row.clear();
+                row.extend((0..columns).map(|x| frame[offset(c, y * columns + x) + byte]));
+                pack_bits(&row, &mut segment);
+            }
+            if segment.len() % 2 == 1 {
+                segment.push(0);
+            }
+            segments.push(segment);
+        }
+    }
+
+    let mut out = Vec::with_capacity(64 + segments.iter().map(Vec::len).sum::<usize>());
+    out.extend_from_slice(&(segment_count as u32).to_le_bytes());
+    let mut position = 64_usize;
+    for index in 0..15 {
+        let value = match segments.get(index) {
+            Some(segment) => {
+                let start = u32::try_from(position)
+                    .map_err(|_| "RLE frame is larger than 4 GiB".to_string())?;
+                position += segment.len();
+                start
+            }
+            None => 0,
+        };
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    for segment in segments {
+        out.extend(segment);
+    }
+    Ok(out)
+}
+
+/// PackBits: runs of 3 to 128 equal bytes are replicated, everything else is copied literally.
+fn pack_bits(row: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    let mut literal_start = 0;
+    while i < row.len() {
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i] {
+            run += 1;
+        }
+        if run >= 3 {
+            flush_literal(&row[literal_start..i], out);
+            out.push((257 - run) as u8);
+            out.push(row[i]);
+            i += run;
+            literal_start = i;
+        } else {
+            i += run;
+        }
+    }
+    flush_literal(&row[literal_start..], out);
+}
+
+fn flush_literal(bytes: &[u8], out: &mut Vec<u8>) {
+    for chunk in bytes.chunks(128) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dicom::object::FileMetaTableBuilder;
+    use dicom::pixeldata::PixelDecoder;
+
+    /// An Explicit VR Little Endian file with native pixel data
+    fn native_file(rows: u16, columns: u16, samples: u16, bits: u16, frames: u32) -> Vec<u8> {
+        let len = rows as usize * columns as usize * samples as usize * (bits as usize / 8);
+        let pixels: Vec<u8> = (0..len * frames as usize)
+            .map(|i| if i % 97 < 40 { 7 } else { (i * 31 % 251) as u8 })
+            .collect();
+        let photometric = if samples == 3 { "RGB" } else { "MONOCHROME2" };
+
+        let mut obj = InMemDicomObject::new_empty();
+        obj.put_str(tags::SOP_CLASS_UID, VR::UI, "1.2.840.10008.5.1.4.1.1.7");
+        obj.put_str(tags::SOP_INSTANCE_UID, VR::UI, "1.2.3.4");
+        obj.put_str(tags::PHOTOMETRIC_INTERPRETATION, VR::CS, photometric);
+        obj.put_str(tags::NUMBER_OF_FRAMES, VR::IS, frames.to_string());
+        for (tag, value) in [
+            (tags::SAMPLES_PER_PIXEL, samples),
+            (tags::ROWS, rows),
+            (tags::COLUMNS, columns),
+            (tags::BITS_ALLOCATED, bits),
+            (tags::BITS_STORED, bits),
+            (tags::HIGH_BIT, bits - 1),
+            (tags::PIXEL_REPRESENTATION, 0),
+            (tags::PLANAR_CONFIGURATION, 0),
+        ] {
+            obj.put(DataElement::new(
+                tag,
+                VR::US,
+                dicom::core::PrimitiveValue::from(value),
+            ));
+        }
+        obj.put(DataElement::new(
+            tags::PIXEL_DATA,
+            if bits == 8 { VR::OB } else { VR::OW },
+            dicom::core::PrimitiveValue::from(pixels),
+        ));
+
+        let file = obj
+            .with_meta(
+                FileMetaTableBuilder::new()
+                    .transfer_syntax(entries::EXPLICIT_VR_LITTLE_ENDIAN.uid())
+                    .media_storage_sop_class_uid("1.2.840.10008.5.1.4.1.1.7")
+                    .media_storage_sop_instance_uid("1.2.3.4"),
+            )
+            .unwrap();
+        let mut out = Vec::new();
+        file.write_all(&mut out).unwrap();
+        out
+    }
+
+    /// Transcode to RLE and check the original samples decode again.
+    fn rle_round_trip(rows: u16, columns: u16, samples: u16, bits: u16, frames: u32) {
+        let native = native_file(rows, columns, samples, bits, frames);
+        let original = OpenFileOptions::new().from_reader(&native[..]).unwrap();
+        let expected = original
+            .element(tags::PIXEL_DATA)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+
+        let rle = transcode(&native, Target::Rle).unwrap();
+        let mut obj = OpenFileOptions::new().from_reader(&rle[..]).unwrap();
+        assert_eq!(obj.meta().transfer_syntax(), entries::RLE_LOSSLESS.uid());
+        decode_rle(&mut obj).unwrap();
+        let decoded = obj.decode_pixel_data().unwrap();
+        assert_eq!(decoded.data(), &expected[..]);
+    }
+
+    #[test]
+    fn rle_eight_bit() {
+        rle_round_trip(19, 33, 1, 8, 1);
+
+        let native = native_file(19, 33, 1, 8, 1);
+        let original = OpenFileOptions::new().from_reader(&native[..]).unwrap();
+        let expected = original
+            .element(tags::PIXEL_DATA)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let len = 19 * 33;
+        let rle = transcode(&native, Target::Rle).unwrap();
+
+        // The pixel data crate alone shifts the samples by one byte; if this starts failing, the
+        // upstream decoder has been fixed and `decode_rle` can go
+        let obj = OpenFileOptions::new().from_reader(&rle[..]).unwrap();
+        let upstream = obj.decode_pixel_data().unwrap();
+        assert_eq!(upstream.data()[1..len], expected[..len - 1]);
+
+        // Back to native through our own decoder
+        let back = transcode(&rle, Target::Explicit).unwrap();
+        let obj = OpenFileOptions::new().from_reader(&back[..]).unwrap();
+        assert_eq!(
+            obj.meta().transfer_syntax(),
+            entries::EXPLICIT_VR_LITTLE_ENDIAN.uid()
+        );
+        let pixel_data = obj.element(tags::PIXEL_DATA).unwrap().to_bytes().unwrap();
+        assert_eq!(pixel_data[..], expected[..]);
+    }
+
+    #[test]
+    fn truncated_rle_is_an_error() {
+        assert!(rle_segments(&[0; 10], 1, 4).is_err());
+        let mut fragment = vec![0; 64];
+        fragment[0] = 1;
+        fragment[4] = 64;
+        // A literal run of 2 bytes for a 4-byte segment
+        fragment.extend_from_slice(&[1, 5, 6]);
+        assert!(rle_segments(&fragment, 1, 4).is_err());
+        assert_eq!(rle_segments(&fragment, 1, 2).unwrap(), vec![vec![5, 6]]);
+    }
+
+    #[test]
+    fn rle_sixteen_bit() {
+        rle_round_trip(16, 300, 1, 16, 1);
+    }
+
+    #[test]
+    fn rle_rgb_multi_frame() {
+        rle_round_trip(8, 12, 3, 8, 3);
+    }
+
+    #[test]
+    fn zero_rows_is_an_error() {
+        let native = native_file(0, 16, 1, 8, 1);
+        assert!(transcode(&native, Target::Rle).is_err());
+        assert!(transcode(&native, Target::JpegLs).is_err());
+    }
+
+    #[test]
+    fn offset_table() {
+        assert_eq!(
+            basic_offset_table(&[vec![0; 10], vec![0; 4], vec![0; 2]]),
+            Some(vec![0, 18, 30])
+        );
+    }
+}
@@ -0,0 +1,916 @@
+// --- Watch-folder ingestion ---
+//
+// `watch` polls drop folders for ZIP archives, sub-folders (e.g. an exported study) and loose
+// files. An input is processed once its size and modification times have not changed for the
+// settle time: the batch pipeline (scan / organize / export / xprot) runs on it, its series are
+// appended to series_catalog.csv (skipped entries to skipped_files.csv), and it is moved to the
+// done or failed folder. Sub-folders and loose files are packed into an uncompressed ZIP in a
+// temporary file so they go through the same pipeline.
+//
+// Every unit of work is recorded in a JSON-lines journal before processing, once its inputs have
+// been moved and after its reports are written. After a restart, units that were started but not
+// finished are processed again into the same output folder before anything new is picked up;
+// units already moved are processed where they were moved to, and their rows in the reports
+// are replaced rather than appended a second time.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::batch::{self, BatchOptions};
+use crate::entry_error::SKIPPED_FILES_HEADER;
+use crate::{
+    SERIES_METADATA_HEADER, SettledArchive, escape_csv_field, map_archive, sanitize_filename,
+};
+
+pub struct WatchOptions<'a> {
+    pub dirs: &'a [PathBuf],
+    /// Where processed inputs go (default: <watched dir>/done)
+    pub done: Option<&'a Path>,
+    /// Where inputs that failed go (default: <watched dir>/failed)
+    pub failed: Option<&'a Path>,
+    pub journal: &'a Path,
+    /// Directory for series_catalog.csv, failures.csv and skipped_files.csv
+    pub report_dir: &'a Path,
+    pub settle: Duration,
+    pub poll: Duration,
+    /// Stop once every input present has been processed
+    pub once: bool,
+}
+
+/// Name suffixes of files that are still being downloaded
+const PARTIAL_SUFFIXES: &[&str] = &[".part", ".partial", ".crdownload", ".tmp", ".download"];
+
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+    time: u64,
+    /// "started", "moved", "done" or "failed"
+    state: String,
+    inputs: Vec<PathBuf>,
+    /// Output sub-directory of the unit, reused when an unfinished unit is resumed
+    sub_dir: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    moved_to: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+struct Journal {
+    file: File,
+    /// Units whose last entry is "started" or "moved"
+    unfinished: Vec<JournalEntry>,
+    used_sub_dirs: HashSet<PathBuf>,
+}
+
+impl Journal {
+    fn open(path: &Path) -> Result<Journal, Box<dyn std::error::Error>> {
+        let mut last: HashMap<Vec<PathBuf>, JournalEntry> = HashMap::new();
+        let mut order = Vec::new();
+        let mut used_sub_dirs = HashSet::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                // A line cut short by a crash is ignored
+                let Ok(entry) = serde_json::from_str::<JournalEntry>(&line) else {
+                    continue;
+                };
+                used_sub_dirs.insert(entry.sub_dir.clone());
+                if !last.contains_key(&entry.inputs) {
+                    order.push(entry.inputs.clone());
+                }
+                last.insert(entry.inputs.clone(), entry);
+            }
+        }
+        let unfinished = order
+            .into_iter()
+            .filter_map(|inputs| last.remove(&inputs))
+            .filter(|entry| entry.state == "started" || entry.state == "moved")
+            .collect();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Terminate a line torn by a crash so the next entry starts on its own line
+        if fs::read(path)?.last().is_some_and(|b| *b != b'\n') {
+            writeln!(file)?;
+        }
+        Ok(Journal {
+            file,
+            unfinished,
+            used_sub_dirs,
+        })
+    }
+
+    fn record(&mut self, entry: &JournalEntry) -> Result<(), Box<dyn std::error::Error>> {
+        writeln!(self.file, "{}", serde_json::to_string(entry)?)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Archive,
+    Folder,
+    Files,
+}
+
+/// Inputs processed together, with the watched folder they were found in
+struct Unit {
+    kind: Kind,
+    watch_dir: PathBuf,
+    inputs: Vec<PathBuf>,
+    /// Where the inputs already are when a unit is resumed after they were moved
+    moved_to: Vec<PathBuf>,
+}
+
+impl Unit {
+    /// The files and folders to read: the inputs, or where they were moved to
+    fn paths(&self) -> &[PathBuf] {
+        if self.moved_to.is_empty() {
+            &self.inputs
+        } else {
+            &self.moved_to
+        }
+    }
+}
+
+/// File count, total size and newest modification time of a file or folder
+type Signature = (u64, u64, Option<SystemTime>);
+
+/// Last signature of each input and since when it has been unchanged
+#[derive(Default)]
+struct Tracker {
+    seen: HashMap<PathBuf, (Signature, Instant)>,
+}
+
+impl Tracker {
+    fn is_stable(&mut self, path: &Path, settle: Duration) -> bool {
+        let Some(signature) = signature(path) else {
+            return false;
+        };
+        if is_locked(path) {
+            return false;
+        }
+        match self.seen.get(path) {
+            Some((previous, since)) if *previous == signature => since.elapsed() >= settle,
+            _ => {
+                self.seen
+                    .insert(path.to_path_buf(), (signature, Instant::now()));
+                false
+            }
+        }
+    }
+}
+
+fn signature(path: &Path) -> Option<Signature> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_dir() {
+        return Some((1, metadata.len(), metadata.modified().ok()));
+    }
+    let mut signature: Signature = (0, 0, None);
+    for file in walk(path).ok()? {
+        let metadata = fs::metadata(&file).ok()?;
+        signature.0 += 1;
+        signature.1 += metadata.len();
+        signature.2 = signature.2.max(metadata.modified().ok());
+    }
+    Some(signature)
+}
+
+/// Whether a writer holds a lock on the file
+fn is_locked(path: &Path) -> bool {
+    File::open(path).is_ok_and(|file| {
+        matches!(
+            file.try_lock_shared(),
+            Err(std::fs::TryLockError::WouldBlock)
+        )
+    })
+}
+
+fn walk(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                stack.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Watch the folders until the process is stopped (or, with `once`, until they are empty).
+pub fn watch(
+    options: &WatchOptions,
+    batch_options: &BatchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for dir in options.dirs {
+        if !dir.is_dir() {
+            return Err(format!("Watch folder does not exist: {}", dir.display()).into());
+        }
+    }
+    let mut journal = Journal::open(options.journal)?;
+    let mut used = journal.used_sub_dirs.clone();
+    if let Some(output) = batch_options.output
+        && let Ok(entries) = fs::read_dir(output)
+    {
+        for entry in entries.flatten() {
+            used.insert(PathBuf::from(entry.file_name()));
+        }
+    }
+
+    // Never pick up our own folders when they live inside a watched folder
+    let mut excluded: Vec<PathBuf> = [
+        options.done,
+        options.failed,
+        Some(options.journal),
+        Some(options.report_dir),
+        batch_options.output,
+        batch_options.xprot,
+        batch_options.nifti,
+    ]
+    .into_iter()
+    .flatten()
+    .map(Path::to_path_buf)
+    .collect();
+    excluded.push(options.report_dir.join("series_catalog.csv"));
+    excluded.push(options.report_dir.join("failures.csv"));
+    excluded.push(options.report_dir.join("skipped_files.csv"));
+    for dir in options.dirs {
+        excluded.push(dir.join("done"));
+        excluded.push(dir.join("failed"));
+    }
+
+    info!(
+        "Watching {} (settle {}s, poll {}s, journal {})",
+        options
+            .dirs
+            .iter()
+            .map(|d| d.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        options.settle.as_secs(),
+        options.poll.as_secs(),
+        options.journal.display()
+    );
+
+    for entry in std::mem::take(&mut journal.unfinished) {
+        let unit = match resumed_unit(
+            entry.state == "moved",
+            entry.inputs,
+            entry.moved_to,
+            options,
+        ) {
+            Some(unit) => unit,
+            None => continue,
+        };
+        info!("Resuming unfinished input {}", unit.paths()[0].display());
+        process_or_fail(&unit, &entry.sub_dir, &mut journal, options, batch_options);
+    }
+
+    let mut tracker = Tracker::default();
+    loop {
+        let mut units = Vec::new();
+        let mut waiting = 0;
+        for dir in options.dirs {
+            let mut loose = Vec::new();
+            let mut loose_stable = true;
+            let paths = match candidates(dir, &excluded) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    error!("Cannot list {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            for path in paths {
+                let stable = tracker.is_stable(&path, options.settle);
+                if path.is_dir() || is_zip(&path) {
+                    if stable {
+                        let kind = if path.is_dir() {
+                            Kind::Folder
+                        } else {
+                            Kind::Archive
+                        };
+                        units.push(Unit {
+                            kind,
+                            watch_dir: dir.clone(),
+                            inputs: vec![path],
+                            moved_to: Vec::new(),
+                        });
+                    } else {
+                        waiting += 1;
+                    }
+                } else {
+                    loose_stable &= stable;
+                    loose.push(path);
+                }
+            }
+            // Loose files arrive together (e.g. a copied series); wait until all have settled
+            if !loose.is_empty() {
+                if loose_stable {
+                    units.push(Unit {
+                        kind: Kind::Files,
+                        watch_dir: dir.clone(),
+                        inputs: loose,
+                        moved_to: Vec::new(),
+                    });
+                } else {
+                    waiting += loose.len();
+                }
+            }
+        }
+
+        for unit in &units {
+            let name = match unit.kind {
+                Kind::Files => format!("loose_{}", now()),
+                _ => unit.inputs[0]
+                    .file_stem()
+                    .map(|s| sanitize_filename(&s.to_string_lossy()))
+                    .unwrap_or_else(|| "input".to_string()),
+            };
+            let sub_dir = crate::unique_output_path(PathBuf::from(name), &mut used);
+            process_or_fail(unit, &sub_dir, &mut journal, options, batch_options);
+            for input in &unit.inputs {
+                tracker.seen.remove(input);
+            }
+        }
+
+        if options.once && units.is_empty() && waiting == 0 {
+            info!("Nothing left to process");
+            return Ok(());
+        }
+        std::thread::sleep(options.poll);
+    }
+}
+
+/// The unit of an unfinished journal entry, with the paths that still exist. Moved units keep
+/// their journal inputs so their final entry closes the same unit.
+fn resumed_unit(
+    moved: bool,
+    inputs: Vec<PathBuf>,
+    moved_to: Vec<PathBuf>,
+    options: &WatchOptions,
+) -> Option<Unit> {
+    let (inputs, moved_to) = if moved {
+        (
+            inputs,
+            moved_to.into_iter().filter(|p| p.exists()).collect(),
+        )
+    } else {
+        (
+            inputs.into_iter().filter(|p| p.exists()).collect(),
+            Vec::new(),
+        )
+    };
+    let first = if moved {
+        moved_to.first()?
+    } else {
+        inputs.first()?
+    };
+    let watch_dir = options.dirs.iter().find(|d| inputs[0].starts_with(d))?;
+    let count = if moved { moved_to.len() } else { inputs.len() };
+    let kind = if count == 1 && first.is_dir() {
+        Kind::Folder
+    } else if count == 1 && is_zip(first) {
+        Kind::Archive
+    } else {
+        Kind::Files
+    };
+    Some(Unit {
+        kind,
+        watch_dir: watch_dir.clone(),
+        inputs,
+        moved_to,
+    })
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+/// Entries of a watched folder that may be inputs
+fn candidates(
+    dir: &Path,
+    excluded: &[PathBuf],
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.starts_with('.') || PARTIAL_SUFFIXES.iter().any(|s| name.ends_with(s)) {
+            continue;
+        }
+        // Compared canonically, as these folders may be created while watching
+        if let Ok(canonical) = path.canonicalize()
+            && excluded
+                .iter()
+                .any(|e| e.canonicalize().is_ok_and(|e| e == canonical))
+        {
+            continue;
+        }
+        paths.push(path);
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Process a unit. When that fails outside the pipeline (journal, move or report I/O), log it and
+/// move the inputs still in place to the failed folder, so one bad input does not stop the
+/// daemon.
+fn process_or_fail(
+    unit: &Unit,
+    sub_dir: &Path,
+    journal: &mut Journal,
+    options: &WatchOptions,
+    batch_options: &BatchOptions,
+) {
+    let Err(e) = process(unit, sub_dir, journal, options, batch_options) else {
+        return;
+    };
+    error!(input = %unit.inputs[0].display(), "Processing failed: {}", e);
+    let failed = options
+        .failed
+        .map_or_else(|| unit.watch_dir.join("failed"), Path::to_path_buf);
+    let failed = match unit.kind {
+        Kind::Files => failed.join(sub_dir),
+        _ => failed,
+    };
+    for input in unit.paths().iter().filter(|input| input.exists()) {
+        if let Err(e) = move_input(input, &failed) {
+            error!(input = %input.display(), "Cannot move to {}: {}", failed.display(), e);
+        }
+    }
+}
+
+/// Run the pipeline on one unit, move its inputs and record the outcome.
+fn process(
+    unit: &Unit,
+    sub_dir: &Path,
+    journal: &mut Journal,
+    options: &WatchOptions,
+    batch_options: &BatchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let resumed = !unit.moved_to.is_empty();
+    if !resumed {
+        journal.record(&JournalEntry {
+            time: now(),
+            state: "started".to_string(),
+            inputs: unit.inputs.clone(),
+            sub_dir: sub_dir.to_path_buf(),
+            moved_to: Vec::new(),
+            error: None,
+        })?;
+    }
+
+    let paths = unit.paths();
+    // Names in the packed ZIP are relative to the watched folder, or to where the inputs were
+    // moved
+    let base = paths[0].parent().unwrap_or(&unit.watch_dir);
+    let result = match unit.kind {
+        // The tracker has already seen the archive settle; only the lock is taken here
+        Kind::Archive => SettledArchive::open(&paths[0], Duration::ZERO).and_then(|zip_data| {
+            batch::process_zip_data(&paths[0], &zip_data, true, sub_dir, batch_options)
+        }),
+        Kind::Folder | Kind::Files => pack(paths, base).and_then(|zip_data| {
+            batch::process_zip_data(&paths[0], &zip_data, false, sub_dir, batch_options)
+        }),
+    };
+
+    let state = if result.is_ok() { "done" } else { "failed" };
+    let moved_to = if resumed {
+        unit.moved_to.clone()
+    } else {
+        let destination = match &result {
+            Ok(_) => options.done,
+            Err(_) => options.failed,
+        };
+        let destination = destination.map_or_else(|| unit.watch_dir.join(state), Path::to_path_buf);
+        // Loose files are kept together in a folder named after the unit
+        let destination = match unit.kind {
+            Kind::Files => destination.join(sub_dir),
+            _ => destination,
+        };
+        let mut moved_to = Vec::new();
+        for input in &unit.inputs {
+            moved_to.push(move_input(input, &destination)?);
+        }
+        journal.record(&JournalEntry {
+            time: now(),
+            state: "moved".to_string(),
+            inputs: unit.inputs.clone(),
+            sub_dir: sub_dir.to_path_buf(),
+            moved_to: moved_to.clone(),
+            error: None,
+        })?;
+        moved_to
+    };
+    let source = match unit.kind {
+        Kind::Files => moved_to[0].parent().unwrap_or(base).display().to_string(),
+        _ => moved_to[0].display().to_string(),
+    };
+    // Rows of an attempt cut short after the move are replaced
+    let report = |name: &str, header: &str, rows: &[String]| {
+        write_report(
+            &options.report_dir.join(name),
+            header,
+            resumed.then_some(source.as_str()),
+            rows,
+        )
+    };
+
+    fs::create_dir_all(options.report_dir)?;
+    let error = match result {
+        Ok(result) => {
+            info!(
+                input = %unit.inputs[0].display(),
+                files = result.file_count,
+                series = result.series_rows.len(),
+                "Processed, moved to {}",
+                source
+            );
+            let rows: Vec<String> = result
+                .series_rows
+                .iter()
+                .map(|row| format!("{},{}", escape_csv_field(&source), row.join(",")))
+                .collect();
+            report(
+                "series_catalog.csv",
+                &format!("SourceArchive,{}", SERIES_METADATA_HEADER),
+                &rows,
+            )?;
+            let skipped: Vec<String> = result
+                .skipped
+                .iter()
+                .map(|e| format!("{},{}", escape_csv_field(&source), e.csv_row()))
+                .collect();
+            report(
+                "skipped_files.csv",
+                &format!("SourceArchive,{}", SKIPPED_FILES_HEADER),
+                &skipped,
+            )?;
+            None
+        }
+        Err(e) => {
+            error!(input = %unit.inputs[0].display(), "Failed: {}, moved to {}", e, source);
+            report(
+                "failures.csv",
+                "SourceArchive,Error",
+                &[format!(
+                    "{},{}",
+                    escape_csv_field(&source),
+                    escape_csv_field(&e.to_string())
+                )],
+            )?;
+            Some(e.to_string())
+        }
+    };
+
+    journal.record(&JournalEntry {
+        time: now(),
+        state: state.to_string(),
+        inputs: unit.inputs.clone(),
+        sub_dir: sub_dir.to_path_buf(),
+        moved_to,
+        error,
+    })
+}
+
+/// Files and folders packed into an uncompressed ZIP (or an uploaded body) in a temporary file,
+/// memory-mapped. The file is removed on drop.
+pub struct Packed {
+    path: PathBuf,
+    mmap: Option<memmap2::Mmap>,
+}
+
+impl Deref for Packed {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.mmap.as_deref().unwrap_or_default()
+    }
+}
+
+impl Drop for Packed {
+    fn drop(&mut self) {
+        // Unmap first: Windows cannot delete a mapped file
+        self.mmap.take();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Number of the next temporary ZIP of this process
+static PACKED: AtomicU64 = AtomicU64::new(0);
+
+/// A new temporary ZIP file, removed again when the returned `Packed` is dropped unmapped
+fn temporary_zip() -> Result<(File, Packed), Box<dyn std::error::Error>> {
+    let path = std::env::temp_dir().join(format!(
+        "dicom_scanner_{}_{}.zip",
+        std::process::id(),
+        PACKED.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    Ok((file, Packed { path, mmap: None }))
+}
+
+/// Copy `reader` into a temporary file. None when it holds more than `limit` bytes; an empty
+/// body is returned unmapped.
+pub fn spool(reader: impl Read, limit: u64) -> Result<Option<Packed>, Box<dyn std::error::Error>> {
+    let (file, mut packed) = temporary_zip()?;
+    let mut writer = BufWriter::new(file);
+    let copied = std::io::copy(&mut reader.take(limit + 1), &mut writer)?;
+    writer.flush()?;
+    if copied > limit {
+        return Ok(None);
+    }
+    if copied > 0 {
+        packed.mmap = Some(map_archive(&packed.path)?);
+    }
+    Ok(Some(packed))
+}
+
+/// Pack files and folders into an uncompressed ZIP, named relative to `base`.
+pub fn pack(inputs: &[PathBuf], base: &Path) -> Result<Packed, Box<dyn std::error::Error>> {
+    // Removes the file again if packing fails
+    let (file, mut packed) = temporary_zip()?;
+
+    let mut zip = zip::ZipWriter::new(BufWriter::new(file));
+    for input in inputs {
+        let files = if input.is_dir() {
+            walk(input)?
+        } else {
+            vec![input.clone()]
+        };
+        for file in files {
+            let name = file
+                .strip_prefix(base)
+                .unwrap_or(&file)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let size = fs::metadata(&file)?.len();
+            let options = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored)
+                .large_file(size >= u32::MAX as u64);
+            zip.start_file(name, options)?;
+            std::io::copy(&mut File::open(&file)?, &mut zip)?;
+        }
+    }
+    zip.finish()?.flush()?;
+    packed.mmap = Some(map_archive(&packed.path)?);
+    Ok(packed)
+}
+
+/// Move a file or folder into `destination`, adding `_1`, `_2`, ... when the name is taken.
+fn move_input(input: &Path, destination: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    fs::create_dir_all(destination)?;
+    let name = input.file_name().ok_or("input without a name")?;
+    let mut target = destination.join(name);
+    let mut counter = 1;
+    while target.exists() {
+        let stem = Path::new(name)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let extension = Path::new(name)
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        target = destination.join(format!("{}_{}{}", stem, counter, extension));
+        counter += 1;
+    }
+
+    // Renames fail across file systems; copy and delete instead
+    if fs::rename(input, &target).is_err() {
+        if input.is_dir() {
+            for file in walk(input)? {
+                let copy = target.join(file.strip_prefix(input)?);
+                if let Some(parent) = copy.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&file, copy)?;
+            }
+            fs::remove_dir_all(input)?;
+        } else {
+            fs::copy(input, &target)?;
+            fs::remove_file(input)?;
+        }
+    }
+    Ok(target)
+}
+
+/// Append rows to a CSV report, writing the header when the file is new. With `replace`, the rows
+/// already there for that source, and a last line torn by a crash, are dropped first.
+fn write_report(
+    path: &Path,
+    header: &str,
+    replace: Option<&str>,
+    rows: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(source) = replace
+        && path.exists()
+    {
+        let prefix = format!("{},", escape_csv_field(source));
+        let kept: String = csv_records(&fs::read_to_string(path)?)
+            .into_iter()
+            .filter(|record| record.ends_with('\n') && !record.starts_with(&prefix))
+            .collect();
+        let temporary = path.with_extension("csv.tmp");
+        fs::write(&temporary, kept)?;
+        fs::rename(&temporary, path)?;
+    }
+
+    let new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if new {
+        writeln!(file, "{}", header)?;
+    }
+    for row in rows {
+        writeln!(file, "{}", row)?;
+    }
+    Ok(())
+}
+
+/// Split CSV text into records, each with its line break; quoted fields may span lines.
+fn csv_records(text: &str) -> Vec<&str> {
+    let mut records = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '\n' if !quoted => {
+                records.push(&text[start..=i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < text.len() {
+        records.push(&text[start..]);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{file_bytes, instance, scratch_dir, zip_of};
+
+    fn archive(study: &str) -> Vec<u8> {
+        let obj = instance(
+            "P1",
+            study,
+            &format!("{}.1", study),
+            &format!("{}.1.1", study),
+        );
+        zip_of(&[("DICOM/IM0", file_bytes(obj))])
+    }
+
+    fn entry(state: &str, inputs: &[PathBuf], moved_to: &[PathBuf]) -> JournalEntry {
+        JournalEntry {
+            time: 0,
+            state: state.to_string(),
+            inputs: inputs.to_vec(),
+            sub_dir: PathBuf::from("a"),
+            moved_to: moved_to.to_vec(),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn torn_journal_lines_are_skipped() {
+        let dir = scratch_dir("watch_journal");
+        let path = dir.join("journal.jsonl");
+        let started = serde_json::to_string(&entry("started", &[dir.join("a.zip")], &[])).unwrap();
+        let done = serde_json::to_string(&entry("done", &[dir.join("a.zip")], &[])).unwrap();
+        fs::write(&path, format!("{}\n{}", started, &done[..done.len() / 2])).unwrap();
+
+        let mut journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.unfinished.len(), 1);
+        journal
+            .record(&entry("done", &[dir.join("a.zip")], &[]))
+            .unwrap();
+        drop(journal);
+
+        // The torn line stays on its own; the new entry closes the unit
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert!(Journal::open(&path).unwrap().unfinished.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn moved_units_resume_without_duplicate_rows() {
+        let dir = scratch_dir("watch_resume");
+        let watched = dir.join("in");
+        let done = watched.join("done");
+        fs::create_dir_all(&done).unwrap();
+        // Own folders inside the watched folder are never inputs
+        let journal = watched.join("journal.jsonl");
+        let reports = watched.join("reports");
+        let output = watched.join("out");
+        fs::create_dir_all(&reports).unwrap();
+        fs::create_dir_all(&output).unwrap();
+
+        // A crash after the move, with one report row and a torn second one written
+        let moved = done.join("a.zip");
+        fs::write(&moved, archive("1.2.1")).unwrap();
+        let inputs = [watched.join("a.zip")];
+        let lines: Vec<String> = [
+            entry("started", &inputs, &[]),
+            entry("moved", &inputs, std::slice::from_ref(&moved)),
+        ]
+        .iter()
+        .map(|e| serde_json::to_string(e).unwrap() + "\n")
+        .collect();
+        fs::write(&journal, lines.concat()).unwrap();
+        let catalog = reports.join("series_catalog.csv");
+        let source = moved.display().to_string();
+        fs::write(
+            &catalog,
+            format!(
+                "SourceArchive,{}\n{},old\n{},to",
+                SERIES_METADATA_HEADER, source, source
+            ),
+        )
+        .unwrap();
+
+        fs::write(watched.join("b.zip"), archive("1.2.2")).unwrap();
+
+        let options = WatchOptions {
+            dirs: std::slice::from_ref(&watched),
+            done: None,
+            failed: None,
+            journal: &journal,
+            report_dir: &reports,
+            settle: Duration::ZERO,
+            poll: Duration::from_millis(10),
+            once: true,
+        };
+        let batch_options = BatchOptions {
+            output: Some(&output),
+            xprot: None,
+            report_dir: None,
+            template: None,
+            dicom_media: false,
+            jobs: 1,
+            scan_index: None,
+            hierarchy_db: None,
+            parquet: None,
+            transcode: None,
+            preview: None,
+            nifti: None,
+            bids: None,
+            bids_rules: &[],
+            bids_subjects: None,
+            filter: None,
+        };
+        watch(&options, &batch_options).unwrap();
+
+        let text = fs::read_to_string(&catalog).unwrap();
+        let rows: Vec<&str> = text.lines().skip(1).collect();
+        assert_eq!(rows.len(), 2, "{}", text);
+        assert!(rows[0].starts_with(&format!("{},", source)));
+        assert!(rows[0].contains("1.2.1.1"));
+        assert!(rows[1].starts_with(&format!("{},", done.join("b.zip").display())));
+
+        assert!(moved.exists() && done.join("b.zip").exists());
+        assert!(journal.exists() && reports.is_dir() && output.join("a").is_dir());
+        assert!(Journal::open(&journal).unwrap().unfinished.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multi_line_fields_stay_in_one_record() {
+        assert_eq!(
+            csv_records("a,b\nc,\"x\ny\"\nd"),
+            ["a,b\n", "c,\"x\ny\"\n", "d"]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! `listen` in its own process, fed by a storage SCU from the test.
+
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use dicom::core::{DataElement, PrimitiveValue, VR};
+use dicom::dictionary_std::{tags, uids};
+use dicom::object::mem::InMemDicomObject;
+use dicom::transfer_syntax::entries;
+use dicom_scanner::dimse::{self, Incoming, Proposal, Requestor};
+
+const SERIES: &str = "1.2.826.0.1.3680043.2.1125.7.2";
+const INSTANCE: &str = "1.2.826.0.1.3680043.2.1125.7.2.1";
+
+/// The receiver process, stopped when the test ends
+struct Receiver(Child);
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Poll until `ready` holds, for at most ten seconds.
+fn wait_for(what: &str, mut ready: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !ready() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "timed out: {}",
+            what
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn associate(port: u16, calling_ae_title: &str) -> Result<Requestor, Box<dyn std::error::Error>> {
+    Requestor::establish(
+        &format!("127.0.0.1:{}", port),
+        &Proposal {
+            calling_ae_title,
+            called_ae_title: "DICOM_SCANNER",
+            abstract_syntaxes: vec![dimse::VERIFICATION_SOP_CLASS, uids::CT_IMAGE_STORAGE],
+            transfer_syntaxes: &[entries::EXPLICIT_VR_LITTLE_ENDIAN.uid()],
+            scp_roles: &[],
+        },
+    )
+}
+
+/// Send a request and return the status of its response.
+fn request(
+    association: &mut Requestor,
+    abstract_syntax: &str,
+    field: u16,
+    instance: Option<(&str, &[u8])>,
+) -> u16 {
+    let (context_id, _) = association.context(abstract_syntax).unwrap();
+    let mut elements = vec![
+        dimse::uid(tags::AFFECTED_SOP_CLASS_UID, abstract_syntax),
+        dimse::ushort(tags::MESSAGE_ID, 1),
+    ];
+    if let Some((uid, _)) = instance {
+        elements.push(dimse::ushort(tags::PRIORITY, 0));
+        elements.push(dimse::uid(tags::AFFECTED_SOP_INSTANCE_UID, uid));
+    }
+    let command = dimse::command(field, elements, instance.is_some()).unwrap();
+    dimse::send(
+        association,
+        context_id,
+        &command,
+        instance.map(|(_, data)| data),
+    )
+    .unwrap();
+    let Incoming::Message(response) = dimse::receive(association).unwrap() else {
+        panic!("no response");
+    };
+    assert_eq!(response.field(), field | 0x8000);
+    response.uint(tags::STATUS).unwrap()
+}
+
+fn ct_instance(instance: &str, series: &str) -> Vec<u8> {
+    let text = |tag, vr, value: &str| DataElement::new(tag, vr, PrimitiveValue::from(value));
+    let obj = InMemDicomObject::from_element_iter([
+        text(tags::SOP_CLASS_UID, VR::UI, uids::CT_IMAGE_STORAGE),
+        text(tags::SOP_INSTANCE_UID, VR::UI, instance),
+        text(
+            tags::STUDY_INSTANCE_UID,
+            VR::UI,
+            "1.2.826.0.1.3680043.2.1125.7",
+        ),
+        text(tags::SERIES_INSTANCE_UID, VR::UI, series),
+        text(tags::SERIES_NUMBER, VR::IS, "2"),
+        text(tags::MODALITY, VR::CS, "CT"),
+    ]);
+    dimse::encode_data(&obj, entries::EXPLICIT_VR_LITTLE_ENDIAN.uid()).unwrap()
+}
+
+fn stored(output: &Path) -> Vec<PathBuf> {
+    glob::glob(&format!("{}/**/*.dcm", output.display()))
+        .unwrap()
+        .flatten()
+        .collect()
+}
+
+/// Start `listen` into a fresh output directory named after `test`, accepting MODALITY only.
+fn start(test: &str) -> (Receiver, PathBuf, u16) {
+    let output =
+        std::env::temp_dir().join(format!("dicom_scanner_{}_{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&output);
+    let port = free_port();
+    let receiver = Receiver(
+        Command::new(env!("CARGO_BIN_EXE_dicom_scanner"))
+            .arg("-qq")
+            .arg("listen")
+            .arg(&output)
+            .args(["--port", &port.to_string()])
+            .args(["--allow", "MODALITY", "--idle-timeout", "1"])
+            .spawn()
+            .unwrap(),
+    );
+    wait_for("receiver", || {
+        TcpStream::connect(("127.0.0.1", port)).is_ok()
+    });
+    (receiver, output, port)
+}
+
+#[test]
+fn receives_from_allowed_callers_only() {
+    let (_receiver, output, port) = start("listen");
+
+    assert!(associate(port, "STRANGER").is_err());
+
+    let mut association = associate(port, "MODALITY").unwrap();
+    assert_eq!(
+        request(
+            &mut association,
+            dimse::VERIFICATION_SOP_CLASS,
+            dimse::C_ECHO_RQ,
+            None
+        ),
+        dimse::STATUS_SUCCESS
+    );
+    assert_eq!(
+        request(
+            &mut association,
+            uids::CT_IMAGE_STORAGE,
+            dimse::C_STORE_RQ,
+            Some((INSTANCE, &ct_instance(INSTANCE, SERIES)))
+        ),
+        dimse::STATUS_SUCCESS
+    );
+    association.release().unwrap();
+
+    let files = stored(&output);
+    assert_eq!(files.len(), 1);
+    assert!(files[0].ends_with(format!("{}.dcm", INSTANCE)));
+
+    // The study is exported once it has been idle for a second
+    let csv = output.join("series_metadata.csv");
+    wait_for("series_metadata.csv", || {
+        std::fs::read_to_string(&csv).is_ok_and(|text| text.contains(SERIES))
+    });
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn non_ascii_uids_do_not_stop_storage() {
+    let (_receiver, output, port) = start("listen_non_ascii");
+    let mut association = associate(port, "MODALITY").unwrap();
+
+    // Read back as ISO-8859-1, every byte of the last component is a two-byte character
+    let odd = "1.2.826.0.1.3680043.2.1125.7.3.1\u{e4}\u{e4}\u{e4}\u{e4}\u{e4}\u{e4}\u{e4}\u{e4}";
+    let instance = "1.2.826.0.1.3680043.2.1125.7.3.1.1";
+    assert_eq!(
+        request(
+            &mut association,
+            uids::CT_IMAGE_STORAGE,
+            dimse::C_STORE_RQ,
+            Some((instance, &ct_instance(instance, odd)))
+        ),
+        dimse::STATUS_SUCCESS
+    );
+    // Later instances are still stored
+    assert_eq!(
+        request(
+            &mut association,
+            uids::CT_IMAGE_STORAGE,
+            dimse::C_STORE_RQ,
+            Some((INSTANCE, &ct_instance(INSTANCE, SERIES)))
+        ),
+        dimse::STATUS_SUCCESS
+    );
+    association.release().unwrap();
+
+    assert_eq!(stored(&output).len(), 2);
+    std::fs::remove_dir_all(&output).unwrap();
+}